@@ -0,0 +1,219 @@
+//! Benchmarks for the hot proxy path.
+//!
+//! These run the `hdnode` binary in development mode in front of a mock remote
+//! node that replies immediately, so that they measure hdnode's own overhead
+//! for proxying single requests, fanning out batches and signing, including
+//! concurrently for many accounts.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Server,
+};
+use rocket::{
+    futures::future,
+    serde::json::{
+        serde_json::{self, json},
+        Value,
+    },
+    tokio::{self, runtime::Runtime},
+};
+use std::{
+    convert::Infallible,
+    net::{SocketAddr, TcpListener},
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+/// The first development account.
+const ACCOUNT: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+
+/// An imported private key, and its account.
+const IMPORTED_KEY: &str = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+const IMPORTED_ACCOUNT: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+/// Starts a mock remote node, replying to every request immediately.
+async fn upstream() -> SocketAddr {
+    let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(reply)) });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let address = server.local_addr();
+    tokio::spawn(server);
+    address
+}
+
+async fn reply(request: hyper::Request<Body>) -> Result<hyper::Response<Body>, Infallible> {
+    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+    let response = match serde_json::from_slice::<Value>(&body).unwrap() {
+        Value::Array(requests) => Value::Array(requests.iter().map(result).collect()),
+        request => result(&request),
+    };
+    Ok(hyper::Response::builder()
+        .header("content-type", "application/json")
+        .body(Body::from(response.to_string()))
+        .unwrap())
+}
+
+/// Returns the mock result for a request, which is the chain ID of a
+/// development node, or zero for any other method.
+fn result(request: &Value) -> Value {
+    let result = match request["method"].as_str() {
+        Some("eth_chainId") => "0x7a69",
+        _ => "0x0",
+    };
+    json!({ "jsonrpc": "2.0", "result": result, "id": request["id"] })
+}
+
+fn request(method: &str, params: Value, id: usize) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params, "id": id })
+}
+
+/// An `hdnode` process, killed when dropped.
+struct Hdnode {
+    process: Child,
+    url: String,
+}
+
+impl Hdnode {
+    /// Spawns `hdnode` in development mode proxying the remote node, with an
+    /// additional imported account, and waits for it to serve requests.
+    fn spawn(runtime: &Runtime, upstream: SocketAddr) -> Self {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let process = Command::new(env!("CARGO_BIN_EXE_hdnode"))
+            .args(["serve", "--dev"])
+            .env("ROCKET_PORT", port.to_string())
+            .env("ROCKET_REMOTE_NODE_URL", format!("http://{upstream}"))
+            .env("ROCKET_PRIVATE_KEYS", format!("[\"{IMPORTED_KEY}\"]"))
+            .env("ROCKET_LOG_LEVEL", "off")
+            .env("RUST_LOG", "off")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let hdnode = Self {
+            process,
+            url: format!("http://127.0.0.1:{port}"),
+        };
+
+        runtime.block_on(async {
+            let client = reqwest::Client::new();
+            for _ in 0..100 {
+                let ready = client
+                    .post(&hdnode.url)
+                    .json(&request("eth_chainId", json!([]), 1))
+                    .send()
+                    .await;
+                if ready.is_ok() {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            panic!("hdnode did not start serving requests");
+        });
+        hdnode
+    }
+}
+
+impl Drop for Hdnode {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+fn proxy(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let upstream = runtime.block_on(upstream());
+    let hdnode = Hdnode::spawn(&runtime, upstream);
+    let client = reqwest::Client::new();
+    let call = |body: &Value| {
+        let request = client.post(&hdnode.url).json(body);
+        async move { request.send().await.unwrap().bytes().await.unwrap() }
+    };
+    // Requests are checked once before being benchmarked, so that errors
+    // don't go unnoticed.
+    let check = |body: &Value| {
+        let responses = match serde_json::from_slice(&runtime.block_on(call(body))).unwrap() {
+            Value::Array(responses) => responses,
+            response => vec![response],
+        };
+        for response in responses {
+            assert!(response.get("error").is_none(), "{response}");
+        }
+    };
+
+    let single = request("eth_blockNumber", json!([]), 1);
+    check(&single);
+    c.bench_function("proxy/single", |b| {
+        b.to_async(&runtime).iter(|| call(&single))
+    });
+
+    let mut group = c.benchmark_group("proxy/batch");
+    for size in [1, 10, 100] {
+        let batch = Value::Array(
+            (0..size)
+                .map(|id| request("eth_getBalance", json!([ACCOUNT, "latest"]), id))
+                .collect(),
+        );
+        check(&batch);
+        group.throughput(Throughput::Elements(size as _));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &batch, |b, batch| {
+            b.to_async(&runtime).iter(|| call(batch))
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("signing");
+    let message = request("eth_sign", json!([ACCOUNT, "0x68656c6c6f"]), 1);
+    check(&message);
+    group.bench_function("eth_sign", |b| b.to_async(&runtime).iter(|| call(&message)));
+    // Imported keys sign with a precomputed context, unlike derived ones.
+    let imported = request("eth_sign", json!([IMPORTED_ACCOUNT, "0x68656c6c6f"]), 1);
+    check(&imported);
+    group.bench_function("eth_sign/imported", |b| {
+        b.to_async(&runtime).iter(|| call(&imported))
+    });
+    let transaction = request(
+        "eth_signTransaction",
+        json!([{
+            "from": ACCOUNT,
+            "to": ACCOUNT,
+            "gas": "0x5208",
+            "maxFeePerGas": "0x4a817c800",
+            "maxPriorityFeePerGas": "0x3b9aca00",
+            "nonce": "0x0",
+        }]),
+        1,
+    );
+    check(&transaction);
+    group.bench_function("eth_signTransaction", |b| {
+        b.to_async(&runtime).iter(|| call(&transaction))
+    });
+    let public_key = request("hdnode_getPublicKey", json!([ACCOUNT]), 1);
+    check(&public_key);
+    group.bench_function("hdnode_getPublicKey", |b| {
+        b.to_async(&runtime).iter(|| call(&public_key))
+    });
+
+    // Relayer-style workloads sign for many accounts concurrently.
+    let accounts = runtime.block_on(call(&request("eth_accounts", json!([]), 1)));
+    let accounts = serde_json::from_slice::<Value>(&accounts).unwrap()["result"].clone();
+    let messages = (0..100)
+        .map(|id| {
+            let account = &accounts[id % accounts.as_array().unwrap().len()];
+            request("eth_sign", json!([account, "0x68656c6c6f"]), id)
+        })
+        .collect::<Vec<_>>();
+    messages.iter().for_each(check);
+    group.throughput(Throughput::Elements(messages.len() as _));
+    group.bench_function("eth_sign/concurrent", |b| {
+        b.to_async(&runtime)
+            .iter(|| future::join_all(messages.iter().map(call)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, proxy);
+criterion_main!(benches);
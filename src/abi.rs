@@ -0,0 +1,119 @@
+//! Module with minimal Solidity ABI encoding helpers.
+//!
+//! This only implements what is needed for hashing and building calldata for
+//! the handful of types hdnode deals with internally.
+
+use anyhow::{ensure, Context as _, Result};
+use ethnum::U256;
+use hdwallet::account::Address;
+use sha3::{Digest as _, Keccak256};
+
+/// A 32-byte ABI word.
+pub type Word = [u8; 32];
+
+/// Computes the Keccak-256 hash of some data.
+pub fn keccak256(data: impl AsRef<[u8]>) -> [u8; 32] {
+    Keccak256::digest(data.as_ref()).into()
+}
+
+/// Encodes an address as an ABI word.
+pub fn address(address: Address) -> Word {
+    let mut word = [0_u8; 32];
+    word[12..].copy_from_slice(&address.0);
+    word
+}
+
+/// Encodes an unsigned integer as an ABI word.
+pub fn uint(value: U256) -> Word {
+    value.to_be_bytes()
+}
+
+/// Encodes a sequence of static ABI words.
+pub fn encode(words: &[Word]) -> Vec<u8> {
+    words.concat()
+}
+
+/// Computes the 4-byte function selector for the specified signature.
+pub fn selector(signature: &str) -> [u8; 4] {
+    let hash = keccak256(signature);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Encodes calldata for a function call with static arguments.
+pub fn call(signature: &str, words: &[Word]) -> Vec<u8> {
+    let mut calldata = selector(signature).to_vec();
+    calldata.extend_from_slice(&encode(words));
+    calldata
+}
+
+/// Returns the ABI word at the specified offset, if the data is long enough.
+fn word(data: &[u8], offset: usize) -> Option<&Word> {
+    data.get(offset..offset.checked_add(32)?)?.try_into().ok()
+}
+
+/// Decodes an unsigned integer ABI word at the specified offset.
+pub fn decode_uint(data: &[u8], offset: usize) -> Result<U256> {
+    let word = word(data, offset).context("ABI data too short for uint")?;
+    Ok(U256::from_be_bytes(*word))
+}
+
+/// Decodes an address ABI word at the specified offset.
+pub fn decode_address(data: &[u8], offset: usize) -> Result<Address> {
+    let word = word(data, offset).context("ABI data too short for address")?;
+    ensure!(word[..12] == [0; 12], "invalid ABI address");
+    Ok(Address(word[12..].try_into().unwrap()))
+}
+
+/// Decodes a dynamic array of unsigned integers whose offset is stored at the
+/// specified offset.
+pub fn decode_uint_array(data: &[u8], offset: usize) -> Result<Vec<U256>> {
+    let offset = usize::try_from(decode_uint(data, offset)?).context("invalid array offset")?;
+    let len = usize::try_from(decode_uint(data, offset)?).context("invalid array length")?;
+    let start = offset.checked_add(32).context("invalid array offset")?;
+    ensure!(
+        data.len().saturating_sub(start) / 32 >= len,
+        "ABI data too short for array"
+    );
+    (0..len)
+        .map(|i| decode_uint(data, start + 32 * i))
+        .collect()
+}
+
+/// Decodes a dynamic string returned as the only value of a function call.
+pub fn decode_string(data: &[u8]) -> Result<String> {
+    let offset = usize::try_from(decode_uint(data, 0)?).context("invalid string offset")?;
+    let len = usize::try_from(decode_uint(data, offset)?).context("invalid string length")?;
+    let bytes = offset
+        .checked_add(32)
+        .and_then(|start| data.get(start..start.checked_add(len)?))
+        .context("ABI data too short for string")?;
+    Ok(String::from_utf8(bytes.to_vec())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_strings() {
+        let mut text = [0; 32];
+        text[..4].copy_from_slice(b"Test");
+        let data = encode(&[uint(U256::new(0x20)), uint(U256::new(4)), text]);
+        assert_eq!(decode_string(&data).unwrap(), "Test");
+        assert_eq!(decode_uint(&data, 32).unwrap(), 4);
+        assert!(decode_string(&data[..64]).is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_offsets() {
+        let max = uint(U256::from(usize::MAX as u64));
+        assert!(decode_uint(&max, usize::MAX - 16).is_err());
+        assert!(decode_address(&max, usize::MAX).is_err());
+        assert!(decode_uint_array(&encode(&[max]), 0).is_err());
+        assert!(decode_string(&encode(&[max])).is_err());
+
+        // A string whose length overflows when added to its start.
+        let data = encode(&[uint(U256::new(0x20)), max]);
+        assert!(decode_string(&data).is_err());
+    }
+}
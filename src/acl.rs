@@ -0,0 +1,203 @@
+//! Source IP allowlists.
+//!
+//! For deployments that can't put a firewall directly in front of the node,
+//! the RPC and admin endpoints can each be restricted to a list of networks.
+//! Allowlists are checked against the address of the connecting peer before
+//! request bodies are read, and deny everything they don't list.
+
+use crate::serialization::Str;
+use ipnet::{AddrParseError, IpNet};
+use rocket::{
+    http::Status,
+    request::{self, FromRequest},
+    serde::Deserialize,
+    Request,
+};
+use std::{
+    fmt::{self, Display, Formatter},
+    net::IpAddr,
+    str::FromStr,
+};
+
+/// Network access control options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Options {
+    /// The networks allowed to access the RPC endpoints, over HTTP, WebSocket
+    /// and gRPC. All networks are allowed when not specified.
+    #[serde(default)]
+    pub rpc_allowlist: Option<Allowlist>,
+
+    /// The networks allowed to access the admin endpoints. All networks are
+    /// allowed when not specified.
+    #[serde(default)]
+    pub admin_allowlist: Option<Allowlist>,
+}
+
+/// A list of allowed networks.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Allowlist(Vec<Str<Network>>);
+
+impl Allowlist {
+    /// Returns whether or not the address is allowed.
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of dual-stack listeners show up as mapped addresses.
+        let ip = match ip {
+            IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(ip.into(), IpAddr::V4),
+            ip => ip,
+        };
+        self.0.iter().any(|network| network.0 .0.contains(&ip))
+    }
+}
+
+/// Returns whether or not an optional allowlist allows an address. Unknown
+/// addresses are only allowed without an allowlist.
+pub fn allows(allowlist: Option<&Allowlist>, ip: Option<IpAddr>) -> bool {
+    match (allowlist, ip) {
+        (None, _) => true,
+        (Some(allowlist), Some(ip)) => allowlist.allows(ip),
+        (Some(_), None) => false,
+    }
+}
+
+/// A network in CIDR notation, or a single IP address.
+#[derive(Clone, Copy, Debug)]
+pub struct Network(IpNet);
+
+impl FromStr for Network {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<IpAddr>() {
+            Ok(ip) => Ok(Self(ip.into())),
+            Err(_) => Ok(Self(s.parse()?)),
+        }
+    }
+}
+
+impl Display for Network {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Request guard rejecting peers that the RPC allowlist doesn't allow.
+pub struct RpcClient;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RpcClient {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let options = request.rocket().state::<Options>();
+        guard(
+            request,
+            options.and_then(|o| o.rpc_allowlist.as_ref()),
+            RpcClient,
+        )
+    }
+}
+
+/// Request guard rejecting peers that the admin allowlist doesn't allow.
+pub struct AdminClient;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminClient {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let options = request.rocket().state::<Options>();
+        guard(
+            request,
+            options.and_then(|o| o.admin_allowlist.as_ref()),
+            AdminClient,
+        )
+    }
+}
+
+/// Checks the address of the peer, and not any forwarding headers which can
+/// be spoofed, against an allowlist.
+fn guard<T>(
+    request: &Request,
+    allowlist: Option<&Allowlist>,
+    client: T,
+) -> request::Outcome<T, ()> {
+    let ip = request.remote().map(|remote| remote.ip());
+    if allows(allowlist, ip) {
+        request::Outcome::Success(client)
+    } else {
+        tracing::debug!(?ip, uri = %request.uri(), "rejected request from disallowed address");
+        request::Outcome::Error((Status::Forbidden, ()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{client_with, MockUpstream};
+    use rocket::{
+        http::Header,
+        serde::json::serde_json::{self, json},
+    };
+
+    #[test]
+    fn allows_listed_networks() {
+        let allowlist =
+            serde_json::from_value::<Allowlist>(json!(["10.0.0.0/8", "192.168.1.7", "fd00::/8"]))
+                .unwrap();
+        for (ip, allowed) in [
+            ("10.1.2.3", true),
+            ("11.0.0.1", false),
+            ("192.168.1.7", true),
+            ("192.168.1.8", false),
+            ("fd12::1", true),
+            ("::ffff:10.0.0.1", true),
+            ("fe80::1", false),
+        ] {
+            assert_eq!(allowlist.allows(ip.parse().unwrap()), allowed, "{ip}");
+        }
+
+        assert!(allows(None, None));
+        assert!(!allows(Some(&allowlist), None));
+        assert!(serde_json::from_value::<Allowlist>(json!(["10.0.0.0/33"])).is_err());
+    }
+
+    #[rocket::async_test]
+    async fn enforces_network_allowlists() {
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment
+                .merge(("rpc_allowlist", ["10.0.0.0/8"]))
+                .merge(("admin_allowlist", ["192.168.1.7"]))
+                .merge(("admin_token", "secret"))
+        })
+        .await;
+        let request = json!({ "jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 1 });
+
+        for (remote, status) in [
+            ("10.1.2.3:1234", rocket::http::Status::Ok),
+            ("127.0.0.1:1234", rocket::http::Status::Forbidden),
+        ] {
+            let response = client
+                .post("/")
+                .remote(remote.parse().unwrap())
+                .json(&request)
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), status, "{remote}");
+        }
+        for (remote, status) in [
+            ("192.168.1.7:1234", rocket::http::Status::Ok),
+            ("10.1.2.3:1234", rocket::http::Status::Forbidden),
+        ] {
+            let response = client
+                .get("/admin/overview")
+                .remote(remote.parse().unwrap())
+                .header(Header::new("Authorization", "Bearer secret"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), status, "{remote}");
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! Key-value backends for state shared between hdnode replicas.
+//!
+//! By default, state such as cached responses is kept in memory. When
+//! multiple replicas are deployed behind a load balancer, a Redis backend can
+//! be configured instead, so that they share it.
+//!
+//! In cluster mode, instances serving the same accounts additionally
+//! coordinate transaction nonces and signing quotas through the backend, and
+//! elect a leader to run singleton background tasks.
+
+pub mod memory;
+pub mod redis;
+
+use self::{memory::Memory, redis::Redis};
+use crate::serialization::Str;
+use anyhow::{bail, ensure, Result};
+use reqwest::Url;
+use rocket::{serde::Deserialize, tokio::time};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher as _, Hasher as _},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// The interval at which a held lock is polled.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Shared state backend options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Options {
+    /// The URL of the backend for state shared between replicas, for example
+    /// `redis://:password@localhost:6379/0`. State is kept in memory when not
+    /// specified.
+    #[serde(default)]
+    pub backend_url: Option<Str<Url>>,
+
+    /// Whether or not multiple instances serve the same accounts, in which
+    /// case they coordinate transaction nonces and signing quotas through the
+    /// backend.
+    #[serde(default)]
+    pub cluster: bool,
+}
+
+/// A key-value backend. Values are strings and keys can expire.
+///
+/// Operations are synchronous, since they are also needed by signers, and
+/// implementations that perform I/O block in place.
+pub trait Backend: Send + Sync {
+    /// Returns the value for a key.
+    fn get(&self, key: &str) -> Result<Option<String>>;
+
+    /// Sets the value for a key, expiring after `ttl` if specified.
+    fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<()>;
+
+    /// Sets the value for a key only if it doesn't have one, returning
+    /// whether the value was set.
+    fn set_if_absent(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<bool>;
+
+    /// Atomically increments the integer value for a key and returns the new
+    /// value. Keys without a value start from zero and expire after `ttl` if
+    /// specified.
+    fn increment(&self, key: &str, by: i64, ttl: Option<Duration>) -> Result<i64>;
+
+    /// Deletes the value for a key.
+    fn delete(&self, key: &str) -> Result<()>;
+
+    /// Deletes the value for a key only if it is equal to `value`, returning
+    /// whether it was deleted.
+    fn delete_if(&self, key: &str, value: &str) -> Result<bool>;
+
+    /// Makes a key expire after `ttl` only if its value is equal to `value`,
+    /// returning whether its expiry was updated.
+    fn expire_if(&self, key: &str, value: &str, ttl: Duration) -> Result<bool>;
+}
+
+/// A shared backend.
+pub type SharedBackend = Arc<dyn Backend>;
+
+/// Connects to the backend configured by the specified options.
+pub fn connect(options: &Options) -> Result<SharedBackend> {
+    let url = match &options.backend_url {
+        Some(url) => &url.0,
+        None => return Ok(Arc::new(Memory::new())),
+    };
+    match url.scheme() {
+        "memory" => Ok(Arc::new(Memory::new())),
+        "redis" | "rediss" => Ok(Arc::new(Redis::new(url)?)),
+        scheme => bail!("unsupported backend scheme '{scheme}'"),
+    }
+}
+
+/// Returns an identifier for this instance, unique among the instances
+/// sharing a backend.
+pub fn instance_id() -> &'static str {
+    static ID: OnceLock<String> = OnceLock::new();
+    ID.get_or_init(|| {
+        // Each new `RandomState` is keyed differently, so hashing with it is a
+        // cheap source of randomness.
+        let random = RandomState::new().build_hasher().finish();
+        format!("{}-{random:016x}", process::id())
+    })
+}
+
+/// A lock held in the backend, released when dropped.
+pub struct Lock {
+    backend: SharedBackend,
+    key: String,
+    token: String,
+    ttl: Duration,
+}
+
+impl Lock {
+    /// Acquires a lock, waiting for it to be released if another instance or
+    /// task holds it. Locks expire after `ttl`, so that they are not held
+    /// forever by instances that fail.
+    pub async fn acquire(backend: &SharedBackend, key: &str, ttl: Duration) -> Result<Self> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let token = format!(
+            "{}:{}",
+            instance_id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+
+        // The lock expires after `ttl`, so waiting for much longer than that
+        // means that its holder keeps re-acquiring it.
+        let deadline = Instant::now() + ttl * 2;
+        while !backend.set_if_absent(key, &token, Some(ttl))? {
+            if Instant::now() > deadline {
+                bail!("timed out waiting for lock {key}");
+            }
+            time::sleep(LOCK_POLL_INTERVAL).await;
+        }
+        Ok(Self {
+            backend: backend.clone(),
+            key: key.to_owned(),
+            token,
+            ttl,
+        })
+    }
+
+    /// Renews the lock for another `ttl`, failing if it expired and may have
+    /// been acquired by another instance in the meantime.
+    pub fn renew(&self) -> Result<()> {
+        ensure!(
+            self.backend.expire_if(&self.key, &self.token, self.ttl)?,
+            "lost lock {}",
+            self.key
+        );
+        Ok(())
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        match self.backend.delete_if(&self.key, &self.token) {
+            Ok(true) => {}
+            Ok(false) => tracing::warn!(key = %self.key, "lock expired before it was released"),
+            Err(err) => tracing::warn!(?err, key = %self.key, "failed to release lock"),
+        }
+    }
+}
+
+/// Leader election for a background task that must only run on one instance,
+/// through a lease in the backend.
+///
+/// The leader renews its lease every time it checks whether it still leads,
+/// so another instance takes over once the lease expires if it fails.
+pub struct Election {
+    backend: SharedBackend,
+    task: &'static str,
+    key: String,
+    id: String,
+    ttl: Duration,
+    leader: AtomicBool,
+}
+
+impl Election {
+    /// Creates a new election for a task, whose leases expire after `ttl`.
+    pub fn new(backend: SharedBackend, task: &'static str, ttl: Duration) -> Self {
+        Self {
+            backend,
+            task,
+            key: format!("hdnode:leader:{task}"),
+            id: instance_id().to_owned(),
+            ttl,
+            leader: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns whether this instance leads, acquiring or renewing the lease.
+    /// This must be called more often than the lease expires to keep leading.
+    /// Backend errors are treated as losing the election, so that the task
+    /// doesn't run on multiple instances.
+    pub fn elect(&self) -> bool {
+        let result = match self
+            .backend
+            .set_if_absent(&self.key, &self.id, Some(self.ttl))
+        {
+            Ok(true) => Ok(true),
+            Ok(false) => self.backend.expire_if(&self.key, &self.id, self.ttl),
+            Err(err) => Err(err),
+        };
+        let leader = result.unwrap_or_else(|err| {
+            tracing::warn!(?err, task = self.task, "failed to renew leader lease");
+            false
+        });
+        if self.leader.swap(leader, Ordering::SeqCst) != leader {
+            tracing::info!(task = self.task, leader, "leadership changed");
+        }
+        leader
+    }
+}
+
+impl Drop for Election {
+    fn drop(&mut self) {
+        // Hand over leadership right away instead of waiting for the lease to
+        // expire.
+        if self.leader.load(Ordering::SeqCst) {
+            if let Err(err) = self.backend.delete_if(&self.key, &self.id) {
+                tracing::warn!(?err, task = self.task, "failed to release leader lease");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn locks_are_exclusive() {
+        let backend: SharedBackend = Arc::new(Memory::new());
+        let ttl = Duration::from_secs(10);
+
+        let lock = Lock::acquire(&backend, "lock", ttl).await.unwrap();
+        assert_eq!(backend.get("lock").unwrap().as_deref(), Some(&*lock.token));
+        let waiting = Lock::acquire(&backend, "lock", ttl);
+        rocket::tokio::pin!(waiting);
+        assert!(time::timeout(Duration::from_millis(100), &mut waiting)
+            .await
+            .is_err());
+
+        lock.renew().unwrap();
+        drop(lock);
+        let lock = waiting.await.unwrap();
+        assert!(lock.token.starts_with(instance_id()));
+
+        backend.delete("lock").unwrap();
+        assert!(lock.renew().is_err());
+    }
+
+    #[test]
+    fn elects_single_leader() {
+        let backend: SharedBackend = Arc::new(Memory::new());
+        let ttl = Duration::from_secs(10);
+        let first = Election::new(backend.clone(), "task", ttl);
+        let mut second = Election::new(backend.clone(), "task", ttl);
+        second.id = "other".to_owned();
+
+        assert!(first.elect());
+        assert!(!second.elect());
+        assert!(first.elect());
+
+        drop(first);
+        assert!(second.elect());
+
+        // Leases of failed leaders expire.
+        backend
+            .set("hdnode:leader:task", "failed", Some(Duration::ZERO))
+            .unwrap();
+        assert!(second.elect());
+    }
+}
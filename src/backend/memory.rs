@@ -0,0 +1,151 @@
+//! In-memory backend for a single hdnode instance.
+
+use super::Backend;
+use anyhow::{Context as _, Result};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// A value with its optional expiry.
+struct Entry {
+    value: String,
+    expires: Option<Instant>,
+}
+
+impl Entry {
+    fn is_live(&self, now: Instant) -> bool {
+        self.expires.is_none_or(|expires| expires > now)
+    }
+}
+
+/// A backend keeping values in memory.
+#[derive(Default)]
+pub struct Memory {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl Memory {
+    /// Creates a new empty in-memory backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs a function with the live entries, dropping expired ones.
+    fn with_entries<T>(&self, f: impl FnOnce(&mut HashMap<String, Entry>, Instant) -> T) -> T {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.is_live(now));
+        f(&mut entries, now)
+    }
+}
+
+impl Backend for Memory {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.with_entries(|entries, _| entries.get(key).map(|entry| entry.value.clone())))
+    }
+
+    fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<()> {
+        self.with_entries(|entries, now| {
+            entries.insert(
+                key.to_owned(),
+                Entry {
+                    value: value.to_owned(),
+                    expires: ttl.map(|ttl| now + ttl),
+                },
+            );
+        });
+        Ok(())
+    }
+
+    fn set_if_absent(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<bool> {
+        Ok(self.with_entries(|entries, now| {
+            if entries.contains_key(key) {
+                return false;
+            }
+            entries.insert(
+                key.to_owned(),
+                Entry {
+                    value: value.to_owned(),
+                    expires: ttl.map(|ttl| now + ttl),
+                },
+            );
+            true
+        }))
+    }
+
+    fn increment(&self, key: &str, by: i64, ttl: Option<Duration>) -> Result<i64> {
+        self.with_entries(|entries, now| {
+            let entry = entries.entry(key.to_owned()).or_insert_with(|| Entry {
+                value: "0".to_owned(),
+                expires: ttl.map(|ttl| now + ttl),
+            });
+            let value = entry
+                .value
+                .parse::<i64>()
+                .ok()
+                .and_then(|value| value.checked_add(by))
+                .with_context(|| format!("value for {key} is not an integer"))?;
+            entry.value = value.to_string();
+            Ok(value)
+        })
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.with_entries(|entries, _| entries.remove(key));
+        Ok(())
+    }
+
+    fn delete_if(&self, key: &str, value: &str) -> Result<bool> {
+        Ok(self.with_entries(|entries, _| {
+            let equal = entries.get(key).is_some_and(|entry| entry.value == value);
+            if equal {
+                entries.remove(key);
+            }
+            equal
+        }))
+    }
+
+    fn expire_if(&self, key: &str, value: &str, ttl: Duration) -> Result<bool> {
+        Ok(
+            self.with_entries(|entries, now| match entries.get_mut(key) {
+                Some(entry) if entry.value == value => {
+                    entry.expires = Some(now + ttl);
+                    true
+                }
+                _ => false,
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires_values() {
+        let memory = Memory::new();
+        memory.set("long", "a", None).unwrap();
+        memory.set("short", "b", Some(Duration::ZERO)).unwrap();
+        assert_eq!(memory.get("long").unwrap().as_deref(), Some("a"));
+        assert_eq!(memory.get("short").unwrap(), None);
+        assert_eq!(memory.entries.lock().unwrap().len(), 1);
+
+        assert!(!memory.set_if_absent("long", "c", None).unwrap());
+        assert!(memory.set_if_absent("short", "c", None).unwrap());
+        assert!(!memory.delete_if("short", "b").unwrap());
+        assert!(memory.expire_if("short", "c", Duration::ZERO).unwrap());
+        assert!(!memory.expire_if("short", "c", Duration::ZERO).unwrap());
+        assert!(memory.set_if_absent("short", "c", None).unwrap());
+        assert!(memory.delete_if("short", "c").unwrap());
+        memory.delete("long").unwrap();
+        assert!(memory.entries.lock().unwrap().is_empty());
+
+        assert_eq!(memory.increment("count", 2, None).unwrap(), 2);
+        assert_eq!(memory.increment("count", -3, None).unwrap(), -1);
+        memory.set("text", "a", None).unwrap();
+        assert!(memory.increment("text", 1, None).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Module implementing the command line interface.
+//!
+//! Besides running the node, the binary provides a few offline commands that
+//! read the same configuration as the server.
+
+use crate::{
+    node::transaction::{Transaction, TransactionRequest},
+    selectors::Selectors,
+    serialization::Str,
+    signer::{
+        approval::Decision,
+        log_recorder::LogRecorder,
+        validator::{self, store::Store, Validator},
+        wallet::Wallet,
+        Signing as _,
+    },
+};
+use anyhow::{Context as _, Result};
+use clap::{Parser, Subcommand};
+use hdwallet::{account::Address, mnemonic::Mnemonic};
+use rocket::{
+    figment::Figment,
+    serde::{
+        json::{serde_json, Value},
+        Deserialize,
+    },
+};
+use std::{
+    fs,
+    io::{self, BufRead as _, BufReader, Write as _},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+};
+
+/// Ethereum node RPC proxy with HD wallet account management.
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Available commands.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Runs the node. This is the default command.
+    Serve {
+        /// Runs the node for local development, using a well-known test
+        /// mnemonic and permissive policies. Never use this with real funds.
+        #[arg(long)]
+        dev: bool,
+    },
+
+    /// Prints the accounts derived from the configured mnemonic.
+    Accounts {
+        /// The mnemonic to derive accounts from, instead of the configured
+        /// one.
+        #[arg(long, env = "HDNODE_MNEMONIC", hide_env_values = true)]
+        mnemonic: Option<String>,
+
+        /// The number of accounts to derive, instead of the configured
+        /// `account_count`.
+        #[arg(long)]
+        count: Option<usize>,
+    },
+
+    /// Dry-runs the validator on a transaction without signing it.
+    Validate {
+        /// A JSON file with a fully specified `eth_signTransaction` request.
+        transaction: PathBuf,
+
+        /// The validator module to use, instead of the configured one.
+        #[arg(long)]
+        validator: Option<PathBuf>,
+    },
+
+    /// Signs a transaction offline and prints the raw signed transaction.
+    SignTx {
+        /// A JSON file with a fully specified `eth_signTransaction` request.
+        transaction: PathBuf,
+    },
+
+    /// Connects to a running node's approval socket and prompts for approval
+    /// of each pending signing request.
+    Approve {
+        /// The path of the approval socket.
+        socket: PathBuf,
+    },
+}
+
+/// The well-known test mnemonic used by development tools such as Hardhat and
+/// Anvil.
+const DEV_MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+/// Returns the node configuration, with the development defaults in
+/// development mode. Reloading uses the same configuration as startup.
+pub fn figment(dev: bool) -> Figment {
+    let figment = rocket::Config::figment();
+    if dev {
+        dev_figment(figment)
+    } else {
+        figment
+    }
+}
+
+/// Returns the configuration for development mode. The development defaults
+/// are only used for values that are not otherwise configured.
+pub fn dev_figment(figment: Figment) -> Figment {
+    figment
+        .join(("mnemonic", DEV_MNEMONIC))
+        .join(("account_count", 10))
+        .join(("remote_node_url", "http://localhost:8545"))
+        .merge(("dev", true))
+}
+
+/// Wallet configuration used by offline commands.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct WalletConfig {
+    mnemonic: Str<Mnemonic>,
+    #[serde(default)]
+    password: String,
+    account_count: usize,
+    #[serde(flatten)]
+    validator: ValidatorConfig,
+}
+
+/// Validator configuration used by offline commands.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct ValidatorConfig {
+    validator: Option<PathBuf>,
+    #[serde(default)]
+    validator_limits: validator::Limits,
+    validator_store: Option<PathBuf>,
+}
+
+impl ValidatorConfig {
+    /// Opens the configured validator store.
+    fn store(&self) -> Result<Store> {
+        match &self.validator_store {
+            Some(path) => Store::open(path),
+            None => Ok(Store::memory()),
+        }
+    }
+}
+
+impl Command {
+    /// Runs an offline command with the specified configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called for the `serve` command.
+    pub fn run(self, figment: Figment) -> Result<()> {
+        match self {
+            Self::Serve { .. } => unreachable!("serve is not an offline command"),
+            Self::Accounts { mnemonic, count } => {
+                let mut figment = figment;
+                if let Some(mnemonic) = mnemonic {
+                    figment = figment.merge(("mnemonic", mnemonic));
+                }
+                if let Some(count) = count {
+                    figment = figment.merge(("account_count", count));
+                }
+                let config = figment.extract::<WalletConfig>()?;
+
+                #[allow(clippy::explicit_auto_deref)]
+                let wallet =
+                    Wallet::new(&*config.mnemonic, &config.password, config.account_count)?;
+                for (index, account) in wallet.accounts().iter().enumerate() {
+                    println!("m/44'/60'/0'/0/{index}\t{account}");
+                }
+            }
+            Self::Validate {
+                transaction,
+                validator,
+            } => {
+                let config = figment.extract::<ValidatorConfig>()?;
+                let module = validator
+                    .or_else(|| config.validator.clone())
+                    .context("no validator configured")?;
+                let (account, transaction) = read_transaction(&transaction)?;
+
+                // Dry runs must not change the state of the store.
+                let store = config.store()?.detached();
+                Validator::new((), Some(&module), config.validator_limits, store)?
+                    .validate_transaction(account, &transaction)?;
+                println!("allowed");
+            }
+            Self::SignTx { transaction } => {
+                let config = figment.extract::<WalletConfig>()?;
+                #[allow(clippy::explicit_auto_deref)]
+                let wallet =
+                    Wallet::new(&*config.mnemonic, &config.password, config.account_count)?;
+                let signer = Validator::new(
+                    LogRecorder::new(wallet, Selectors::default()),
+                    config.validator.validator.as_deref(),
+                    config.validator.validator_limits,
+                    config.validator.store()?,
+                )?;
+                let (account, transaction) = read_transaction(&transaction)?;
+
+                let signature = signer.sign_transaction(account, &transaction)?;
+                println!("0x{}", hex::encode(transaction.encode(signature)));
+            }
+            Self::Approve { socket } => approve(&socket)?,
+        }
+        Ok(())
+    }
+}
+
+/// Prompts the operator for approval of signing requests on the terminal.
+fn approve(socket: &Path) -> Result<()> {
+    let stream = UnixStream::connect(socket)
+        .with_context(|| format!("failed to connect to {}", socket.display()))?;
+    let mut writer = stream.try_clone()?;
+    let stdin = io::stdin();
+
+    for line in BufReader::new(stream).lines() {
+        let request = serde_json::from_str::<Value>(&line?)?;
+        let id = request["id"].as_u64().context("invalid signing request")?;
+        println!("{}", serde_json::to_string_pretty(&request)?);
+        print!("approve request {id}? [y/N] ");
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        stdin.lock().read_line(&mut answer)?;
+        let approved = matches!(answer.trim(), "y" | "Y" | "yes");
+        let decision = serde_json::to_string(&Decision { id, approved })?;
+        writeln!(writer, "{decision}")?;
+    }
+    Ok(())
+}
+
+/// Reads a fully specified transaction request from a JSON file.
+fn read_transaction(path: &Path) -> Result<(Address, Transaction)> {
+    let json = fs::read_to_string(path)
+        .with_context(|| format!("failed to read transaction file {}", path.display()))?;
+    let request =
+        serde_json::from_str::<TransactionRequest>(&json).context("invalid transaction request")?;
+    request.into_filled()
+}
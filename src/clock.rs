@@ -0,0 +1,12 @@
+//! Wall clock helpers.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Returns the current UNIX timestamp in seconds, or 0 if the system clock is
+/// set before the epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
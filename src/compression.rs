@@ -0,0 +1,179 @@
+//! Module implementing HTTP compression.
+//!
+//! Response bodies are compressed while they are streamed to the client, and
+//! compressed request bodies are decompressed while they are read.
+
+use async_compression::tokio::bufread::{DeflateDecoder, DeflateEncoder, GzipDecoder, GzipEncoder};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    tokio::io::{AsyncRead, BufReader},
+    Request, Response,
+};
+use std::pin::Pin;
+
+/// The minimum body size for a response to get compressed. Compressing very
+/// small bodies only adds overhead.
+const MIN_SIZE: usize = 1024;
+
+/// A fairing that compresses HTTP response bodies with the best encoding
+/// accepted by the client.
+pub struct Compression;
+
+#[rocket::async_trait]
+impl Fairing for Compression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Response Compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.headers().contains("Content-Encoding") {
+            return;
+        }
+        if response
+            .body()
+            .preset_size()
+            .is_some_and(|size| size < MIN_SIZE)
+        {
+            return;
+        }
+
+        // Caches must not serve compressed responses to other clients.
+        response.adjoin_header(Header::new("Vary", "Accept-Encoding"));
+        let encoding = match Encoding::negotiate(request.headers().get("Accept-Encoding")) {
+            Some(encoding) => encoding,
+            None => return,
+        };
+
+        let body = BufReader::new(response.body_mut().take());
+        response.set_header(Header::new("Content-Encoding", encoding.name()));
+        match encoding {
+            Encoding::Gzip => response.set_streamed_body(GzipEncoder::new(body)),
+            Encoding::Deflate => response.set_streamed_body(DeflateEncoder::new(body)),
+        }
+    }
+}
+
+/// A supported content encoding.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// Negotiates the encoding to use from `Accept-Encoding` header values,
+    /// preferring gzip over deflate.
+    fn negotiate<'a>(accepted: impl Iterator<Item = &'a str>) -> Option<Self> {
+        let mut result = None;
+        for coding in accepted.flat_map(|value| value.split(',')) {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let disabled = parts.any(|param| {
+                matches!(
+                    param.strip_prefix("q=").map(str::parse::<f32>),
+                    Some(Ok(q)) if q <= 0.,
+                )
+            });
+            if disabled {
+                continue;
+            }
+
+            match name.to_ascii_lowercase().as_str() {
+                "gzip" | "*" => return Some(Self::Gzip),
+                "deflate" => result = Some(Self::Deflate),
+                _ => {}
+            }
+        }
+        result
+    }
+
+    /// Returns the name of the encoding as used in HTTP headers.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+}
+
+/// Returns a reader decompressing a request body with the specified
+/// `Content-Encoding`, or `None` if the encoding is not supported.
+pub fn decode<'a>(
+    encoding: Option<&str>,
+    body: impl AsyncRead + Send + 'a,
+) -> Option<Pin<Box<dyn AsyncRead + Send + 'a>>> {
+    let encoding = encoding.map(|encoding| encoding.trim().to_ascii_lowercase());
+    Some(match encoding.as_deref() {
+        None | Some("identity") => Box::pin(body),
+        Some("gzip") => Box::pin(GzipDecoder::new(BufReader::new(body))),
+        Some("deflate") => Box::pin(DeflateDecoder::new(BufReader::new(body))),
+        Some(_) => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{client_with, MockUpstream, ACCOUNT};
+    use rocket::serde::json::{serde_json::json, Value};
+
+    #[test]
+    fn negotiate_encoding() {
+        let negotiate = |values: &[&str]| Encoding::negotiate(values.iter().copied());
+
+        assert_eq!(negotiate(&[]), None);
+        assert_eq!(negotiate(&["br"]), None);
+        assert_eq!(negotiate(&["deflate, gzip"]), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&["deflate", "br"]), Some(Encoding::Deflate));
+        assert_eq!(negotiate(&["gzip;q=0, deflate"]), Some(Encoding::Deflate));
+        assert_eq!(negotiate(&["GZIP; q=0.5"]), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&["*"]), Some(Encoding::Gzip));
+    }
+
+    #[rocket::async_test]
+    async fn compresses_requests_and_responses() {
+        use async_compression::tokio::bufread::{GzipDecoder, GzipEncoder};
+        use rocket::tokio::io::AsyncReadExt as _;
+
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| figment.merge(("compression", true))).await;
+        let data = format!("0x{}", "00".repeat(1024));
+        upstream.respond("eth_call", json!(data));
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": "eth_call",
+            "params": [{ "to": ACCOUNT }, "latest"],
+            "id": 1,
+        })
+        .to_string();
+        let mut body = Vec::new();
+        GzipEncoder::new(request.as_bytes())
+            .read_to_end(&mut body)
+            .await
+            .unwrap();
+        let response = client
+            .post("/")
+            .header(rocket::http::ContentType::JSON)
+            .header(rocket::http::Header::new("Content-Encoding", "gzip"))
+            .header(rocket::http::Header::new("Accept-Encoding", "gzip"))
+            .body(body)
+            .dispatch()
+            .await;
+        assert_eq!(response.headers().get_one("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.headers().get_one("Vary"), Some("Accept-Encoding"));
+
+        let compressed = response.into_bytes().await.unwrap();
+        let mut body = Vec::new();
+        GzipDecoder::new(&compressed[..])
+            .read_to_end(&mut body)
+            .await
+            .unwrap();
+        let response = serde_json::from_slice::<Value>(&body).unwrap();
+        assert_eq!(response["result"], data);
+    }
+}
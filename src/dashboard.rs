@@ -0,0 +1,350 @@
+//! Admin web dashboard.
+//!
+//! The dashboard is a single server-rendered page giving operators an overview
+//! of the node: accounts and balances, recent signing activity, pending
+//! approvals, quotas and queues, and remote node health and metrics. The same
+//! overview is served as JSON for scripts. Both require the `admin_token`, and
+//! are disabled when it is not configured.
+
+use crate::{
+    acl::AdminClient,
+    clock,
+    node::{
+        overview::{Overview, Remote},
+        Node,
+    },
+    signer::quota::Count,
+};
+use ethnum::U256;
+use rocket::{
+    http::{Header, Status},
+    request::{self, FromRequest},
+    response::content::RawHtml,
+    serde::{json::Json, Deserialize},
+    Request, State,
+};
+use std::{fmt::Write as _, sync::Arc};
+
+/// The interval in seconds at which the dashboard page refreshes itself.
+const REFRESH_INTERVAL: u64 = 30;
+
+/// Dashboard options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Options {
+    /// The token required to access the admin dashboard, either as the
+    /// password for HTTP basic authentication or as a bearer token. The
+    /// dashboard is disabled when not specified.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+}
+
+/// Request guard for authenticated administrators.
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let token = match request
+            .rocket()
+            .state::<Options>()
+            .and_then(|options| options.admin_token.as_deref())
+        {
+            Some(token) => token,
+            None => return request::Outcome::Forward(Status::NotFound),
+        };
+        let authorized = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(credentials)
+            .is_some_and(|credentials| constant_time_eq(credentials.as_bytes(), token.as_bytes()));
+        if authorized {
+            request::Outcome::Success(Admin)
+        } else {
+            request::Outcome::Error((Status::Unauthorized, ()))
+        }
+    }
+}
+
+/// Response asking the browser for credentials.
+#[derive(rocket::Responder)]
+#[response(status = 401)]
+pub struct Unauthorized(&'static str, Header<'static>);
+
+#[rocket::catch(401)]
+pub fn unauthorized() -> Unauthorized {
+    Unauthorized(
+        "admin token required",
+        Header::new("WWW-Authenticate", "Basic realm=\"hdnode\""),
+    )
+}
+
+/// Serves the dashboard page.
+#[rocket::get("/admin")]
+pub async fn page(_client: AdminClient, _admin: Admin, node: &State<Arc<Node>>) -> RawHtml<String> {
+    RawHtml(render(&node.overview().await))
+}
+
+/// Serves the dashboard overview as JSON.
+#[rocket::get("/admin/overview")]
+pub async fn overview(
+    _client: AdminClient,
+    _admin: Admin,
+    node: &State<Arc<Node>>,
+) -> Json<Overview> {
+    Json(node.overview().await)
+}
+
+/// Returns the token from an `Authorization` header, either the password of
+/// basic credentials or a bearer token.
+fn credentials(header: &str) -> Option<String> {
+    let (scheme, value) = header.trim().split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") {
+        Some(value.trim().to_owned())
+    } else if scheme.eq_ignore_ascii_case("basic") {
+        let decoded = String::from_utf8(decode_base64(value.trim())?).ok()?;
+        let (_, password) = decoded.split_once(':')?;
+        Some(password.to_owned())
+    } else {
+        None
+    }
+}
+
+/// Decodes standard base64, with or without padding.
+fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(value.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0_u32, 0);
+    for c in value.trim_end_matches('=').bytes() {
+        let sextet = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | u32::from(sextet);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+/// Compares secrets in constant time with respect to their contents.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Renders the dashboard page for an overview.
+fn render(overview: &Overview) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta http-equiv=\"refresh\" content=\"{REFRESH_INTERVAL}\">\
+         <title>hdnode</title><style>\
+         body{{font-family:sans-serif;margin:2em}}\
+         table{{border-collapse:collapse;margin-bottom:1em}}\
+         th,td{{border:1px solid #ccc;padding:.25em .5em;text-align:left}}\
+         td{{font-family:monospace}}.bad{{color:#b00}}\
+         </style></head><body><h1>hdnode</h1>",
+    );
+
+    html += "<h2>Status</h2><table>";
+    let signing = if overview.signing_paused {
+        "<span class=\"bad\">paused</span>"
+    } else {
+        "active"
+    };
+    let _ = write!(html, "<tr><th>Signing</th><td>{signing}</td></tr>");
+    render_remote(&mut html, &overview.remote);
+    if let Some(pending) = overview.pending_transactions {
+        let _ = write!(
+            html,
+            "<tr><th>Pending transactions</th><td>{pending}</td></tr>"
+        );
+    }
+    html += "</table>";
+
+    html += "<h2>Accounts</h2><table><tr><th>Address</th><th>Balance (ETH)</th>\
+             <th>Hourly signatures</th><th>Daily signatures</th><th>Queued</th></tr>";
+    for account in &overview.accounts {
+        let address = account.address.to_string();
+        let quota = overview
+            .quotas
+            .as_ref()
+            .and_then(|quotas| quotas.get(&address));
+        let count = |count: Option<&Count>| match count {
+            Some(count) => match count.limit {
+                Some(limit) => format!("{} / {limit}", count.used),
+                None => count.used.to_string(),
+            },
+            None => "-".to_owned(),
+        };
+        let queued = overview
+            .queue
+            .as_ref()
+            .and_then(|queue| queue.get(&address))
+            .map(|status| status.depth.to_string())
+            .unwrap_or_else(|| "-".to_owned());
+        let _ = write!(
+            html,
+            "<tr><td>{address}</td><td>{}</td><td>{}</td><td>{}</td><td>{queued}</td></tr>",
+            account
+                .balance
+                .map(|balance| ether(balance.0))
+                .unwrap_or_else(|| "?".to_owned()),
+            count(quota.map(|usage| &usage.hourly)),
+            count(quota.map(|usage| &usage.daily)),
+        );
+    }
+    html += "</table>";
+
+    html += "<h2>Pending Approvals</h2>";
+    if overview.pending_approvals.is_empty() {
+        html += "<p>None.</p>";
+    } else {
+        html += "<table><tr><th>ID</th><th>Operation</th><th>Account</th></tr>";
+        for request in &overview.pending_approvals {
+            let field = |name: &str| escape(&request[name].to_string().replace('"', ""));
+            let _ = write!(
+                html,
+                "<tr id=\"approval-{}\"><td>{}</td><td>{}</td><td>{}</td></tr>",
+                field("id"),
+                field("id"),
+                field("operation"),
+                field("account"),
+            );
+        }
+        html += "</table>";
+    }
+
+    html += "<h2>Recent Signatures</h2>";
+    match &overview.recent_receipts {
+        Some(receipts) if !receipts.is_empty() => {
+            let now = clock::now();
+            html += "<table><tr><th>Age</th><th>Operation</th><th>Account</th>\
+                     <th>Payload hash</th></tr>";
+            for receipt in receipts {
+                let receipt = &receipt.receipt;
+                let _ = write!(
+                    html,
+                    "<tr><td>{}s</td><td>{}</td><td>{}</td><td>{:?}</td></tr>",
+                    now.saturating_sub(receipt.timestamp),
+                    receipt.operation,
+                    *receipt.account,
+                    receipt.payload_hash,
+                );
+            }
+            html += "</table>";
+        }
+        Some(_) => html += "<p>None.</p>",
+        None => html += "<p>Enable the <code>receipt</code> signer to list signatures.</p>",
+    }
+
+    html += "<h2>Remote Nodes</h2><table><tr><th>Remote node</th><th>Method</th>\
+             <th>Requests</th><th>Errors</th><th>Average latency (ms)</th></tr>";
+    for (upstream, methods) in &overview.upstreams {
+        for (method, stats) in methods {
+            let errors = stats.errors.transport + stats.errors.http + stats.errors.rpc;
+            let latency = stats.latency * 1000.0 / stats.requests.max(1) as f64;
+            let _ = write!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{errors}</td><td>{latency:.1}</td></tr>",
+                escape(upstream),
+                escape(method),
+                stats.requests,
+            );
+        }
+    }
+    html += "</table></body></html>";
+    html
+}
+
+fn render_remote(html: &mut String, remote: &Remote) {
+    let quantity = |value: Option<_>| match value {
+        Some(value) => format!("{value}"),
+        None => "?".to_owned(),
+    };
+    let _ = write!(
+        html,
+        "<tr><th>Chain ID</th><td>{}</td></tr><tr><th>Block number</th><td>{}</td></tr>",
+        quantity(remote.chain_id.map(|chain_id| chain_id.0)),
+        quantity(remote.block_number.map(|block_number| block_number.0)),
+    );
+    if let Some(error) = &remote.error {
+        let _ = write!(
+            html,
+            "<tr><th>Remote node</th><td class=\"bad\">{}</td></tr>",
+            escape(error),
+        );
+    }
+}
+
+/// Formats an amount of Wei in Ether, with 6 decimals.
+pub fn ether(wei: U256) -> String {
+    let micro = wei / U256::new(1_000_000_000_000);
+    format!("{}.{:06}", micro / 1_000_000, (micro % 1_000_000).as_u64())
+}
+
+/// Escapes text for HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{client, client_with, MockUpstream, ACCOUNT};
+    use rocket::{
+        http::Header,
+        serde::json::{serde_json::json, Value},
+    };
+
+    #[rocket::async_test]
+    async fn serves_admin_dashboard() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+        let status = client.get("/admin").dispatch().await.status();
+        assert_eq!(status, rocket::http::Status::NotFound);
+
+        let client = client_with(&upstream, |figment| {
+            figment.merge(("admin_token", "secret"))
+        })
+        .await;
+        upstream.respond("eth_getBalance", json!("0xde0b6b3a7640000"));
+        upstream.respond("eth_blockNumber", json!("0x2a"));
+
+        let response = client.get("/admin").dispatch().await;
+        assert_eq!(response.status(), rocket::http::Status::Unauthorized);
+        assert!(response.headers().contains("WWW-Authenticate"));
+
+        // Basic credentials for `admin:secret`.
+        let response = client
+            .get("/admin")
+            .header(Header::new("Authorization", "Basic YWRtaW46c2VjcmV0"))
+            .dispatch()
+            .await;
+        let page = response.into_string().await.unwrap();
+        assert!(page.contains(ACCOUNT));
+        assert!(page.contains("1.000000"));
+
+        let overview = client
+            .get("/admin/overview")
+            .header(Header::new("Authorization", "Bearer secret"))
+            .dispatch()
+            .await
+            .into_json::<Value>()
+            .await
+            .unwrap();
+        assert_eq!(overview["remote"]["blockNumber"], "0x2a");
+        assert_eq!(overview["signingPaused"], false);
+    }
+}
@@ -0,0 +1,546 @@
+//! Module implementing the gRPC signing API.
+//!
+//! The signing operations are also served over gRPC, as defined in
+//! `proto/hdnode.proto`, for internal services that prefer strongly typed RPC.
+//! Calls are translated to their JSON RPC counterparts and handled by the node
+//! like any other request, so they go through the same signer pipeline. Only
+//! unary calls with uncompressed messages are supported.
+
+pub mod protobuf;
+
+use self::protobuf::{Decoder, Encoder};
+use crate::{
+    abi,
+    acl::{self, Allowlist},
+    jsonrpc::{self, Id, JsonRpc, Params, Request},
+    node::{Caller, Node},
+};
+use anyhow::{ensure, Context as _, Result};
+use ethnum::U256;
+use hyper::{
+    body::{Bytes, HttpBody},
+    header::HeaderValue,
+    server::conn::AddrStream,
+    service::{make_service_fn, service_fn},
+    Body, HeaderMap, Method, Response, Server,
+};
+use rocket::serde::json::{
+    serde_json::{self, json, Map},
+    Value,
+};
+use std::{
+    convert::Infallible,
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// The path prefix of the signer service methods.
+const SERVICE: &str = "/hdnode.v1.Signer/";
+
+/// The maximum size of a request message.
+const MAX_MESSAGE_SIZE: usize = 4 << 20;
+
+/// gRPC status codes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Code {
+    Ok = 0,
+    Unknown = 2,
+    InvalidArgument = 3,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+}
+
+/// A gRPC call status.
+struct Status {
+    code: Code,
+    message: String,
+}
+
+impl Status {
+    fn new(code: Code, message: impl Display) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    /// Returns the status for a JSON RPC error.
+    fn from_jsonrpc(err: jsonrpc::Error) -> Self {
+        let code = match err.code {
+            -32700 | -32600 | -32602 => Code::InvalidArgument,
+            -32601 => Code::Unimplemented,
+            -32603 => Code::Internal,
+            -32002 => Code::Unavailable,
+            -32005 => Code::ResourceExhausted,
+            4001 | 4100 => Code::PermissionDenied,
+            4902 => Code::FailedPrecondition,
+            _ => Code::Unknown,
+        };
+        match err.data {
+            Some(Value::String(data)) => Self::new(code, format!("{}: {data}", err.message)),
+            _ => Self::new(code, err.message),
+        }
+    }
+}
+
+/// Serves gRPC connections on the specified address. Calls from addresses
+/// that the allowlist doesn't allow are denied without reading them.
+pub async fn serve(
+    address: SocketAddr,
+    node: Arc<Node>,
+    allowlist: Option<Allowlist>,
+) -> Result<()> {
+    let service = make_service_fn(move |connection: &AddrStream| {
+        let node = node.clone();
+        let ip = connection.remote_addr().ip();
+        let allowed = acl::allows(allowlist.as_ref(), Some(ip));
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let node = node.clone();
+                async move {
+                    if !allowed {
+                        tracing::debug!(%ip, "denied gRPC call from disallowed address");
+                        let denied = Status::new(Code::PermissionDenied, "address not allowed");
+                        return Ok::<_, Infallible>(respond(Err(denied)));
+                    }
+                    Ok::<_, Infallible>(handle(request, ip, &node).await)
+                }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&address)?.http2_only(true);
+    tracing::info!(%address, "listening for gRPC connections");
+    server.serve(service).await?;
+    Ok(())
+}
+
+/// Handles a gRPC request.
+pub async fn handle(request: hyper::Request<Body>, ip: IpAddr, node: &Node) -> Response<Unary> {
+    respond(call(request, ip, node).await)
+}
+
+/// Handles a gRPC call, returning the encoded response message.
+async fn call(request: hyper::Request<Body>, ip: IpAddr, node: &Node) -> Result<Vec<u8>, Status> {
+    let method = match request.uri().path().strip_prefix(SERVICE) {
+        Some(method) if request.method() == Method::POST => method.to_owned(),
+        _ => return Err(Status::new(Code::Unimplemented, "unknown service")),
+    };
+    let message = read_message(request.into_body()).await?;
+    let (rpc_method, params) = decode(&method, &message)?;
+
+    let request = Request {
+        jsonrpc: JsonRpc::V2,
+        method: rpc_method.to_owned(),
+        params: Some(Params::new(params).map_err(|err| Status::new(Code::Internal, err))?),
+        id: Id::Number(1.into()),
+    };
+    let response = node
+        .handle_request(request, &Caller::new(Some(ip), None, None))
+        .await;
+    let result = response.result.map_err(Status::from_jsonrpc)?;
+    serde_json::from_str(result.get())
+        .map_err(anyhow::Error::from)
+        .and_then(|result| encode(&method, &result))
+        .map_err(|err| Status::new(Code::Internal, format!("{err:#}")))
+}
+
+/// Reads the single length-prefixed message of a unary call.
+async fn read_message(mut body: Body) -> Result<Vec<u8>, Status> {
+    let mut buffer = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| Status::new(Code::Unavailable, err))?;
+        buffer.extend_from_slice(&chunk);
+        if buffer.len() > MAX_MESSAGE_SIZE + 5 {
+            return Err(Status::new(Code::ResourceExhausted, "message too large"));
+        }
+    }
+
+    let invalid = || Status::new(Code::InvalidArgument, "invalid message framing");
+    let (header, message) = buffer.split_at_checked(5).ok_or_else(invalid)?;
+    if header[0] != 0 {
+        return Err(Status::new(
+            Code::Unimplemented,
+            "compressed messages are not supported",
+        ));
+    }
+    let length = u32::from_be_bytes(header[1..].try_into().unwrap());
+    if usize::try_from(length).ok() != Some(message.len()) {
+        return Err(invalid());
+    }
+    Ok(message.to_vec())
+}
+
+/// Builds the HTTP response for a call result.
+fn respond(result: Result<Vec<u8>, Status>) -> Response<Unary> {
+    let (message, status) = match result {
+        Ok(message) => {
+            let mut frame = vec![0];
+            frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            frame.extend_from_slice(&message);
+            (Some(Bytes::from(frame)), Status::new(Code::Ok, ""))
+        }
+        Err(status) => (None, status),
+    };
+
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from(status.code as u16));
+    if let Ok(message) = HeaderValue::from_str(&percent_encode(&status.message)) {
+        if !status.message.is_empty() {
+            trailers.insert("grpc-message", message);
+        }
+    }
+    Response::builder()
+        .header("content-type", "application/grpc")
+        .body(Unary {
+            message,
+            trailers: Some(trailers),
+        })
+        .unwrap()
+}
+
+/// Percent-encodes a status message as required for the `grpc-message`
+/// trailer.
+fn percent_encode(message: &str) -> String {
+    message
+        .bytes()
+        .map(|b| match b {
+            b' '..=b'~' if b != b'%' => char::from(b).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// A unary response body: at most one message followed by trailers.
+pub struct Unary {
+    message: Option<Bytes>,
+    trailers: Option<HeaderMap>,
+}
+
+impl HttpBody for Unary {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Infallible>>> {
+        Poll::Ready(self.message.take().map(Ok))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Infallible>> {
+        Poll::Ready(Ok(self.trailers.take()))
+    }
+}
+
+/// Decodes a request message into the JSON RPC method and parameters it is
+/// handled with.
+fn decode(method: &str, message: &[u8]) -> Result<(&'static str, Value), Status> {
+    let invalid = |err: anyhow::Error| Status::new(Code::InvalidArgument, format!("{err:#}"));
+    match method {
+        "Accounts" => Ok(("eth_accounts", json!([]))),
+        "FillTransaction" => Ok((
+            "eth_fillTransaction",
+            json!([transaction_to_json(message).map_err(invalid)?]),
+        )),
+        "SignTransaction" => Ok((
+            "eth_signTransaction",
+            json!([transaction_to_json(message).map_err(invalid)?]),
+        )),
+        "SignMessage" => {
+            let [account, data] = fields(message).map_err(invalid)?;
+            let account = address(account).map_err(invalid)?;
+            Ok(("eth_sign", json!([account, hex(data)])))
+        }
+        "SignTypedData" => {
+            let [account, typed_data] = fields(message).map_err(invalid)?;
+            let typed_data = serde_json::from_slice::<Value>(typed_data)
+                .context("invalid typed data JSON")
+                .map_err(invalid)?;
+            let account = address(account).map_err(invalid)?;
+            Ok(("eth_signTypedData", json!([account, typed_data])))
+        }
+        _ => Err(Status::new(
+            Code::Unimplemented,
+            format!("unknown method {method}"),
+        )),
+    }
+}
+
+/// Encodes the JSON RPC result of a call as its response message.
+fn encode(method: &str, result: &Value) -> Result<Vec<u8>> {
+    let mut encoder = Encoder::new();
+    match method {
+        "Accounts" => {
+            for account in result.as_array().context("expected accounts array")? {
+                encoder.bytes(1, &hex_value(account)?);
+            }
+        }
+        "FillTransaction" => {
+            encoder
+                .bytes(1, &transaction_from_json(result)?)
+                .uint64(2, uint64(&result["type"])?);
+        }
+        "SignTransaction" => {
+            let raw = hex_value(result)?;
+            encoder.bytes(1, &raw).bytes(2, &abi::keccak256(&raw));
+        }
+        _ => {
+            encoder.bytes(1, &hex_value(result)?);
+        }
+    }
+    Ok(encoder.finish())
+}
+
+/// Returns the first two bytes fields of a message, which is all of the
+/// fields of the signing request messages.
+fn fields(message: &[u8]) -> Result<[&[u8]; 2]> {
+    let mut values = [&[][..]; 2];
+    for field in Decoder::new(message) {
+        let (number, field) = field?;
+        if let Some(value) = values.get_mut((number as usize).wrapping_sub(1)) {
+            *value = field.bytes()?;
+        }
+    }
+    Ok(values)
+}
+
+/// Converts a `TransactionRequest` message to its JSON RPC representation.
+fn transaction_to_json(message: &[u8]) -> Result<Value> {
+    let mut transaction = Map::new();
+    let mut access_list = Vec::new();
+    for field in Decoder::new(message) {
+        let (number, field) = field?;
+        let (key, value) = match number {
+            1 => ("from", address(field.bytes()?)?),
+            2 => ("to", address(field.bytes()?)?),
+            3 => ("gas", format!("{:#x}", field.uint64()?)),
+            4 => ("gasPrice", quantity(field.bytes()?)?),
+            5 => ("maxFeePerGas", quantity(field.bytes()?)?),
+            6 => ("maxPriorityFeePerGas", quantity(field.bytes()?)?),
+            7 => ("value", quantity(field.bytes()?)?),
+            8 => ("data", hex(field.bytes()?)),
+            9 => ("nonce", format!("{:#x}", field.uint64()?)),
+            10 => ("chainId", format!("{:#x}", field.uint64()?)),
+            11 => {
+                let mut item = (None, Vec::new());
+                for field in Decoder::new(field.bytes()?) {
+                    match field? {
+                        (1, address) => item.0 = Some(self::address(address.bytes()?)?),
+                        (2, key) => {
+                            let key = key.bytes()?;
+                            ensure!(key.len() == 32, "storage keys must be 32 bytes");
+                            item.1.push(hex(key));
+                        }
+                        _ => {}
+                    }
+                }
+                access_list.push(json!([
+                    item.0.context("missing access list address")?,
+                    item.1
+                ]));
+                continue;
+            }
+            _ => continue,
+        };
+        transaction.insert(key.to_owned(), Value::String(value));
+    }
+    if !access_list.is_empty() {
+        transaction.insert("accessList".to_owned(), Value::Array(access_list));
+    }
+    Ok(Value::Object(transaction))
+}
+
+/// Converts a JSON RPC transaction to a `TransactionRequest` message.
+fn transaction_from_json(transaction: &Value) -> Result<Vec<u8>> {
+    let field = |name| transaction.get(name).filter(|value| !value.is_null());
+    let mut encoder = Encoder::new();
+    for (number, name) in [(1, "from"), (2, "to")] {
+        if let Some(value) = field(name) {
+            encoder.bytes(number, &hex_value(value)?);
+        }
+    }
+    if let Some(gas) = field("gas") {
+        encoder.uint64(3, uint64(gas)?);
+    }
+    for (number, name) in [
+        (4, "gasPrice"),
+        (5, "maxFeePerGas"),
+        (6, "maxPriorityFeePerGas"),
+        (7, "value"),
+    ] {
+        if let Some(value) = field(name) {
+            encoder.bytes(number, &quantity_bytes(value)?);
+        }
+    }
+    if let Some(data) = field("data") {
+        encoder.bytes(8, &hex_value(data)?);
+    }
+    for (number, name) in [(9, "nonce"), (10, "chainId")] {
+        if let Some(value) = field(name) {
+            encoder.uint64(number, uint64(value)?);
+        }
+    }
+    for item in field("accessList")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let mut entry = Encoder::new();
+        entry.bytes(1, &hex_value(&item[0])?);
+        for key in item[1].as_array().into_iter().flatten() {
+            entry.bytes(2, &hex_value(key)?);
+        }
+        encoder.bytes(11, &entry.finish());
+    }
+    Ok(encoder.finish())
+}
+
+/// Formats a 20-byte address.
+fn address(bytes: &[u8]) -> Result<String> {
+    ensure!(bytes.len() == 20, "addresses must be 20 bytes");
+    Ok(hex(bytes))
+}
+
+/// Formats a big-endian unsigned integer as a quantity.
+fn quantity(bytes: &[u8]) -> Result<String> {
+    ensure!(bytes.len() <= 32, "quantities must be at most 32 bytes");
+    let mut buffer = [0; 32];
+    buffer[32 - bytes.len()..].copy_from_slice(bytes);
+    Ok(format!("{:#x}", U256::from_be_bytes(buffer)))
+}
+
+/// Parses a quantity as a minimal big-endian unsigned integer.
+fn quantity_bytes(value: &Value) -> Result<Vec<u8>> {
+    let digits = value
+        .as_str()
+        .and_then(|value| value.strip_prefix("0x"))
+        .context("expected quantity")?;
+    let bytes = U256::from_str_radix(digits, 16)?.to_be_bytes();
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    Ok(bytes[start..].to_vec())
+}
+
+/// Parses a 64-bit quantity.
+fn uint64(value: &Value) -> Result<u64> {
+    let digits = value
+        .as_str()
+        .and_then(|value| value.strip_prefix("0x"))
+        .context("expected quantity")?;
+    Ok(u64::from_str_radix(digits, 16)?)
+}
+
+/// Formats bytes as a hex string.
+fn hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+/// Parses a hex string.
+fn hex_value(value: &Value) -> Result<Vec<u8>> {
+    let digits = value
+        .as_str()
+        .and_then(|value| value.strip_prefix("0x"))
+        .context("expected hex string")?;
+    Ok(hex::decode(digits)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{client, MockUpstream, ACCOUNT};
+    use rocket::serde::json::serde_json::json;
+
+    #[test]
+    fn converts_transactions() {
+        let mut item = Encoder::new();
+        item.bytes(1, &[0x11; 20]).bytes(2, &[0x22; 32]);
+        let mut message = Encoder::new();
+        message
+            .bytes(1, &[0x42; 20])
+            .uint64(3, 21000)
+            .bytes(5, &[0x01, 0x00])
+            .bytes(7, &[])
+            .bytes(8, &[0xab])
+            .uint64(10, 1)
+            .bytes(11, &item.finish());
+        let message = message.finish();
+
+        let transaction = transaction_to_json(&message).unwrap();
+        assert_eq!(
+            transaction,
+            json!({
+                "from": hex(&[0x42; 20]),
+                "gas": "0x5208",
+                "maxFeePerGas": "0x100",
+                "value": "0x0",
+                "data": "0xab",
+                "chainId": "0x1",
+                "accessList": [[hex(&[0x11; 20]), [hex(&[0x22; 32])]]],
+            }),
+        );
+        assert_eq!(transaction_from_json(&transaction).unwrap(), message);
+
+        let mut invalid = Encoder::new();
+        invalid.bytes(1, &[0x42; 19]);
+        assert!(transaction_to_json(&invalid.finish()).is_err());
+    }
+
+    #[rocket::async_test]
+    async fn serves_grpc_signing_api() {
+        use hyper::body::HttpBody as _;
+
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+        let node = client.rocket().state::<Arc<Node>>().unwrap();
+        let grpc = |method: &str, message: &[u8]| {
+            let mut frame = vec![0];
+            frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            frame.extend_from_slice(message);
+            let request = hyper::Request::post(format!("/hdnode.v1.Signer/{method}"))
+                .header("content-type", "application/grpc")
+                .body(hyper::Body::from(frame))
+                .unwrap();
+            async move {
+                let mut body = handle(request, [127, 0, 0, 1].into(), node)
+                    .await
+                    .into_body();
+                let message = body.data().await.map(|data| data.unwrap()[5..].to_vec());
+                let trailers = body.trailers().await.unwrap().unwrap();
+                (
+                    message,
+                    trailers["grpc-status"].to_str().unwrap().to_owned(),
+                )
+            }
+        };
+
+        // `AccountsResponse` with the first development account.
+        let account = hex::decode(&ACCOUNT[2..]).unwrap();
+        let (message, status) = grpc("Accounts", &[]).await;
+        assert_eq!(status, "0");
+        assert_eq!(
+            message.unwrap()[..22],
+            [[0x0a, 20].as_slice(), &account].concat()
+        );
+
+        // `SignMessageRequest` returning a 65-byte `Signature`.
+        let request = [[0x0a, 20].as_slice(), &account, &[0x12, 2], b"hi"].concat();
+        let (message, status) = grpc("SignMessage", &request).await;
+        assert_eq!(status, "0");
+        assert_eq!(message.unwrap()[..2], [0x0a, 65]);
+
+        let (message, status) = grpc("SignMessage", &[0x0a, 1, 0]).await;
+        assert_eq!((message, status.as_str()), (None, "3"));
+        let (message, status) = grpc("Unknown", &[]).await;
+        assert_eq!((message, status.as_str()), (None, "12"));
+    }
+}
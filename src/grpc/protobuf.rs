@@ -0,0 +1,188 @@
+//! Minimal protobuf wire format encoding and decoding.
+//!
+//! Only the wire types used by the hdnode service are supported: varints and
+//! length-delimited fields. Fixed-size fields are skipped when decoding.
+
+use anyhow::{bail, ensure, Context as _, Result};
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LEN: u64 = 2;
+const FIXED32: u64 = 5;
+
+/// A protobuf message encoder.
+#[derive(Default)]
+pub struct Encoder(Vec<u8>);
+
+impl Encoder {
+    /// Creates a new encoder for an empty message.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the encoded message.
+    pub fn finish(self) -> Vec<u8> {
+        self.0
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn tag(&mut self, field: u32, wire_type: u64) {
+        self.varint((u64::from(field) << 3) | wire_type);
+    }
+
+    /// Encodes an unsigned integer field.
+    pub fn uint64(&mut self, field: u32, value: u64) -> &mut Self {
+        self.tag(field, VARINT);
+        self.varint(value);
+        self
+    }
+
+    /// Encodes a bytes field. Strings and embedded messages are encoded the
+    /// same way.
+    pub fn bytes(&mut self, field: u32, value: &[u8]) -> &mut Self {
+        self.tag(field, LEN);
+        self.varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+        self
+    }
+}
+
+/// A decoded field value.
+pub enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    /// A fixed-size field, which is not used by the service.
+    Fixed,
+}
+
+impl<'a> Field<'a> {
+    /// Returns the value of an unsigned integer field.
+    pub fn uint64(&self) -> Result<u64> {
+        match self {
+            Self::Varint(value) => Ok(*value),
+            _ => bail!("expected varint field"),
+        }
+    }
+
+    /// Returns the value of a bytes, string or embedded message field.
+    pub fn bytes(&self) -> Result<&'a [u8]> {
+        match self {
+            Self::Bytes(value) => Ok(value),
+            _ => bail!("expected length-delimited field"),
+        }
+    }
+}
+
+/// A protobuf message decoder, iterating over its fields.
+pub struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    /// Creates a new decoder for an encoded message.
+    pub fn new(message: &'a [u8]) -> Self {
+        Self(message)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = self.0.split_first().context("truncated varint")?;
+            self.0 = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("varint too long")
+    }
+
+    fn take(&mut self, length: u64) -> Result<&'a [u8]> {
+        let length = usize::try_from(length)?;
+        ensure!(length <= self.0.len(), "truncated field");
+        let (value, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(value)
+    }
+
+    fn field(&mut self) -> Result<(u32, Field<'a>)> {
+        let tag = self.varint()?;
+        let number = u32::try_from(tag >> 3).context("invalid field number")?;
+        let field = match tag & 0x7 {
+            VARINT => Field::Varint(self.varint()?),
+            LEN => {
+                let length = self.varint()?;
+                Field::Bytes(self.take(length)?)
+            }
+            FIXED64 => {
+                self.take(8)?;
+                Field::Fixed
+            }
+            FIXED32 => {
+                self.take(4)?;
+                Field::Fixed
+            }
+            wire_type => bail!("unsupported wire type {wire_type}"),
+        };
+        Ok((number, field))
+    }
+}
+
+impl<'a> Iterator for Decoder<'a> {
+    type Item = Result<(u32, Field<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_err() {
+            // Stop at the first error, as the rest of the message can't be
+            // decoded reliably.
+            self.0 = &[];
+        }
+        Some(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrips_fields() {
+        let mut inner = Encoder::new();
+        inner.bytes(1, b"nested");
+        let mut encoder = Encoder::new();
+        encoder
+            .uint64(1, 300)
+            .bytes(2, b"hello")
+            .bytes(3, &inner.finish());
+        let encoded = encoder.finish();
+        assert_eq!(&encoded[..3], [0x08, 0xac, 0x02]);
+
+        // Fixed-size fields are skipped.
+        let mut message = encoded.clone();
+        message.extend_from_slice(&[0x25, 0, 0, 0, 0]);
+        let fields = Decoder::new(&message).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(fields.len(), 4);
+        assert_eq!(fields[0].0, 1);
+        assert_eq!(fields[0].1.uint64().unwrap(), 300);
+        assert_eq!(fields[1].1.bytes().unwrap(), b"hello");
+        let (number, nested) = Decoder::new(fields[2].1.bytes().unwrap())
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!((number, nested.bytes().unwrap()), (1, &b"nested"[..]));
+        assert!(matches!(fields[3].1, Field::Fixed));
+
+        assert!(Decoder::new(&encoded[..encoded.len() - 1])
+            .collect::<Result<Vec<_>>>()
+            .is_err());
+    }
+}
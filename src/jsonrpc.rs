@@ -1,33 +1,105 @@
 //! Module for JSON RPC types.
 
-use crate::VERSION;
+mod cassette;
+
+pub use self::cassette::Cassettes;
+
+use self::cassette::Cassette;
+use crate::{
+    metrics::{ErrorKind, Metrics},
+    VERSION,
+};
 use anyhow::{bail, ensure, Context as _, Result};
 use reqwest::Url;
-use rocket::serde::{
-    de::{self, DeserializeOwned},
-    json::{
-        self,
-        serde_json::{self, Map, Number},
-        Value,
+use rocket::{
+    futures::future,
+    serde::{
+        de::{self, DeserializeOwned, IgnoredAny},
+        json::{
+            serde_json::{self, value::RawValue, Number},
+            Value,
+        },
+        Deserialize, Deserializer, Serialize, Serializer,
     },
-    Deserialize, Deserializer, Serialize, Serializer,
 };
 use std::{
     borrow::Cow,
+    collections::HashMap,
     fmt::{self, Display, Formatter},
+    path::PathBuf,
+    sync::Arc,
+    time::Instant,
 };
+use thiserror::Error;
+
+/// JSON RPC client options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ClientOptions {
+    /// The maximum size in bytes of a response from the remote node. Larger
+    /// responses are rejected. There is no limit by default.
+    #[serde(default)]
+    pub max_response_size: Option<usize>,
+
+    /// Whether or not to request compressed (gzip or deflate) responses from
+    /// the remote node.
+    #[serde(default)]
+    pub remote_compression: bool,
+
+    /// Records all requests to the remote node along with their responses to
+    /// the specified file.
+    #[serde(default)]
+    pub remote_record_file: Option<PathBuf>,
+
+    /// Replays the responses recorded with `remote_record_file` from the
+    /// specified file instead of sending requests to the remote node.
+    #[serde(default)]
+    pub remote_replay_file: Option<PathBuf>,
+
+    /// The maximum number of requests sent to the remote node in a single
+    /// JSON RPC batch, for remote nodes that reject or limit batches. Larger
+    /// batches are split into chunks that are sent concurrently, and a size of
+    /// 1 disables batching altogether. Batches are not limited by default.
+    #[serde(default)]
+    pub max_batch_size: Option<usize>,
+
+    /// The metrics that requests to remote nodes are recorded to, shared by
+    /// the clients created with these options.
+    #[serde(skip)]
+    pub metrics: Metrics,
+
+    /// The cassettes opened by the clients created with these options.
+    #[serde(skip)]
+    pub cassettes: Cassettes,
+}
 
 /// JSON RPC client.
+#[derive(Clone)]
 pub struct Client {
     client: reqwest::Client,
     url: Url,
+    options: ClientOptions,
+    cassette: Option<Arc<Cassette>>,
 }
 
 impl Client {
     /// Creates a new client for the given URL.
-    pub fn new(url: Url) -> Result<Self> {
-        let client = reqwest::Client::builder().user_agent(VERSION).build()?;
-        Ok(Self { client, url })
+    pub fn new(url: Url, options: ClientOptions) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(VERSION)
+            .gzip(options.remote_compression)
+            .deflate(options.remote_compression)
+            .build()?;
+        let cassette = options.cassettes.open(
+            options.remote_record_file.as_deref(),
+            options.remote_replay_file.as_deref(),
+        )?;
+        Ok(Self {
+            client,
+            url,
+            options,
+            cassette,
+        })
     }
 
     /// Returns the URL of the current RPC client.
@@ -35,15 +107,27 @@ impl Client {
         &self.url
     }
 
+    /// Returns the options of the current RPC client.
+    pub fn options(&self) -> &ClientOptions {
+        &self.options
+    }
+
     /// Executes a JSON RPC request.
     pub async fn execute(&self, request: &Request) -> Result<Response> {
-        let response = self.post::<_, Response>(request).await?;
+        if let Some(response) = self.play(request) {
+            return response;
+        }
+
+        let start = Instant::now();
+        let result = self.post::<_, Response>(request).await;
+        let response = self.record(&request.method, start, result)?;
 
         if request.id != response.id {
             tracing::error!(?request, ?response, "mismatched request and response");
             bail!("mismatched request and response");
         }
 
+        self.capture(request, &response)?;
         Ok(response)
     }
 
@@ -52,31 +136,102 @@ impl Client {
         if requests.is_empty() {
             return Ok(Vec::new());
         }
+        if self.replaying() {
+            return requests
+                .iter()
+                .filter_map(|request| self.play(request))
+                .collect();
+        }
 
-        let responses = self.post::<_, Vec<Response>>(requests).await?;
+        match self.options.max_batch_size {
+            Some(size) if size <= 1 => {
+                future::try_join_all(requests.iter().map(|request| self.execute(request))).await
+            }
+            Some(size) if requests.len() > size => {
+                let chunks = future::try_join_all(
+                    requests.chunks(size).map(|chunk| self.execute_batch(chunk)),
+                )
+                .await?;
+                Ok(chunks.into_iter().flatten().collect())
+            }
+            _ => self.execute_batch(requests).await,
+        }
+    }
 
-        if requests.len() != responses.len()
-            || requests
-                .iter()
-                .zip(responses.iter())
-                .any(|(request, response)| request.id != response.id)
-        {
-            tracing::error!(
-                ?requests,
-                ?responses,
-                "mismatched batched requests and responses",
-            );
-            bail!("mismatched batched requests and responses");
+    /// Sends a JSON RPC request batch to the remote node.
+    async fn execute_batch(&self, requests: &[Request]) -> Result<Vec<Response>> {
+        let start = Instant::now();
+        let result = self
+            .post::<_, Vec<Response>>(requests)
+            .await
+            .map(|responses| correlate(requests, responses));
+        let elapsed = start.elapsed();
+        for (index, request) in requests.iter().enumerate() {
+            let error = match &result {
+                Ok(responses) => responses.get(index).and_then(error_kind),
+                Err(err) => Some(transport_error_kind(err)),
+            };
+            self.options
+                .metrics
+                .record(&self.url, &request.method, elapsed, error);
         }
+        let responses = result?;
 
+        for (request, response) in requests.iter().zip(&responses) {
+            self.capture(request, response)?;
+        }
         Ok(responses)
     }
 
-    /// Perform HTTP POST for the specified JSON data and parse JSON output.
-    async fn post<T, U>(&self, data: T) -> Result<U>
+    /// Executes a JSON RPC request, returning the raw response body.
+    ///
+    /// This allows large responses to be forwarded without parsing them into
+    /// JSON values. The body is read in full, up to the maximum response size,
+    /// so that failures result in an error rather than a truncated response,
+    /// and its ID is checked to match the request. Responses are re-encoded
+    /// when recording or replaying requests.
+    pub async fn execute_raw(&self, request: &Request) -> Result<Vec<u8>> {
+        if self.cassette.is_some() {
+            return Ok(serde_json::to_vec(&self.execute(request).await?)?);
+        }
+
+        /// The members of a response that are checked, without parsing its
+        /// result.
+        #[derive(Deserialize)]
+        #[serde(crate = "rocket::serde")]
+        struct Envelope {
+            id: Id,
+            error: Option<IgnoredAny>,
+        }
+
+        let start = Instant::now();
+        let result = async {
+            let body = self.receive(self.send(request).await?).await?;
+            let envelope = serde_json::from_slice::<Envelope>(&body)
+                .context("failed to parse response as JSON")?;
+            Ok((body, envelope))
+        }
+        .await;
+        let error = match &result {
+            Ok((_, envelope)) => envelope.error.as_ref().map(|_| ErrorKind::Rpc),
+            Err(err) => Some(transport_error_kind(err)),
+        };
+        self.options
+            .metrics
+            .record(&self.url, &request.method, start.elapsed(), error);
+        let (body, envelope) = result?;
+
+        if request.id != envelope.id {
+            tracing::error!(?request, "mismatched request and response");
+            bail!("mismatched request and response");
+        }
+        Ok(body)
+    }
+
+    /// Sends a HTTP POST request for the specified JSON data.
+    async fn send<T>(&self, data: T) -> Result<reqwest::Response>
     where
         T: Serialize,
-        U: DeserializeOwned,
     {
         tracing::trace!(data = %serde_json::to_string(&data)?, "request");
 
@@ -88,19 +243,148 @@ impl Client {
             .await
             .context("failed to send request")?;
 
+        if let (Some(max), Some(len)) = (self.options.max_response_size, response.content_length())
+        {
+            if len > max as u64 {
+                return Err(ResponseTooLargeError(max).into());
+            }
+        }
+
+        Ok(response)
+    }
+
+    /// Perform HTTP POST for the specified JSON data and parse JSON output.
+    async fn post<T, U>(&self, data: T) -> Result<U>
+    where
+        T: Serialize,
+        U: DeserializeOwned,
+    {
+        let body = self.receive(self.send(data).await?).await?;
+        serde_json::from_slice(&body).context("failed to parse response as JSON")
+    }
+
+    /// Reads the body of a successful HTTP response, up to the maximum
+    /// response size.
+    async fn receive(&self, mut response: reqwest::Response) -> Result<Vec<u8>> {
         let status = response.status();
-        let text = response
-            .text()
+        let mut body = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
             .await
-            .context("failed to read response body")?;
+            .context("failed to read response body")?
+        {
+            body.extend_from_slice(&chunk);
+            if let Some(max) = self.options.max_response_size {
+                if body.len() > max {
+                    return Err(ResponseTooLargeError(max).into());
+                }
+            }
+        }
 
-        tracing::trace!(%status, data = %text, "response");
+        tracing::trace!(%status, data = %String::from_utf8_lossy(&body), "response");
 
-        ensure!(status.is_success(), "HTTP {status} error");
-        json::from_str(&text).context("failed to parse response as JSON")
+        if !status.is_success() {
+            return Err(HttpError(status).into());
+        }
+        Ok(body)
+    }
+
+    /// Returns the replayed response to a request when replaying requests.
+    fn play(&self, request: &Request) -> Option<Result<Response>> {
+        self.cassette.as_ref()?.play(request)
+    }
+
+    /// Returns whether or not requests are replayed.
+    fn replaying(&self) -> bool {
+        matches!(self.cassette.as_deref(), Some(Cassette::Replay(_)))
     }
+
+    /// Records a request and its response when recording requests.
+    fn capture(&self, request: &Request, response: &Response) -> Result<()> {
+        match &self.cassette {
+            Some(cassette) => cassette.capture(request, response),
+            None => Ok(()),
+        }
+    }
+
+    /// Records the metrics of a single request to the remote node.
+    fn record(&self, method: &str, start: Instant, result: Result<Response>) -> Result<Response> {
+        let error = match &result {
+            Ok(response) => error_kind(response),
+            Err(err) => Some(transport_error_kind(err)),
+        };
+        self.options
+            .metrics
+            .record(&self.url, method, start.elapsed(), error);
+        result
+    }
+}
+
+/// Returns the kind of error of a response from the remote node, if any.
+fn error_kind(response: &Response) -> Option<ErrorKind> {
+    response.result.as_ref().err().map(|_| ErrorKind::Rpc)
+}
+
+/// Orders batch responses like their requests.
+///
+/// Batch responses may come back in any order, so they are matched to their
+/// requests by ID. Requests that the remote node dropped from its response get
+/// an error response of their own, and responses to unknown IDs are ignored.
+fn correlate(requests: &[Request], responses: Vec<Response>) -> Vec<Response> {
+    let response_count = responses.len();
+    let mut responses = responses
+        .into_iter()
+        .map(|response| (response.id.clone(), response))
+        .collect::<HashMap<_, _>>();
+    if responses.len() != response_count {
+        tracing::warn!("remote node responded with duplicate batch IDs");
+    }
+
+    let ordered = requests
+        .iter()
+        .map(|request| {
+            responses.remove(&request.id).unwrap_or_else(|| {
+                let id = serde_json::to_string(&request.id).unwrap_or_default();
+                tracing::warn!(%id, method = %request.method, "missing batched response");
+                Response {
+                    jsonrpc: request.jsonrpc,
+                    result: Err(Error {
+                        data: Some(Value::String(format!(
+                            "remote node did not respond to request ID {id}"
+                        ))),
+                        ..Error::internal_error()
+                    }),
+                    id: request.id.clone(),
+                }
+            })
+        })
+        .collect();
+    if !responses.is_empty() {
+        tracing::warn!(ids = ?responses.keys(), "unexpected batched responses");
+    }
+    ordered
 }
 
+/// Returns the kind of error of a request that failed without a response.
+fn transport_error_kind(err: &anyhow::Error) -> ErrorKind {
+    if err.is::<HttpError>() {
+        ErrorKind::Http
+    } else {
+        ErrorKind::Transport
+    }
+}
+
+/// An error indicating that the remote node responded with an HTTP error
+/// status.
+#[derive(Debug, Error)]
+#[error("HTTP {0} error")]
+pub struct HttpError(pub reqwest::StatusCode);
+
+/// An error indicating that a remote response exceeded the maximum size.
+#[derive(Debug, Error)]
+#[error("response exceeds maximum size of {0} bytes")]
+pub struct ResponseTooLargeError(pub usize);
+
 /// JSON RPC version.
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(crate = "rocket::serde")]
@@ -116,7 +400,7 @@ pub enum JsonRpc {
 /// > Number, or NULL value if included. If it is not included it is assumed to
 /// > be a notification. The value SHOULD normally not be Null and Numbers
 /// > SHOULD NOT contain fractional parts
-#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 #[serde(crate = "rocket::serde", untagged)]
 pub enum Id {
     String(String),
@@ -129,19 +413,61 @@ pub enum Id {
 /// From the specification:
 /// > If present, parameters for the rpc call MUST be provided as a structured
 /// > value. Either by-position through an Array or by-name through an Object.
-#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
-#[serde(crate = "rocket::serde", untagged)]
-pub enum Params {
-    Array(Vec<Value>),
-    Object(Map<String, Value>),
+///
+/// The parameters are kept as raw JSON so that requests can be forwarded to
+/// the remote node without being decoded and re-encoded.
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde", transparent)]
+pub struct Params(Box<RawValue>);
+
+impl Params {
+    /// Creates new parameters from a serializable value.
+    pub fn new(value: impl Serialize) -> Result<Self> {
+        let raw = serde_json::value::to_raw_value(&value)?;
+        ensure!(
+            Self::is_structured(&raw),
+            "parameters must be an array or an object",
+        );
+        Ok(Self(raw))
+    }
+
+    /// Returns the raw JSON string of the parameters.
+    pub fn get(&self) -> &str {
+        self.0.get()
+    }
+
+    /// Parses the parameters into a value.
+    pub fn parse<T>(&self) -> serde_json::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        serde_json::from_str(self.get())
+    }
+
+    /// Returns true if the raw value is an array or an object.
+    fn is_structured(raw: &RawValue) -> bool {
+        raw.get().starts_with(['[', '{'])
+    }
+}
+
+impl PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
 }
 
-impl From<Params> for Value {
-    fn from(val: Params) -> Self {
-        match val {
-            Params::Array(a) => Value::Array(a),
-            Params::Object(o) => Value::Object(o),
+impl<'de> Deserialize<'de> for Params {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = Box::<RawValue>::deserialize(deserializer)?;
+        if !Self::is_structured(&raw) {
+            return Err(de::Error::custom(
+                "parameters must be an array or an object",
+            ));
         }
+        Ok(Self(raw))
     }
 }
 
@@ -156,32 +482,91 @@ pub struct Request {
 }
 
 /// JSON RPC response.
-#[derive(Debug)]
+///
+/// Like request parameters, the result is kept as raw JSON.
+#[derive(Clone, Debug)]
 pub struct Response {
     pub jsonrpc: JsonRpc,
-    pub result: Result<Value, Error>,
+    pub result: Result<Box<RawValue>, Error>,
     pub id: Id,
 }
 
+impl Response {
+    /// Returns the `Invalid Request` response for a JSON value that is not a
+    /// valid request. The response echoes the request ID when it is valid,
+    /// and its data describes what is wrong with the request.
+    pub fn invalid_request(value: &RawValue) -> Self {
+        #[derive(Deserialize)]
+        #[serde(crate = "rocket::serde")]
+        struct Envelope {
+            jsonrpc: Option<Value>,
+            #[serde(default)]
+            id: Option<Id>,
+        }
+
+        let (id, reason) = match serde_json::from_str::<Envelope>(value.get()) {
+            Ok(Envelope { jsonrpc: None, id }) => (id, "missing jsonrpc version".to_owned()),
+            Ok(Envelope {
+                jsonrpc: Some(version),
+                id,
+            }) if version != "2.0" => (id, format!("unsupported jsonrpc version {version}")),
+            Ok(Envelope { id, .. }) => (id, invalid_reason(value)),
+            Err(_) => (None, invalid_reason(value)),
+        };
+        Self {
+            jsonrpc: JsonRpc::V2,
+            result: Err(Error {
+                data: Some(Value::String(reason)),
+                ..Error::invalid_request()
+            }),
+            id: id.unwrap_or(Id::Null),
+        }
+    }
+}
+
+/// Returns why a JSON value is not a valid request.
+fn invalid_reason(value: &RawValue) -> String {
+    match serde_json::from_str::<Request>(value.get()) {
+        Ok(_) => "invalid request".to_owned(),
+        Err(err) => err.to_string(),
+    }
+}
+
 /// Helper type for generating serialization implemtation for `Response`.
 #[derive(Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 struct Res<'a> {
     jsonrpc: JsonRpc,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    result: Option<Cow<'a, Value>>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    result: Option<Cow<'a, RawValue>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<Cow<'a, Error>>,
     id: Cow<'a, Id>,
 }
 
+/// Deserializes a field that is present, including `null` values, as `Some`.
+///
+/// By default, `Option` fields deserialize `null` as `None` which would make
+/// `null` results indistinguishable from missing ones.
+fn deserialize_present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
 impl Serialize for Response {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
         let (result, error) = match &self.result {
-            Ok(result) => (Some(Cow::Borrowed(result)), None),
+            Ok(result) => (Some(Cow::Borrowed(&**result)), None),
             Err(error) => (None, Some(Cow::Borrowed(error))),
         };
         let res = Res {
@@ -225,6 +610,16 @@ pub struct Error {
 }
 
 impl Error {
+    /// Creates an error indicating the provided body could not be parsed as
+    /// JSON.
+    pub fn parse_error(data: impl Into<Value>) -> Self {
+        Self {
+            code: -32700,
+            message: "Parse error".to_owned(),
+            data: Some(data.into()),
+        }
+    }
+
     /// Creates an error indicating the provided JSON was not a valid request.
     pub fn invalid_request() -> Self {
         Self {
@@ -234,6 +629,16 @@ impl Error {
         }
     }
 
+    /// Creates an error indicating the requested method does not exist or is
+    /// not available.
+    pub fn method_not_found() -> Self {
+        Self {
+            code: -32601,
+            message: "Method not found".to_owned(),
+            data: None,
+        }
+    }
+
     /// Creates an error indicating parameters were invalid.
     pub fn invalid_params() -> Self {
         Self {
@@ -243,6 +648,16 @@ impl Error {
         }
     }
 
+    /// Creates an error indicating the request did not complete within its
+    /// timeout.
+    pub fn timed_out() -> Self {
+        Self {
+            code: -32000,
+            message: "Request timed out".to_owned(),
+            data: None,
+        }
+    }
+
     /// Creates an error indicating an internal server error was encountered.
     pub fn internal_error() -> Self {
         Self {
@@ -264,14 +679,17 @@ impl std::error::Error for Error {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::{collection::vec, option, prelude::*};
     use rocket::serde::json::serde_json::{self, json};
 
     #[test]
+    #[allow(clippy::needless_borrows_for_generic_args)]
     fn jsonrpc_version() {
         assert_eq!(serde_json::to_value(&JsonRpc::V2).unwrap(), json!("2.0"));
     }
 
     #[test]
+    #[allow(clippy::needless_borrows_for_generic_args)]
     fn id() {
         assert_eq!(
             serde_json::to_value(&Id::String("1".to_string())).unwrap(),
@@ -288,6 +706,60 @@ mod tests {
         assert_eq!(serde_json::to_value(&Id::Null).unwrap(), Value::Null);
     }
 
+    #[test]
+    fn correlates_batch_responses() {
+        let request = |id: u64| Request {
+            jsonrpc: JsonRpc::V2,
+            method: "foo".to_owned(),
+            params: None,
+            id: Id::Number(id.into()),
+        };
+        let response = |id: u64| Response {
+            jsonrpc: JsonRpc::V2,
+            result: Ok(serde_json::value::to_raw_value(&id).unwrap()),
+            id: Id::Number(id.into()),
+        };
+
+        let responses = correlate(
+            &[request(1), request(2), request(3)],
+            vec![response(3), response(4), response(1)],
+        );
+        assert_eq!(responses[0].result.as_ref().unwrap().get(), "1");
+        let err = responses[1].result.as_ref().unwrap_err();
+        assert_eq!(err.code, -32603);
+        assert_eq!(
+            err.data,
+            Some(json!("remote node did not respond to request ID 2")),
+        );
+        assert_eq!(responses[1].id, Id::Number(2.into()));
+        assert_eq!(responses[2].result.as_ref().unwrap().get(), "3");
+    }
+
+    #[rocket::async_test]
+    async fn executes_raw_requests() {
+        let upstream = crate::testing::MockUpstream::start().await;
+        let options = ClientOptions {
+            max_response_size: Some(128),
+            ..Default::default()
+        };
+        let client = Client::new(upstream.url(), options).unwrap();
+        let request = Request {
+            jsonrpc: JsonRpc::V2,
+            method: "eth_getLogs".to_owned(),
+            params: None,
+            id: Id::Number(7.into()),
+        };
+
+        upstream.respond("eth_getLogs", json!([]));
+        let body = client.execute_raw(&request).await.unwrap();
+        let response = serde_json::from_slice::<Value>(&body).unwrap();
+        assert_eq!(response, json!({ "jsonrpc": "2.0", "result": [], "id": 7 }));
+
+        upstream.respond("eth_getLogs", json!(vec!["0x00"; 64]));
+        let err = client.execute_raw(&request).await.unwrap_err();
+        assert!(err.is::<ResponseTooLargeError>());
+    }
+
     #[test]
     fn invalid_missing_id() {
         assert!(serde_json::from_value::<Request>(json!({
@@ -304,7 +776,7 @@ mod tests {
             serde_json::to_value(&Request {
                 jsonrpc: JsonRpc::V2,
                 method: "foo".to_string(),
-                params: Some(Params::Array(vec![json!(1), json!("2")])),
+                params: Some(Params::new((1, "2")).unwrap()),
                 id: Id::Number(42.into()),
             })
             .unwrap(),
@@ -324,7 +796,7 @@ mod tests {
             Request {
                 jsonrpc: JsonRpc::V2,
                 method: "eth_accounts".to_string(),
-                params: Some(Params::Array(vec![])),
+                params: Some(Params::new(json!([])).unwrap()),
                 id: Id::Number(0.into()),
             }
         );
@@ -335,7 +807,7 @@ mod tests {
         assert_eq!(
             serde_json::to_value(&Response {
                 jsonrpc: JsonRpc::V2,
-                result: Ok(json!("foo")),
+                result: Ok(serde_json::value::to_raw_value("foo").unwrap()),
                 id: Id::Number(42.into()),
             })
             .unwrap(),
@@ -366,4 +838,84 @@ mod tests {
             }),
         );
     }
+
+    #[test]
+    fn null_result() {
+        let response =
+            serde_json::from_str::<Response>(r#"{"jsonrpc":"2.0","result":null,"id":1}"#).unwrap();
+        assert_eq!(response.result.unwrap().get(), "null");
+
+        assert!(serde_json::from_str::<Response>(r#"{"jsonrpc":"2.0","id":1}"#).is_err());
+    }
+
+    #[test]
+    fn raw_params() {
+        let request = serde_json::from_str::<Request>(
+            r#"{"jsonrpc":"2.0","method":"foo","params":[1, {"a": "b"}],"id":1}"#,
+        )
+        .unwrap();
+        assert_eq!(request.params.unwrap().get(), r#"[1, {"a": "b"}]"#);
+
+        assert!(serde_json::from_str::<Request>(
+            r#"{"jsonrpc":"2.0","method":"foo","params":"bar","id":1}"#,
+        )
+        .is_err());
+    }
+
+    fn any_id() -> impl Strategy<Value = Id> {
+        prop_oneof![
+            any::<String>().prop_map(Id::String),
+            any::<i64>().prop_map(|id| Id::Number(id.into())),
+            any::<u64>().prop_map(|id| Id::Number(id.into())),
+            Just(Id::Null),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn requests_roundtrip(
+            method in "[a-z]{1,8}_[a-zA-Z]{1,16}",
+            params in option::of(vec(any::<u64>(), 0..4)),
+            id in any_id(),
+        ) {
+            let request = Request {
+                jsonrpc: JsonRpc::V2,
+                method,
+                params: params.map(|params| Params::new(params).unwrap()),
+                id,
+            };
+            let encoded = serde_json::to_string(&request).unwrap();
+            prop_assert_eq!(serde_json::from_str::<Request>(&encoded).unwrap(), request);
+        }
+
+        #[test]
+        fn responses_roundtrip(
+            id in any_id(),
+            result in option::of(option::of(any::<u64>())),
+            code in any::<i64>(),
+            message in any::<String>(),
+        ) {
+            let response = Response {
+                jsonrpc: JsonRpc::V2,
+                result: match result {
+                    Some(result) => Ok(serde_json::value::to_raw_value(&result).unwrap()),
+                    None => Err(Error { code, message, data: None }),
+                },
+                id,
+            };
+            let encoded = serde_json::to_string(&response).unwrap();
+            let decoded = serde_json::from_str::<Response>(&encoded).unwrap();
+            prop_assert_eq!(serde_json::to_string(&decoded).unwrap(), encoded);
+        }
+
+        #[test]
+        fn rejects_arbitrary_envelopes(json in r#"\{("(jsonrpc|method|params|id)":("2\.0"|\[\]|1|null|"x"),?){0,5}\}"#) {
+            if let Ok(value) = serde_json::from_str::<Box<RawValue>>(&json) {
+                if serde_json::from_str::<Request>(value.get()).is_err() {
+                    let response = Response::invalid_request(&value);
+                    prop_assert_eq!(response.result.unwrap_err().code, -32600);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,226 @@
+//! Recording and replaying of remote node requests.
+//!
+//! In record mode, every request to a remote node is written with its response
+//! to a cassette file, one JSON object per line. In replay mode, responses are
+//! served from a cassette instead of the network, so integration tests and
+//! bug reproductions can run without access to a real node.
+//!
+//! Recorded requests are matched by method and parameters, ignoring their IDs.
+//! Requests that were recorded several times are replayed in order, and the
+//! last response is repeated once they are exhausted.
+
+use super::{Id, Params, Request, Response};
+use anyhow::{ensure, Context as _, Result};
+use rocket::serde::{
+    json::{
+        serde_json::{self, value::RawValue},
+        Value,
+    },
+    Deserialize, Serialize,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{self, Debug, Formatter},
+    fs::{self, File, OpenOptions},
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// A recorded request and response.
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Entry<'a> {
+    method: &'a str,
+    #[serde(borrow)]
+    params: Option<&'a RawValue>,
+    #[serde(borrow)]
+    response: &'a RawValue,
+}
+
+/// A cassette of remote node requests and responses.
+pub enum Cassette {
+    /// Requests are recorded to a file.
+    Record(Mutex<File>),
+    /// Responses are replayed from recorded ones.
+    Replay(Mutex<HashMap<String, VecDeque<String>>>),
+}
+
+/// The cassettes that are already open, by path. Clients for different
+/// remote nodes share their cassette, so that they record to and replay from
+/// the same file consistently. Clones share the same cassettes.
+#[derive(Clone, Default)]
+pub struct Cassettes(Arc<Mutex<HashMap<PathBuf, Arc<Cassette>>>>);
+
+impl Debug for Cassettes {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let cassettes = self.0.lock().unwrap();
+        f.debug_list().entries(cassettes.keys()).finish()
+    }
+}
+
+impl Cassettes {
+    /// Opens the cassette configured by the specified options, if any, or
+    /// returns it if it is already open.
+    pub fn open(
+        &self,
+        record: Option<&Path>,
+        replay: Option<&Path>,
+    ) -> Result<Option<Arc<Cassette>>> {
+        ensure!(
+            record.is_none() || replay.is_none(),
+            "cannot record and replay remote node requests at the same time",
+        );
+        let (path, open): (_, fn(&Path) -> Result<Cassette>) = match (record, replay) {
+            (Some(path), _) => (path, Cassette::record),
+            (_, Some(path)) => (path, Cassette::replay),
+            _ => return Ok(None),
+        };
+
+        let mut cassettes = self.0.lock().unwrap();
+        if let Some(cassette) = cassettes.get(path) {
+            return Ok(Some(cassette.clone()));
+        }
+        let cassette = Arc::new(open(path)?);
+        cassettes.insert(path.to_owned(), cassette.clone());
+        Ok(Some(cassette))
+    }
+}
+
+impl Cassette {
+    /// Opens a cassette for recording, appending to the specified file.
+    pub fn record(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open cassette {}", path.display()))?;
+        Ok(Self::Record(Mutex::new(file)))
+    }
+
+    /// Opens a cassette for replaying the requests recorded in the specified
+    /// file.
+    pub fn replay(path: &Path) -> Result<Self> {
+        let recorded = fs::read_to_string(path)
+            .with_context(|| format!("failed to read cassette {}", path.display()))?;
+        let mut responses = HashMap::<_, VecDeque<_>>::new();
+        for line in recorded.lines().filter(|line| !line.trim().is_empty()) {
+            let entry = serde_json::from_str::<Entry>(line)
+                .with_context(|| format!("invalid cassette {}", path.display()))?;
+            responses
+                .entry(key(entry.method, entry.params.map(RawValue::get)))
+                .or_default()
+                .push_back(entry.response.get().to_owned());
+        }
+        Ok(Self::Replay(Mutex::new(responses)))
+    }
+
+    /// Returns the recorded response to a request when replaying, or `None`
+    /// when recording.
+    pub fn play(&self, request: &Request) -> Option<Result<Response>> {
+        let responses = match self {
+            Self::Record(_) => return None,
+            Self::Replay(responses) => responses,
+        };
+        let mut responses = responses.lock().unwrap();
+        let recorded = responses.get_mut(&key(
+            &request.method,
+            request.params.as_ref().map(Params::get),
+        ));
+        let response = match recorded {
+            Some(recorded) if recorded.len() > 1 => recorded.pop_front(),
+            Some(recorded) => recorded.front().cloned(),
+            None => None,
+        };
+        Some(
+            response
+                .with_context(|| format!("no recorded response for {}", request.method))
+                .and_then(|response| {
+                    let mut response = serde_json::from_str::<Response>(&response)?;
+                    response.id = request.id.clone();
+                    Ok(response)
+                }),
+        )
+    }
+
+    /// Records a request and its response when recording.
+    pub fn capture(&self, request: &Request, response: &Response) -> Result<()> {
+        let file = match self {
+            Self::Record(file) => file,
+            Self::Replay(_) => return Ok(()),
+        };
+        let params = request
+            .params
+            .as_ref()
+            .map(|params| serde_json::from_str::<&RawValue>(params.get()))
+            .transpose()?;
+        let response = serde_json::value::to_raw_value(&Response {
+            jsonrpc: response.jsonrpc,
+            result: response.result.clone(),
+            id: Id::Null,
+        })?;
+        let line = serde_json::to_string(&Entry {
+            method: &request.method,
+            params,
+            response: &response,
+        })?;
+        writeln!(file.lock().unwrap(), "{line}").context("failed to write cassette")
+    }
+}
+
+/// Returns the key that a request is matched by. Parameters are normalized so
+/// that formatting differences don't prevent matches.
+fn key(method: &str, params: Option<&str>) -> String {
+    let params = params
+        .and_then(|params| serde_json::from_str::<Value>(params).ok())
+        .unwrap_or_default();
+    format!("{method} {params}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::JsonRpc;
+    use rocket::serde::json::json;
+    use std::{env, process};
+
+    #[test]
+    fn records_and_replays_requests() {
+        let path = env::temp_dir().join(format!("hdnode-cassette-{}.jsonl", process::id()));
+        let request = |id: u64, params: Value| Request {
+            jsonrpc: JsonRpc::V2,
+            method: "eth_getBalance".to_owned(),
+            params: Some(Params::new(params).unwrap()),
+            id: Id::Number(id.into()),
+        };
+        let response = |id: u64, balance: &str| Response {
+            jsonrpc: JsonRpc::V2,
+            result: Ok(serde_json::value::to_raw_value(balance).unwrap()),
+            id: Id::Number(id.into()),
+        };
+
+        let recording = Cassette::record(&path).unwrap();
+        let params = json!(["0x4242424242424242424242424242424242424242", "latest"]);
+        assert!(recording.play(&request(1, params.clone())).is_none());
+        for (id, balance) in [(1, "0x1"), (2, "0x2")] {
+            recording
+                .capture(&request(id, params.clone()), &response(id, balance))
+                .unwrap();
+        }
+
+        let replaying = Cassette::replay(&path).unwrap();
+        let balance = |id: u64| {
+            let response = replaying
+                .play(&request(id, params.clone()))
+                .unwrap()
+                .unwrap();
+            assert_eq!(response.id, Id::Number(id.into()));
+            response.result.unwrap().get().to_owned()
+        };
+        assert_eq!(balance(7), r#""0x1""#);
+        assert_eq!(balance(8), r#""0x2""#);
+        assert_eq!(balance(9), r#""0x2""#);
+        assert!(replaying.play(&request(10, json!([]))).unwrap().is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
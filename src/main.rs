@@ -1,80 +1,700 @@
+mod abi;
+mod acl;
+mod backend;
+mod cli;
+mod clock;
+mod compression;
+mod dashboard;
+mod grpc;
 mod jsonrpc;
+mod metrics;
 mod node;
+mod persist;
+mod recovery;
+mod reload;
+mod rest;
+mod runtime;
+mod selectors;
 mod serialization;
 mod signer;
+#[cfg(test)]
+mod testing;
+mod websocket;
 
-use std::path::PathBuf;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use crate::{
-    node::{eth::Eth, Node},
+    cli::{Cli, Command},
+    compression::Compression,
+    jsonrpc::ClientOptions,
+    node::{
+        archive::Archive,
+        cache::Cache,
+        eth::Eth,
+        funding::{self, Funder},
+        journal::{self, Journal},
+        queue::{self, Queue},
+        relay::{self, Relay},
+        tap::{self, Tap},
+        Node, Options,
+    },
+    reload::Reloader,
+    selectors::Selectors,
     serialization::{Addresses, Str},
-    signer::{log_recorder::LogRecorder, validator::Validator, wallet::Wallet, BoxSigner},
+    signer::{
+        identity,
+        registry::{self, Registry},
+    },
+    websocket::subscription::Hub,
 };
-use anyhow::Result;
-use hdwallet::mnemonic::Mnemonic;
+use anyhow::{ensure, Result};
+use clap::Parser as _;
 use reqwest::Url;
-use rocket::{fairing::AdHoc, serde::Deserialize};
+use rocket::{fairing::AdHoc, figment::Figment, serde::Deserialize, Build, Rocket};
 
 const VERSION: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 
 #[derive(Debug, Deserialize)]
 #[serde(crate = "rocket::serde")]
 struct Config {
-    /// The BIP-0039 mnemonic phrase for seeding the HD wallet accounts.
-    mnemonic: Str<Mnemonic>,
+    /// The signer pipeline, as a list of signer specifications. The first one
+    /// is the signing backend and the others are decorators around it.
+    /// Defaults to `["wallet", "log", "quota", "validator", "policy"]`.
+    #[serde(default = "registry::default_pipeline")]
+    signers: Vec<String>,
 
-    /// The password to use with the mnemonic phrase for salting the seed used
-    /// for the HD wallet.
+    /// The remote node being proxied.
+    remote_node_url: Str<Url>,
+
+    /// Additional remote nodes that proxied requests are load balanced
+    /// across.
     #[serde(default)]
-    password: String,
+    replica_node_urls: Vec<Str<Url>>,
 
-    /// The number of accounts to derive from the mnemonic seed phrase.
-    account_count: usize,
+    /// The archive node that reads of pruned state at old blocks are retried
+    /// on when they fail on the remote node.
+    archive_node_url: Option<Str<Url>>,
 
-    /// The remote node being proxied.
-    remote_node_url: Str<Url>,
+    /// The log filter, in the same format as the `RUST_LOG` environment
+    /// variable. Defaults to `RUST_LOG` when not specified. Note that this is
+    /// separate from Rocket's own `log_level` setting.
+    log_filter: Option<String>,
+
+    /// Whether or not to accept WebSocket RPC connections, which are upgraded
+    /// from HTTP requests to the RPC endpoint.
+    #[serde(default)]
+    websocket: bool,
+
+    /// The address to serve the gRPC signing API on. The gRPC endpoint is
+    /// disabled when not specified.
+    grpc_address: Option<SocketAddr>,
+
+    /// The WebSocket URL of the remote node used for `eth_subscribe`
+    /// subscriptions. Subscriptions are not supported when not specified.
+    remote_websocket_url: Option<Str<Url>>,
+
+    /// The maximum number of concurrent subscriptions on the remote node.
+    /// Clients subscribing with the same parameters share a subscription.
+    max_remote_subscriptions: Option<usize>,
+
+    /// Whether or not to compress HTTP responses for clients that accept it.
+    #[serde(default)]
+    compression: bool,
+
+    /// Whether or not to accept quantities such as nonces and values as JSON
+    /// numbers and decimal strings, in addition to hex-encoded strings.
+    #[serde(default)]
+    lenient_quantities: bool,
+
+    /// Options for the client connecting to the remote node.
+    #[serde(flatten)]
+    client: ClientOptions,
+
+    /// Options for the backend of state shared between replicas.
+    #[serde(flatten)]
+    backend: backend::Options,
+
+    /// Options for naming the functions called by calldata.
+    #[serde(flatten)]
+    selectors: selectors::Options,
+
+    /// Options for submitting transactions to a private relay.
+    #[serde(flatten)]
+    relay: relay::Options,
+
+    /// Options for journaling submitted transactions.
+    #[serde(flatten)]
+    journal: journal::Options,
 
-    /// A Lua module to use as a validator.
-    validator: Option<PathBuf>,
+    /// Options for topping up accounts from a funder account.
+    #[serde(flatten)]
+    funding: funding::Options,
+
+    /// Options for queueing transactions per account.
+    #[serde(flatten)]
+    queue: queue::Options,
+
+    /// Options for the debug tap logging selected requests and responses.
+    #[serde(flatten)]
+    tap: tap::Options,
+
+    /// Additional HD node options.
+    #[serde(flatten)]
+    node: Options,
 }
 
 #[rocket::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
+    reload::init_logging();
 
-    rocket::build()
+    match Cli::parse()
+        .command
+        .unwrap_or(Command::Serve { dev: false })
+    {
+        Command::Serve { dev } => serve(cli::figment(dev)).await,
+        command => {
+            if let Err(err) = command.run(rocket::Config::figment()) {
+                eprintln!("Error: {err:?}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+async fn serve(figment: Figment) {
+    rocket(figment).launch().await.unwrap();
+}
+
+/// Builds the HD node server for the specified configuration.
+fn rocket(figment: Figment) -> Rocket<Build> {
+    rocket::custom(figment)
         .attach(AdHoc::config::<Config>())
+        .attach(AdHoc::config::<acl::Options>())
+        .attach(AdHoc::config::<identity::Options>())
+        .attach(AdHoc::config::<dashboard::Options>())
+        .attach(AdHoc::config::<rest::Options>())
         .attach(AdHoc::try_on_ignite("hdnode::Node", |rocket| async {
-            match init(rocket.state().unwrap()).await {
-                Ok(node) => Ok(rocket.manage(node)),
+            match init(rocket.state().unwrap(), rocket.figment()).await {
+                Ok(node) => Ok(rocket.manage(Arc::new(node))),
                 Err(err) => {
                     tracing::error!(?err, "failed to inialize node");
                     Err(rocket)
                 }
             }
         }))
-        .mount("/", rocket::routes![node::handler])
-        .launch()
-        .await
-        .unwrap();
+        .attach(AdHoc::on_ignite("hdnode::Compression", |rocket| async {
+            let config = rocket.state::<Config>().unwrap();
+            if config.compression {
+                rocket.attach(Compression)
+            } else {
+                rocket
+            }
+        }))
+        .attach(AdHoc::on_ignite("hdnode::WebSocket", |rocket| async {
+            let config = rocket.state::<Config>().unwrap();
+            if !config.websocket {
+                return rocket;
+            }
+            let hub = config
+                .remote_websocket_url
+                .as_ref()
+                .map(|url| Arc::new(Hub::new(url.0.clone(), config.max_remote_subscriptions)));
+            rocket
+                .manage(websocket::Endpoint { hub })
+                .mount("/", rocket::routes![websocket::handler])
+        }))
+        .attach(AdHoc::on_liftoff("hdnode::Grpc", |rocket| {
+            Box::pin(async move {
+                let config = rocket.state::<Config>().unwrap();
+                let address = match config.grpc_address {
+                    Some(address) => address,
+                    None => return,
+                };
+                let node = rocket.state::<Arc<Node>>().unwrap().clone();
+                let allowlist = rocket
+                    .state::<acl::Options>()
+                    .unwrap()
+                    .rpc_allowlist
+                    .clone();
+
+                rocket::tokio::spawn(async move {
+                    if let Err(err) = grpc::serve(address, node, allowlist).await {
+                        tracing::error!(?err, "gRPC server error");
+                    }
+                });
+            })
+        }))
+        .attach(AdHoc::on_liftoff("hdnode::Funding", |rocket| {
+            Box::pin(async move {
+                let node = rocket.state::<Arc<Node>>().unwrap().clone();
+                if let Some(funder) = node.funder() {
+                    rocket::tokio::spawn(funder.run(node));
+                }
+            })
+        }))
+        .attach(AdHoc::on_liftoff("hdnode::Reload", |rocket| {
+            Box::pin(async move {
+                let node = rocket.state::<Arc<Node>>().unwrap().clone();
+                rocket::tokio::spawn(async move {
+                    if let Err(err) = reload::reload_on_sighup(node).await {
+                        tracing::error!(?err, "failed to listen for SIGHUP");
+                    }
+                });
+            })
+        }))
+        .mount(
+            "/",
+            rocket::routes![
+                node::handler,
+                metrics::handler,
+                dashboard::page,
+                dashboard::overview,
+                rest::balance,
+                rest::transaction
+            ],
+        )
+        .register("/admin", rocket::catchers![dashboard::unauthorized])
 }
 
-async fn init(config: &Config) -> Result<Node> {
-    let remote = Eth::from_url(config.remote_node_url.0.clone()).unwrap();
+async fn init(config: &Config, figment: &Figment) -> Result<Node> {
+    if let Some(log_filter) = &config.log_filter {
+        reload::set_log_level(log_filter)?;
+    }
+    let selectors = Selectors::new(&config.selectors)?;
+    serialization::set_lenient_quantities(config.lenient_quantities);
+
+    let identities = figment.extract::<identity::Options>()?;
+    ensure!(
+        !identities.require_client_identity || config.grpc_address.is_none(),
+        "client identities can't be required with the gRPC endpoint, which doesn't \
+         authenticate client certificates",
+    );
+    ensure!(
+        identities.identities.is_empty()
+            || config
+                .signers
+                .iter()
+                .any(|spec| spec.split(':').next() == Some("identity")),
+        "client identities require the identity signer in the signers pipeline, \
+         which enforces their account scopes and quotas",
+    );
+
+    let backend = backend::connect(&config.backend)?;
+    let mut remote = Eth::from_url(config.remote_node_url.0.clone(), config.client.clone())?;
+    if let Some(ttl) = config.node.cache_ttl {
+        remote = remote.with_cache(Cache::new(Duration::from_millis(ttl), backend.clone()));
+    }
     let chain = match remote.chain_id().await {
         Ok(chain_id) => chain_id.to_string(),
         err => format!("{:?}", err),
     };
     tracing::debug!(url = %remote.url(), %chain, "connected to remote node");
 
-    let wallet = Wallet::new(&*config.mnemonic, &config.password, config.account_count)?;
-    let recorder = LogRecorder(wallet);
-    let signer: BoxSigner = if let Some(validator) = &config.validator {
-        Box::new(Validator::new(recorder, validator).unwrap())
-    } else {
-        Box::new(recorder)
-    };
+    let cluster = config.backend.cluster.then(|| backend.clone());
+    if cluster.is_some() && config.backend.backend_url.is_none() {
+        tracing::warn!(
+            "cluster mode with an in-memory backend doesn't coordinate with other instances"
+        );
+    }
+    let mut context = registry::Context::new(figment, &remote);
+    context.cluster = cluster.clone();
+    context.selectors = selectors.clone();
+    let signer = Registry::default()
+        .build(&config.signers, &mut context)
+        .await?;
+    let (reloader, quotas, receipts, audit, approvals) = (
+        Reloader::new(context.validators, config.node.dev),
+        context.quotas,
+        context.receipts,
+        context.audit,
+        context.approvals,
+    );
     tracing::debug!(accounts = ?Addresses(signer.accounts()), "derived accounts");
+    if config.node.dev {
+        tracing::warn!("running in development mode, never use this with real funds");
+        for (index, account) in signer.accounts().iter().enumerate() {
+            println!("m/44'/60'/0'/0/{index}\t{account}");
+        }
+    }
+
+    let replicas = config
+        .replica_node_urls
+        .iter()
+        .map(|url| jsonrpc::Client::new(url.0.clone(), config.client.clone()))
+        .collect::<Result<_>>()?;
+
+    let funder = Funder::new(&config.funding, signer.accounts())?.map(|funder| match &cluster {
+        Some(cluster) => funder.with_cluster(cluster.clone()),
+        None => funder,
+    });
+    let mut node = Node::new(signer, remote, replicas, config.node.clone())
+        .with_reloader(reloader)
+        .with_selectors(selectors)
+        .with_approvals(approvals);
+    if let Some(quotas) = quotas {
+        node = node.with_quotas(quotas);
+    }
+    if let Some(receipts) = receipts {
+        node = node.with_receipts(receipts);
+    }
+    if let Some(audit) = audit {
+        node = node.with_audit(audit);
+    }
+    let relay = Relay::new(&config.relay)?.map(Arc::new);
+    if let Some(journal) = Journal::open(&config.journal)? {
+        let journal = Arc::new(journal);
+        let eth = Eth::from_url(config.remote_node_url.0.clone(), config.client.clone())?;
+        rocket::tokio::spawn(journal.clone().monitor(eth, relay.clone()));
+        node = node.with_journal(journal);
+    }
+    if let Some(relay) = relay {
+        node = node.with_relay(relay);
+    }
+    match (Queue::new(&config.queue), cluster) {
+        (Some(queue), Some(cluster)) => node = node.with_queue(queue.with_cluster(cluster)),
+        (Some(queue), None) => node = node.with_queue(queue),
+        (None, Some(_)) => {
+            tracing::warn!("transaction nonces are only coordinated in cluster mode with transaction_queue enabled");
+        }
+        (None, None) => {}
+    }
+    if let Some(tap) = Tap::new(&config.tap)? {
+        node = node.with_tap(tap);
+    }
+    if let Some(funder) = funder {
+        node = node.with_funder(funder);
+    }
+    if let Some(url) = &config.archive_node_url {
+        let client = jsonrpc::Client::new(url.0.clone(), config.client.clone())?;
+        node = node.with_archive(Archive::new(client));
+    }
+    Ok(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, call, client, client_with, MockUpstream, ACCOUNT};
+    use rocket::{
+        http::Header,
+        serde::json::{serde_json::json, Value},
+    };
+
+    #[rocket::async_test]
+    async fn signs_filled_legacy_transaction() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+        upstream.respond("eth_getTransactionCount", json!("0x9"));
+        upstream.respond("eth_estimateGas", json!("0x5208"));
+
+        let response = call(
+            &client,
+            "eth_signTransaction",
+            json!([{
+                "from": ACCOUNT,
+                "to": "0x3535353535353535353535353535353535353535",
+                "value": "0xde0b6b3a7640000",
+                "gasPrice": "0x4a817c800",
+            }]),
+        )
+        .await;
+
+        assert_eq!(
+            response["result"],
+            "0xf86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a0\
+             3016c5b00acdf2ab6417652b9af1b5458ae73a8f2ddbc2ce03ccddde54184f71a0\
+             160362f6bf9e0af5a6f543153b85cfce8bce64cf08607f2cfd486fecb81eba1d",
+        );
+        assert_eq!(
+            upstream.methods(),
+            [
+                "eth_chainId",
+                "eth_chainId",
+                "eth_getTransactionCount",
+                "eth_estimateGas"
+            ],
+        );
+    }
+
+    #[rocket::async_test]
+    async fn fills_transactions_without_signing() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+        upstream.respond("eth_getTransactionCount", json!("0x9"));
+        upstream.respond("eth_estimateGas", json!("0x5208"));
+
+        let response = call(
+            &client,
+            "eth_fillTransaction",
+            json!([{
+                "from": ACCOUNT,
+                "to": "0x3535353535353535353535353535353535353535",
+                "value": "0xde0b6b3a7640000",
+                "gasPrice": "0x4a817c800",
+            }]),
+        )
+        .await;
+
+        assert_eq!(
+            response["result"],
+            json!({
+                "from": ACCOUNT,
+                "type": "0x0",
+                "to": "0x3535353535353535353535353535353535353535",
+                "gas": "0x5208",
+                "gasPrice": "0x4a817c800",
+                "value": "0xde0b6b3a7640000",
+                "data": "0x",
+                "nonce": "0x9",
+                "chainId": "0x1",
+            }),
+        );
+        assert!(!upstream
+            .methods()
+            .iter()
+            .any(|method| method == "eth_sendRawTransaction"));
+    }
+
+    #[rocket::async_test]
+    async fn sends_signed_eip1559_transaction() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+        upstream.respond("eth_getTransactionCount", json!("0x0"));
+        upstream.respond("eth_sendRawTransaction", json!("0x42"));
+
+        let request = json!([{
+            "from": ACCOUNT,
+            "to": "0x3535353535353535353535353535353535353535",
+            "gas": "0x5208",
+            "maxFeePerGas": "0x3b9aca00",
+            "maxPriorityFeePerGas": "0x1",
+            "data": "0x01020304",
+        }]);
+        let signed = call(&client, "eth_signTransaction", request.clone()).await;
+        let sent = call(&client, "eth_sendTransaction", request).await;
+
+        assert_eq!(sent["result"], "0x42");
+        let raw = upstream
+            .requests()
+            .into_iter()
+            .find(|request| request["method"] == "eth_sendRawTransaction")
+            .unwrap();
+        assert_eq!(raw["params"], json!([signed["result"]]));
+        assert!(signed["result"].as_str().unwrap().starts_with("0x02"));
+    }
+
+    #[rocket::async_test]
+    async fn reports_remote_node_errors() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+        upstream.fail("eth_blockNumber", -32000, "header not found");
+
+        let response = call(&client, "eth_blockNumber", json!([])).await;
+        assert_eq!(
+            response["error"],
+            json!({ "code": -32000, "message": "header not found" }),
+        );
+
+        upstream.set_http_failure(Some(503));
+        let response = call(
+            &client,
+            "eth_signTransaction",
+            json!([{ "from": ACCOUNT, "gas": "0x5208", "gasPrice": "0x1" }]),
+        )
+        .await;
+        assert!(response["error"].is_object());
+    }
+
+    #[rocket::async_test]
+    async fn responds_to_malformed_json_with_parse_error() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+        let response = client
+            .post("/")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"jsonrpc":"2.0","method":"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), rocket::http::Status::Ok);
+        let response = response.into_json::<Value>().await.unwrap();
+        assert_eq!(response["error"]["code"], -32700);
+        assert_eq!(response["id"], Value::Null);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_invalid_requests() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+        let post = |body: &'static str| {
+            let client = &client;
+            async move {
+                client
+                    .post("/")
+                    .header(rocket::http::ContentType::JSON)
+                    .body(body)
+                    .dispatch()
+                    .await
+                    .into_json::<Value>()
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let response = post(r#"{"jsonrpc":"1.0","method":"eth_accounts","id":7}"#).await;
+        assert_eq!(response["error"]["code"], -32600);
+        assert_eq!(
+            response["error"]["data"],
+            "unsupported jsonrpc version \"1.0\""
+        );
+        assert_eq!(response["id"], 7);
+
+        let response = post(r#"{"method":"eth_accounts","id":7}"#).await;
+        assert_eq!(response["error"]["data"], "missing jsonrpc version");
+
+        let response = post("[]").await;
+        assert_eq!(response["error"]["code"], -32600);
+        assert_eq!(response["id"], Value::Null);
+
+        let response = post(
+            r#"[{"jsonrpc":"2.0","method":"eth_accounts","params":[],"id":1},{"method":"eth_accounts","id":2},3]"#,
+        )
+        .await;
+        assert_eq!(response[0]["result"][0], ACCOUNT);
+        assert_eq!(response[1]["error"]["code"], -32600);
+        assert_eq!(response[1]["id"], 2);
+        assert_eq!(response[2]["error"]["code"], -32600);
+        assert_eq!(response[2]["id"], Value::Null);
+    }
+
+    #[rocket::async_test]
+    async fn correlates_reordered_batch_responses() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+        upstream.respond("eth_blockNumber", json!("0x2a"));
+        upstream.respond("eth_gasPrice", json!("0x3b9aca00"));
+        upstream.set_reverse_batches(true);
+
+        // Clients may reuse IDs, and internal and remote requests are mixed.
+        let batch = json!([
+            { "jsonrpc": "2.0", "method": "eth_blockNumber", "params": [], "id": 1 },
+            { "jsonrpc": "2.0", "method": "eth_accounts", "params": [], "id": 1 },
+            { "jsonrpc": "2.0", "method": "eth_gasPrice", "params": [], "id": 1 },
+            { "jsonrpc": "2.0", "method": "eth_getCode", "params": [ACCOUNT, "latest"], "id": "a" },
+        ]);
+        let response = client
+            .post("/")
+            .json(&batch)
+            .dispatch()
+            .await
+            .into_json::<Value>()
+            .await
+            .unwrap();
+        assert_eq!(
+            response[0],
+            json!({ "jsonrpc": "2.0", "result": "0x2a", "id": 1 })
+        );
+        assert_eq!(response[1]["result"][0], ACCOUNT);
+        assert_eq!(response[2]["result"], "0x3b9aca00");
+        assert_eq!(response[3]["error"]["code"], -32601);
+        assert_eq!(response[3]["id"], "a");
+
+        // Transport failures fail each remote request, keeping its ID.
+        upstream.set_http_failure(Some(502));
+        let response = client
+            .post("/")
+            .json(&batch)
+            .dispatch()
+            .await
+            .into_json::<Value>()
+            .await
+            .unwrap();
+        assert_eq!(response[1]["result"][0], ACCOUNT);
+        for index in [0, 2, 3] {
+            assert!(response[index]["error"].is_object());
+            assert_eq!(response[index]["id"], batch[index]["id"]);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn signs_golden_transactions() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+        upstream.respond("eth_chainId", json!("0x7a69"));
+
+        for fixture in testing::transaction_fixtures() {
+            upstream.respond(
+                "eth_getTransactionCount",
+                fixture.transaction["nonce"].clone(),
+            );
+            let response = call(&client, "eth_signTransaction", json!([fixture.transaction])).await;
+            assert_eq!(response["result"], fixture.raw, "{}", fixture.name);
+        }
+        // Fixtures are fully specified, so nothing but the chain ID and nonce
+        // are read from the node.
+        assert!(upstream
+            .methods()
+            .iter()
+            .all(|method| method == "eth_chainId" || method == "eth_getTransactionCount"));
+    }
+
+    #[rocket::async_test]
+    async fn requires_admin_token_for_pausing() {
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment.merge(("admin_token", "secret"))
+        })
+        .await;
+
+        let paused = call(&client, "hdnode_pauseSigning", json!([])).await;
+        assert_eq!(paused["error"]["code"], 4100);
+        let signed = call(&client, "eth_sign", json!([ACCOUNT, "0x68656c6c6f"])).await;
+        assert!(signed["result"].is_string());
+
+        let paused = client
+            .post("/")
+            .header(rocket::http::ContentType::JSON)
+            .header(Header::new("Authorization", "Bearer secret"))
+            .body(
+                json!({"jsonrpc": "2.0", "method": "hdnode_pauseSigning", "params": [], "id": 1})
+                    .to_string(),
+            )
+            .dispatch()
+            .await
+            .into_json::<Value>()
+            .await
+            .unwrap();
+        assert_eq!(paused["result"], true);
+        let signed = call(&client, "eth_sign", json!([ACCOUNT, "0x68656c6c6f"])).await;
+        assert_eq!(signed["error"]["code"], -32010);
+    }
+
+    #[rocket::async_test]
+    async fn requires_admin_token_for_reloading() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+
+        let reloaded = call(&client, "hdnode_reloadConfig", json!([])).await;
+        assert_eq!(reloaded["error"]["code"], 4100);
+    }
+
+    #[rocket::async_test]
+    async fn requires_admin_token_for_verifying_audit_logs() {
+        let path =
+            std::env::temp_dir().join(format!("hdnode-audit-admin-{}.jsonl", std::process::id()));
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment
+                .merge(("signers", json!(["wallet", "audit"])))
+                .merge(("audit_log", &path))
+                .merge((
+                    "audit_key",
+                    "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d",
+                ))
+                .merge(("admin_token", "secret"))
+        })
+        .await;
 
-    Ok(Node::new(signer, remote))
+        let verification = call(&client, "hdnode_verifyAuditLog", json!([])).await;
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(verification["error"]["code"], 4100);
+    }
 }
@@ -0,0 +1,209 @@
+//! Remote node request metrics.
+//!
+//! The latency and errors of requests to remote nodes are recorded per remote
+//! node and RPC method, so operators can see which calls a provider is slow
+//! at or throttling. Remote nodes are identified by their origin only, as URL
+//! paths often contain API keys.
+
+use crate::{acl::AdminClient, node::Node};
+use reqwest::Url;
+use rocket::{serde::Serialize, State};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// The upper bounds in seconds of the latency histogram buckets.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The maximum number of distinct methods tracked per remote node. Proxied
+/// method names come from clients, so further methods are grouped together.
+const MAX_METHODS: usize = 256;
+
+/// The method name that untracked methods are grouped under.
+const OTHER_METHOD: &str = "other";
+
+/// The kind of error of a request to a remote node.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    /// The request could not be sent or its response could not be read.
+    Transport,
+    /// The remote node responded with an HTTP error status.
+    Http,
+    /// The remote node responded with a JSON RPC error.
+    Rpc,
+}
+
+/// Request metrics for an RPC method.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct MethodStats {
+    /// The number of requests.
+    pub requests: u64,
+    /// The number of failed requests, by kind of error.
+    pub errors: Errors,
+    /// The total latency of all requests in seconds.
+    pub latency: f64,
+    /// The cumulative latency histogram bucket counts.
+    #[serde(skip)]
+    buckets: [u64; BUCKETS.len()],
+}
+
+/// Error counts by kind.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Errors {
+    pub transport: u64,
+    pub http: u64,
+    pub rpc: u64,
+}
+
+/// Recorded request metrics by remote node origin and RPC method. Clones
+/// share the same metrics.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<BTreeMap<String, BTreeMap<String, MethodStats>>>>);
+
+impl Metrics {
+    /// Records a request to a remote node.
+    pub fn record(
+        &self,
+        upstream: &Url,
+        method: &str,
+        latency: Duration,
+        error: Option<ErrorKind>,
+    ) {
+        let mut upstreams = self.0.lock().unwrap();
+        let methods = upstreams
+            .entry(upstream.origin().ascii_serialization())
+            .or_default();
+        let method = if methods.contains_key(method) || methods.len() < MAX_METHODS {
+            method
+        } else {
+            OTHER_METHOD
+        };
+        let stats = methods.entry(method.to_owned()).or_default();
+
+        let seconds = latency.as_secs_f64();
+        stats.requests += 1;
+        stats.latency += seconds;
+        for (bucket, bound) in stats.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        match error {
+            Some(ErrorKind::Transport) => stats.errors.transport += 1,
+            Some(ErrorKind::Http) => stats.errors.http += 1,
+            Some(ErrorKind::Rpc) => stats.errors.rpc += 1,
+            None => {}
+        }
+    }
+
+    /// Returns the recorded metrics by remote node origin and RPC method.
+    pub fn stats(&self) -> BTreeMap<String, BTreeMap<String, MethodStats>> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Renders the recorded metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let upstreams = self.stats();
+        let series = || {
+            upstreams.iter().flat_map(|(upstream, methods)| {
+                methods.iter().map(move |(method, stats)| {
+                    let labels = format!(
+                        "upstream=\"{}\",method=\"{}\"",
+                        escape(upstream),
+                        escape(method)
+                    );
+                    (labels, stats)
+                })
+            })
+        };
+
+        let mut output = String::new();
+        let latency = "hdnode_upstream_request_duration_seconds";
+        let _ = writeln!(
+            output,
+            "# HELP {latency} Latency of requests to remote nodes."
+        );
+        let _ = writeln!(output, "# TYPE {latency} histogram");
+        for (labels, stats) in series() {
+            for (bucket, bound) in stats.buckets.iter().zip(BUCKETS) {
+                let _ = writeln!(
+                    output,
+                    "{latency}_bucket{{{labels},le=\"{bound}\"}} {bucket}"
+                );
+            }
+            let _ = writeln!(
+                output,
+                "{latency}_bucket{{{labels},le=\"+Inf\"}} {}",
+                stats.requests
+            );
+            let _ = writeln!(output, "{latency}_sum{{{labels}}} {}", stats.latency);
+            let _ = writeln!(output, "{latency}_count{{{labels}}} {}", stats.requests);
+        }
+
+        let errors = "hdnode_upstream_errors_total";
+        let _ = writeln!(output, "# HELP {errors} Failed requests to remote nodes.");
+        let _ = writeln!(output, "# TYPE {errors} counter");
+        for (labels, stats) in series() {
+            for (kind, count) in [
+                ("transport", stats.errors.transport),
+                ("http", stats.errors.http),
+                ("rpc", stats.errors.rpc),
+            ] {
+                let _ = writeln!(output, "{errors}{{{labels},kind=\"{kind}\"}} {count}");
+            }
+        }
+        output
+    }
+}
+
+/// Serves the recorded metrics in the Prometheus text format.
+#[rocket::get("/metrics")]
+pub fn handler(_client: AdminClient, node: &State<Arc<Node>>) -> String {
+    node.metrics().render()
+}
+
+/// Escapes a Prometheus label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_requests_per_method() {
+        let metrics = Metrics::default();
+        let upstream = "https://metrics.test/v3/secret".parse::<Url>().unwrap();
+        metrics.record(&upstream, "eth_call", Duration::from_millis(20), None);
+        metrics.record(
+            &upstream,
+            "eth_call",
+            Duration::from_secs(3),
+            Some(ErrorKind::Http),
+        );
+
+        let stats = &metrics.stats()["https://metrics.test"]["eth_call"];
+        assert_eq!(stats.requests, 2);
+        assert_eq!(stats.errors.http, 1);
+        assert_eq!(stats.buckets[2], 1);
+        assert_eq!(stats.buckets[BUCKETS.len() - 1], 2);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "hdnode_upstream_errors_total{upstream=\"https://metrics.test\",\
+             method=\"eth_call\",kind=\"http\"} 1"
+        ));
+        assert!(!rendered.contains("secret"));
+    }
+}
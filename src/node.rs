@@ -1,37 +1,321 @@
 //! Module implemeting the HD node handler.
 
+pub mod archive;
+pub mod bundle;
+pub mod cache;
+pub mod chain;
+pub mod coalesce;
 pub mod eth;
+pub mod filter;
+pub mod funding;
+pub mod journal;
+pub mod normalize;
+pub mod overview;
+pub mod permissions;
+pub mod permit;
+pub mod preview;
+pub mod queue;
+pub mod relay;
+pub mod revert;
+pub mod session;
+pub mod siwe;
+pub mod tap;
+pub mod token;
 pub mod transaction;
 pub mod typeddata;
 pub mod types;
+pub mod upstream;
+pub mod useroperation;
 
-use self::{eth::Eth, transaction::TransactionRequest, typeddata::TypedData};
+use self::{
+    archive::Archive,
+    bundle::BundleRequest,
+    cache::Cache,
+    chain::{ChainParameters, UnrecognizedChainError},
+    coalesce::Coalescer,
+    eth::Eth,
+    filter::Filters,
+    funding::Funder,
+    journal::Journal,
+    permissions::UnauthorizedError,
+    permit::PermitRequest,
+    queue::{Queue, QueueFullError, StaleNonceError},
+    relay::Relay,
+    session::Session,
+    tap::Tap,
+    transaction::{
+        FillOptions, FilledTransaction, InsufficientFundsError, OutOfSyncError, TransactionRequest,
+    },
+    typeddata::{validation::InvalidTypedDataError, DomainMismatchError, TypedData, VerifyOptions},
+    upstream::Upstreams,
+    useroperation::UserOperationRequest,
+};
 use crate::{
-    jsonrpc::{self, Id, JsonRpc, Params, Request, Response},
-    serialization::{Addresses, Bytes, NoParameters},
-    signer::{wallet::UnknownSignerError, BoxSigner},
+    acl::{AdminClient, RpcClient},
+    clock, compression,
+    dashboard::Admin,
+    jsonrpc::{self, Id, JsonRpc, Params, Request, Response, ResponseTooLargeError},
+    metrics::Metrics,
+    recovery,
+    reload::Reloader,
+    selectors::Selectors,
+    serialization::{Addresses, Bytes, NoParameters, Quantity, Str},
+    signer::{
+        approval::{Approvals, NotApprovedError},
+        audit,
+        identity::{self, IdentityQuotaExceededError, IdentityRequiredError, NotInScopeError},
+        pause::{Pausable, SigningPausedError},
+        quota::{self, QuotaExceededError},
+        receipt,
+        request::RequestContext,
+        wallet::UnknownSignerError,
+        BoxSigner, Signing as _,
+    },
 };
-use anyhow::Result;
+use anyhow::{anyhow, ensure, Context as _, Result};
+use ethnum::U256;
+use hdwallet::{account::Address, message::EthereumMessage};
 use rocket::{
+    data::{self, ByteUnit, Data, FromData, Limits},
     futures::future,
+    http::{ContentType, Status},
+    mtls::Certificate,
+    request::{self, FromRequest},
+    response::{self, Responder},
     serde::{
-        json::{self, Json, Value},
-        Deserialize, DeserializeOwned, Serialize,
+        json::{
+            serde_json::{self, value::RawValue, Map},
+            Json, Value,
+        },
+        Deserialize, DeserializeOwned, Deserializer, Serialize,
     },
+    tokio::{self, io::AsyncReadExt as _, time},
     State,
 };
-use std::future::Future;
+use std::{
+    collections::{BTreeMap, HashSet},
+    convert::Infallible,
+    future::Future,
+    mem,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+tokio::task_local! {
+    /// Whether the request being handled within a timeout signed a
+    /// transaction to send.
+    static COMMITTED: Arc<AtomicBool>;
+}
+
+/// Marks the request being handled as having signed a transaction to send, so
+/// that the request timeout no longer cancels it. Otherwise, the client would
+/// not learn about a transaction that may be broadcast anyway, and the
+/// signature would count against quotas and nonces without being sent.
+fn commit() {
+    let _ = COMMITTED.try_with(|committed| committed.store(true, Ordering::Relaxed));
+}
+
+/// The balance that development accounts get funded with by `hdnode_faucet`,
+/// 100 Ether.
+const FAUCET_BALANCE: U256 = U256::new(100_000_000_000_000_000_000);
 
 /// Helper type with different handler input types.
 ///
 /// This is needed to work around the fact that Rocket shortcuts the forwarding
 /// process if it fails to deserialize its input.
-#[derive(Deserialize)]
-#[serde(crate = "rocket::serde", untagged)]
 pub enum Input {
     Request(Request),
-    Batch(Vec<Request>),
-    Unrecognized(Value),
+    /// A batch, with the responses for its invalid entries in place.
+    Batch(Vec<Result<Request, Response>>),
+    Unrecognized(Box<RawValue>),
+    /// A body that could not be parsed as JSON or that exceeds the input
+    /// limits.
+    Rejected(jsonrpc::Error),
+}
+
+impl<'de> Deserialize<'de> for Input {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Note that we can't use an untagged enum here, as raw JSON values
+        // (which are used for request parameters) don't support it.
+        let raw = Box::<RawValue>::deserialize(deserializer)?;
+        let input = if raw.get().starts_with('[') {
+            serde_json::from_str::<Vec<Box<RawValue>>>(raw.get()).map(|entries| {
+                if entries.is_empty() {
+                    return Input::Rejected(jsonrpc::Error {
+                        data: Some(Value::String("empty batch".to_owned())),
+                        ..jsonrpc::Error::invalid_request()
+                    });
+                }
+                Input::Batch(
+                    entries
+                        .iter()
+                        .map(|entry| {
+                            serde_json::from_str(entry.get())
+                                .map_err(|_| Response::invalid_request(entry))
+                        })
+                        .collect(),
+                )
+            })
+        } else {
+            serde_json::from_str(raw.get()).map(Input::Request)
+        };
+        Ok(input.unwrap_or(Input::Unrecognized(raw)))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for Input {
+    type Error = Infallible;
+
+    async fn from_data(
+        request: &'r rocket::Request<'_>,
+        data: Data<'r>,
+    ) -> data::Outcome<'r, Self> {
+        // Malformed bodies are answered with a JSON RPC parse error instead of
+        // being failed by the data guard, which would produce an HTML error.
+        let input = Input::read(request, data)
+            .await
+            .unwrap_or_else(Input::Rejected);
+        data::Outcome::Success(input)
+    }
+}
+
+/// The limits on the size and nesting of JSON RPC inputs, which apply to
+/// both HTTP request bodies and WebSocket messages.
+#[derive(Clone, Copy, Debug)]
+pub struct InputLimits {
+    /// The maximum size in bytes.
+    pub max_size: ByteUnit,
+    /// The maximum nesting depth of arrays and objects, if limited.
+    pub max_depth: Option<usize>,
+}
+
+impl InputLimits {
+    /// Returns the input limits configured for a request.
+    pub fn of(request: &rocket::Request<'_>) -> Self {
+        let options = request
+            .rocket()
+            .state::<Arc<Node>>()
+            .map(|node| node.options())
+            .unwrap_or_default();
+        Self {
+            max_size: options
+                .max_request_size
+                .map(ByteUnit::from)
+                .or_else(|| request.limits().get("json"))
+                .unwrap_or(Limits::JSON),
+            max_depth: options.max_json_depth,
+        }
+    }
+}
+
+impl Input {
+    /// Reads and parses an HTTP request body within the input limits.
+    async fn read(request: &rocket::Request<'_>, data: Data<'_>) -> Result<Self, jsonrpc::Error> {
+        let limits = InputLimits::of(request);
+        let limit = limits.max_size;
+
+        // The limit applies to both the compressed and decompressed body, and
+        // reading one byte more detects bodies exceeding it.
+        let encoding = request.headers().get_one("Content-Encoding");
+        let raw = data.open(ByteUnit::from(limit.as_u64() + 1));
+        let mut decoded = compression::decode(encoding, raw)
+            .ok_or_else(|| {
+                jsonrpc::Error::parse_error(format!(
+                    "unsupported content encoding {}",
+                    encoding.unwrap_or_default(),
+                ))
+            })?
+            .take(limit.as_u64() + 1);
+        let mut body = String::new();
+        decoded
+            .read_to_string(&mut body)
+            .await
+            .map_err(|err| jsonrpc::Error::parse_error(err.to_string()))?;
+        Self::parse(&body, &limits)
+    }
+
+    /// Parses a JSON RPC request or batch within the input limits.
+    pub fn parse(body: &str, limits: &InputLimits) -> Result<Self, jsonrpc::Error> {
+        if body.len() as u64 > limits.max_size.as_u64() {
+            return Err(jsonrpc::Error::parse_error(format!(
+                "request body exceeds {} bytes",
+                limits.max_size.as_u64()
+            )));
+        }
+        if let Some(max_depth) = limits.max_depth {
+            if json_depth(body) > max_depth {
+                return Err(jsonrpc::Error::parse_error(format!(
+                    "JSON nesting exceeds depth {max_depth}"
+                )));
+            }
+        }
+        serde_json::from_str(body).map_err(|err| jsonrpc::Error::parse_error(err.to_string()))
+    }
+}
+
+/// Returns the maximum nesting depth of arrays and objects in a JSON text,
+/// without parsing it.
+fn json_depth(json: &str) -> usize {
+    let (mut depth, mut max_depth) = (0_usize, 0);
+    let (mut in_string, mut escaped) = (false, false);
+    for b in json.bytes() {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            _ if in_string => {}
+            b'[' | b'{' => {
+                depth += 1;
+                max_depth = max_depth.max(depth);
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    max_depth
+}
+
+impl Input {
+    /// Returns the output for when handling the input times out.
+    pub fn timed_out(&self) -> Output {
+        match self {
+            Self::Request(request) => Output::Response(timed_out(request)),
+            Self::Batch(batch) => Output::Batch(timed_out_batch(batch)),
+            Self::Unrecognized(_) | Self::Rejected(_) => Output::Response(Response {
+                jsonrpc: JsonRpc::V2,
+                result: Err(jsonrpc::Error::timed_out()),
+                id: Id::Null,
+            }),
+        }
+    }
+}
+
+/// Returns the response for when handling a request times out.
+pub fn timed_out(request: &Request) -> Response {
+    Response {
+        jsonrpc: request.jsonrpc,
+        result: Err(jsonrpc::Error::timed_out()),
+        id: request.id.clone(),
+    }
+}
+
+/// Returns the responses for when handling a batch times out.
+pub fn timed_out_batch(batch: &[Result<Request, Response>]) -> Vec<Response> {
+    batch
+        .iter()
+        .map(|entry| match entry {
+            Ok(request) => timed_out(request),
+            Err(response) => response.clone(),
+        })
+        .collect()
 }
 
 /// Helper type with different handler output types.
@@ -42,58 +326,656 @@ pub enum Output {
     Batch(Vec<Response>),
 }
 
+/// Handler HTTP reply.
+pub enum Reply {
+    /// A JSON output produced by the node.
+    Json(Json<Output>),
+
+    /// A raw JSON response from the remote node.
+    Raw(Vec<u8>),
+}
+
+impl<'r> Responder<'r, 'r> for Reply {
+    fn respond_to(self, request: &'r rocket::Request<'_>) -> response::Result<'r> {
+        match self {
+            Self::Json(output) => output.respond_to(request),
+            Self::Raw(body) => (ContentType::JSON, body).respond_to(request),
+        }
+    }
+}
+
+/// The request timeout requested by the client with the `X-Request-Timeout`
+/// header, in milliseconds.
+pub struct RequestTimeout(pub Option<Duration>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestTimeout {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r rocket::Request<'_>) -> request::Outcome<Self, Infallible> {
+        let timeout = request
+            .headers()
+            .get_one("X-Request-Timeout")
+            .and_then(|timeout| timeout.parse().ok())
+            .map(Duration::from_millis);
+        request::Outcome::Success(Self(timeout))
+    }
+}
+
+/// The client making a request.
+#[derive(Clone, Debug, Default)]
+pub struct Caller {
+    /// The IP address of the client, if known.
+    pub ip: Option<IpAddr>,
+    /// The origin of the web page making the request, if any, from the
+    /// `Origin` header or else the `Referer` header.
+    pub origin: Option<String>,
+    /// The session of the client's connection, for long-lived connections.
+    pub session: Option<Arc<Session>>,
+    /// The identity of the client, from its TLS client certificate, if any.
+    pub identity: Option<String>,
+    /// Whether or not the client is an administrator, allowed by the admin
+    /// allowlist and authenticated with the `admin_token`.
+    pub admin: bool,
+}
+
+impl Caller {
+    /// Creates a caller from its IP address and the values of its `Origin`
+    /// and `Referer` headers.
+    pub fn new(ip: Option<IpAddr>, origin: Option<&str>, referer: Option<&str>) -> Self {
+        let origin = origin
+            .and_then(normalize_origin)
+            .or_else(|| referer.and_then(normalize_origin));
+        Self {
+            ip,
+            origin,
+            session: None,
+            identity: None,
+            admin: false,
+        }
+    }
+}
+
+/// Ensures that the caller is an administrator, for methods controlling the
+/// node itself.
+fn ensure_admin(caller: &Caller) -> Result<(), jsonrpc::Error> {
+    if caller.admin {
+        return Ok(());
+    }
+    tracing::warn!(ip = ?caller.ip, "rejected admin method from non-admin caller");
+    Err(jsonrpc::Error {
+        code: 4100,
+        message: "The requested account and/or method has not been authorized by the user."
+            .to_owned(),
+        data: Some(Value::String("method requires the admin token".to_owned())),
+    })
+}
+
+/// Normalizes an origin or URL to its serialized origin, such as
+/// `https://app.example.com`. Opaque origins, such as `null`, are ignored.
+fn normalize_origin(value: &str) -> Option<String> {
+    let origin = value.trim().parse::<reqwest::Url>().ok()?.origin();
+    origin
+        .is_tuple()
+        .then(|| origin.ascii_serialization().to_lowercase())
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Caller {
+    type Error = ();
+
+    async fn from_request(request: &'r rocket::Request<'_>) -> request::Outcome<Self, ()> {
+        let headers = request.headers();
+        let mut caller = Self::new(
+            request.client_ip(),
+            headers.get_one("Origin"),
+            headers.get_one("Referer"),
+        );
+        // Administrators are recognized with the same guards as the admin
+        // dashboard, so that the same allowlist and token protect both.
+        caller.admin = request.guard::<AdminClient>().await.succeeded().is_some()
+            && request.guard::<Admin>().await.succeeded().is_some();
+
+        let options = match request.rocket().state::<identity::Options>() {
+            Some(options) => options,
+            None => return request::Outcome::Success(caller),
+        };
+        let certificate = request.guard::<Certificate<'_>>().await.succeeded();
+        caller.identity = certificate
+            .and_then(|certificate| options.identify(certificate.subject().common_names()))
+            .map(String::from);
+        if options.require_client_identity && caller.identity.is_none() {
+            tracing::debug!(ip = ?caller.ip, "rejected request without client identity");
+            return request::Outcome::Error((Status::Forbidden, ()));
+        }
+        request::Outcome::Success(caller)
+    }
+}
+
 #[rocket::post("/", format = "json", data = "<input>")]
-pub async fn handler(input: Json<Input>, node: &State<Node>) -> Json<Output> {
-    let output = match input.into_inner() {
-        Input::Request(request) => Output::Response(node.handle_request(request).await),
-        Input::Batch(requests) => Output::Batch(node.handle_requests(requests).await),
+pub async fn handler(
+    _client: RpcClient,
+    input: Input,
+    caller: Caller,
+    timeout: RequestTimeout,
+    node: &State<Arc<Node>>,
+) -> Reply {
+    let timed_out = input.timed_out();
+    node.within_timeout(timeout.0, handle(input, &caller, node), || {
+        Reply::Json(Json(timed_out))
+    })
+    .await
+}
+
+/// Handles an HTTP request body.
+async fn handle(input: Input, caller: &Caller, node: &Node) -> Reply {
+    let output = match input {
+        Input::Request(request) => match node.handle_request_raw(request, caller).await {
+            Ok(body) => return Reply::Raw(body),
+            Err(response) => Output::Response(response),
+        },
+        Input::Batch(batch) => Output::Batch(node.handle_batch(batch, caller).await),
         Input::Unrecognized(data) => {
             tracing::debug!(%data, "HTTP body is not a valid request or batch");
+            Output::Response(Response::invalid_request(&data))
+        }
+        Input::Rejected(err) => {
+            tracing::debug!(%err, "HTTP body rejected");
             Output::Response(Response {
                 jsonrpc: JsonRpc::V2,
-                result: Err(jsonrpc::Error::invalid_request()),
+                result: Err(err),
                 id: Id::Null,
             })
         }
     };
-    Json(output)
+    Reply::Json(Json(output))
+}
+
+/// HD Node options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Options {
+    /// The ERC-4337 entry point contract used for computing user operation
+    /// hashes. User operation signing is disabled when not specified.
+    #[serde(default)]
+    pub entry_point: Option<Str<Address>>,
+
+    /// Enables the `hdnode_signHash` method for signing arbitrary digests.
+    /// This is dangerous, as it can be used to sign anything (including
+    /// transactions), so it is disabled by default.
+    #[serde(default)]
+    pub sign_hash: bool,
+
+    /// The accounts that are permitted to sign arbitrary digests.
+    #[serde(default)]
+    pub sign_hash_accounts: Vec<Str<Address>>,
+
+    /// The time in milliseconds for which `eth_blockNumber`, `eth_gasPrice`
+    /// and `eth_maxPriorityFeePerGas` results are cached, for both proxied
+    /// requests and filling transactions. Results are not cached when not
+    /// specified.
+    #[serde(default)]
+    pub cache_ttl: Option<u64>,
+
+    /// Coalesces identical concurrent read requests that are proxied to the
+    /// remote node into a single remote call, sharing its response.
+    #[serde(default)]
+    pub coalesce_requests: bool,
+
+    /// Allows all accounts to sign 32-byte messages. These look like
+    /// transaction or typed data hashes, which would be signed blindly, so
+    /// they are rejected by default.
+    #[serde(default)]
+    pub allow_blind_signing: bool,
+
+    /// The accounts that are permitted to sign 32-byte messages.
+    #[serde(default)]
+    pub blind_signing_accounts: Vec<Str<Address>>,
+
+    /// Starts the node with signing paused, rejecting all signing methods
+    /// while still proxying other requests. Signing can be paused and resumed
+    /// at runtime with the `hdnode_pauseSigning` and `hdnode_resumeSigning`
+    /// methods.
+    #[serde(default)]
+    pub signing_paused: bool,
+
+    /// Enables development mode, which allows all accounts to sign raw hashes
+    /// and exposes development conveniences such as `hdnode_setBalance` for
+    /// Hardhat and Anvil remote nodes.
+    #[serde(default)]
+    pub dev: bool,
+
+    /// Implements filter methods locally by polling for new blocks and logs
+    /// instead of installing filters on the remote node.
+    #[serde(default)]
+    pub local_filters: bool,
+
+    /// The default timeout in milliseconds for handling a request, including
+    /// calls to the remote node. Clients can request a shorter timeout with
+    /// the `X-Request-Timeout` header. Requests are not limited when neither
+    /// is specified.
+    #[serde(default)]
+    pub request_timeout: Option<u64>,
+
+    /// The maximum size in bytes of HTTP request bodies. Defaults to Rocket's
+    /// `limits.json` setting, which is 1 MiB unless configured.
+    #[serde(default)]
+    pub max_request_size: Option<u64>,
+
+    /// The maximum nesting depth of arrays and objects in HTTP request
+    /// bodies. Only the JSON parser's own limit of 128 applies when not
+    /// specified.
+    #[serde(default)]
+    pub max_json_depth: Option<usize>,
+
+    /// Verifies that the sending account's balance covers the maximum cost of
+    /// transactions when filling them, instead of signing transactions that
+    /// the network will reject.
+    #[serde(default)]
+    pub balance_check: bool,
+
+    /// The maximum age in seconds of the remote node's latest block for
+    /// filling transactions. When set, transactions are not filled or signed
+    /// while the remote node is syncing or lagging behind, since its nonce and
+    /// fee data would be stale. Other requests are still proxied.
+    #[serde(default)]
+    pub max_block_lag: Option<u64>,
+
+    /// Verifies that the `verifyingContract` of typed data domains has code on
+    /// the connected chain before signing, catching signatures for contracts
+    /// that are not deployed there.
+    #[serde(default)]
+    pub typed_data_code_check: bool,
+
+    /// Verifies that typed data domain separators match the ones returned by
+    /// the `DOMAIN_SEPARATOR()` function of their verifying contracts, for
+    /// contracts that implement it, catching signatures destined for the wrong
+    /// deployment.
+    #[serde(default)]
+    pub typed_data_domain_check: bool,
+
+    /// Normalizes blocks, transactions and receipts from the remote node to a
+    /// consistent schema, filling in fields that some clients omit.
+    #[serde(default)]
+    pub normalize_responses: bool,
 }
 
 /// HD Node.
 pub struct Node {
-    signer: BoxSigner,
+    signer: Pausable<BoxSigner>,
     remote: Eth,
+    upstreams: Upstreams,
+    filters: Filters,
+    coalescer: Coalescer,
+    options: RwLock<Arc<Options>>,
+    reloader: Option<Reloader>,
+    quotas: Option<quota::Handle>,
+    receipts: Option<receipt::Handle>,
+    audit: Option<audit::Handle>,
+    tap: Option<Tap>,
+    queue: Option<Queue>,
+    journal: Option<Arc<Journal>>,
+    relay: Option<Arc<Relay>>,
+    funder: Option<Arc<Funder>>,
+    archive: Option<Archive>,
+    selectors: Selectors,
+    approvals: Approvals,
+    impersonated: Mutex<HashSet<Address>>,
 }
 
 impl Node {
     /// Creates a new HD node instance.
-    pub fn new(signer: BoxSigner, remote: Eth) -> Self {
-        Self { signer, remote }
+    ///
+    /// Proxied requests are load balanced across the remote node and the
+    /// specified replicas, while requests that are partially handled by the
+    /// node only use the remote node.
+    pub fn new(
+        signer: BoxSigner,
+        remote: Eth,
+        replicas: Vec<jsonrpc::Client>,
+        options: Options,
+    ) -> Self {
+        let upstreams = Upstreams::new(
+            std::iter::once((*remote).clone())
+                .chain(replicas)
+                .map(Eth::new)
+                .collect(),
+        );
+        Self {
+            signer: Pausable::new(signer, options.signing_paused),
+            remote,
+            upstreams,
+            filters: Filters::new(),
+            coalescer: Coalescer::new(),
+            options: RwLock::new(Arc::new(options)),
+            reloader: None,
+            quotas: None,
+            receipts: None,
+            audit: None,
+            tap: None,
+            queue: None,
+            journal: None,
+            relay: None,
+            archive: None,
+            selectors: Selectors::default(),
+            approvals: Approvals::default(),
+            impersonated: Mutex::default(),
+            funder: None,
+        }
+    }
+
+    /// Names the functions called by transactions and the custom errors of
+    /// reverts with the specified function signature database.
+    pub fn with_selectors(mut self, selectors: Selectors) -> Self {
+        self.selectors = selectors;
+        self
+    }
+
+    /// Lists the signing requests pending the specified approvals in the
+    /// admin overview.
+    pub fn with_approvals(mut self, approvals: Approvals) -> Self {
+        self.approvals = approvals;
+        self
+    }
+
+    /// Enables reloading the node's configuration with the specified
+    /// reloader.
+    pub fn with_reloader(mut self, reloader: Reloader) -> Self {
+        self.reloader = Some(reloader);
+        self
+    }
+
+    /// Enables reporting signing quota usage with the specified handle.
+    pub fn with_quotas(mut self, quotas: quota::Handle) -> Self {
+        self.quotas = Some(quotas);
+        self
+    }
+
+    /// Enables looking up signing receipts with the specified handle.
+    pub fn with_receipts(mut self, receipts: receipt::Handle) -> Self {
+        self.receipts = Some(receipts);
+        self
+    }
+
+    /// Enables verifying the audit log with the specified handle.
+    pub fn with_audit(mut self, audit: audit::Handle) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Enables logging selected requests and responses with the specified
+    /// debug tap.
+    pub fn with_tap(mut self, tap: Tap) -> Self {
+        self.tap = Some(tap);
+        self
+    }
+
+    /// Enables submitting transactions sequentially per account with the
+    /// specified queue.
+    pub fn with_queue(mut self, queue: Queue) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    /// Enables journaling submitted transactions with the specified journal.
+    pub fn with_journal(mut self, journal: Arc<Journal>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Enables submitting signed transactions to the specified private relay
+    /// instead of the remote node.
+    pub fn with_relay(mut self, relay: Arc<Relay>) -> Self {
+        self.relay = Some(relay);
+        self
+    }
+
+    /// Enables retrying pruned state reads on the specified archive node.
+    pub fn with_archive(mut self, archive: Archive) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    /// Enables topping up accounts with the specified funder.
+    pub fn with_funder(mut self, funder: Funder) -> Self {
+        self.funder = Some(Arc::new(funder));
+        self
+    }
+
+    /// Returns the account funder, if any.
+    pub fn funder(&self) -> Option<Arc<Funder>> {
+        self.funder.clone()
+    }
+
+    /// Returns the metrics of requests to the remote nodes.
+    pub fn metrics(&self) -> &Metrics {
+        &self.remote.options().metrics
+    }
+
+    /// Returns the current node options.
+    fn options(&self) -> Arc<Options> {
+        self.options.read().unwrap().clone()
+    }
+
+    /// Replaces the node options. Requests that are already being handled
+    /// keep using the previous options.
+    pub fn set_options(&self, options: Options) {
+        *self.options.write().unwrap() = Arc::new(options);
+    }
+
+    /// Reloads the node's configuration.
+    pub fn reload_config(&self) -> Result<()> {
+        self.reloader
+            .as_ref()
+            .context("configuration reloading is not enabled")?
+            .reload(self)
+    }
+
+    /// Runs a future handling requests, returning the result of `timed_out`
+    /// if it doesn't complete within the request timeout. The future is
+    /// dropped on timeout, cancelling any in-flight calls to the remote node,
+    /// unless it already signed a transaction to send, in which case it runs
+    /// to completion so that the client learns about the transaction.
+    pub async fn within_timeout<T>(
+        &self,
+        requested: Option<Duration>,
+        f: impl Future<Output = T>,
+        timed_out: impl FnOnce() -> T,
+    ) -> T {
+        let configured = self.options().request_timeout.map(Duration::from_millis);
+        let timeout = match (requested, configured) {
+            (Some(requested), Some(configured)) => Some(requested.min(configured)),
+            (requested, configured) => requested.or(configured),
+        };
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return f.await,
+        };
+        let committed = Arc::new(AtomicBool::new(false));
+        let f = COMMITTED.scope(committed.clone(), f);
+        tokio::pin!(f);
+        match time::timeout(timeout, &mut f).await {
+            Ok(output) => output,
+            Err(_) if committed.load(Ordering::Relaxed) => {
+                tracing::debug!(?timeout, "request timed out after signing, completing it");
+                f.await
+            }
+            Err(_) => {
+                tracing::debug!(?timeout, "request timed out");
+                timed_out()
+            }
+        }
     }
 
-    /// Handles an RPC request.
-    pub async fn handle_request(&self, request: Request) -> Response {
-        match self.mux(request).await {
+    /// Handles an RPC request from a client with the specified IP address.
+    pub async fn handle_request(&self, request: Request, caller: &Caller) -> Response {
+        let tapped = self.tap.as_ref().and_then(|tap| tap.capture(&request));
+        self.handle_tapped_request(request, caller, tapped).await
+    }
+
+    /// Handles an RPC request, recording it with its response to the debug
+    /// tap if it was captured.
+    async fn handle_tapped_request(
+        &self,
+        request: Request,
+        caller: &Caller,
+        tapped: Option<Value>,
+    ) -> Response {
+        let response = match self.mux(request, caller).await {
             Outcome::Internal(response) => response,
-            Outcome::Remote(request) => match self.remote.execute(&request).await {
-                Ok(response) => response,
+            Outcome::Remote(request) => self.execute_remote(request).await,
+        };
+        if let (Some(tap), Some(request)) = (&self.tap, tapped) {
+            tap.record(&request, &response, caller.ip);
+        }
+        response
+    }
+
+    /// Handles an RPC request, returning the raw response from the remote node
+    /// when the request is proxied.
+    ///
+    /// This avoids parsing potentially large responses (for example, for
+    /// `eth_getLogs` queries) into JSON values. Requests that are handled
+    /// internally or that fail on the remote node return a regular response.
+    pub async fn handle_request_raw(
+        &self,
+        request: Request,
+        caller: &Caller,
+    ) -> Result<Vec<u8>, Response> {
+        // Tapped responses need to be recorded as JSON, so aren't raw.
+        if let Some(tapped) = self.tap.as_ref().and_then(|tap| tap.capture(&request)) {
+            return Err(self
+                .handle_tapped_request(request, caller, Some(tapped))
+                .await);
+        }
+        match self.mux(request, caller).await {
+            Outcome::Internal(response) => Err(response),
+            Outcome::Remote(request) if self.parses(&request.method) => {
+                Err(self.execute_remote(request).await)
+            }
+            Outcome::Remote(request) => match self.upstreams.select().execute_raw(&request).await {
+                Ok(body) => Ok(body),
                 Err(err) => {
                     tracing::debug!(?err, ?request, "error executing remote request");
-                    Response {
+                    Err(Response {
                         jsonrpc: request.jsonrpc,
-                        result: Err(jsonrpc::Error::internal_error()),
+                        result: Err(err.into()),
                         id: request.id,
-                    }
+                    })
                 }
             },
         }
     }
 
+    /// Returns true if proxied responses for the method are parsed instead of
+    /// returned raw. Errors with revert data are small, and are parsed so
+    /// that the revert reason can be decoded. Coalesced and cached responses
+    /// are shared, so they are parsed as well, and so are responses that may
+    /// need to be retried on the archive node.
+    fn parses(&self, method: &str) -> bool {
+        revert::decodes(method)
+            || self.coalesces(method)
+            || (self.remote.cache().is_some() && Cache::method(method).is_some())
+            || (self.archive.is_some() && Archive::falls_back(method))
+            || (self.options().normalize_responses && normalize::normalizes(method))
+    }
+
+    /// Returns true if proxied requests for the method are coalesced.
+    fn coalesces(&self, method: &str) -> bool {
+        self.options().coalesce_requests && Coalescer::coalesces(method)
+    }
+
+    /// Executes a request on the remote node, coalescing it with identical
+    /// in-flight requests when enabled.
+    async fn execute_remote(&self, request: Request) -> Response {
+        let cached = self.remote.cache().and_then(|cache| {
+            let method = Cache::method(&request.method)?;
+            let no_params = request
+                .params
+                .as_ref()
+                .is_none_or(|params| params.parse::<NoParameters>().is_ok());
+            no_params.then_some((cache, method))
+        });
+        if let Some(value) = cached.and_then(|(cache, method)| cache.get(method)) {
+            return Response {
+                jsonrpc: request.jsonrpc,
+                result: serde_json::value::to_raw_value(&Quantity(value))
+                    .map_err(|_| jsonrpc::Error::internal_error()),
+                id: request.id,
+            };
+        }
+
+        let execute = || async {
+            let response = match self.upstreams.select().execute(&request).await {
+                Ok(response) => response,
+                Err(err) => {
+                    tracing::debug!(?err, ?request, "error executing remote request");
+                    return Err(err.into());
+                }
+            };
+            match &self.archive {
+                Some(archive) => archive.retry(&request, response).await.result,
+                None => response.result,
+            }
+        };
+        let result = if self.coalesces(&request.method) {
+            self.coalescer.execute(&request, execute).await
+        } else {
+            execute().await
+        };
+        if let (Some((cache, method)), Ok(result)) = (cached, &result) {
+            if let Ok(value) = serde_json::from_str::<Quantity>(result.get()) {
+                cache.insert(method, value.0);
+            }
+        }
+
+        let mut response = Response {
+            jsonrpc: request.jsonrpc,
+            result,
+            id: request.id,
+        };
+        revert::annotate_response(&request.method, &mut response, &self.selectors);
+        if self.options().normalize_responses {
+            normalize::normalize_response(&request.method, &mut response);
+        }
+        response
+    }
+
+    /// Handles a batch of RPC requests, keeping the responses for its invalid
+    /// entries in place.
+    pub async fn handle_batch(
+        &self,
+        batch: Vec<Result<Request, Response>>,
+        caller: &Caller,
+    ) -> Vec<Response> {
+        let mut requests = Vec::with_capacity(batch.len());
+        let invalid = batch
+            .into_iter()
+            .map(|entry| entry.map(|request| requests.push(request)).err())
+            .collect::<Vec<_>>();
+        let mut responses = self.handle_requests(requests, caller).await.into_iter();
+        invalid
+            .into_iter()
+            .map(|invalid| invalid.or_else(|| responses.next()).unwrap())
+            .collect()
+    }
+
     /// Handles an RPC batch.
-    pub async fn handle_requests(&self, requests: Vec<Request>) -> Vec<Response> {
+    pub async fn handle_requests(&self, requests: Vec<Request>, caller: &Caller) -> Vec<Response> {
         let request_count = requests.len();
-        let outcomes =
-            future::join_all(requests.into_iter().map(|request| self.mux(request))).await;
+        let tapped = match &self.tap {
+            Some(tap) => requests
+                .iter()
+                .map(|request| tap.capture(request))
+                .collect(),
+            None => Vec::new(),
+        };
+        let outcomes = future::join_all(
+            requests
+                .into_iter()
+                .map(|request| self.mux(request, caller)),
+        )
+        .await;
         let (responses, remote_requests) = outcomes.into_iter().fold(
             (
                 Vec::with_capacity(request_count),
@@ -111,27 +993,70 @@ impl Node {
             },
         );
 
-        let remote_responses = match self.remote.execute_many(&remote_requests).await {
-            Ok(responses) => responses,
+        // Remote requests are sent with their position in the batch as ID, as
+        // clients may reuse IDs within a batch. The client's IDs are restored
+        // in the responses.
+        let mut remote_requests = remote_requests;
+        let originals = remote_requests
+            .iter_mut()
+            .enumerate()
+            .map(|(index, request)| {
+                let id = mem::replace(&mut request.id, Id::Number(index.into()));
+                (request.jsonrpc, id)
+            })
+            .collect::<Vec<_>>();
+        let remote_responses = match self.upstreams.select().execute_many(&remote_requests).await {
+            Ok(responses) => {
+                let mut responses = match &self.archive {
+                    Some(archive) => {
+                        future::join_all(
+                            remote_requests
+                                .iter()
+                                .zip(responses)
+                                .map(|(request, response)| archive.retry(request, response)),
+                        )
+                        .await
+                    }
+                    None => responses,
+                };
+                let normalize = self.options().normalize_responses;
+                for (request, response) in remote_requests.iter().zip(&mut responses) {
+                    revert::annotate_response(&request.method, response, &self.selectors);
+                    if normalize {
+                        normalize::normalize_response(&request.method, response);
+                    }
+                }
+                responses
+            }
             Err(err) => {
                 tracing::debug!(
                     ?err,
                     ?remote_requests,
                     "error executing remote batched requests"
                 );
+                let err = jsonrpc::Error::from(err);
                 remote_requests
-                    .into_iter()
-                    .map(|request| Response {
-                        jsonrpc: request.jsonrpc,
-                        result: Err(jsonrpc::Error::internal_error()),
-                        id: request.id,
+                    .iter()
+                    .map(|_| Response {
+                        jsonrpc: JsonRpc::V2,
+                        result: Err(err.clone()),
+                        id: Id::Null,
                     })
                     .collect::<Vec<_>>()
             }
         };
+        let remote_responses = remote_responses
+            .into_iter()
+            .zip(originals)
+            .map(|(response, (jsonrpc, id))| Response {
+                jsonrpc,
+                id,
+                ..response
+            })
+            .collect::<Vec<_>>();
 
         let mut remote_responses = remote_responses.into_iter();
-        let responses = responses
+        let responses: Vec<Response> = responses
             .into_iter()
             .map(|response| {
                 response
@@ -144,6 +1069,13 @@ impl Node {
             "leftover remote response"
         );
 
+        if let Some(tap) = &self.tap {
+            for (request, response) in tapped.iter().zip(&responses) {
+                if let Some(request) = request {
+                    tap.record(request, response, caller.ip);
+                }
+            }
+        }
         responses
     }
 
@@ -152,68 +1084,339 @@ impl Node {
     ///
     /// This allows requests to either be completely proxied to the remote node
     /// or partially handled internally.
-    async fn mux(&self, request: Request) -> Outcome {
-        match self
-            .mux_handler(&request.method, request.params.clone())
+    ///
+    /// The request metadata is made available to signers while the request is
+    /// being handled.
+    async fn mux(&self, request: Request, caller: &Caller) -> Outcome {
+        let Request {
+            jsonrpc,
+            method,
+            params,
+            id,
+        } = request;
+        let context = RequestContext {
+            method: method.clone(),
+            id: id.clone(),
+            client_ip: caller.ip,
+            origin: caller.origin.clone(),
+            identity: caller.identity.clone(),
+            timestamp: clock::now(),
+        };
+        match context
+            .scope(self.mux_handler(&method, params, caller))
             .await
         {
             Ok(Handled::Internal(value)) => Outcome::Internal(Response {
-                jsonrpc: request.jsonrpc,
+                jsonrpc,
                 result: Ok(value),
-                id: request.id,
+                id,
             }),
             Ok(Handled::Remote(method, params)) => Outcome::Remote(Request {
-                jsonrpc: request.jsonrpc,
+                jsonrpc,
                 method,
                 params,
-                id: request.id,
+                id,
             }),
-            Err(err) => {
-                tracing::debug!(?request, "error processing request");
+            Err(mut err) => {
+                tracing::debug!(%method, ?id, "error processing request");
+                revert::annotate(&mut err, &self.selectors);
                 Outcome::Internal(Response {
-                    jsonrpc: request.jsonrpc,
+                    jsonrpc,
                     result: Err(err),
-                    id: request.id,
+                    id,
                 })
             }
         }
     }
 
+    /// Returns the options for filling transactions.
+    fn fill_options(&self) -> FillOptions {
+        let options = self.options();
+        FillOptions {
+            check_balance: options.balance_check,
+            max_block_lag: options.max_block_lag.map(Duration::from_secs),
+            ..FillOptions::default()
+        }
+    }
+
+    /// Returns the options for verifying typed data domains.
+    fn verify_options(&self) -> VerifyOptions {
+        let options = self.options();
+        VerifyOptions {
+            check_contract_code: options.typed_data_code_check,
+            check_domain_separator: options.typed_data_domain_check,
+        }
+    }
+
+    /// Fills, signs and submits a transaction, through the queue if enabled.
+    async fn send_transaction(&self, transaction: TransactionRequest) -> Result<Bytes<[u8; 32]>> {
+        let submit = |account, nonce, raw| self.submit(account, nonce, raw);
+        match &self.queue {
+            Some(queue) => {
+                queue
+                    .send(
+                        &self.signer,
+                        &self.remote,
+                        transaction,
+                        self.fill_options(),
+                        submit,
+                    )
+                    .await
+            }
+            None => {
+                let (account, transaction) =
+                    transaction.fill(&self.remote, self.fill_options()).await?;
+                let signature = self.signer.sign_transaction(account, &transaction)?;
+                let nonce = transaction.request().nonce.unwrap_or_default();
+                submit(account, nonce, transaction.encode(signature)).await
+            }
+        }
+    }
+
+    /// Submits a transaction signed by the node to the private relay, if any,
+    /// or to the remote node, journaling it first if enabled.
+    async fn submit(
+        &self,
+        account: Address,
+        nonce: Quantity<u64>,
+        transaction: Vec<u8>,
+    ) -> Result<Bytes<[u8; 32]>> {
+        commit();
+        if let Some(journal) = &self.journal {
+            journal.record(account, nonce, &transaction)?;
+        }
+        relay::submit(self.relay.as_deref(), &self.remote, transaction).await
+    }
+
+    /// Ensures that the specified account is allowed to sign raw hashes.
+    fn ensure_sign_hash_permitted(&self, account: Address) -> Result<()> {
+        let options = self.options();
+        if options.dev {
+            return Ok(());
+        }
+        ensure!(
+            options
+                .sign_hash_accounts
+                .iter()
+                .any(|allowed| allowed.0 == account),
+            "account {account} is not permitted to sign raw hashes",
+        );
+        Ok(())
+    }
+
+    /// Returns true if the sender of `eth_sendTransaction` parameters is an
+    /// impersonated account.
+    fn is_impersonated(&self, params: Option<&Params>) -> bool {
+        let impersonated = self.impersonated.lock().unwrap();
+        if impersonated.is_empty() {
+            return false;
+        }
+        params
+            .and_then(|params| params.parse::<(Map<String, Value>,)>().ok())
+            .and_then(|(transaction,)| {
+                serde_json::from_value::<Str<Address>>(transaction.get("from")?.clone()).ok()
+            })
+            .is_some_and(|sender| impersonated.contains(&sender.0))
+    }
+
+    /// Ensures that the specified account is allowed to sign a message,
+    /// rejecting 32-byte payloads that look like hashes unless permitted.
+    fn ensure_message_permitted(&self, account: Address, message: &[u8]) -> Result<()> {
+        let options = self.options();
+        if message.len() != 32 || options.allow_blind_signing {
+            return Ok(());
+        }
+        ensure!(
+            options
+                .blind_signing_accounts
+                .iter()
+                .any(|allowed| allowed.0 == account),
+            "account {account} is not permitted to sign 32-byte messages, \
+             which may be transaction or typed data hashes",
+        );
+        Ok(())
+    }
+
     /// Handler method for a particular request method and parameters.
     async fn mux_handler(
         &self,
         method: &str,
         params: Option<Params>,
+        caller: &Caller,
     ) -> Result<Handled, jsonrpc::Error> {
+        let session = caller.session.as_deref();
+        let options = self.options();
         match method {
+            // The Engine API drives the execution client from the consensus
+            // client, and must never be reachable through the proxy.
+            _ if method.starts_with("engine_") => {
+                tracing::warn!(%method, ip = ?caller.ip, "blocked Engine API request");
+                Err(jsonrpc::Error::method_not_found())
+            }
             "eth_accounts" => {
                 Handled::internal(params, |_: NoParameters| async {
                     Ok(Addresses(self.signer.accounts()))
                 })
                 .await
             }
+            "eth_requestAccounts" => {
+                Handled::internal(params, |_: NoParameters| async {
+                    let accounts = permissions::request_accounts(self.signer.accounts())?;
+                    if let Some(session) = session {
+                        session.grant_accounts(accounts);
+                    }
+                    Ok(Addresses(accounts))
+                })
+                .await
+            }
+            "wallet_getPermissions" => {
+                Handled::internal(params, |_: NoParameters| async {
+                    // Sessions only have the permissions they requested.
+                    Ok(match session {
+                        Some(session) => {
+                            permissions::get(&session.granted_accounts().unwrap_or_default())
+                        }
+                        None => permissions::get(self.signer.accounts()),
+                    })
+                })
+                .await
+            }
+            "wallet_requestPermissions" => {
+                Handled::internal(
+                    params,
+                    |(requested,): (BTreeMap<String, Value>,)| async move {
+                        let granted = permissions::request(&requested, self.signer.accounts())?;
+                        if let Some(session) = session {
+                            session.grant_accounts(self.signer.accounts());
+                        }
+                        Ok(granted)
+                    },
+                )
+                .await
+            }
+            "wallet_switchEthereumChain" | "wallet_addEthereumChain" => {
+                Handled::internal(params, |(chain,): (ChainParameters,)| async {
+                    let chain_id = chain::switch(&self.remote, chain).await?;
+                    if let Some(session) = session {
+                        session.select_chain(chain_id);
+                    }
+                    Ok(Value::Null)
+                })
+                .await
+            }
+            "hdnode_session" => match session {
+                Some(session) => {
+                    Handled::internal(params, |_: NoParameters| async { Ok(session.status()) })
+                        .await
+                }
+                None => Err(jsonrpc::Error::method_not_found()),
+            },
+            "hdnode_hasAccount" => {
+                Handled::internal(params, |(account,): (String,)| async move {
+                    // Accept addresses regardless of casing, so clients with
+                    // different checksum conventions can query accounts.
+                    let account = account
+                        .to_lowercase()
+                        .parse::<Address>()
+                        .map_err(|err| anyhow!("invalid address: {err}"))?;
+                    Ok(self.signer.accounts().contains(&account))
+                })
+                .await
+            }
+            "hdnode_getPublicKey" => {
+                Handled::internal(params, |(account,): (Address,)| async move {
+                    Ok(Bytes(self.signer.public_key(account)?))
+                })
+                .await
+            }
+            "hdnode_hashMessage" => {
+                Handled::internal(params, |(data,): (Bytes<Vec<u8>>,)| async move {
+                    Ok(Bytes(EthereumMessage(&data).signing_message()))
+                })
+                .await
+            }
+            "hdnode_recoverAddress" => {
+                Handled::internal(
+                    params,
+                    |(data, signature): (Bytes<Vec<u8>>, Bytes<Vec<u8>>)| async move {
+                        let signature = recovery::parse_signature(&signature)?;
+                        let hash = EthereumMessage(&data).signing_message();
+                        Ok(Str(recovery::recover_address(hash, &signature)?))
+                    },
+                )
+                .await
+            }
+            "eth_fillTransaction" => {
+                Handled::internal(params, |(transaction,): (TransactionRequest,)| async {
+                    let (account, transaction) =
+                        transaction.fill(&self.remote, self.fill_options()).await?;
+                    Ok(FilledTransaction::new(account, transaction))
+                })
+                .await
+            }
+            // Transactions from impersonated accounts are sent unsigned, for the
+            // development node to execute on their behalf.
+            "eth_sendTransaction" if options.dev && self.is_impersonated(params.as_ref()) => {
+                Ok(Handled::Remote(method.to_owned(), params))
+            }
+            "eth_sendTransaction" if self.queue.is_some() || self.relay.is_some() => {
+                Handled::internal(params, |(transaction,): (TransactionRequest,)| async {
+                    Ok(self.send_transaction(transaction).await?)
+                })
+                .await
+            }
             "eth_sendTransaction" | "eth_signTransaction" => {
                 let signed_transaction =
                     Handled::internal(params, |(transaction,): (TransactionRequest,)| async {
-                        let (account, transaction) = transaction.fill(&self.remote).await?;
+                        let (account, transaction) =
+                            transaction.fill(&self.remote, self.fill_options()).await?;
                         let signature = self.signer.sign_transaction(account, &transaction)?;
-                        Ok(Bytes(transaction.encode(signature)))
+                        let raw = transaction.encode(signature);
+                        if method == "eth_sendTransaction" {
+                            commit();
+                        }
+                        if let (Some(journal), "eth_sendTransaction") = (&self.journal, method) {
+                            let nonce = transaction.request().nonce.unwrap_or_default();
+                            journal.record(account, nonce, &raw)?;
+                        }
+                        Ok(Bytes(raw))
                     })
                     .await?;
 
                 if method == "eth_sendTransaction" {
                     Ok(Handled::Remote(
                         "eth_sendRawTransaction".to_owned(),
-                        Some(Params::Array(vec![signed_transaction
-                            .into_internal()
-                            .unwrap()])),
+                        Some(Params::new([signed_transaction.into_internal().unwrap()])?),
                     ))
                 } else {
                     Ok(signed_transaction)
                 }
             }
+            "eth_sendBundle" => match &self.relay {
+                Some(relay) => {
+                    Handled::internal(params, |(bundle,): (BundleRequest,)| async move {
+                        let bundle = bundle
+                            .sign(&self.signer, &self.remote, self.fill_options())
+                            .await?;
+                        Ok(relay.send_bundle(&bundle).await?)
+                    })
+                    .await
+                }
+                None => Err(jsonrpc::Error::method_not_found()),
+            },
             "eth_sign" => {
                 Handled::internal(params, |(account, data): (_, Bytes<Vec<_>>)| async move {
+                    self.ensure_message_permitted(account, &data)?;
+                    Ok(Bytes::from_signature(
+                        self.signer.sign_message(account, &data)?,
+                    ))
+                })
+                .await
+            }
+            // Same as `eth_sign`, but with the parameters swapped.
+            "personal_sign" => {
+                Handled::internal(params, |(data, account): (Bytes<Vec<_>>, _)| async move {
+                    self.ensure_message_permitted(account, &data)?;
                     Ok(Bytes::from_signature(
                         self.signer.sign_message(account, &data)?,
                     ))
@@ -221,14 +1424,222 @@ impl Node {
                 .await
             }
             "eth_signTypedData" => {
-                Handled::internal(params, |(account, typed_data): (_, TypedData)| async move {
-                    typed_data.verify(&self.remote).await?;
+                Handled::internal(params, |(account, typed_data): (_, Value)| async move {
+                    let typed_data = TypedData::from_value(typed_data)?;
+                    typed_data
+                        .verify(&self.remote, self.verify_options())
+                        .await?;
                     Ok(Bytes::from_signature(
                         self.signer.sign_typed_data(account, &typed_data)?,
                     ))
                 })
                 .await
             }
+            // Hardhat balance manipulation methods are also supported by Anvil.
+            "hdnode_setBalance" if options.dev => {
+                Ok(Handled::Remote("hardhat_setBalance".to_owned(), params))
+            }
+            "hdnode_faucet" if options.dev => {
+                let (account,) = params
+                    .and_then(|params| params.parse::<(Str<Address>,)>().ok())
+                    .ok_or_else(jsonrpc::Error::invalid_params)?;
+                Ok(Handled::Remote(
+                    "hardhat_setBalance".to_owned(),
+                    Some(Params::new((account, Quantity(FAUCET_BALANCE)))?),
+                ))
+            }
+            "hdnode_impersonateAccount" | "hdnode_stopImpersonatingAccount" if options.dev => {
+                Handled::internal(params, |(account,): (Str<Address>,)| async move {
+                    let impersonate = method == "hdnode_impersonateAccount";
+                    self.remote
+                        .impersonate_account(account.0, impersonate)
+                        .await?;
+                    let mut impersonated = self.impersonated.lock().unwrap();
+                    if impersonate {
+                        impersonated.insert(account.0);
+                    } else {
+                        impersonated.remove(&account.0);
+                    }
+                    Ok(true)
+                })
+                .await
+            }
+            "hdnode_pauseSigning" | "hdnode_resumeSigning" => {
+                ensure_admin(caller)?;
+                Handled::internal(params, |_: NoParameters| async move {
+                    let paused = method == "hdnode_pauseSigning";
+                    self.signer.set_paused(paused);
+                    if paused {
+                        tracing::warn!("signing paused");
+                    } else {
+                        tracing::warn!("signing resumed");
+                    }
+                    Ok(true)
+                })
+                .await
+            }
+            "hdnode_reloadConfig" if self.reloader.is_some() => {
+                ensure_admin(caller)?;
+                Handled::internal(params, |_: NoParameters| async move {
+                    self.reload_config()?;
+                    Ok(true)
+                })
+                .await
+            }
+            "hdnode_quotas" => match &self.quotas {
+                Some(quotas) => {
+                    ensure_admin(caller)?;
+                    Handled::internal(params, |_: NoParameters| async move { Ok(quotas.usage()) })
+                        .await
+                }
+                None => Err(jsonrpc::Error::method_not_found()),
+            },
+            "hdnode_queue" => match &self.queue {
+                Some(queue) => {
+                    ensure_admin(caller)?;
+                    Handled::internal(params, |_: NoParameters| async move { Ok(queue.status()) })
+                        .await
+                }
+                None => Err(jsonrpc::Error::method_not_found()),
+            },
+            "hdnode_getSigningReceipt" => match &self.receipts {
+                Some(receipts) => {
+                    Handled::internal(params, |(signature,): (String,)| async move {
+                        Ok(receipts.get(&signature))
+                    })
+                    .await
+                }
+                None => Err(jsonrpc::Error::method_not_found()),
+            },
+            "hdnode_verifyAuditLog" => match &self.audit {
+                Some(audit) => {
+                    ensure_admin(caller)?;
+                    Handled::internal(params, |_: NoParameters| async move { Ok(audit.verify()?) })
+                        .await
+                }
+                None => Err(jsonrpc::Error::method_not_found()),
+            },
+            "hdnode_stats" => {
+                Handled::internal(params, |_: NoParameters| async {
+                    Ok(self.metrics().stats())
+                })
+                .await
+            }
+            "hdnode_signHash" if options.sign_hash || options.dev => {
+                Handled::internal(
+                    params,
+                    |(account, hash): (Address, Bytes<[u8; 32]>)| async move {
+                        self.ensure_sign_hash_permitted(account)?;
+                        Ok(Bytes::from_signature(
+                            self.signer.sign_hash(account, hash.0)?,
+                        ))
+                    },
+                )
+                .await
+            }
+            "hdnode_signHash" => Err(jsonrpc::Error::method_not_found()),
+            "hdnode_previewTransaction" => {
+                Handled::internal(params, |(transaction,): (TransactionRequest,)| async {
+                    Ok(preview::preview(
+                        &self.signer,
+                        &self.remote,
+                        transaction,
+                        self.fill_options(),
+                        &self.selectors,
+                    )
+                    .await?)
+                })
+                .await
+            }
+            "hdnode_signPermit" => {
+                Handled::internal(params, |(account, permit): (_, PermitRequest)| async move {
+                    let typed_data = permit.typed_data(&self.remote, account).await?;
+                    Ok(Bytes::from_signature(
+                        self.signer.sign_typed_data(account, &typed_data)?,
+                    ))
+                })
+                .await
+            }
+            "hdnode_signUserOperation" => {
+                Handled::internal(
+                    params,
+                    |(account, user_operation): (_, UserOperationRequest)| async move {
+                        let entry_point = options
+                            .entry_point
+                            .as_deref()
+                            .copied()
+                            .context("user operation signing requires an entry point")?;
+                        let user_operation =
+                            user_operation.prepare(&self.remote, entry_point).await?;
+                        Ok(Bytes::from_signature(
+                            self.signer.sign_user_operation(account, &user_operation)?,
+                        ))
+                    },
+                )
+                .await
+            }
+            "eth_newFilter" if options.local_filters => {
+                Handled::internal(params, |(criteria,): (Map<String, Value>,)| async move {
+                    Ok(self
+                        .filters
+                        .new_filter(self.upstreams.select(), criteria)
+                        .await?)
+                })
+                .await
+            }
+            "eth_newBlockFilter" if options.local_filters => {
+                Handled::internal(params, |_: NoParameters| async move {
+                    Ok(self
+                        .filters
+                        .new_block_filter(self.upstreams.select())
+                        .await?)
+                })
+                .await
+            }
+            "eth_newPendingTransactionFilter" if options.local_filters => {
+                Err(jsonrpc::Error::method_not_found())
+            }
+            "eth_getFilterChanges" if options.local_filters => {
+                Handled::internal(params, |(id,): (String,)| async move {
+                    Ok(self
+                        .filters
+                        .get_filter_changes(self.upstreams.select(), &id)
+                        .await?)
+                })
+                .await
+            }
+            "eth_getFilterLogs" if options.local_filters => {
+                Handled::internal(params, |(id,): (String,)| async move {
+                    Ok(self
+                        .filters
+                        .get_filter_logs(self.upstreams.select(), &id)
+                        .await?)
+                })
+                .await
+            }
+            "eth_uninstallFilter" if options.local_filters => {
+                Handled::internal(params, |(id,): (String,)| async move {
+                    Ok(self.filters.uninstall_filter(&id))
+                })
+                .await
+            }
+
+            // Filters are local to the remote node that installed them, so
+            // filter calls need to be routed to the same remote node.
+            "eth_newFilter" | "eth_newBlockFilter" | "eth_newPendingTransactionFilter"
+                if self.upstreams.is_load_balanced() =>
+            {
+                Ok(Handled::Internal(
+                    self.upstreams.new_filter(method, params).await?,
+                ))
+            }
+            "eth_getFilterChanges" | "eth_getFilterLogs" | "eth_uninstallFilter"
+                if self.upstreams.is_load_balanced() =>
+            {
+                Ok(Handled::Internal(
+                    self.upstreams.filter_call(method, params).await?,
+                ))
+            }
 
             _ => Ok(Handled::Remote(method.to_owned(), params)),
         }
@@ -249,7 +1660,7 @@ enum Outcome {
 enum Handled {
     /// Request was handled internally my the node and produced the following
     /// result value.
-    Internal(Value),
+    Internal(Box<RawValue>),
 
     /// Request was either partially handled or not handled at all by the node.
     /// The specified request method and parameters must be forwarded to the
@@ -266,14 +1677,17 @@ impl Handled {
         F: FnOnce(T) -> Fut,
         Fut: Future<Output = Result<U, jsonrpc::Error>>,
     {
-        let params = params.map(Value::from).unwrap_or(Value::Null);
-        let params = T::deserialize(params).map_err(|err| {
+        let params = match params {
+            Some(params) => params.parse(),
+            None => T::deserialize(Value::Null),
+        }
+        .map_err(|err| {
             tracing::debug!(?err, "failed to deserialize parameters");
             jsonrpc::Error::invalid_params()
         })?;
 
         let value = f(params).await?;
-        let value = json::serde_json::to_value(&value).map_err(|err| {
+        let value = serde_json::value::to_raw_value(&value).map_err(|err| {
             tracing::error!(?err, "unexpected error serializing response JSON");
             jsonrpc::Error::internal_error()
         })?;
@@ -283,7 +1697,7 @@ impl Handled {
 
     /// Returns the result value if it was handled internally or `None`
     /// otherwise.
-    fn into_internal(self) -> Option<Value> {
+    fn into_internal(self) -> Option<Box<RawValue>> {
         match self {
             Self::Internal(value) => Some(value),
             _ => None,
@@ -296,10 +1710,336 @@ impl From<anyhow::Error> for jsonrpc::Error {
         tracing::debug!(?err, "encountered error");
         if let Some(err) = err.downcast_ref::<jsonrpc::Error>() {
             err.clone()
-        } else if err.downcast_ref::<UnknownSignerError>().is_some() {
-            jsonrpc::Error::invalid_params()
+        } else if let Some(err) = err.downcast_ref::<ResponseTooLargeError>() {
+            jsonrpc::Error {
+                data: Some(Value::String(err.to_string())),
+                ..jsonrpc::Error::internal_error()
+            }
+        } else if err.is::<SigningPausedError>() {
+            jsonrpc::Error {
+                code: -32010,
+                message: "Signing paused".to_owned(),
+                data: None,
+            }
+        } else if let Some(err) = err.downcast_ref::<QuotaExceededError>() {
+            jsonrpc::Error {
+                code: -32005,
+                message: "Limit exceeded".to_owned(),
+                data: Some(Value::String(err.to_string())),
+            }
+        } else if let Some(err) = err.downcast_ref::<IdentityQuotaExceededError>() {
+            jsonrpc::Error {
+                code: -32005,
+                message: "Limit exceeded".to_owned(),
+                data: Some(Value::String(err.to_string())),
+            }
+        } else if let Some(err) = err.downcast_ref::<QueueFullError>() {
+            jsonrpc::Error {
+                code: -32005,
+                message: "Limit exceeded".to_owned(),
+                data: Some(Value::String(err.to_string())),
+            }
+        } else if let Some(err) = err.downcast_ref::<OutOfSyncError>() {
+            jsonrpc::Error {
+                code: -32002,
+                message: "Resource unavailable".to_owned(),
+                data: Some(Value::String(err.to_string())),
+            }
+        } else if let Some(err) = err.downcast_ref::<InsufficientFundsError>() {
+            // Use the same error as Geth, so that clients handle it the same
+            // way as when submitting the transaction to the remote node.
+            jsonrpc::Error {
+                code: -32000,
+                message: err.to_string(),
+                data: None,
+            }
+        } else if let Some(err) = err.downcast_ref::<InvalidTypedDataError>() {
+            jsonrpc::Error {
+                data: Some(Value::String(err.to_string())),
+                ..jsonrpc::Error::invalid_params()
+            }
+        } else if let Some(err) = err.downcast_ref::<DomainMismatchError>() {
+            jsonrpc::Error {
+                data: Some(Value::String(err.to_string())),
+                ..jsonrpc::Error::invalid_params()
+            }
+        } else if let Some(err) = err.downcast_ref::<StaleNonceError>() {
+            jsonrpc::Error {
+                code: -32000,
+                message: err.to_string(),
+                data: None,
+            }
+        } else if let Some(err) = err.downcast_ref::<NotApprovedError>() {
+            jsonrpc::Error {
+                code: 4001,
+                message: "User rejected the request.".to_owned(),
+                data: Some(Value::String(err.to_string())),
+            }
+        } else if let Some(err) = err.downcast_ref::<UnrecognizedChainError>() {
+            jsonrpc::Error {
+                code: 4902,
+                message: "Unrecognized chain ID.".to_owned(),
+                data: Some(Value::String(err.to_string())),
+            }
+        } else if let Some(err) = err.downcast_ref::<UnauthorizedError>() {
+            jsonrpc::Error {
+                code: 4100,
+                message: "The requested account and/or method has not been authorized by the user."
+                    .to_owned(),
+                data: Some(Value::String(err.to_string())),
+            }
+        } else if err.is::<NotInScopeError>() || err.is::<IdentityRequiredError>() {
+            jsonrpc::Error {
+                code: 4100,
+                message: "The requested account and/or method has not been authorized by the user."
+                    .to_owned(),
+                data: Some(Value::String(err.to_string())),
+            }
+        } else if let Some(err) = err.downcast_ref::<UnknownSignerError>() {
+            jsonrpc::Error {
+                data: Some(Value::String(err.to_string())),
+                ..jsonrpc::Error::invalid_params()
+            }
         } else {
             jsonrpc::Error::internal_error()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{call, client, client_with, MockUpstream, ACCOUNT};
+    use rocket::{
+        http::Header,
+        serde::json::{serde_json::json, Value},
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn caller_origin() {
+        let origin = |origin, referer| Caller::new(None, origin, referer).origin;
+        assert_eq!(
+            origin(Some("https://App.example.com"), None).as_deref(),
+            Some("https://app.example.com"),
+        );
+        assert_eq!(
+            origin(None, Some("https://app.example.com:8443/swap?token=1")).as_deref(),
+            Some("https://app.example.com:8443"),
+        );
+        assert_eq!(origin(Some("null"), None), None);
+        assert_eq!(origin(None, None), None);
+    }
+
+    #[test]
+    fn input() {
+        let request = r#"{"jsonrpc":"2.0","method":"foo","params":[1],"id":1}"#;
+        assert!(matches!(
+            serde_json::from_str::<Input>(request).unwrap(),
+            Input::Request(request) if request.params.as_ref().unwrap().get() == "[1]",
+        ));
+        assert!(matches!(
+            serde_json::from_str::<Input>(&format!("[{request},{request}]")).unwrap(),
+            Input::Batch(requests) if requests.len() == 2,
+        ));
+        assert!(matches!(
+            serde_json::from_str::<Input>(&format!(r#"[{request},{{"method":"foo","id":2}}]"#))
+                .unwrap(),
+            Input::Batch(entries) if matches!(
+                &entries[..],
+                [Ok(_), Err(Response { id: Id::Number(id), .. })] if id.as_u64() == Some(2),
+            ),
+        ));
+        assert!(matches!(
+            serde_json::from_str::<Input>("[]").unwrap(),
+            Input::Rejected(_),
+        ));
+        assert!(matches!(
+            serde_json::from_str::<Input>(r#"{"foo":"bar"}"#).unwrap(),
+            Input::Unrecognized(_),
+        ));
+    }
+
+    #[test]
+    fn json_depth() {
+        assert_eq!(super::json_depth(r#""[{""#), 0);
+        assert_eq!(super::json_depth(r#"{"a":[1,{"b":"]\"["}],"c":[]}"#), 3);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_transactions_to_destinations_not_allowed() {
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment.merge((
+                "allowed_destinations",
+                ["0x3535353535353535353535353535353535353535"],
+            ))
+        })
+        .await;
+        upstream.respond("eth_getTransactionCount", json!("0x0"));
+
+        let transaction = |to: Option<&str>| {
+            json!([{
+                "from": ACCOUNT,
+                "to": to,
+                "gas": "0x5208",
+                "gasPrice": "0x1",
+            }])
+        };
+        let allowed = call(
+            &client,
+            "eth_signTransaction",
+            transaction(Some("0x3535353535353535353535353535353535353535")),
+        )
+        .await;
+        let denied = call(
+            &client,
+            "eth_signTransaction",
+            transaction(Some("0x4242424242424242424242424242424242424242")),
+        )
+        .await;
+        let creation = call(&client, "eth_signTransaction", transaction(None)).await;
+
+        assert!(allowed["result"].is_string());
+        assert!(denied["error"].is_object());
+        assert!(creation["error"].is_object());
+    }
+
+    #[rocket::async_test]
+    async fn times_out_stalled_requests() {
+        let upstream = MockUpstream::start().await;
+        let configured =
+            client_with(&upstream, |figment| figment.merge(("request_timeout", 100))).await;
+        let requested = client(&upstream).await;
+        upstream.respond("eth_blockNumber", json!("0x1"));
+        upstream.set_latency(Duration::from_secs(1));
+
+        let timed_out = call(&configured, "eth_blockNumber", json!([])).await;
+        assert_eq!(timed_out["error"]["code"], -32000);
+        assert_eq!(timed_out["id"], 1);
+
+        let timed_out = requested
+            .post("/")
+            .header(Header::new("X-Request-Timeout", "100"))
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "method": "eth_blockNumber",
+                "params": [],
+                "id": 1,
+            }))
+            .dispatch()
+            .await
+            .into_json::<Value>()
+            .await
+            .unwrap();
+        assert_eq!(timed_out["error"]["code"], -32000);
+    }
+
+    #[rocket::async_test]
+    async fn completes_sent_transactions_after_timeouts() {
+        let upstream = MockUpstream::start().await;
+        let client =
+            client_with(&upstream, |figment| figment.merge(("request_timeout", 300))).await;
+        upstream.respond("eth_getTransactionCount", json!("0x0"));
+        upstream.respond("eth_sendRawTransaction", json!("0x42"));
+        upstream.set_latency(Duration::from_millis(200));
+
+        // Filling the transaction takes a single call to the remote node, so
+        // that the timeout expires while broadcasting it.
+        let sent = call(
+            &client,
+            "eth_sendTransaction",
+            json!([{
+                "from": ACCOUNT,
+                "to": "0x3535353535353535353535353535353535353535",
+                "gas": "0x5208",
+                "gasPrice": "0x1",
+                "nonce": "0x0",
+                "chainId": "0x1",
+            }]),
+        )
+        .await;
+        assert_eq!(sent["result"], "0x42");
+    }
+
+    #[rocket::async_test]
+    async fn rejects_blind_signing() {
+        let upstream = MockUpstream::start().await;
+        let hash = json!([ACCOUNT, format!("0x{}", "42".repeat(32))]);
+
+        let swapped = json!([hash[1], hash[0]]);
+
+        let client = client(&upstream).await;
+        let denied = call(&client, "eth_sign", hash.clone()).await;
+        assert!(denied["error"].is_object());
+        let denied = call(&client, "personal_sign", swapped.clone()).await;
+        assert!(denied["error"].is_object());
+
+        let client = client_with(&upstream, |figment| {
+            figment.merge(("blind_signing_accounts", json!([ACCOUNT])))
+        })
+        .await;
+        let signed = call(&client, "eth_sign", hash).await;
+        assert!(signed["result"].is_string());
+        let signed = call(&client, "personal_sign", swapped).await;
+        assert!(signed["result"].is_string());
+    }
+
+    #[rocket::async_test]
+    async fn blocks_engine_api() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+        upstream.respond("engine_exchangeCapabilities", json!([]));
+
+        let response = call(&client, "engine_exchangeCapabilities", json!([[]])).await;
+        assert_eq!(response["error"]["code"], -32601);
+        assert!(!upstream
+            .methods()
+            .contains(&"engine_exchangeCapabilities".to_owned()));
+    }
+
+    #[rocket::async_test]
+    async fn limits_request_bodies() {
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment
+                .merge(("max_request_size", 100))
+                .merge(("max_json_depth", 3))
+        })
+        .await;
+
+        let response = call(&client, "eth_accounts", json!([])).await;
+        assert_eq!(response["result"][0], ACCOUNT);
+        let response = call(&client, "eth_call", json!([{ "data": "0x".repeat(50) }])).await;
+        assert_eq!(response["error"]["code"], -32700);
+        let response = call(&client, "eth_call", json!([[[[]]]])).await;
+        assert_eq!(response["error"]["code"], -32700);
+        assert_eq!(response["error"]["data"], "JSON nesting exceeds depth 3");
+    }
+
+    #[rocket::async_test]
+    async fn sends_transactions_from_impersonated_accounts() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+        upstream.respond("hardhat_impersonateAccount", json!(true));
+        upstream.respond("hardhat_stopImpersonatingAccount", json!(true));
+        upstream.respond(
+            "eth_sendTransaction",
+            json!(format!("0x{}", "11".repeat(32))),
+        );
+
+        let whale = "0x00000000219ab540356cbb839cbe05303d7705fa";
+        let transaction = json!([{ "from": whale, "to": ACCOUNT, "value": "0x1" }]);
+        let response = call(&client, "hdnode_impersonateAccount", json!([whale])).await;
+        assert_eq!(response["result"], true);
+        let response = call(&client, "eth_sendTransaction", transaction.clone()).await;
+        assert_eq!(response["result"], format!("0x{}", "11".repeat(32)));
+        let request = upstream.requests().pop().unwrap();
+        assert_eq!(request["method"], "eth_sendTransaction");
+        assert_eq!(request["params"], transaction);
+
+        call(&client, "hdnode_stopImpersonatingAccount", json!([whale])).await;
+        let response = call(&client, "eth_sendTransaction", transaction).await;
+        assert!(response["error"].is_object());
+    }
+}
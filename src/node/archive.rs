@@ -0,0 +1,131 @@
+//! Archive node fallback.
+//!
+//! Full nodes prune historical state, and fail requests reading state at old
+//! blocks. When an archive node is configured, such requests are retried on it
+//! before the error is returned to the client.
+
+use crate::jsonrpc::{self, Request, Response};
+
+/// Methods that read state at a block, which pruned nodes can't serve for old
+/// blocks.
+const METHODS: &[&str] = &[
+    "eth_call",
+    "eth_createAccessList",
+    "eth_estimateGas",
+    "eth_getBalance",
+    "eth_getCode",
+    "eth_getProof",
+    "eth_getStorageAt",
+    "eth_getTransactionCount",
+    "debug_traceBlockByHash",
+    "debug_traceBlockByNumber",
+    "debug_traceCall",
+    "debug_traceTransaction",
+];
+
+/// Error messages of the various clients for pruned state, in lowercase.
+const PRUNED_STATE_ERRORS: &[&str] = &[
+    "missing trie node",
+    "historical state",
+    "state is not available",
+    "state not available",
+    "world state unavailable",
+    "pruned",
+];
+
+/// An archive node that pruned state reads fall back to.
+pub struct Archive {
+    client: jsonrpc::Client,
+}
+
+impl Archive {
+    /// Creates a new archive fallback using the specified client.
+    pub fn new(client: jsonrpc::Client) -> Self {
+        Self { client }
+    }
+
+    /// Returns true if requests for the method fall back to the archive node.
+    pub fn falls_back(method: &str) -> bool {
+        METHODS.contains(&method)
+    }
+
+    /// Retries a request on the archive node if its response is a pruned
+    /// state error, returning the archive node's response instead.
+    pub async fn retry(&self, request: &Request, response: Response) -> Response {
+        match &response.result {
+            Err(err) if Self::falls_back(&request.method) && is_pruned(err) => {}
+            _ => return response,
+        }
+        tracing::debug!(method = %request.method, "retrying pruned state read on archive node");
+        match self.client.execute(request).await {
+            Ok(archived) => archived,
+            Err(err) => {
+                tracing::debug!(?err, ?request, "error executing archive request");
+                response
+            }
+        }
+    }
+}
+
+/// Returns true if the error indicates that the state is pruned.
+fn is_pruned(err: &jsonrpc::Error) -> bool {
+    let message = err.message.to_lowercase();
+    PRUNED_STATE_ERRORS
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{call, client_with, MockUpstream, ACCOUNT};
+    use rocket::serde::json::{serde_json::json, Value};
+
+    #[test]
+    fn detects_pruned_state_errors() {
+        let error = |message: &str| jsonrpc::Error {
+            code: -32000,
+            message: message.to_owned(),
+            data: None,
+        };
+        assert!(is_pruned(&error(
+            "missing trie node 5ad5b4... (path ) state 0x5ad5b4... is not available"
+        )));
+        assert!(is_pruned(&error("State not available for block 123")));
+        assert!(!is_pruned(&error("execution reverted")));
+    }
+
+    #[rocket::async_test]
+    async fn retries_pruned_state_reads_on_archive_node() {
+        let upstream = MockUpstream::start().await;
+        let archive = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment.merge(("archive_node_url", archive.url().as_str()))
+        })
+        .await;
+        upstream.fail(
+            "eth_getBalance",
+            -32000,
+            "missing trie node 0x5ad5 (path ) state 0x5ad5 is not available",
+        );
+        archive.respond("eth_getBalance", json!("0x2a"));
+
+        let response = call(&client, "eth_getBalance", json!([ACCOUNT, "0x1"])).await;
+        assert_eq!(response["result"], "0x2a");
+
+        let batch = json!([
+            { "jsonrpc": "2.0", "method": "eth_getBalance", "params": [ACCOUNT, "0x1"], "id": 7 },
+        ]);
+        let response = client
+            .post("/")
+            .json(&batch)
+            .dispatch()
+            .await
+            .into_json::<Value>()
+            .await
+            .unwrap();
+        assert_eq!(response[0]["result"], "0x2a");
+        assert_eq!(response[0]["id"], 7);
+        assert_eq!(archive.methods(), ["eth_getBalance", "eth_getBalance"]);
+    }
+}
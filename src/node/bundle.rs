@@ -0,0 +1,101 @@
+//! Flashbots bundles for `eth_sendBundle` RPC calls.
+//!
+//! Bundles are lists of transactions that are included atomically and in
+//! order in a target block, or not at all. In addition to the pre-signed raw
+//! transactions accepted by relays, bundle requests may contain transaction
+//! requests for accounts managed by the node, which are filled and signed with
+//! consecutive nonces before the bundle is forwarded to the relay.
+
+use crate::{
+    node::{
+        eth::Eth,
+        transaction::{FillOptions, TransactionRequest},
+    },
+    serialization::{Bytes, Quantity},
+    signer::Signing,
+};
+use anyhow::Result;
+use rocket::serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Bundle parameters for `eth_sendBundle` RPC calls.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct BundleRequest {
+    /// The transactions of the bundle, in order.
+    pub txs: Vec<BundleTransaction>,
+    /// The block that the bundle is valid for.
+    pub block_number: Quantity,
+    /// The minimum timestamp for which the bundle is valid.
+    #[serde(default)]
+    pub min_timestamp: Option<u64>,
+    /// The maximum timestamp for which the bundle is valid.
+    #[serde(default)]
+    pub max_timestamp: Option<u64>,
+    /// The hashes of transactions that are allowed to revert.
+    #[serde(default)]
+    pub reverting_tx_hashes: Vec<Bytes<[u8; 32]>>,
+}
+
+/// A transaction in a bundle request.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", untagged)]
+pub enum BundleTransaction {
+    /// A signed raw transaction.
+    Signed(Bytes<Vec<u8>>),
+    /// A transaction to sign with an account managed by the node.
+    Request(Box<TransactionRequest>),
+}
+
+/// A bundle of signed transactions, as forwarded to the relay.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Bundle {
+    /// The signed transactions of the bundle, in order.
+    pub txs: Vec<Bytes<Vec<u8>>>,
+    /// The block that the bundle is valid for.
+    pub block_number: Quantity,
+    /// The minimum timestamp for which the bundle is valid.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_timestamp: Option<u64>,
+    /// The maximum timestamp for which the bundle is valid.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_timestamp: Option<u64>,
+    /// The hashes of transactions that are allowed to revert.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reverting_tx_hashes: Vec<Bytes<[u8; 32]>>,
+}
+
+impl BundleRequest {
+    /// Fills and signs the transaction requests of the bundle. Transactions
+    /// from the same account get consecutive nonces, since none of them are
+    /// included in a block before the bundle is.
+    pub async fn sign(self, signer: &impl Signing, eth: &Eth, fill: FillOptions) -> Result<Bundle> {
+        let mut nonces = HashMap::<[u8; 20], u64>::new();
+        let mut txs = Vec::with_capacity(self.txs.len());
+        for transaction in self.txs {
+            let raw = match transaction {
+                BundleTransaction::Signed(raw) => raw,
+                BundleTransaction::Request(request) => {
+                    let next_nonce = nonces.get(&request.from.0 .0).copied();
+                    let (account, transaction) = request
+                        .fill(eth, FillOptions { next_nonce, ..fill })
+                        .await?;
+                    let signature = signer.sign_transaction(account, &transaction)?;
+                    let nonce = transaction.request().nonce.unwrap_or_default();
+                    nonces.insert(account.0, nonce.0 + 1);
+                    Bytes(transaction.encode(signature))
+                }
+            };
+            txs.push(raw);
+        }
+
+        Ok(Bundle {
+            txs,
+            block_number: self.block_number,
+            min_timestamp: self.min_timestamp,
+            max_timestamp: self.max_timestamp,
+            reverting_tx_hashes: self.reverting_tx_hashes,
+        })
+    }
+}
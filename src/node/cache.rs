@@ -0,0 +1,95 @@
+//! Short-lived cache for frequently polled chain values.
+//!
+//! High-frequency pollers hammer `eth_blockNumber`, `eth_gasPrice` and
+//! `eth_maxPriorityFeePerGas`, whose results only change from block to block.
+//! Their results are cached for a short time, both for proxied requests and
+//! for filling transactions.
+//!
+//! Results are kept in the shared state backend, so that replicas behind a
+//! load balancer share them.
+
+use crate::backend::SharedBackend;
+use ethnum::U256;
+use std::time::Duration;
+
+/// The cached methods, none of which take parameters.
+const METHODS: &[&str] = &[
+    "eth_blockNumber",
+    "eth_gasPrice",
+    "eth_maxPriorityFeePerGas",
+];
+
+/// A cache of recent quantity results by method.
+pub struct Cache {
+    ttl: Duration,
+    backend: SharedBackend,
+}
+
+impl Cache {
+    /// Creates a new cache keeping results in the specified backend for the
+    /// specified duration.
+    pub fn new(ttl: Duration, backend: SharedBackend) -> Self {
+        Self { ttl, backend }
+    }
+
+    /// Returns the cached method matching the specified method name, if it is
+    /// cached.
+    pub fn method(method: &str) -> Option<&'static str> {
+        METHODS.iter().copied().find(|cached| *cached == method)
+    }
+
+    /// Returns the result for a method if it is still fresh. Backend errors
+    /// are treated as cache misses.
+    pub fn get(&self, method: &str) -> Option<U256> {
+        let value = match self.backend.get(&key(method)) {
+            Ok(value) => value?,
+            Err(err) => {
+                tracing::warn!(?err, %method, "failed to read cached result");
+                return None;
+            }
+        };
+        U256::from_str_radix(value.strip_prefix("0x")?, 16).ok()
+    }
+
+    /// Caches the result for a method.
+    pub fn insert(&self, method: &'static str, value: U256) {
+        if let Err(err) = self
+            .backend
+            .set(&key(method), &format!("{value:#x}"), Some(self.ttl))
+        {
+            tracing::warn!(?err, %method, "failed to cache result");
+        }
+    }
+}
+
+/// Returns the backend key for a cached method.
+fn key(method: &str) -> String {
+    format!("hdnode:cache:{method}")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{call, client_with, MockUpstream};
+    use rocket::serde::json::serde_json::json;
+
+    #[rocket::async_test]
+    async fn caches_polled_quantities() {
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| figment.merge(("cache_ttl", 60_000))).await;
+        upstream.respond("eth_gasPrice", json!("0x2a"));
+
+        let first = call(&client, "eth_gasPrice", json!([])).await;
+        let second = call(&client, "eth_gasPrice", json!([])).await;
+
+        assert_eq!(first["result"], "0x2a");
+        assert_eq!(second["result"], "0x2a");
+        assert_eq!(
+            upstream
+                .methods()
+                .iter()
+                .filter(|method| *method == "eth_gasPrice")
+                .count(),
+            1,
+        );
+    }
+}
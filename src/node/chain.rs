@@ -0,0 +1,70 @@
+//! Chain switching for wallet RPC calls.
+//!
+//! Dapps call `wallet_switchEthereumChain` and `wallet_addEthereumChain` to
+//! make sure that the wallet is connected to the chain they expect. The node
+//! serves the single chain of its remote node, so switching to that chain
+//! succeeds without doing anything, and any other chain is unknown.
+
+use crate::{node::eth::Eth, serialization::Quantity};
+use anyhow::Result;
+use ethnum::U256;
+use rocket::serde::Deserialize;
+use thiserror::Error;
+
+/// Chain parameters for `wallet_switchEthereumChain` and
+/// `wallet_addEthereumChain` calls. Additional chain metadata, such as its
+/// name and RPC URLs, is ignored.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ChainParameters {
+    /// The chain ID.
+    #[serde(rename = "chainId")]
+    pub chain_id: Quantity,
+}
+
+/// An error indicating that a chain is not served by the node.
+#[derive(Debug, Error)]
+#[error("unrecognized chain ID {0:#x}")]
+pub struct UnrecognizedChainError(pub U256);
+
+/// Switches to the specified chain, returning its chain ID, and failing if it
+/// isn't the chain of the remote node.
+pub async fn switch(eth: &Eth, chain: ChainParameters) -> Result<U256> {
+    let chain_id = eth.chain_id().await?;
+    if chain.chain_id.0 != chain_id {
+        return Err(UnrecognizedChainError(chain.chain_id.0).into());
+    }
+    Ok(chain_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{call, client, MockUpstream};
+    use rocket::serde::json::{serde_json::json, Value};
+
+    #[rocket::async_test]
+    async fn switches_only_to_remote_chain() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+
+        let switched = call(
+            &client,
+            "wallet_switchEthereumChain",
+            json!([{ "chainId": "0x1" }]),
+        )
+        .await;
+        let added = call(
+            &client,
+            "wallet_addEthereumChain",
+            json!([{
+                "chainId": "0x64",
+                "chainName": "Gnosis",
+                "rpcUrls": ["https://rpc.gnosischain.com"],
+            }]),
+        )
+        .await;
+
+        assert_eq!(switched["result"], Value::Null);
+        assert_eq!(added["error"]["code"], 4902);
+    }
+}
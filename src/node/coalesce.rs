@@ -0,0 +1,206 @@
+//! Request coalescing for identical concurrent reads.
+//!
+//! Bursty clients often send the same read request many times at once (for
+//! example, `eth_blockNumber` from several pollers). Identical in-flight
+//! requests are coalesced into a single remote node call whose result is
+//! shared between them.
+
+use crate::jsonrpc::{self, Request};
+use rocket::{serde::json::serde_json::value::RawValue, tokio::sync::broadcast};
+use std::{collections::HashMap, future::Future, sync::Mutex};
+
+/// Read methods whose identical concurrent requests can share a response.
+/// `eth_getLogs` is omitted since its potentially large responses are
+/// forwarded raw instead of parsed.
+const METHODS: &[&str] = &[
+    "eth_blockNumber",
+    "eth_call",
+    "eth_chainId",
+    "eth_estimateGas",
+    "eth_feeHistory",
+    "eth_gasPrice",
+    "eth_getBalance",
+    "eth_getBlockByHash",
+    "eth_getBlockByNumber",
+    "eth_getCode",
+    "eth_getStorageAt",
+    "eth_getTransactionByHash",
+    "eth_getTransactionCount",
+    "eth_getTransactionReceipt",
+    "eth_maxPriorityFeePerGas",
+    "eth_syncing",
+    "net_version",
+];
+
+/// The result of a remote call shared between coalesced requests.
+type Shared = Result<Box<RawValue>, jsonrpc::Error>;
+
+/// The method and parameters identifying coalesced requests.
+type Key = (String, String);
+
+/// Coalesces identical concurrent read requests.
+#[derive(Default)]
+pub struct Coalescer {
+    inflight: Mutex<HashMap<Key, broadcast::Sender<Shared>>>,
+}
+
+impl Coalescer {
+    /// Creates a new request coalescer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if requests for the method can be coalesced.
+    pub fn coalesces(method: &str) -> bool {
+        METHODS.contains(&method)
+    }
+
+    /// Executes a request with `execute`, unless an identical request is
+    /// already in flight, in which case its result is shared instead.
+    pub async fn execute<F, Fut>(&self, request: &Request, execute: F) -> Shared
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Shared>,
+    {
+        let key = (
+            request.method.clone(),
+            request
+                .params
+                .as_ref()
+                .map(|params| params.get().to_owned())
+                .unwrap_or_default(),
+        );
+        let follower = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.get(&key) {
+                Some(sender) => Some(sender.subscribe()),
+                None => {
+                    inflight.insert(key.clone(), broadcast::channel(1).0);
+                    None
+                }
+            }
+        };
+        if let Some(mut receiver) = follower {
+            // The leading request may be cancelled before it completes, in
+            // which case the request is executed on its own.
+            return match receiver.recv().await {
+                Ok(result) => result,
+                Err(_) => execute().await,
+            };
+        }
+
+        let leader = Leader {
+            coalescer: self,
+            key: Some(key),
+        };
+        let result = execute().await;
+        if let Some(sender) = leader.finish() {
+            let _ = sender.send(result.clone());
+        }
+        result
+    }
+}
+
+/// The request executing a coalesced call, which stops coalescing requests
+/// with its key when finished or dropped.
+struct Leader<'a> {
+    coalescer: &'a Coalescer,
+    key: Option<Key>,
+}
+
+impl Leader<'_> {
+    fn finish(mut self) -> Option<broadcast::Sender<Shared>> {
+        let key = self.key.take()?;
+        self.coalescer.inflight.lock().unwrap().remove(&key)
+    }
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.coalescer.inflight.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::{Id, JsonRpc, Params};
+    use rocket::{
+        serde::json::serde_json,
+        tokio::{sync::oneshot, task},
+    };
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[rocket::async_test]
+    async fn shares_inflight_results() {
+        let coalescer = Arc::new(Coalescer::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let request = |block: &str| Request {
+            jsonrpc: JsonRpc::V2,
+            method: "eth_getBalance".to_owned(),
+            params: Some(
+                Params::new(("0x0000000000000000000000000000000000000000", block)).unwrap(),
+            ),
+            id: Id::Number(1.into()),
+        };
+
+        let (release, released) = oneshot::channel::<()>();
+        let leader = task::spawn({
+            let (coalescer, calls) = (coalescer.clone(), calls.clone());
+            let request = request("latest");
+            async move {
+                coalescer
+                    .execute(&request, || async move {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        released.await.unwrap();
+                        Ok(serde_json::value::to_raw_value("0x1").unwrap())
+                    })
+                    .await
+            }
+        });
+        while coalescer.inflight.lock().unwrap().is_empty() {
+            task::yield_now().await;
+        }
+
+        let execute = |result: &'static str| {
+            let calls = calls.clone();
+            move || async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Ok(serde_json::value::to_raw_value(result).unwrap())
+            }
+        };
+        let follower = task::spawn({
+            let (coalescer, execute) = (coalescer.clone(), execute("0x2"));
+            let request = request("latest");
+            async move { coalescer.execute(&request, execute).await }
+        });
+        let other = coalescer
+            .execute(&request("pending"), execute("0x3"))
+            .await
+            .unwrap();
+        let followers = || {
+            coalescer
+                .inflight
+                .lock()
+                .unwrap()
+                .values()
+                .map(|sender| sender.receiver_count())
+                .sum::<usize>()
+        };
+        while followers() == 0 {
+            task::yield_now().await;
+        }
+        release.send(()).unwrap();
+
+        assert_eq!(leader.await.unwrap().unwrap().get(), r#""0x1""#);
+        assert_eq!(follower.await.unwrap().unwrap().get(), r#""0x1""#);
+        assert_eq!(other.get(), r#""0x3""#);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(coalescer.inflight.lock().unwrap().is_empty());
+    }
+}
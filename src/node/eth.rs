@@ -3,18 +3,25 @@
 use crate::{
     jsonrpc::{self, Id, JsonRpc, Params, Request, Response},
     node::{
+        cache::Cache,
         transaction::TransactionRequest,
-        types::{Block, FeeHistory},
+        types::{
+            AccessListResult, Block, BlockTag, CallRequest, FeeHistory, Receipt, SimulationRequest,
+        },
     },
-    serialization::{NoParameters, Quantity, Str},
+    serialization::{Bytes, NoParameters, Quantity, Str},
 };
-use anyhow::{bail, Result};
+use anyhow::{ensure, Context as _, Result};
 use ethnum::U256;
 use hdwallet::account::Address;
 use reqwest::Url;
 use rocket::{
+    futures::future::{self, Either},
     serde::{
-        json::{self, serde_json, Value},
+        json::{
+            serde_json::{self, value::RawValue, Map},
+            Value,
+        },
         DeserializeOwned, Serialize,
     },
     tokio::sync::oneshot,
@@ -22,17 +29,22 @@ use rocket::{
 use std::{
     future::Future,
     ops::Deref,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
 static ID: AtomicU64 = AtomicU64::new(1);
 
 /// Prepares a request.
 fn prepare(method: &'static str, params: impl Serialize) -> Result<Request> {
-    let params = match serde_json::to_value(params)? {
-        Value::Array(array) => Params::Array(array),
-        other => bail!("invalid Ethereum JSON RPC parameters {other}"),
-    };
+    let params = Params::new(params)?;
+    ensure!(
+        params.get().starts_with('['),
+        "invalid Ethereum JSON RPC parameters {}",
+        params.get(),
+    );
     let id = ID.fetch_add(1, Ordering::SeqCst);
 
     Ok(Request {
@@ -46,23 +58,39 @@ fn prepare(method: &'static str, params: impl Serialize) -> Result<Request> {
 /// An Ethereum RPC client.
 pub struct Eth {
     client: jsonrpc::Client,
+    cache: Option<Arc<Cache>>,
 }
 
 impl Eth {
     /// Creates a new Ethereum RPC client.
     pub fn new(client: jsonrpc::Client) -> Self {
-        Self { client }
+        Self {
+            client,
+            cache: None,
+        }
+    }
+
+    /// Caches frequently polled results with the specified cache.
+    pub fn with_cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Returns the cache for frequently polled results, if any.
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_deref()
     }
 
     /// Creates a new Ethereum RPC client from a URL.
-    pub fn from_url(url: Url) -> Result<Self> {
-        Ok(Self::new(jsonrpc::Client::new(url)?))
+    pub fn from_url(url: Url, options: jsonrpc::ClientOptions) -> Result<Self> {
+        Ok(Self::new(jsonrpc::Client::new(url, options)?))
     }
 
     /// Creates a new batch of Ethereum RPC calls.
     pub fn batch(&self) -> Batch<'_> {
         Batch {
             client: &self.client,
+            cache: self.cache(),
             queue: Vec::new(),
         }
     }
@@ -75,10 +103,22 @@ impl Eth {
     {
         let request = prepare(method, params)?;
         let response = self.client.execute(&request).await?;
-        let result = json::from_value::<O>(response.result?)?;
+        let result = serde_json::from_str::<O>(response.result?.get())?;
         Ok(result)
     }
 
+    /// Starts or stops impersonating an account on a Hardhat or Anvil
+    /// development node.
+    pub async fn impersonate_account(&self, account: Address, impersonate: bool) -> Result<()> {
+        let method = if impersonate {
+            "hardhat_impersonateAccount"
+        } else {
+            "hardhat_stopImpersonatingAccount"
+        };
+        self.call::<_, Value>(method, (Str(account),)).await?;
+        Ok(())
+    }
+
     /// Retrieves the chain ID.
     pub async fn chain_id(&self) -> Result<U256> {
         Ok(self
@@ -86,6 +126,32 @@ impl Eth {
             .await?
             .0)
     }
+
+    /// Retrieves the number of the most recent block.
+    pub async fn block_number(&self) -> Result<U256> {
+        if let Some(block_number) = self.cache().and_then(|cache| cache.get("eth_blockNumber")) {
+            return Ok(block_number);
+        }
+        let block_number = self
+            .call::<_, Quantity>("eth_blockNumber", NoParameters::default())
+            .await?
+            .0;
+        if let Some(cache) = self.cache() {
+            cache.insert("eth_blockNumber", block_number);
+        }
+        Ok(block_number)
+    }
+
+    /// Submits a signed transaction, returning its hash.
+    pub async fn send_raw_transaction(&self, transaction: Vec<u8>) -> Result<Bytes<[u8; 32]>> {
+        self.call("eth_sendRawTransaction", (Bytes(transaction),))
+            .await
+    }
+
+    /// Retrieves the logs matching the specified filter criteria.
+    pub async fn get_logs(&self, criteria: Map<String, Value>) -> Result<Vec<Box<RawValue>>> {
+        self.call("eth_getLogs", (criteria,)).await
+    }
 }
 
 impl Deref for Eth {
@@ -99,6 +165,7 @@ impl Deref for Eth {
 /// A batched Ethereum RPC client.
 pub struct Batch<'a> {
     client: &'a jsonrpc::Client,
+    cache: Option<&'a Cache>,
     queue: Vec<(Request, oneshot::Sender<Response>)>,
 }
 
@@ -130,11 +197,28 @@ impl<'a> Batch<'a> {
 
         async move {
             let response = response?.await?;
-            let result = json::from_value::<O>(response.result?)?;
+            let result = serde_json::from_str::<O>(response.result?.get())?;
             Ok(result)
         }
     }
 
+    /// Adds a call for a quantity without parameters to the batch, unless its
+    /// result is cached.
+    fn cached_call(&mut self, method: &'static str) -> impl Future<Output = Result<U256>> + 'a {
+        let cache = self.cache;
+        if let Some(value) = cache.and_then(|cache| cache.get(method)) {
+            return Either::Left(future::ready(Ok(value)));
+        }
+        let response = self.call::<_, Quantity>(method, NoParameters::default());
+        Either::Right(async move {
+            let value = response.await?.0;
+            if let Some(cache) = cache {
+                cache.insert(method, value);
+            }
+            Ok(value)
+        })
+    }
+
     /// Retrieves the chain ID.
     pub fn chain_id(&mut self) -> impl Future<Output = Result<U256>> {
         let response = self.call::<_, Quantity>("eth_chainId", NoParameters::default());
@@ -145,41 +229,194 @@ impl<'a> Batch<'a> {
     pub fn get_transaction_count(
         &mut self,
         account: Address,
-        block: Block,
+        block: BlockTag,
+    ) -> impl Future<Output = Result<u64>> {
+        let response =
+            self.call::<_, Quantity<u64>>("eth_getTransactionCount", (Str(account), block));
+        async move { Ok(response.await?.0) }
+    }
+
+    /// Retrieves an accounts Ether balance.
+    pub fn get_balance(
+        &mut self,
+        account: Address,
+        block: BlockTag,
     ) -> impl Future<Output = Result<U256>> {
-        let response = self.call::<_, Quantity>("eth_getTransactionCount", (Str(account), block));
+        let response = self.call::<_, Quantity>("eth_getBalance", (Str(account), block));
         async move { Ok(response.await?.0) }
     }
 
-    /// Retrieves an accounts transaction count (i.e. their next nonce).
+    /// Estimates the gas limit of a transaction.
     pub fn estimate_gas(
         &mut self,
         transaction: TransactionRequest,
-        block: Block,
-    ) -> impl Future<Output = Result<U256>> {
-        let response = self.call::<_, Quantity>("eth_estimateGas", (transaction, block));
+        block: BlockTag,
+    ) -> impl Future<Output = Result<u64>> {
+        let response = self.call::<_, Quantity<u64>>("eth_estimateGas", (transaction, block));
         async move { Ok(response.await?.0) }
     }
 
     /// Estimates a reasonable max priority fee to use for transactions.
-    pub fn max_priority_fee_per_gas(&mut self) -> impl Future<Output = Result<U256>> {
-        let response =
-            self.call::<_, Quantity>("eth_maxPriorityFeePerGas", NoParameters::default());
-        async move { Ok(response.await?.0) }
+    pub fn max_priority_fee_per_gas(&mut self) -> impl Future<Output = Result<U256>> + 'a {
+        self.cached_call("eth_maxPriorityFeePerGas")
     }
 
     /// Returns the base fee for the next block.
     pub fn base_fee(&mut self) -> impl Future<Output = Result<U256>> {
         let response = self.call::<_, FeeHistory>(
             "eth_feeHistory",
-            (Quantity(U256::new(1)), Block::Latest, <[f64; 0]>::default()),
+            (
+                Quantity(U256::new(1)),
+                BlockTag::Latest,
+                <[f64; 0]>::default(),
+            ),
         );
         async move { Ok(response.await?.base_fee_per_gas[1].0) }
     }
 
-    /// Estimates a legacy gas price to use for transactions.
-    pub fn gas_price(&mut self) -> impl Future<Output = Result<U256>> {
-        let response = self.call::<_, Quantity>("eth_gasPrice", NoParameters::default());
+    /// Executes a message call against a contract and returns its result.
+    pub fn call_contract(
+        &mut self,
+        to: Address,
+        data: Vec<u8>,
+        block: BlockTag,
+    ) -> impl Future<Output = Result<Vec<u8>>> {
+        let request = CallRequest {
+            to: Str(to),
+            data: Bytes(data),
+        };
+        let response = self.call::<_, Bytes<Vec<u8>>>("eth_call", (request, block));
+        async move { Ok(response.await?.0) }
+    }
+
+    /// Simulates a transaction from an account and returns its result.
+    pub fn simulate<'t>(
+        &mut self,
+        account: Address,
+        transaction: &'t TransactionRequest,
+        block: BlockTag,
+    ) -> impl Future<Output = Result<Vec<u8>>> + 't {
+        let request = SimulationRequest {
+            from: Str(account),
+            transaction,
+        };
+        let response = self.call::<_, Bytes<Vec<u8>>>("eth_call", (request, block));
         async move { Ok(response.await?.0) }
     }
+
+    /// Retrieves the code of an account.
+    pub fn get_code(
+        &mut self,
+        account: Address,
+        block: BlockTag,
+    ) -> impl Future<Output = Result<Vec<u8>>> {
+        let response = self.call::<_, Bytes<Vec<u8>>>("eth_getCode", (Str(account), block));
+        async move { Ok(response.await?.0) }
+    }
+
+    /// Creates an access list for a transaction from an account.
+    pub fn create_access_list<'t>(
+        &mut self,
+        account: Address,
+        transaction: &'t TransactionRequest,
+        block: BlockTag,
+    ) -> impl Future<Output = Result<AccessListResult>> + 't {
+        let request = SimulationRequest {
+            from: Str(account),
+            transaction,
+        };
+        self.call("eth_createAccessList", (request, block))
+    }
+
+    /// Retrieves the receipt of a transaction, if it was included in a block.
+    pub fn get_transaction_receipt(
+        &mut self,
+        hash: [u8; 32],
+    ) -> impl Future<Output = Result<Option<Receipt>>> {
+        self.call("eth_getTransactionReceipt", (Bytes(hash),))
+    }
+
+    /// Retrieves a block by number, with its transaction hashes.
+    pub fn get_block(&mut self, number: U256) -> impl Future<Output = Result<Block>> {
+        let response =
+            self.call::<_, Option<Block>>("eth_getBlockByNumber", (Quantity(number), false));
+        async move { response.await?.context("block not found") }
+    }
+
+    /// Retrieves the latest block, with its transaction hashes.
+    pub fn get_latest_block(&mut self) -> impl Future<Output = Result<Block>> {
+        let response =
+            self.call::<_, Option<Block>>("eth_getBlockByNumber", (BlockTag::Latest, false));
+        async move { response.await?.context("block not found") }
+    }
+
+    /// Retrieves whether or not the node is syncing.
+    pub fn syncing(&mut self) -> impl Future<Output = Result<bool>> {
+        // Nodes return `false` when they are not syncing, and an object with
+        // the sync progress otherwise.
+        let response = self.call::<_, Value>("eth_syncing", NoParameters::default());
+        async move { Ok(response.await? != Value::Bool(false)) }
+    }
+
+    /// Estimates a legacy gas price to use for transactions.
+    pub fn gas_price(&mut self) -> impl Future<Output = Result<U256>> + 'a {
+        self.cached_call("eth_gasPrice")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockUpstream;
+    use rocket::serde::json::json;
+
+    #[rocket::async_test]
+    async fn typed_batch_calls() {
+        let upstream = MockUpstream::start().await;
+        let eth = Eth::new(
+            jsonrpc::Client::new(upstream.url(), jsonrpc::ClientOptions::default()).unwrap(),
+        );
+        upstream.respond("eth_call", json!("0x2a"));
+        upstream.respond("eth_getCode", json!("0x6000"));
+        upstream.respond(
+            "eth_createAccessList",
+            json!({
+                "accessList": [{
+                    "address": "0x3535353535353535353535353535353535353535",
+                    "storageKeys": [format!("0x{}", "00".repeat(32))],
+                }],
+                "gasUsed": "0x5208",
+            }),
+        );
+
+        let account = Address([0x42; 20]);
+        let transaction = TransactionRequest {
+            from: Str(account),
+            to: Some(Str(Address([0x35; 20]))),
+            gas: None,
+            gas_price: None,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            value: Quantity(U256::ZERO),
+            data: Bytes(Vec::new()),
+            nonce: None,
+            access_list: None,
+            chain_id: None,
+        };
+        let mut batch = eth.batch();
+        let result = batch.simulate(account, &transaction, BlockTag::Latest);
+        let code = batch.get_code(Address([0x35; 20]), BlockTag::Latest);
+        let access_list = batch.create_access_list(account, &transaction, BlockTag::Pending);
+        batch.execute().await.unwrap();
+
+        assert_eq!(result.await.unwrap(), [0x2a]);
+        assert_eq!(code.await.unwrap(), [0x60, 0x00]);
+        let access_list = access_list.await.unwrap();
+        assert_eq!(access_list.access_list[0].storage_keys.len(), 1);
+        assert_eq!(access_list.gas_used.0, 21000);
+        assert_eq!(
+            upstream.requests()[0]["params"][0]["from"],
+            json!(account.to_string().to_lowercase()),
+        );
+    }
 }
@@ -0,0 +1,273 @@
+//! Module implementing filter methods locally.
+//!
+//! Filters are emulated by polling `eth_blockNumber` and `eth_getLogs`, so
+//! they don't depend on any remote node state. This allows filters to survive
+//! remote node failover and to work with providers that don't support them.
+
+use crate::{jsonrpc, node::eth::Eth, serialization::Quantity};
+use anyhow::{ensure, Result};
+use ethnum::U256;
+use rocket::serde::{
+    json::{
+        serde_json::{self, value::RawValue, Map},
+        Value,
+    },
+    Deserialize as _,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+/// The duration after which filters that are not polled get uninstalled. This
+/// is the same timeout that Geth uses.
+pub(super) const FILTER_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// The maximum number of block hashes returned by a single block filter poll.
+const MAX_BLOCK_CHANGES: u64 = 256;
+
+/// A table of locally implemented filters.
+pub struct Filters {
+    filters: Mutex<HashMap<String, Filter>>,
+    next_id: AtomicU64,
+}
+
+/// A locally implemented filter.
+struct Filter {
+    kind: Kind,
+    /// The first block that has not yet been reported by the filter.
+    next_block: U256,
+    /// The last time the filter was used.
+    last_poll: Instant,
+}
+
+/// The kind of filter.
+enum Kind {
+    /// A filter for logs matching some criteria.
+    Logs(LogCriteria),
+    /// A filter for new blocks.
+    Blocks,
+}
+
+/// A query for new filter changes.
+enum Query {
+    Logs(Option<Map<String, Value>>),
+    Blocks(U256),
+}
+
+impl Filters {
+    /// Creates a new empty filter table.
+    pub fn new() -> Self {
+        Self {
+            filters: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Installs a new log filter, returning its ID.
+    pub async fn new_filter(&self, eth: &Eth, criteria: Map<String, Value>) -> Result<String> {
+        let criteria = LogCriteria::new(criteria)?;
+        let head = eth.block_number().await?;
+        Ok(self.install(Kind::Logs(criteria), head + 1))
+    }
+
+    /// Installs a new block filter, returning its ID.
+    pub async fn new_block_filter(&self, eth: &Eth) -> Result<String> {
+        let head = eth.block_number().await?;
+        Ok(self.install(Kind::Blocks, head + 1))
+    }
+
+    /// Returns the logs or block hashes for a filter since it was last polled.
+    pub async fn get_filter_changes(&self, eth: &Eth, id: &str) -> Result<Vec<Box<RawValue>>> {
+        let head = eth.block_number().await?;
+        let query = {
+            let mut filters = self.lock();
+            let filter = filters.get_mut(id).ok_or_else(filter_not_found)?;
+            filter.last_poll = Instant::now();
+            if head < filter.next_block {
+                return Ok(Vec::new());
+            }
+
+            let from = std::mem::replace(&mut filter.next_block, head + 1);
+            match &filter.kind {
+                Kind::Logs(criteria) => Query::Logs(criteria.range(from, head)),
+                Kind::Blocks => Query::Blocks(from),
+            }
+        };
+
+        match query {
+            Query::Logs(Some(criteria)) => eth.get_logs(criteria).await,
+            Query::Logs(None) => Ok(Vec::new()),
+            Query::Blocks(from) => {
+                let count = (head - from).as_u64().saturating_add(1);
+                let skipped = count.saturating_sub(MAX_BLOCK_CHANGES);
+                let mut batch = eth.batch();
+                let headers = (skipped..count)
+                    .map(|offset| batch.get_block(from + U256::from(offset)))
+                    .collect::<Vec<_>>();
+                batch.execute().await?;
+
+                let mut hashes = Vec::with_capacity(headers.len());
+                for header in headers {
+                    hashes.push(serde_json::value::to_raw_value(&header.await?.hash)?);
+                }
+                Ok(hashes)
+            }
+        }
+    }
+
+    /// Returns all logs matching a log filter's criteria.
+    pub async fn get_filter_logs(&self, eth: &Eth, id: &str) -> Result<Vec<Box<RawValue>>> {
+        let criteria = {
+            let mut filters = self.lock();
+            match filters.get_mut(id) {
+                Some(Filter {
+                    kind: Kind::Logs(criteria),
+                    last_poll,
+                    ..
+                }) => {
+                    *last_poll = Instant::now();
+                    criteria.criteria.clone()
+                }
+                _ => return Err(filter_not_found().into()),
+            }
+        };
+        eth.get_logs(criteria).await
+    }
+
+    /// Uninstalls a filter, returning whether or not it existed.
+    pub fn uninstall_filter(&self, id: &str) -> bool {
+        self.lock().remove(id).is_some()
+    }
+
+    /// Adds a filter to the table and returns its newly issued ID.
+    fn install(&self, kind: Kind, next_block: U256) -> String {
+        let id = format!("{:#x}", self.next_id.fetch_add(1, Ordering::SeqCst));
+        self.lock().insert(
+            id.clone(),
+            Filter {
+                kind,
+                next_block,
+                last_poll: Instant::now(),
+            },
+        );
+        id
+    }
+
+    /// Locks the filter table, uninstalling any filters that timed out.
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Filter>> {
+        let mut filters = self.filters.lock().unwrap();
+        filters.retain(|_, filter| filter.last_poll.elapsed() < FILTER_TIMEOUT);
+        filters
+    }
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Log filter criteria.
+struct LogCriteria {
+    /// The raw criteria, including the addresses and topics to match.
+    criteria: Map<String, Value>,
+    /// The first block to include logs for, if bounded.
+    from_block: Option<U256>,
+    /// The last block to include logs for, if bounded.
+    to_block: Option<U256>,
+}
+
+impl LogCriteria {
+    /// Creates new log criteria from `eth_newFilter` parameters.
+    fn new(criteria: Map<String, Value>) -> Result<Self> {
+        ensure!(
+            !criteria.contains_key("blockHash"),
+            "block hash log filters are not supported",
+        );
+        let from_block = block_number(criteria.get("fromBlock"))?;
+        let to_block = block_number(criteria.get("toBlock"))?;
+        Ok(Self {
+            criteria,
+            from_block,
+            to_block,
+        })
+    }
+
+    /// Returns the `eth_getLogs` criteria for querying logs in the specified
+    /// block range, or `None` if the range is outside of the filter's blocks.
+    fn range(&self, from: U256, to: U256) -> Option<Map<String, Value>> {
+        let from = self.from_block.map_or(from, |block| block.max(from));
+        let to = self.to_block.map_or(to, |block| block.min(to));
+        if from > to {
+            return None;
+        }
+
+        let mut criteria = self.criteria.clone();
+        criteria.insert("fromBlock".to_owned(), quantity(from));
+        criteria.insert("toBlock".to_owned(), quantity(to));
+        Some(criteria)
+    }
+}
+
+/// Parses a block number from filter criteria. Block tags other than
+/// `earliest` refer to the chain head and leave the range unbounded.
+fn block_number(value: Option<&Value>) -> Result<Option<U256>> {
+    match value {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(tag)) if tag == "earliest" => Ok(Some(U256::ZERO)),
+        Some(Value::String(tag))
+            if matches!(tag.as_str(), "latest" | "pending" | "safe" | "finalized") =>
+        {
+            Ok(None)
+        }
+        Some(value) => Ok(Some(Quantity::deserialize(value)?.0)),
+    }
+}
+
+/// Converts an integer to a JSON quantity value.
+fn quantity(value: U256) -> Value {
+    Value::String(format!("{value:#x}"))
+}
+
+/// Creates the error returned for unknown filter IDs. This matches the error
+/// returned by Geth.
+pub fn filter_not_found() -> jsonrpc::Error {
+    jsonrpc::Error {
+        code: -32000,
+        message: "filter not found".to_owned(),
+        data: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::serde_json::json;
+
+    #[test]
+    fn log_criteria_range() {
+        let criteria = |value: Value| LogCriteria::new(value.as_object().unwrap().clone()).unwrap();
+
+        let unbounded = criteria(json!({ "address": "0x01", "fromBlock": "latest" }));
+        assert_eq!(
+            Value::Object(unbounded.range(U256::new(1), U256::new(2)).unwrap()),
+            json!({ "address": "0x01", "fromBlock": "0x1", "toBlock": "0x2" }),
+        );
+
+        let bounded = criteria(json!({ "fromBlock": "0x5", "toBlock": "0x7" }));
+        assert_eq!(
+            Value::Object(bounded.range(U256::new(1), U256::new(10)).unwrap()),
+            json!({ "fromBlock": "0x5", "toBlock": "0x7" }),
+        );
+        assert!(bounded.range(U256::new(8), U256::new(10)).is_none());
+
+        assert!(
+            LogCriteria::new(json!({ "blockHash": "0x00" }).as_object().unwrap().clone()).is_err()
+        );
+    }
+}
@@ -0,0 +1,443 @@
+//! Automatic account top-ups from a funder account.
+//!
+//! Relayer accounts managed by the node continuously spend Ether on gas. The
+//! funder periodically checks the balances of the funded accounts, and sends a
+//! top-up transfer from a designated funder account to any account whose
+//! balance dropped below a threshold. Top-ups go through the regular signer
+//! pipeline and submission path, so they are subject to the same policies as
+//! any other transaction, and are limited to a maximum amount per day. Every
+//! top-up is recorded in an append-only audit log.
+//!
+//! In cluster mode, only the instance leading the cluster funds accounts.
+
+use crate::{
+    backend::{Election, SharedBackend},
+    clock,
+    node::{eth::Eth, transaction::TransactionRequest, types::BlockTag, Node},
+    serialization::{Bytes, Quantity, Str},
+};
+use anyhow::{ensure, Context as _, Result};
+use ethnum::U256;
+use hdwallet::account::Address;
+use rocket::{
+    serde::{json::serde_json, Deserialize, Serialize},
+    tokio::time,
+};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    future::Future,
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// The default interval in seconds at which balances are checked.
+const DEFAULT_POLL_INTERVAL: u64 = 60;
+
+/// The default time in seconds after which top-ups that are not included in a
+/// block are given up on.
+const DEFAULT_PENDING_TIMEOUT: u64 = 60 * 60;
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Account funding options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Options {
+    /// The managed account that top-ups are sent from. Accounts are not
+    /// funded when not specified.
+    #[serde(default)]
+    pub funder_account: Option<Str<Address>>,
+
+    /// The balance in Wei below which accounts are topped up.
+    #[serde(default)]
+    pub funding_threshold: Option<Str<U256>>,
+
+    /// The amount in Wei sent with each top-up.
+    #[serde(default)]
+    pub funding_amount: Option<Str<U256>>,
+
+    /// The accounts that are funded. Defaults to all managed accounts other
+    /// than the funder.
+    #[serde(default)]
+    pub funded_accounts: Option<Vec<Str<Address>>>,
+
+    /// The maximum total amount in Wei sent by the funder per day. Top-ups are
+    /// not limited when not specified.
+    #[serde(default)]
+    pub max_daily_funding: Option<Str<U256>>,
+
+    /// The file that top-ups are recorded to, one JSON object per line.
+    #[serde(default)]
+    pub funding_log: Option<PathBuf>,
+
+    /// The interval in seconds at which balances are checked. Defaults to 60
+    /// seconds.
+    #[serde(default)]
+    pub funding_poll_interval: Option<u64>,
+
+    /// The time in seconds after which a top-up that is not included in a
+    /// block, for example because it was dropped from the mempool, no longer
+    /// prevents topping up the account again. Defaults to one hour.
+    #[serde(default)]
+    pub funding_pending_timeout: Option<u64>,
+}
+
+/// A top-up sent by the funder, as recorded in the audit log.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TopUp {
+    /// The Unix timestamp of the top-up.
+    pub timestamp: u64,
+    /// The funded account.
+    pub account: Str<Address>,
+    /// The balance of the account before the top-up.
+    pub balance: Quantity,
+    /// The amount sent.
+    pub amount: Quantity,
+    /// The transaction hash.
+    pub hash: Bytes<[u8; 32]>,
+}
+
+/// The funder state.
+struct State {
+    /// The day and total amount sent that day.
+    spent: (u64, U256),
+    /// The hashes and timestamps of top-ups that are not yet included in a
+    /// block, by funded account.
+    pending: HashMap<[u8; 20], ([u8; 32], u64)>,
+    log: Option<File>,
+}
+
+/// The account funder.
+pub struct Funder {
+    funder: Address,
+    threshold: U256,
+    amount: U256,
+    accounts: Vec<Address>,
+    max_daily: Option<U256>,
+    interval: Duration,
+    pending_timeout: u64,
+    state: Mutex<State>,
+    election: Option<Election>,
+}
+
+impl Funder {
+    /// Creates a new funder from the specified options for the managed
+    /// accounts, or `None` if funding is not configured.
+    pub fn new(options: &Options, managed: &[Address]) -> Result<Option<Self>> {
+        let funder = match &options.funder_account {
+            Some(funder) => funder.0,
+            None => return Ok(None),
+        };
+        ensure!(
+            managed.contains(&funder),
+            "funder account {funder} is not managed by the node",
+        );
+        let threshold = options
+            .funding_threshold
+            .context("funding requires a funding threshold")?
+            .0;
+        let amount = options
+            .funding_amount
+            .context("funding requires a funding amount")?
+            .0;
+        let accounts = match &options.funded_accounts {
+            Some(accounts) => accounts.iter().map(|account| account.0).collect(),
+            None => managed
+                .iter()
+                .copied()
+                .filter(|account| *account != funder)
+                .collect(),
+        };
+
+        let (spent, log) = match &options.funding_log {
+            Some(path) => {
+                let spent = load(path)?;
+                let log = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("failed to open funding log {}", path.display()))?;
+                (spent, Some(log))
+            }
+            None => ((clock::now() / DAY, U256::ZERO), None),
+        };
+
+        Ok(Some(Self {
+            funder,
+            threshold,
+            amount,
+            accounts,
+            max_daily: options.max_daily_funding.map(|max| max.0),
+            interval: Duration::from_secs(
+                options
+                    .funding_poll_interval
+                    .unwrap_or(DEFAULT_POLL_INTERVAL),
+            ),
+            pending_timeout: options
+                .funding_pending_timeout
+                .unwrap_or(DEFAULT_PENDING_TIMEOUT),
+            state: Mutex::new(State {
+                spent,
+                pending: HashMap::new(),
+                log,
+            }),
+            election: None,
+        }))
+    }
+
+    /// Only funds accounts while leading the specified cluster, so that
+    /// accounts are not topped up by multiple instances.
+    pub fn with_cluster(mut self, backend: SharedBackend) -> Self {
+        // Leases outlive a few polls, so that leadership is kept while the
+        // leader is alive.
+        self.election = Some(Election::new(backend, "funding", self.interval * 3));
+        self
+    }
+
+    /// Funds accounts until the node shuts down.
+    pub async fn run(self: Arc<Self>, node: Arc<Node>) {
+        loop {
+            if !self.election.as_ref().is_none_or(Election::elect) {
+                time::sleep(self.interval).await;
+                continue;
+            }
+            let result = self
+                .check(&node.remote, |request| node.send_transaction(request))
+                .await;
+            if let Err(err) = result {
+                tracing::warn!(?err, "failed to fund accounts");
+            }
+            time::sleep(self.interval).await;
+        }
+    }
+
+    /// Checks the balances of the funded accounts and tops up the ones below
+    /// the threshold with `send`.
+    async fn check<F, Fut>(&self, eth: &Eth, send: F) -> Result<()>
+    where
+        F: Fn(TransactionRequest) -> Fut,
+        Fut: Future<Output = Result<Bytes<[u8; 32]>>>,
+    {
+        if self.accounts.is_empty() {
+            return Ok(());
+        }
+        let pending = self.state.lock().unwrap().pending.clone();
+        let mut batch = eth.batch();
+        let checks = self
+            .accounts
+            .iter()
+            .map(|account| {
+                let receipt = pending
+                    .get(&account.0)
+                    .map(|(hash, sent)| (batch.get_transaction_receipt(*hash), *sent));
+                (
+                    *account,
+                    batch.get_balance(*account, BlockTag::Latest),
+                    receipt,
+                )
+            })
+            .collect::<Vec<_>>();
+        batch.execute().await?;
+
+        for (account, balance, receipt) in checks {
+            // Wait for previous top-ups to be included, so that accounts are
+            // not funded twice.
+            if let Some((receipt, sent)) = receipt {
+                match receipt.await? {
+                    Some(receipt) if receipt.reverted() => {
+                        tracing::warn!(%account, hash = ?receipt.transaction_hash, "top-up reverted");
+                    }
+                    Some(_) => {}
+                    // Give up on top-ups that were probably dropped, so that
+                    // the account isn't left unfunded forever.
+                    None if clock::now().saturating_sub(sent) >= self.pending_timeout => {
+                        tracing::warn!(%account, "top-up not included in time, funding again");
+                    }
+                    None => continue,
+                }
+                self.state.lock().unwrap().pending.remove(&account.0);
+            }
+            let balance = balance.await?;
+            if balance >= self.threshold {
+                continue;
+            }
+
+            self.reserve(account)?;
+            let request = TransactionRequest {
+                from: Str(self.funder),
+                to: Some(Str(account)),
+                gas: None,
+                gas_price: None,
+                max_fee_per_gas: None,
+                max_priority_fee_per_gas: None,
+                value: Quantity(self.amount),
+                data: Bytes(Vec::new()),
+                nonce: None,
+                access_list: None,
+                chain_id: None,
+            };
+            let hash = match send(request).await {
+                Ok(hash) => hash,
+                Err(err) => {
+                    self.release();
+                    return Err(err.context(format!("failed to top up account {account}")));
+                }
+            };
+            tracing::info!(%account, ?hash, amount = %self.amount, "topped up account");
+            self.record(TopUp {
+                timestamp: clock::now(),
+                account: Str(account),
+                balance: Quantity(balance),
+                amount: Quantity(self.amount),
+                hash,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Reserves a top-up within the daily limit.
+    fn reserve(&self, account: Address) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let day = clock::now() / DAY;
+        if state.spent.0 != day {
+            state.spent = (day, U256::ZERO);
+        }
+        let spent = state.spent.1.saturating_add(self.amount);
+        if let Some(max_daily) = self.max_daily {
+            ensure!(
+                spent <= max_daily,
+                "daily funding limit of {max_daily} Wei reached, not topping up account {account}",
+            );
+        }
+        state.spent.1 = spent;
+        Ok(())
+    }
+
+    /// Releases a reserved top-up after failing to send it.
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.spent.1 = state.spent.1.saturating_sub(self.amount);
+    }
+
+    fn record(&self, top_up: TopUp) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .pending
+            .insert(top_up.account.0 .0, (top_up.hash.0, top_up.timestamp));
+        if let Some(log) = &mut state.log {
+            writeln!(log, "{}", serde_json::to_string(&top_up)?)
+                .and_then(|_| log.sync_data())
+                .context("failed to write funding log")?;
+        }
+        Ok(())
+    }
+}
+
+/// Loads the amount sent today from a funding log, so that restarting the
+/// node does not reset the daily limit.
+fn load(path: &Path) -> Result<(u64, U256)> {
+    let day = clock::now() / DAY;
+    let mut spent = U256::ZERO;
+    if path.exists() {
+        let log = fs::read_to_string(path)
+            .with_context(|| format!("failed to read funding log {}", path.display()))?;
+        for line in log.lines().filter(|line| !line.trim().is_empty()) {
+            let top_up = serde_json::from_str::<TopUp>(line)
+                .with_context(|| format!("invalid funding log {}", path.display()))?;
+            if top_up.timestamp / DAY == day {
+                spent = spent.saturating_add(top_up.amount.0);
+            }
+        }
+    }
+    Ok((day, spent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{jsonrpc, testing::MockUpstream};
+    use rocket::serde::json::json;
+    use std::{env, process};
+
+    #[rocket::async_test]
+    async fn tops_up_accounts_within_daily_limit() {
+        let path = env::temp_dir().join(format!("hdnode-funding-{}.jsonl", process::id()));
+        let (funder, first, second) = (Address([1; 20]), Address([2; 20]), Address([3; 20]));
+        let options = Options {
+            funder_account: Some(Str(funder)),
+            funding_threshold: Some(Str(U256::new(100))),
+            funding_amount: Some(Str(U256::new(1000))),
+            max_daily_funding: Some(Str(U256::new(1500))),
+            funding_log: Some(path.clone()),
+            ..Options::default()
+        };
+        let funder = Funder::new(&options, &[funder, first, second])
+            .unwrap()
+            .unwrap();
+
+        let upstream = MockUpstream::start().await;
+        let eth = Eth::new(
+            jsonrpc::Client::new(upstream.url(), jsonrpc::ClientOptions::default()).unwrap(),
+        );
+        upstream.respond("eth_getBalance", json!("0x10"));
+        let sent = Mutex::new(Vec::new());
+        let send = |request: TransactionRequest| {
+            sent.lock().unwrap().push(request.to.unwrap().0);
+            async { Ok(Bytes([0x42; 32])) }
+        };
+
+        // The second top-up would exceed the daily limit.
+        assert!(funder.check(&eth, send).await.is_err());
+        assert_eq!(*sent.lock().unwrap(), [first]);
+
+        // Accounts with pending top-ups are not funded again.
+        upstream.respond("eth_getTransactionReceipt", json!(null));
+        assert!(funder.check(&eth, send).await.is_err());
+        assert_eq!(*sent.lock().unwrap(), [first]);
+
+        // The amount sent today survives restarts.
+        let managed = [options.funder_account.unwrap().0, first, second];
+        let funder = Funder::new(&options, &managed).unwrap().unwrap();
+        assert_eq!(funder.state.lock().unwrap().spent.1, 1000);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn gives_up_on_pending_top_ups() {
+        let (funder, account) = (Address([1; 20]), Address([2; 20]));
+        let options = Options {
+            funder_account: Some(Str(funder)),
+            funding_threshold: Some(Str(U256::new(100))),
+            funding_amount: Some(Str(U256::new(1000))),
+            funding_pending_timeout: Some(60),
+            ..Options::default()
+        };
+        let funder = Funder::new(&options, &[funder, account]).unwrap().unwrap();
+
+        let upstream = MockUpstream::start().await;
+        let eth = Eth::new(
+            jsonrpc::Client::new(upstream.url(), jsonrpc::ClientOptions::default()).unwrap(),
+        );
+        upstream.respond("eth_getBalance", json!("0x10"));
+        upstream.respond("eth_getTransactionReceipt", json!(null));
+        let sent = Mutex::new(0);
+        let send = |_| {
+            *sent.lock().unwrap() += 1;
+            async { Ok(Bytes([0x42; 32])) }
+        };
+
+        funder.check(&eth, send).await.unwrap();
+        funder.check(&eth, send).await.unwrap();
+        assert_eq!(*sent.lock().unwrap(), 1);
+
+        for (_, sent) in funder.state.lock().unwrap().pending.values_mut() {
+            *sent -= 60;
+        }
+        funder.check(&eth, send).await.unwrap();
+        assert_eq!(*sent.lock().unwrap(), 2);
+    }
+}
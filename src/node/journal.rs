@@ -0,0 +1,316 @@
+//! Persistent journal of submitted transactions.
+//!
+//! Signed transactions are written to the journal before they are submitted
+//! to the remote node, so that they aren't silently lost if the node crashes
+//! or the remote node drops them. Pending transactions are monitored until
+//! they are included in a block or their nonce is used by another transaction,
+//! and are resubmitted in the meantime. This also picks up any transactions
+//! left pending by a previous run on startup.
+//!
+//! The journal is persisted as an append-only file with one JSON event per
+//! line, which is compacted on startup.
+
+use crate::{
+    abi,
+    node::{
+        eth::Eth,
+        relay::{self, Relay},
+        types::BlockTag,
+    },
+    persist,
+    serialization::{Bytes, Quantity, Str},
+};
+use anyhow::{Context as _, Result};
+use hdwallet::account::Address;
+use rocket::{
+    serde::{json::serde_json, Deserialize, Serialize},
+    tokio::time,
+};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// The default interval in seconds at which pending transactions are checked.
+const DEFAULT_POLL_INTERVAL: u64 = 15;
+
+/// Transaction journal options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Options {
+    /// The file that submitted transactions are journaled to. Transactions
+    /// are not journaled when not specified.
+    #[serde(default)]
+    pub transaction_journal: Option<PathBuf>,
+
+    /// The interval in seconds at which pending transactions are checked and
+    /// resubmitted. Defaults to 15 seconds.
+    #[serde(default)]
+    pub journal_poll_interval: Option<u64>,
+}
+
+/// A signed transaction that was submitted.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Submitted {
+    /// The transaction hash.
+    pub hash: Bytes<[u8; 32]>,
+    /// The account that signed the transaction.
+    pub account: Str<Address>,
+    /// The transaction nonce.
+    pub nonce: Quantity<u64>,
+    /// The signed transaction.
+    pub raw: Bytes<Vec<u8>>,
+}
+
+/// A journal event.
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", tag = "event", rename_all = "camelCase")]
+enum Event {
+    /// A transaction is about to be submitted.
+    Submitted(Submitted),
+    /// A transaction was included in a block.
+    Confirmed { hash: Bytes<[u8; 32]> },
+    /// The nonce of a transaction was used by another transaction.
+    Replaced { hash: Bytes<[u8; 32]> },
+}
+
+/// The journal state.
+struct State {
+    /// The pending transactions, by hash.
+    pending: BTreeMap<[u8; 32], Submitted>,
+    file: File,
+}
+
+/// The transaction journal.
+pub struct Journal {
+    interval: Duration,
+    state: Mutex<State>,
+}
+
+impl Journal {
+    /// Opens the journal configured by the specified options, if any. Pending
+    /// transactions are loaded from the journal file.
+    pub fn open(options: &Options) -> Result<Option<Self>> {
+        let path = match &options.transaction_journal {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let (pending, file) = load(path)?;
+        if !pending.is_empty() {
+            tracing::info!(count = pending.len(), "loaded pending transactions");
+        }
+
+        Ok(Some(Self {
+            interval: Duration::from_secs(
+                options
+                    .journal_poll_interval
+                    .unwrap_or(DEFAULT_POLL_INTERVAL),
+            ),
+            state: Mutex::new(State { pending, file }),
+        }))
+    }
+
+    /// Records a signed transaction that is about to be submitted.
+    pub fn record(&self, account: Address, nonce: Quantity<u64>, raw: &[u8]) -> Result<()> {
+        let submitted = Submitted {
+            hash: Bytes(abi::keccak256(raw)),
+            account: Str(account),
+            nonce,
+            raw: Bytes(raw.to_vec()),
+        };
+        let mut state = self.state.lock().unwrap();
+        append(&mut state.file, &Event::Submitted(submitted.clone()))?;
+        state.pending.insert(submitted.hash.0, submitted);
+        Ok(())
+    }
+
+    /// Returns the pending transactions.
+    pub fn pending(&self) -> Vec<Submitted> {
+        self.state
+            .lock()
+            .unwrap()
+            .pending
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Monitors pending transactions until the node shuts down, resubmitting
+    /// them to the private relay if there is one. This runs on every instance
+    /// of a cluster, as each one only monitors its own journal.
+    pub async fn monitor(self: Arc<Self>, eth: Eth, relay: Option<Arc<Relay>>) {
+        loop {
+            if let Err(err) = self.check(&eth, relay.as_deref()).await {
+                tracing::warn!(?err, "failed to check pending transactions");
+            }
+            time::sleep(self.interval).await;
+        }
+    }
+
+    /// Checks whether pending transactions were included in a block or
+    /// replaced, and resubmits the others.
+    async fn check(&self, eth: &Eth, relay: Option<&Relay>) -> Result<()> {
+        for transaction in self.pending() {
+            let mut batch = eth.batch();
+            let receipt = batch.get_transaction_receipt(transaction.hash.0);
+            let nonce = batch.get_transaction_count(transaction.account.0, BlockTag::Latest);
+            batch.execute().await?;
+
+            let hash = transaction.hash;
+            if let Some(receipt) = receipt.await? {
+                if receipt.reverted() {
+                    tracing::warn!(?hash, "pending transaction confirmed but reverted");
+                } else {
+                    tracing::debug!(?hash, "pending transaction confirmed");
+                }
+                self.resolve(Event::Confirmed { hash })?;
+            } else if nonce.await? > transaction.nonce.0 {
+                tracing::warn!(?hash, "pending transaction was replaced");
+                self.resolve(Event::Replaced { hash })?;
+            } else if let Err(err) = relay::submit(relay, eth, transaction.raw.0).await {
+                // Resubmitting transactions that the remote node already
+                // knows about fails, so this is expected.
+                tracing::debug!(?hash, ?err, "failed to resubmit pending transaction");
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, event: Event) -> Result<()> {
+        let hash = match &event {
+            Event::Confirmed { hash } | Event::Replaced { hash } => hash.0,
+            Event::Submitted(_) => unreachable!("submissions are not resolutions"),
+        };
+        let mut state = self.state.lock().unwrap();
+        append(&mut state.file, &event)?;
+        state.pending.remove(&hash);
+        Ok(())
+    }
+}
+
+/// Loads the pending transactions from a journal file, compacting it by
+/// dropping resolved transactions, and opens it for appending.
+fn load(path: &Path) -> Result<(BTreeMap<[u8; 32], Submitted>, File)> {
+    let mut pending = BTreeMap::new();
+    if path.exists() {
+        let journal = fs::read_to_string(path)
+            .with_context(|| format!("failed to read transaction journal {}", path.display()))?;
+        for line in journal.lines().filter(|line| !line.trim().is_empty()) {
+            let event = serde_json::from_str::<Event>(line)
+                .with_context(|| format!("invalid transaction journal {}", path.display()))?;
+            match event {
+                Event::Submitted(submitted) => {
+                    pending.insert(submitted.hash.0, submitted);
+                }
+                Event::Confirmed { hash } | Event::Replaced { hash } => {
+                    pending.remove(&hash.0);
+                }
+            }
+        }
+    }
+
+    let mut compacted = String::new();
+    for submitted in pending.values() {
+        compacted += &serde_json::to_string(&Event::Submitted(submitted.clone()))?;
+        compacted.push('\n');
+    }
+    persist::write_atomic(path, compacted)
+        .with_context(|| format!("failed to write transaction journal {}", path.display()))?;
+
+    let file = OpenOptions::new()
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open transaction journal {}", path.display()))?;
+    Ok((pending, file))
+}
+
+fn append(file: &mut File, event: &Event) -> Result<()> {
+    writeln!(file, "{}", serde_json::to_string(event)?)
+        .and_then(|_| file.sync_data())
+        .context("failed to write transaction journal")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{jsonrpc, testing::MockUpstream};
+    use rocket::serde::json::json;
+    use std::{env, process};
+
+    #[rocket::async_test]
+    async fn resubmits_and_resolves_pending_transactions() {
+        let path = env::temp_dir().join(format!("hdnode-journal-{}.jsonl", process::id()));
+        let options = Options {
+            transaction_journal: Some(path.clone()),
+            journal_poll_interval: None,
+        };
+        let journal = Journal::open(&options).unwrap().unwrap();
+        let account = Address([0x42; 20]);
+        journal.record(account, Quantity(1), &[0x01]).unwrap();
+        journal.record(account, Quantity(2), &[0x02]).unwrap();
+
+        // Pending transactions survive restarts.
+        drop(journal);
+        let journal = Journal::open(&options).unwrap().unwrap();
+        assert_eq!(journal.pending().len(), 2);
+
+        let upstream = MockUpstream::start().await;
+        let eth = Eth::new(
+            jsonrpc::Client::new(upstream.url(), jsonrpc::ClientOptions::default()).unwrap(),
+        );
+        upstream.respond("eth_getTransactionReceipt", json!(null));
+        upstream.respond("eth_getTransactionCount", json!("0x2"));
+        upstream.respond(
+            "eth_sendRawTransaction",
+            json!(format!("0x{}", hex::encode(abi::keccak256([0x02])))),
+        );
+        journal.check(&eth, None).await.unwrap();
+
+        let pending = journal.pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].nonce.0, 2);
+        assert_eq!(
+            upstream
+                .methods()
+                .iter()
+                .filter(|method| *method == "eth_sendRawTransaction")
+                .count(),
+            1,
+        );
+
+        let hash = format!("0x{}", "42".repeat(32));
+        upstream.respond(
+            "eth_getTransactionReceipt",
+            json!({
+                "transactionHash": hash,
+                "transactionIndex": "0x0",
+                "blockHash": hash,
+                "blockNumber": "0x1",
+                "from": "0x4242424242424242424242424242424242424242",
+                "to": null,
+                "cumulativeGasUsed": "0x5208",
+                "gasUsed": "0x5208",
+                "effectiveGasPrice": "0x1",
+                "contractAddress": null,
+                "logs": [],
+                "logsBloom": format!("0x{}", "00".repeat(256)),
+                "status": "0x1",
+                "type": "0x2",
+            }),
+        );
+        journal.check(&eth, None).await.unwrap();
+        assert!(journal.pending().is_empty());
+        drop(journal);
+        assert!(Journal::open(&options)
+            .unwrap()
+            .unwrap()
+            .pending()
+            .is_empty());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+//! Normalization of remote node responses.
+//!
+//! Execution clients and providers differ slightly in how they represent
+//! blocks, transactions and receipts: some omit fields that are `null` or
+//! empty, and some use different names for the same field. When enabled,
+//! responses are normalized to a consistent schema, matching Geth's, so that
+//! clients don't depend on which remote node served them.
+
+use crate::jsonrpc::Response;
+use rocket::serde::json::{
+    serde_json::{self, Map},
+    Value,
+};
+
+/// Returns whether or not responses for the specified method are normalized.
+pub fn normalizes(method: &str) -> bool {
+    matches!(
+        method,
+        "eth_getBlockByHash"
+            | "eth_getBlockByNumber"
+            | "eth_getTransactionByHash"
+            | "eth_getTransactionByBlockHashAndIndex"
+            | "eth_getTransactionByBlockNumberAndIndex"
+            | "eth_getTransactionReceipt"
+            | "eth_getBlockReceipts"
+    )
+}
+
+/// Normalizes the result of a response from the remote node.
+pub fn normalize_response(method: &str, response: &mut Response) {
+    if !normalizes(method) {
+        return;
+    }
+    let mut value = match &response.result {
+        Ok(result) => match serde_json::from_str::<Value>(result.get()) {
+            Ok(value) => value,
+            Err(_) => return,
+        },
+        Err(_) => return,
+    };
+    match (method, &mut value) {
+        ("eth_getBlockByHash" | "eth_getBlockByNumber", Value::Object(block)) => {
+            normalize_block(block)
+        }
+        ("eth_getTransactionReceipt", Value::Object(receipt)) => normalize_receipt(receipt),
+        ("eth_getBlockReceipts", Value::Array(receipts)) => {
+            for receipt in receipts {
+                if let Value::Object(receipt) = receipt {
+                    normalize_receipt(receipt);
+                }
+            }
+        }
+        (_, Value::Object(transaction)) => normalize_transaction(transaction),
+        _ => return,
+    }
+    if let Ok(result) = serde_json::value::to_raw_value(&value) {
+        response.result = Ok(result);
+    }
+}
+
+/// Normalizes a block, including its transactions when they are included in
+/// full.
+fn normalize_block(block: &mut Map<String, Value>) {
+    rename(block, "author", "miner");
+    default(block, "uncles", Value::Array(Vec::new()));
+    default(block, "transactions", Value::Array(Vec::new()));
+    if let Some(Value::Array(transactions)) = block.get_mut("transactions") {
+        for transaction in transactions {
+            if let Value::Object(transaction) = transaction {
+                normalize_transaction(transaction);
+            }
+        }
+    }
+}
+
+/// Normalizes a transaction.
+fn normalize_transaction(transaction: &mut Map<String, Value>) {
+    rename(transaction, "data", "input");
+    for field in ["blockHash", "blockNumber", "transactionIndex", "to"] {
+        insert_missing(transaction, field, Value::Null);
+    }
+    default(transaction, "type", Value::from("0x0"));
+    if transaction["type"] != "0x0" {
+        default(transaction, "accessList", Value::Array(Vec::new()));
+        if let Some(v) = transaction.get("v").cloned() {
+            insert_missing(transaction, "yParity", v);
+        }
+    }
+}
+
+/// Normalizes a transaction receipt.
+fn normalize_receipt(receipt: &mut Map<String, Value>) {
+    for field in ["contractAddress", "to"] {
+        insert_missing(receipt, field, Value::Null);
+    }
+    default(receipt, "type", Value::from("0x0"));
+    default(receipt, "logs", Value::Array(Vec::new()));
+    if let Some(Value::Array(logs)) = receipt.get_mut("logs") {
+        for log in logs {
+            if let Value::Object(log) = log {
+                default(log, "removed", Value::Bool(false));
+            }
+        }
+    }
+}
+
+/// Inserts a field that is missing. Fields that are `null` are kept.
+fn insert_missing(object: &mut Map<String, Value>, field: &str, value: Value) {
+    object.entry(field).or_insert(value);
+}
+
+/// Sets a field that is missing or `null` to a default value.
+fn default(object: &mut Map<String, Value>, field: &str, value: Value) {
+    let entry = object.entry(field).or_insert(Value::Null);
+    if entry.is_null() {
+        *entry = value;
+    }
+}
+
+/// Renames a field used by some clients to its standard name, unless the
+/// standard field is also present.
+fn rename(object: &mut Map<String, Value>, from: &str, to: &str) {
+    if !object.contains_key(to) {
+        if let Some(value) = object.remove(from) {
+            object.insert(to.to_owned(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::{Id, JsonRpc};
+    use rocket::serde::json::serde_json::json;
+
+    fn normalize(method: &str, result: Value) -> Value {
+        let mut response = Response {
+            jsonrpc: JsonRpc::V2,
+            result: Ok(serde_json::value::to_raw_value(&result).unwrap()),
+            id: Id::Null,
+        };
+        normalize_response(method, &mut response);
+        serde_json::from_str(response.result.unwrap().get()).unwrap()
+    }
+
+    #[test]
+    fn normalizes_blocks_and_transactions() {
+        let block = normalize(
+            "eth_getBlockByNumber",
+            json!({
+                "author": "0x01",
+                "uncles": null,
+                "transactions": [{ "hash": "0x02", "data": "0x", "type": "0x2", "v": "0x1" }],
+            }),
+        );
+        assert_eq!(
+            block,
+            json!({
+                "miner": "0x01",
+                "uncles": [],
+                "transactions": [{
+                    "hash": "0x02",
+                    "input": "0x",
+                    "blockHash": null,
+                    "blockNumber": null,
+                    "transactionIndex": null,
+                    "to": null,
+                    "type": "0x2",
+                    "accessList": [],
+                    "v": "0x1",
+                    "yParity": "0x1",
+                }],
+            }),
+        );
+
+        assert_eq!(normalize("eth_getBlockByNumber", Value::Null), Value::Null);
+    }
+
+    #[test]
+    fn normalizes_receipts() {
+        let receipt = normalize(
+            "eth_getTransactionReceipt",
+            json!({ "to": "0x01", "logs": [{ "data": "0x" }] }),
+        );
+        assert_eq!(
+            receipt,
+            json!({
+                "to": "0x01",
+                "contractAddress": null,
+                "type": "0x0",
+                "logs": [{ "data": "0x", "removed": false }],
+            }),
+        );
+    }
+}
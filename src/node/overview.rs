@@ -0,0 +1,131 @@
+//! A snapshot of the node's state for operators.
+//!
+//! This gathers the state that is otherwise spread across several `hdnode_*`
+//! RPC methods, along with account balances and remote node health, for the
+//! admin dashboard.
+
+use crate::{
+    metrics::MethodStats,
+    node::{queue, types::BlockTag, Node},
+    serialization::{Quantity, Str},
+    signer::{quota, receipt::SignedReceipt, Signing as _},
+};
+use hdwallet::account::Address;
+use rocket::{
+    futures::future,
+    serde::{json::Value, Serialize},
+};
+use std::collections::{BTreeMap, HashMap};
+
+/// The number of recent signing receipts included in an overview.
+const RECENT_RECEIPTS: usize = 20;
+
+/// A snapshot of the node's state.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Overview {
+    /// Whether or not signing is paused.
+    pub signing_paused: bool,
+    /// The state of the remote node.
+    pub remote: Remote,
+    /// The managed accounts.
+    pub accounts: Vec<Account>,
+    /// The most recent signing receipts, newest first, if receipts are
+    /// issued.
+    pub recent_receipts: Option<Vec<SignedReceipt>>,
+    /// The signing requests pending operator approval.
+    pub pending_approvals: Vec<Value>,
+    /// The signing quota usage, if quotas are enforced.
+    pub quotas: Option<HashMap<String, quota::Usage>>,
+    /// The transaction queues, if transactions are queued.
+    pub queue: Option<HashMap<String, queue::Status>>,
+    /// The number of journaled transactions pending inclusion, if
+    /// transactions are journaled.
+    pub pending_transactions: Option<usize>,
+    /// Request metrics by remote node and method.
+    pub upstreams: BTreeMap<String, BTreeMap<String, MethodStats>>,
+}
+
+/// The state of the remote node.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Remote {
+    /// The chain ID, if the remote node responded.
+    pub chain_id: Option<Quantity>,
+    /// The latest block number, if the remote node responded.
+    pub block_number: Option<Quantity>,
+    /// The error querying the remote node, if any.
+    pub error: Option<String>,
+}
+
+/// A managed account.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Account {
+    pub address: Str<Address>,
+    /// The account's balance, if the remote node responded.
+    pub balance: Option<Quantity>,
+}
+
+impl Node {
+    /// Returns a snapshot of the node's state.
+    pub async fn overview(&self) -> Overview {
+        let accounts = self.signer.accounts();
+        let mut batch = self.remote.batch();
+        let chain_id = batch.chain_id();
+        let balances = accounts
+            .iter()
+            .map(|account| batch.get_balance(*account, BlockTag::Latest))
+            .collect::<Vec<_>>();
+        let result = batch.execute().await;
+        let block_number = self.remote.block_number().await;
+
+        let (remote, balances) = match result {
+            Ok(()) => {
+                let balances = future::join_all(balances).await;
+                let chain_id = chain_id.await;
+                let error = chain_id
+                    .as_ref()
+                    .err()
+                    .or(block_number.as_ref().err())
+                    .map(|err| format!("{err:#}"));
+                let remote = Remote {
+                    chain_id: chain_id.ok().map(Quantity),
+                    block_number: block_number.ok().map(Quantity),
+                    error,
+                };
+                (remote, balances.into_iter().map(Result::ok).collect())
+            }
+            Err(err) => {
+                let remote = Remote {
+                    chain_id: None,
+                    block_number: None,
+                    error: Some(format!("{err:#}")),
+                };
+                (remote, vec![None; accounts.len()])
+            }
+        };
+
+        Overview {
+            signing_paused: self.signer.is_paused(),
+            remote,
+            accounts: accounts
+                .iter()
+                .zip(balances)
+                .map(|(account, balance)| Account {
+                    address: Str(*account),
+                    balance: balance.map(Quantity),
+                })
+                .collect(),
+            recent_receipts: self
+                .receipts
+                .as_ref()
+                .map(|receipts| receipts.recent(RECENT_RECEIPTS)),
+            pending_approvals: self.approvals.pending(),
+            quotas: self.quotas.as_ref().map(quota::Handle::usage),
+            queue: self.queue.as_ref().map(|queue| queue.status()),
+            pending_transactions: self.journal.as_ref().map(|journal| journal.pending().len()),
+            upstreams: self.metrics().stats(),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! EIP-2255 wallet permissions for RPC calls.
+//!
+//! Injected provider shims expect wallets to implement the permission methods
+//! of EIP-1193 wallets, where dapps request access to accounts before using
+//! them. The node doesn't prompt anyone, so permissions are derived from its
+//! configuration instead: the accounts visible to the caller, which depend on
+//! the policy for the origin of the request. Requesting permissions never
+//! grants more than that.
+
+use crate::{serialization::Str, signer::request::RequestContext};
+use anyhow::Result;
+use hdwallet::account::Address;
+use rocket::serde::{json::Value, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// The only capability supported by the node, access to accounts.
+const ETH_ACCOUNTS: &str = "eth_accounts";
+
+/// A permission, as returned by `wallet_getPermissions`.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Permission {
+    /// The method that the permission grants access to.
+    pub parent_capability: &'static str,
+    /// The origin that the permission is granted to, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invoker: Option<String>,
+    /// Restrictions of the permission.
+    pub caveats: Vec<Caveat>,
+}
+
+/// A restriction of a permission.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Caveat {
+    /// The kind of restriction.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// The accounts that are returned.
+    pub value: Vec<Str<Address>>,
+}
+
+/// An error indicating that the caller is not authorized to access any
+/// accounts.
+#[derive(Debug, Error)]
+#[error("no accounts are available to {}", .0.as_deref().unwrap_or("the caller"))]
+pub struct UnauthorizedError(pub Option<String>);
+
+/// Returns the permissions of the caller with the specified visible accounts.
+pub fn get(accounts: &[Address]) -> Vec<Permission> {
+    if accounts.is_empty() {
+        return Vec::new();
+    }
+    vec![Permission {
+        parent_capability: ETH_ACCOUNTS,
+        invoker: origin(),
+        caveats: vec![Caveat {
+            kind: "restrictReturnedAccounts",
+            value: accounts.iter().copied().map(Str).collect(),
+        }],
+    }]
+}
+
+/// Requests permissions for the caller with the specified visible accounts,
+/// returning the requested permissions that are granted. Unsupported
+/// capabilities are never granted.
+pub fn request(
+    requested: &BTreeMap<String, Value>,
+    accounts: &[Address],
+) -> Result<Vec<Permission>> {
+    if !requested.contains_key(ETH_ACCOUNTS) {
+        return Err(UnauthorizedError(origin()).into());
+    }
+    request_accounts(accounts)?;
+    Ok(get(accounts))
+}
+
+/// Requests access to the specified visible accounts, failing if there are
+/// none.
+pub fn request_accounts(accounts: &[Address]) -> Result<&[Address]> {
+    if accounts.is_empty() {
+        return Err(UnauthorizedError(origin()).into());
+    }
+    Ok(accounts)
+}
+
+fn origin() -> Option<String> {
+    RequestContext::current()?.origin
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::{json, serde_json};
+
+    #[test]
+    fn grants_account_permissions() {
+        let accounts = [Address([0x11; 20])];
+        let requested = |value: Value| serde_json::from_value(value).unwrap();
+
+        let granted = request(&requested(json!({ "eth_accounts": {} })), &accounts).unwrap();
+        assert_eq!(
+            serde_json::to_value(granted).unwrap(),
+            json!([{
+                "parentCapability": "eth_accounts",
+                "caveats": [{
+                    "type": "restrictReturnedAccounts",
+                    "value": [accounts[0].to_string()],
+                }],
+            }]),
+        );
+        assert!(request(&requested(json!({ "eth_accounts": {} })), &[]).is_err());
+        assert!(request(&requested(json!({ "snap_dialog": {} })), &accounts).is_err());
+        assert!(get(&[]).is_empty());
+    }
+}
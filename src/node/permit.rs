@@ -0,0 +1,183 @@
+//! EIP-2612 permit helper for RPC calls.
+//!
+//! Constructing permit typed data by hand is error prone, so this builds the
+//! canonical payload from the token's on-chain domain instead.
+
+use crate::{
+    abi, clock,
+    node::{eth::Eth, typeddata::TypedData, types::BlockTag},
+    serialization::{Quantity, Str},
+};
+use anyhow::{ensure, Context as _, Result};
+use ethnum::U256;
+use hdwallet::account::Address;
+use rocket::serde::{
+    json::{self, serde_json::json},
+    Deserialize,
+};
+
+/// Permit parameters used for the `hdnode_signPermit` RPC call.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(crate = "rocket::serde", deny_unknown_fields)]
+pub struct PermitRequest {
+    /// The EIP-2612 token to sign a permit for.
+    pub token: Str<Address>,
+    /// The account being granted an allowance.
+    pub spender: Str<Address>,
+    /// The allowance amount.
+    pub value: Quantity,
+    /// The timestamp until which the permit is valid.
+    pub deadline: Quantity,
+}
+
+impl PermitRequest {
+    /// Builds the `Permit` typed data for the specified owner, reading the
+    /// token's EIP-712 domain and the owner's current nonce from the node.
+    pub async fn typed_data(self, eth: &Eth, owner: Address) -> Result<TypedData> {
+        ensure!(
+            self.spender.0 .0 != [0; 20],
+            "permit spender is the zero address"
+        );
+        ensure!(
+            self.deadline.0 > U256::from(clock::now()),
+            "permit deadline is in the past",
+        );
+
+        let token = self.token.0;
+        let mut batch = eth.batch();
+        let chain_id = batch.chain_id();
+        let name = batch.call_contract(token, abi::call("name()", &[]), BlockTag::Latest);
+        let version = batch.call_contract(token, abi::call("version()", &[]), BlockTag::Latest);
+        let nonce = batch.call_contract(
+            token,
+            abi::call("nonces(address)", &[abi::address(owner)]),
+            BlockTag::Latest,
+        );
+
+        batch.execute().await?;
+
+        let chain_id = chain_id.await?;
+        let name = abi::decode_string(&name.await?).context("failed to read token name")?;
+        // Not all EIP-2612 tokens expose their domain version, in which case
+        // fall back to "1" which is what the OpenZeppelin implementation uses.
+        let version = match version.await {
+            Ok(data) => abi::decode_string(&data).context("failed to read token version")?,
+            Err(_) => "1".to_owned(),
+        };
+        let nonce = abi::decode_uint(&nonce.await?, 0).context("failed to read permit nonce")?;
+
+        let typed_data = json!({
+            "types": {
+                "EIP712Domain": [
+                    { "name": "name", "type": "string" },
+                    { "name": "version", "type": "string" },
+                    { "name": "chainId", "type": "uint256" },
+                    { "name": "verifyingContract", "type": "address" },
+                ],
+                "Permit": [
+                    { "name": "owner", "type": "address" },
+                    { "name": "spender", "type": "address" },
+                    { "name": "value", "type": "uint256" },
+                    { "name": "nonce", "type": "uint256" },
+                    { "name": "deadline", "type": "uint256" },
+                ],
+            },
+            "primaryType": "Permit",
+            "domain": {
+                "name": name,
+                "version": version,
+                "chainId": Quantity(chain_id),
+                "verifyingContract": Str(token),
+            },
+            "message": {
+                "owner": Str(owner),
+                "spender": self.spender,
+                "value": self.value,
+                "nonce": Quantity(nonce),
+                "deadline": self.deadline,
+            },
+        });
+        Ok(json::from_value(typed_data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockUpstream;
+    use rocket::serde::json::from_value;
+
+    fn request(deadline: &str) -> PermitRequest {
+        from_value(json!({
+            "token": "0x3535353535353535353535353535353535353535",
+            "spender": "0x7070707070707070707070707070707070707070",
+            "value": "0x64",
+            "deadline": deadline,
+        }))
+        .unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn builds_permit_typed_data() {
+        let upstream = MockUpstream::start().await;
+        upstream.respond("eth_chainId", json!("0x1"));
+        // The same reply is used for the token's `name()`, `version()` and
+        // `nonces(address)` calls: a string at offset 0x20, which is also
+        // read as a nonce of 0x20.
+        let mut name = [0; 32];
+        name[..4].copy_from_slice(b"Test");
+        let reply = abi::encode(&[abi::uint(U256::new(0x20)), abi::uint(U256::new(4)), name]);
+        upstream.respond("eth_call", json!(format!("0x{}", hex::encode(reply))));
+        let eth = Eth::from_url(upstream.url(), Default::default()).unwrap();
+        let owner = Address([0x42; 20]);
+
+        let typed_data = request("0xffffffffff")
+            .typed_data(&eth, owner)
+            .await
+            .unwrap();
+        let value = typed_data.value();
+        assert_eq!(value["primaryType"], "Permit");
+        assert_eq!(
+            value["domain"],
+            json!({
+                "name": "Test",
+                "version": "Test",
+                "chainId": "0x1",
+                "verifyingContract": "0x3535353535353535353535353535353535353535",
+            }),
+        );
+        assert_eq!(
+            value["message"],
+            json!({
+                "owner": "0x4242424242424242424242424242424242424242",
+                "spender": "0x7070707070707070707070707070707070707070",
+                "value": "0x64",
+                "nonce": "0x20",
+                "deadline": "0xffffffffff",
+            }),
+        );
+    }
+
+    #[rocket::async_test]
+    async fn rejects_invalid_permits() {
+        let upstream = MockUpstream::start().await;
+        let eth = Eth::from_url(upstream.url(), Default::default()).unwrap();
+        let owner = Address([0x42; 20]);
+
+        let err = request("0x1").typed_data(&eth, owner).await.unwrap_err();
+        assert!(err.to_string().contains("deadline is in the past"));
+
+        let mut permit = request("0xffffffffff");
+        permit.spender = Str(Address([0; 20]));
+        let err = permit.typed_data(&eth, owner).await.unwrap_err();
+        assert!(err.to_string().contains("zero address"));
+
+        upstream.respond("eth_chainId", json!("0x1"));
+        upstream.respond("eth_call", json!("0x"));
+        let err = request("0xffffffffff")
+            .typed_data(&eth, owner)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("failed to read token name"));
+    }
+}
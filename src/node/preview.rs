@@ -0,0 +1,246 @@
+//! Transaction previews for RPC calls.
+//!
+//! A preview runs a transaction through everything the node would do before
+//! signing it (filling, simulation and the checks of the signer pipeline),
+//! without producing a signature. This allows UIs to show users the outcome of
+//! a transaction before committing to it.
+
+use crate::{
+    jsonrpc,
+    node::{
+        eth::Eth,
+        revert,
+        token::{self, TokenOperation},
+        transaction::{FillOptions, FilledTransaction, TransactionRequest},
+        types::{AccessListItem, BlockTag},
+    },
+    selectors::Selectors,
+    serialization::{Bytes, Quantity},
+    signer::{
+        request::{self, Decision, PreviewError},
+        Signing,
+    },
+};
+use anyhow::Result;
+use ethnum::U256;
+use rocket::serde::Serialize;
+
+/// The would-be decision about signing a transaction.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub enum Verdict {
+    /// The transaction would be signed.
+    Allow,
+    /// The transaction would be rejected.
+    Reject,
+}
+
+/// The preview of a transaction, as returned by `hdnode_previewTransaction`.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Preview {
+    /// Whether the transaction would be signed or rejected.
+    pub decision: Verdict,
+    /// The reason the transaction would be rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// The decisions made by the signers in the pipeline.
+    pub decisions: Vec<Decision>,
+    /// The filled transaction.
+    pub transaction: FilledTransaction,
+    /// The estimated fees of the transaction.
+    pub fees: Fees,
+    /// The signature of the called function, if known.
+    pub function: Option<String>,
+    /// The decoded token transfers and approvals.
+    pub tokens: Vec<TokenOperation>,
+    /// The result of simulating the transaction.
+    pub simulation: Simulation,
+}
+
+/// The estimated fees of a transaction.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Fees {
+    /// The gas limit.
+    pub gas: Quantity<u64>,
+    /// The maximum price paid per unit of gas, either the gas price or the
+    /// maximum fee per gas.
+    pub max_gas_price: Quantity,
+    /// The maximum fee paid for the transaction.
+    pub max_fee: Quantity,
+    /// The maximum total cost of the transaction, including its value.
+    pub max_cost: Quantity,
+}
+
+/// The result of simulating a transaction.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Simulation {
+    /// Whether or not the simulation succeeded.
+    pub success: bool,
+    /// The data returned by the transaction.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_data: Option<Bytes<Vec<u8>>>,
+    /// The error that the simulation failed with, such as a revert.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The decoded revert reason, if the simulation reverted with one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revert_reason: Option<String>,
+    /// The accounts and storage slots accessed by the transaction, if the
+    /// remote node supports creating access lists.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_list: Option<Vec<AccessListItem>>,
+    /// The gas used by the transaction with its access list.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_used: Option<Quantity>,
+    /// Whether or not the recipient of the transaction has code. This is not
+    /// included for contract creations.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recipient_has_code: Option<bool>,
+}
+
+/// Previews signing a transaction request.
+pub async fn preview(
+    signer: &impl Signing,
+    eth: &Eth,
+    request: TransactionRequest,
+    fill: FillOptions,
+    selectors: &Selectors,
+) -> Result<Preview> {
+    let (account, transaction) = request.fill(eth, fill).await?;
+    let filled = transaction.request();
+
+    let mut batch = eth.batch();
+    let result = batch.simulate(account, filled, BlockTag::Pending);
+    let access_list = batch.create_access_list(account, filled, BlockTag::Pending);
+    let code = filled.to.map(|to| batch.get_code(to.0, BlockTag::Pending));
+    batch.execute().await?;
+
+    let (access_list, gas_used) = match access_list.await {
+        Ok(result) => (Some(result.access_list), Some(result.gas_used)),
+        Err(_) => (None, None),
+    };
+    let recipient_has_code = match code {
+        Some(code) => code.await.ok().map(|code| !code.is_empty()),
+        None => None,
+    };
+    let simulation = match result.await {
+        Ok(data) => Simulation {
+            success: true,
+            return_data: Some(Bytes(data)),
+            error: None,
+            revert_reason: None,
+            access_list,
+            gas_used,
+            recipient_has_code,
+        },
+        Err(err) => Simulation {
+            success: false,
+            return_data: None,
+            revert_reason: err
+                .downcast_ref::<jsonrpc::Error>()
+                .and_then(|err| revert::error_reason(err, selectors)),
+            error: Some(err.to_string()),
+            access_list,
+            gas_used,
+            recipient_has_code,
+        },
+    };
+
+    // Filled transactions always have a gas limit and a gas price or maximum
+    // fee per gas.
+    let gas = filled.gas.unwrap_or_default().0;
+    let max_gas_price = filled
+        .max_fee_per_gas
+        .or(filled.gas_price)
+        .unwrap_or_default()
+        .0;
+    let max_fee = U256::from(gas).saturating_mul(max_gas_price);
+    let fees = Fees {
+        gas: Quantity(gas),
+        max_gas_price: Quantity(max_gas_price),
+        max_fee: Quantity(max_fee),
+        max_cost: Quantity(max_fee.saturating_add(filled.value.0)),
+    };
+    let function = selectors.function(&filled.data.0);
+    let tokens = match &filled.to {
+        Some(to) => token::decode(to.0, &filled.data.0),
+        None => Vec::new(),
+    };
+
+    let result = request::preview(async { signer.sign_transaction(account, &transaction) }).await;
+    let decisions = request::take_decisions();
+    let (decision, reason) = match result {
+        Err(err) if !err.is::<PreviewError>() => (Verdict::Reject, Some(err.to_string())),
+        _ => (Verdict::Allow, None),
+    };
+
+    Ok(Preview {
+        decision,
+        reason,
+        decisions,
+        transaction: FilledTransaction::new(account, transaction),
+        fees,
+        function,
+        tokens,
+        simulation,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{call, client_with, MockUpstream, ACCOUNT};
+    use rocket::serde::json::serde_json::json;
+
+    #[rocket::async_test]
+    async fn previews_transactions_without_signing() {
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment.merge((
+                "allowed_destinations",
+                ["0x3535353535353535353535353535353535353535"],
+            ))
+        })
+        .await;
+        upstream.respond("eth_getTransactionCount", json!("0x0"));
+        upstream.respond("eth_call", json!("0x"));
+        upstream.respond("eth_getCode", json!("0x"));
+
+        let transaction = |to: &str| {
+            json!([{
+                "from": ACCOUNT,
+                "to": to,
+                "gas": "0x5208",
+                "gasPrice": "0x2",
+                "value": "0x1",
+            }])
+        };
+        let allowed = call(
+            &client,
+            "hdnode_previewTransaction",
+            transaction("0x3535353535353535353535353535353535353535"),
+        )
+        .await;
+        let denied = call(
+            &client,
+            "hdnode_previewTransaction",
+            transaction("0x4242424242424242424242424242424242424242"),
+        )
+        .await;
+
+        assert_eq!(allowed["result"]["decision"], "allow");
+        assert_eq!(allowed["result"]["fees"]["maxFee"], "0xa410");
+        assert_eq!(allowed["result"]["fees"]["maxCost"], "0xa411");
+        assert_eq!(allowed["result"]["simulation"]["success"], true);
+        assert_eq!(allowed["result"]["simulation"]["recipientHasCode"], false);
+        assert_eq!(allowed["result"]["transaction"]["nonce"], "0x0");
+        assert_eq!(denied["result"]["decision"], "reject");
+        assert!(denied["result"]["reason"].is_string());
+        assert!(!upstream
+            .methods()
+            .iter()
+            .any(|method| method == "eth_sendRawTransaction"));
+    }
+}
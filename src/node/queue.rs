@@ -0,0 +1,478 @@
+//! Sequential transaction submission per account.
+//!
+//! Clients sending transactions for the same account in parallel race for
+//! nonces: each one is filled with the account's transaction count, so all but
+//! one end up being rejected or replacing each other. The queue orders
+//! `eth_sendTransaction` calls per account, and fills, signs and submits them
+//! one at a time with consecutive nonces.
+//!
+//! The next nonce of an account is cached between transactions, and can go
+//! stale when the account also sends transactions outside of the node. When
+//! the remote node rejects a transaction because its nonce is already used,
+//! the cached nonce is dropped so that the next transaction is filled with the
+//! account's transaction count again.
+//!
+//! In cluster mode, instances serving the same accounts additionally hold a
+//! per-account lock in the shared backend while submitting a transaction, and
+//! keep the next nonce there, so that they never sign conflicting nonces.
+
+use crate::{
+    backend::{Lock, SharedBackend},
+    jsonrpc,
+    node::{
+        eth::Eth,
+        transaction::{FillOptions, TransactionRequest},
+    },
+    serialization::{Bytes, Quantity},
+    signer::Signing,
+};
+use anyhow::{Context as _, Result};
+use hdwallet::account::Address;
+use rocket::{
+    serde::{Deserialize, Serialize},
+    tokio::sync::Mutex as AsyncMutex,
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use thiserror::Error;
+
+/// The default maximum number of queued transactions per account.
+const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// How long a cluster lock on an account's nonce is held at most, in case the
+/// instance holding it fails. The lock is renewed before each submission.
+const NONCE_LOCK_TTL: Duration = Duration::from_secs(30);
+
+/// Transaction queue options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Options {
+    /// Whether or not to queue `eth_sendTransaction` calls per account.
+    #[serde(default)]
+    pub transaction_queue: bool,
+
+    /// The maximum number of transactions queued per account, including the
+    /// one being submitted. Defaults to 64.
+    #[serde(default)]
+    pub max_queue_depth: Option<usize>,
+
+    /// Whether or not to refill and resubmit a transaction once with a fresh
+    /// nonce when the remote node rejects it because its nonce is already
+    /// used. Transactions with an explicit nonce are never resubmitted.
+    #[serde(default)]
+    pub retry_stale_nonce: bool,
+}
+
+/// The queue of an account, which only exists while it has transactions.
+#[derive(Default)]
+struct AccountQueue {
+    /// The number of queued transactions.
+    depth: usize,
+    /// Held while a transaction is being submitted.
+    turn: Arc<AsyncMutex<()>>,
+}
+
+/// The state of an account's queue, as returned by `hdnode_queue`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Status {
+    /// The number of queued transactions, including the one being submitted.
+    pub depth: usize,
+    /// The nonce following the last submitted transaction, if known.
+    pub next_nonce: Option<Quantity<u64>>,
+}
+
+/// Per-account transaction submission queues.
+pub struct Queue {
+    max_depth: usize,
+    retry: bool,
+    accounts: Mutex<HashMap<[u8; 20], AccountQueue>>,
+    /// The nonce following the last submitted transaction of each account.
+    next_nonces: Mutex<HashMap<[u8; 20], u64>>,
+    cluster: Option<SharedBackend>,
+}
+
+impl Queue {
+    /// Creates a new transaction queue from the specified options, or `None`
+    /// if queueing is not enabled.
+    pub fn new(options: &Options) -> Option<Self> {
+        options.transaction_queue.then(|| Self {
+            max_depth: options.max_queue_depth.unwrap_or(DEFAULT_MAX_DEPTH),
+            retry: options.retry_stale_nonce,
+            accounts: Mutex::default(),
+            next_nonces: Mutex::default(),
+            cluster: None,
+        })
+    }
+
+    /// Coordinates nonces with other instances through the specified shared
+    /// backend.
+    pub fn with_cluster(mut self, backend: SharedBackend) -> Self {
+        self.cluster = Some(backend);
+        self
+    }
+
+    /// Queues a transaction, returning its hash once it is submitted with
+    /// `submit`.
+    pub async fn send<F, Fut>(
+        &self,
+        signer: &impl Signing,
+        eth: &Eth,
+        request: TransactionRequest,
+        fill: FillOptions,
+        submit: F,
+    ) -> Result<Bytes<[u8; 32]>>
+    where
+        F: Fn(Address, Quantity<u64>, Vec<u8>) -> Fut,
+        Fut: Future<Output = Result<Bytes<[u8; 32]>>>,
+    {
+        let account = request.from.0;
+        // Transactions for accounts that the signer doesn't manage fail to
+        // sign, so they are not queued, and don't leave queues behind.
+        if !signer.accounts().contains(&account) {
+            let (account, transaction) = request.fill(eth, fill).await?;
+            let signature = signer.sign_transaction(account, &transaction)?;
+            let nonce = transaction.request().nonce.unwrap_or_default();
+            return submit(account, nonce, transaction.encode(signature)).await;
+        }
+        let (_entry, turn) = Entry::new(self, account)?;
+
+        // Tokio mutexes are fair, so transactions are submitted in the order
+        // they were queued.
+        let _turn = turn.lock().await;
+        let lock = match &self.cluster {
+            Some(backend) => {
+                Some(Lock::acquire(backend, &lock_key(account), NONCE_LOCK_TTL).await?)
+            }
+            None => None,
+        };
+        let mut retry = self.retry && request.nonce.is_none();
+        loop {
+            let next_nonce = self.next_nonce(account)?;
+            let (account, transaction) = request
+                .clone()
+                .fill(eth, FillOptions { next_nonce, ..fill })
+                .await?;
+            let signature = signer.sign_transaction(account, &transaction)?;
+            let nonce = transaction.request().nonce.unwrap_or_default();
+            // Filling and signing can take a while, for example when waiting
+            // for operator approval, so make sure that no other instance took
+            // over the nonce in the meantime and that the lock outlives the
+            // submission.
+            if let Some(lock) = &lock {
+                lock.renew()?;
+            }
+            match submit(account, nonce, transaction.encode(signature)).await {
+                Ok(hash) => {
+                    self.set_next_nonce(account, Some(nonce.0 + 1));
+                    return Ok(hash);
+                }
+                Err(err) => {
+                    let reason = match nonce_conflict(&err) {
+                        Some(reason) => reason.to_owned(),
+                        None => return Err(err),
+                    };
+                    tracing::warn!(%account, nonce = %nonce.0, %reason, "resyncing stale nonce");
+                    self.set_next_nonce(account, None);
+                    if !retry {
+                        return Err(StaleNonceError {
+                            account,
+                            nonce: nonce.0,
+                            reason,
+                        }
+                        .into());
+                    }
+                    retry = false;
+                }
+            }
+        }
+    }
+
+    /// Returns the nonce following the last submitted transaction of an
+    /// account, shared between instances in cluster mode.
+    fn next_nonce(&self, account: Address) -> Result<Option<u64>> {
+        match &self.cluster {
+            Some(backend) => backend
+                .get(&nonce_key(account))?
+                .map(|nonce| nonce.parse().context("invalid shared nonce"))
+                .transpose(),
+            None => Ok(self.next_nonces.lock().unwrap().get(&account.0).copied()),
+        }
+    }
+
+    /// Updates the nonce following the last submitted transaction of an
+    /// account, or drops it so that it is resynced.
+    fn set_next_nonce(&self, account: Address, nonce: Option<u64>) {
+        let mut next_nonces = self.next_nonces.lock().unwrap();
+        match nonce {
+            Some(nonce) => next_nonces.insert(account.0, nonce),
+            None => next_nonces.remove(&account.0),
+        };
+        drop(next_nonces);
+        if let Some(backend) = &self.cluster {
+            let key = nonce_key(account);
+            let result = match nonce {
+                Some(nonce) => backend.set(&key, &nonce.to_string(), None),
+                None => backend.delete(&key),
+            };
+            // The transaction was already submitted at this point, so only
+            // report the error. Nonce conflicts resync the shared nonce.
+            if let Err(err) = result {
+                tracing::warn!(?err, %account, "failed to update shared nonce");
+            }
+        }
+    }
+
+    /// Returns the state of the queues of all accounts with queued
+    /// transactions or a known next nonce.
+    pub fn status(&self) -> HashMap<String, Status> {
+        let accounts = self.accounts.lock().unwrap();
+        let next_nonces = self.next_nonces.lock().unwrap();
+        accounts
+            .keys()
+            .chain(next_nonces.keys())
+            .map(|account| {
+                let status = Status {
+                    depth: accounts.get(account).map_or(0, |queue| queue.depth),
+                    next_nonce: next_nonces.get(account).copied().map(Quantity),
+                };
+                (Address(*account).to_string(), status)
+            })
+            .collect()
+    }
+}
+
+/// Returns the backend key of an account's next nonce.
+fn nonce_key(account: Address) -> String {
+    format!("hdnode:nonce:{account}")
+}
+
+/// Returns the backend key of the lock on an account's nonce.
+fn lock_key(account: Address) -> String {
+    format!("hdnode:lock:nonce:{account}")
+}
+
+/// A transaction in an account's queue, removed from it when dropped. The
+/// queue itself is removed with its last transaction.
+struct Entry<'a> {
+    queue: &'a Queue,
+    account: Address,
+}
+
+impl<'a> Entry<'a> {
+    /// Adds a transaction to an account's queue, returning the entry and the
+    /// lock to hold while submitting it.
+    fn new(queue: &'a Queue, account: Address) -> Result<(Self, Arc<AsyncMutex<()>>)> {
+        let mut accounts = queue.accounts.lock().unwrap();
+        let depth = accounts.get(&account.0).map_or(0, |queue| queue.depth);
+        if depth >= queue.max_depth {
+            return Err(QueueFullError {
+                account,
+                max_depth: queue.max_depth,
+            }
+            .into());
+        }
+        let account_queue = accounts.entry(account.0).or_default();
+        account_queue.depth += 1;
+        Ok((Self { queue, account }, account_queue.turn.clone()))
+    }
+}
+
+impl Drop for Entry<'_> {
+    fn drop(&mut self) {
+        let mut accounts = self.queue.accounts.lock().unwrap();
+        if let Some(queue) = accounts.get_mut(&self.account.0) {
+            queue.depth -= 1;
+            if queue.depth == 0 {
+                accounts.remove(&self.account.0);
+            }
+        }
+    }
+}
+
+/// An error indicating that an account's transaction queue is full.
+#[derive(Debug, Error)]
+#[error("transaction queue for {account} is full ({max_depth} transactions)")]
+pub struct QueueFullError {
+    pub account: Address,
+    pub max_depth: usize,
+}
+
+/// An error indicating that the remote node rejected a transaction because
+/// its nonce is already used.
+#[derive(Debug, Error)]
+#[error("nonce {nonce} for {account} is already used ({reason}), the account's nonce was resynced")]
+pub struct StaleNonceError {
+    pub account: Address,
+    pub nonce: u64,
+    pub reason: String,
+}
+
+/// Returns the remote node's error message if a submission error indicates
+/// that the transaction's nonce is already used.
+fn nonce_conflict(err: &anyhow::Error) -> Option<&str> {
+    let message = &err.downcast_ref::<jsonrpc::Error>()?.message;
+    let lowercase = message.to_lowercase();
+    (lowercase.contains("nonce too low")
+        || (lowercase.contains("replacement") && lowercase.contains("underpriced")))
+    .then_some(message.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{call, call_as_admin, client_with, MockUpstream, ACCOUNT};
+    use rocket::serde::json::serde_json::json;
+
+    #[test]
+    fn limits_queue_depth() {
+        let queue = Queue::new(&Options {
+            transaction_queue: true,
+            max_queue_depth: Some(2),
+            ..Default::default()
+        })
+        .unwrap();
+        let account = Address([0x42; 20]);
+
+        let first = Entry::new(&queue, account).unwrap();
+        let second = Entry::new(&queue, account).unwrap();
+        assert!(Entry::new(&queue, account)
+            .err()
+            .unwrap()
+            .is::<QueueFullError>());
+        assert_eq!(queue.status()[&account.to_string()].depth, 2);
+
+        drop((first, second));
+        assert!(queue.accounts.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn detects_nonce_conflicts() {
+        let error = |message: &str| {
+            anyhow::Error::new(jsonrpc::Error {
+                code: -32000,
+                message: message.to_owned(),
+                data: None,
+            })
+        };
+
+        assert_eq!(
+            nonce_conflict(&error("nonce too low: next nonce 8, tx nonce 7")),
+            Some("nonce too low: next nonce 8, tx nonce 7"),
+        );
+        assert!(nonce_conflict(&error("replacement transaction underpriced")).is_some());
+        assert!(nonce_conflict(&error("insufficient funds for gas * price + value")).is_none());
+        assert!(nonce_conflict(&anyhow::anyhow!("nonce too low")).is_none());
+    }
+
+    #[rocket::async_test]
+    async fn queues_transactions_with_consecutive_nonces() {
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment
+                .merge(("transaction_queue", true))
+                .merge(("admin_token", "secret"))
+        })
+        .await;
+        upstream.respond("eth_getTransactionCount", json!("0x7"));
+        upstream.respond(
+            "eth_sendRawTransaction",
+            json!(format!("0x{}", "42".repeat(32))),
+        );
+
+        let transaction = json!([{
+            "from": ACCOUNT,
+            "to": "0x3535353535353535353535353535353535353535",
+            "gas": "0x5208",
+            "gasPrice": "0x1",
+        }]);
+        let (first, second) = rocket::tokio::join!(
+            call(&client, "eth_sendTransaction", transaction.clone()),
+            call(&client, "eth_sendTransaction", transaction),
+        );
+        let queue = call_as_admin(&client, "hdnode_queue", json!([])).await;
+
+        assert!(first["result"].is_string());
+        assert!(second["result"].is_string());
+        let submitted = upstream
+            .requests()
+            .into_iter()
+            .filter(|request| request["method"] == "eth_sendRawTransaction")
+            .count();
+        assert_eq!(submitted, 2);
+        let (_, status) = queue["result"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .find(|(account, _)| account.eq_ignore_ascii_case(ACCOUNT))
+            .unwrap();
+        assert_eq!(status, &json!({ "depth": 0, "nextNonce": "0x9" }));
+    }
+
+    #[rocket::async_test]
+    async fn only_queues_managed_accounts() {
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment
+                .merge(("transaction_queue", true))
+                .merge(("admin_token", "secret"))
+        })
+        .await;
+        upstream.respond("eth_getTransactionCount", json!("0x7"));
+
+        let transaction = json!([{
+            "from": "0x3535353535353535353535353535353535353535",
+            "to": ACCOUNT,
+            "gas": "0x5208",
+            "gasPrice": "0x1",
+        }]);
+        let response = call(&client, "eth_sendTransaction", transaction).await;
+        let unauthorized = call(&client, "hdnode_queue", json!([])).await;
+        let queue = call_as_admin(&client, "hdnode_queue", json!([])).await;
+
+        assert!(response["error"].is_object());
+        assert_eq!(unauthorized["error"]["code"], 4100);
+        assert_eq!(queue["result"], json!({}));
+    }
+
+    #[rocket::async_test]
+    async fn resyncs_stale_nonces() {
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment
+                .merge(("transaction_queue", true))
+                .merge(("retry_stale_nonce", true))
+        })
+        .await;
+        upstream.respond("eth_getTransactionCount", json!("0x7"));
+        upstream.fail(
+            "eth_sendRawTransaction",
+            -32000,
+            "nonce too low: next nonce 8, tx nonce 7",
+        );
+
+        let transaction = json!([{
+            "from": ACCOUNT,
+            "to": "0x3535353535353535353535353535353535353535",
+            "gas": "0x5208",
+            "gasPrice": "0x1",
+        }]);
+        let response = call(&client, "eth_sendTransaction", transaction).await;
+
+        assert_eq!(response["error"]["code"], -32000);
+        assert!(response["error"]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("nonce 7 for"));
+        // The transaction is refilled with the account's transaction count
+        // and resubmitted once.
+        let methods = upstream.methods();
+        let count = |method: &str| methods.iter().filter(|m| *m == method).count();
+        assert_eq!(count("eth_getTransactionCount"), 2);
+        assert_eq!(count("eth_sendRawTransaction"), 2);
+    }
+}
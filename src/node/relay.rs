@@ -0,0 +1,215 @@
+//! Private transaction relay submission.
+//!
+//! Transactions broadcast through the public mempool can be front-run or
+//! sandwiched. Private relays, such as Flashbots Protect or MEV Blocker, accept
+//! signed transactions over the standard `eth_sendRawTransaction` method and
+//! include them without exposing them publicly. Relays that identify senders
+//! expect the request body to be signed in the `X-Flashbots-Signature` header
+//! with a searcher identity key, which is unrelated to the signing accounts.
+
+use crate::{
+    abi,
+    jsonrpc::{Id, JsonRpc, Params, Request, Response},
+    node::{bundle::Bundle, eth::Eth},
+    serialization::{Bytes, Str},
+    VERSION,
+};
+use anyhow::{Context as _, Result};
+use hdwallet::{
+    account::{PrivateKey, Signature},
+    message::EthereumMessage,
+};
+use reqwest::{header::CONTENT_TYPE, Url};
+use rocket::serde::{
+    json::serde_json::{self, value::RawValue},
+    Deserialize,
+};
+
+/// Private relay options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Options {
+    /// The private relay that signed transactions are submitted to instead
+    /// of the remote node.
+    #[serde(default)]
+    pub private_relay_url: Option<Str<Url>>,
+
+    /// The hex private key of the searcher identity that relay requests are
+    /// signed with. Requests are not signed when not specified.
+    #[serde(default)]
+    pub relay_signing_key: Option<String>,
+}
+
+/// A private transaction relay.
+pub struct Relay {
+    client: reqwest::Client,
+    url: Url,
+    key: Option<PrivateKey>,
+}
+
+impl Relay {
+    /// Creates a new relay from the specified options, or `None` if no relay
+    /// is configured.
+    pub fn new(options: &Options) -> Result<Option<Self>> {
+        let url = match &options.private_relay_url {
+            Some(url) => url.0.clone(),
+            None => return Ok(None),
+        };
+        // Never include the key material in errors, since they get logged.
+        let key = options
+            .relay_signing_key
+            .as_deref()
+            .map(|key| key.trim().parse::<PrivateKey>())
+            .transpose()
+            .map_err(|_| anyhow::anyhow!("invalid relay signing key"))?;
+        let client = reqwest::Client::builder().user_agent(VERSION).build()?;
+
+        Ok(Some(Self { client, url, key }))
+    }
+
+    /// Sends a JSON RPC request to the relay, signing its body if a signing
+    /// key is configured.
+    pub async fn call(&self, method: &str, params: Params) -> Result<Response> {
+        let body = serde_json::to_vec(&Request {
+            jsonrpc: JsonRpc::V2,
+            method: method.to_owned(),
+            params: Some(params),
+            id: Id::Number(1.into()),
+        })?;
+
+        let mut request = self
+            .client
+            .post(self.url.clone())
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(key) = &self.key {
+            let signature = sign_body(key, &body);
+            request = request.header(
+                "X-Flashbots-Signature",
+                format!("{}:{signature}", key.address()),
+            );
+        }
+        request
+            .body(body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context("failed to send request to private relay")?
+            .json::<Response>()
+            .await
+            .context("invalid private relay response")
+    }
+
+    /// Submits a signed transaction to the relay, returning its hash.
+    pub async fn send_raw_transaction(&self, transaction: &[u8]) -> Result<Bytes<[u8; 32]>> {
+        let response = self
+            .call(
+                "eth_sendRawTransaction",
+                Params::new((Bytes(transaction),))?,
+            )
+            .await?;
+        Ok(serde_json::from_str(response.result?.get())?)
+    }
+
+    /// Submits a signed bundle to the relay, returning the relay's result.
+    pub async fn send_bundle(&self, bundle: &Bundle) -> Result<Box<RawValue>> {
+        let response = self.call("eth_sendBundle", Params::new((bundle,))?).await?;
+        Ok(response.result?)
+    }
+}
+
+/// Submits a signed transaction to the private relay, if there is one, or to
+/// the remote node otherwise.
+pub async fn submit(
+    relay: Option<&Relay>,
+    eth: &Eth,
+    transaction: Vec<u8>,
+) -> Result<Bytes<[u8; 32]>> {
+    match relay {
+        Some(relay) => relay.send_raw_transaction(&transaction).await,
+        None => eth.send_raw_transaction(transaction).await,
+    }
+}
+
+/// Signs a request body for the `X-Flashbots-Signature` header, as an EIP-191
+/// message of the hex encoded hash of the body.
+fn sign_body(key: &PrivateKey, body: &[u8]) -> Signature {
+    let message = format!("0x{}", hex::encode(abi::keccak256(body)));
+    key.sign(EthereumMessage(message.as_bytes()).signing_message())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        recovery,
+        testing::{call, client_with, MockUpstream, ACCOUNT},
+    };
+    use rocket::serde::json::serde_json::json;
+
+    #[test]
+    fn signs_request_bodies() {
+        let key = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+            .parse::<PrivateKey>()
+            .unwrap();
+        let body = br#"{"jsonrpc":"2.0","method":"eth_sendRawTransaction"}"#;
+
+        let signature = sign_body(&key, body);
+        let message = format!("0x{}", hex::encode(abi::keccak256(body)));
+        let public_key = recovery::recover_public_key(
+            EthereumMessage(message.as_bytes()).signing_message(),
+            &signature,
+        )
+        .unwrap();
+        assert_eq!(recovery::address(&public_key), key.address());
+    }
+
+    #[rocket::async_test]
+    async fn signs_and_forwards_bundles_to_relay() {
+        let upstream = MockUpstream::start().await;
+        let relay = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment
+                .merge(("private_relay_url", relay.url().as_str()))
+                .merge((
+                    "relay_signing_key",
+                    "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d",
+                ))
+        })
+        .await;
+        upstream.respond("eth_getTransactionCount", json!("0x7"));
+        relay.respond(
+            "eth_sendBundle",
+            json!({ "bundleHash": format!("0x{}", "42".repeat(32)) }),
+        );
+
+        let transaction = json!({
+            "from": ACCOUNT,
+            "to": "0x3535353535353535353535353535353535353535",
+            "gas": "0x5208",
+            "gasPrice": "0x1",
+        });
+        let response = call(
+            &client,
+            "eth_sendBundle",
+            json!([{
+                "txs": ["0x01", transaction, transaction],
+                "blockNumber": "0x10",
+            }]),
+        )
+        .await;
+
+        assert!(response["result"]["bundleHash"].is_string());
+        let bundles = relay.requests();
+        assert_eq!(bundles.len(), 1);
+        let bundle = &bundles[0]["params"][0];
+        assert_eq!(bundle["blockNumber"], "0x10");
+        let txs = bundle["txs"].as_array().unwrap();
+        assert_eq!(txs.len(), 3);
+        assert_eq!(txs[0], "0x01");
+        assert_ne!(txs[1], txs[2]);
+        assert!(upstream
+            .requests()
+            .iter()
+            .all(|request| request["method"] != "eth_sendRawTransaction"));
+    }
+}
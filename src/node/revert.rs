@@ -0,0 +1,117 @@
+//! Human-readable revert reasons for failed calls.
+//!
+//! Remote nodes include the raw revert data as the error data of failed
+//! `eth_estimateGas`, `eth_call` and `eth_sendRawTransaction` calls. Standard
+//! `Error(string)` and `Panic(uint256)` payloads are decoded, and custom errors
+//! are named by looking up their selector in the function signature database,
+//! since custom error selectors are computed the same way as function ones.
+
+use crate::{abi, jsonrpc, selectors::Selectors, serialization::Bytes};
+use rocket::serde::json::{json, serde_json};
+
+/// The selector of `Error(string)` reverts.
+const ERROR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// The selector of `Panic(uint256)` reverts.
+const PANIC: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// Returns whether or not errors of the specified method can carry revert
+/// data to decode.
+pub fn decodes(method: &str) -> bool {
+    matches!(
+        method,
+        "eth_estimateGas" | "eth_call" | "eth_sendRawTransaction"
+    )
+}
+
+/// Decodes a revert reason from some revert data, if possible, naming custom
+/// errors with the specified function signature database.
+pub fn reason(data: &[u8], selectors: &Selectors) -> Option<String> {
+    let selector: [u8; 4] = data.get(..4)?.try_into().ok()?;
+    let args = &data[4..];
+    match selector {
+        ERROR => abi::decode_string(args).ok(),
+        PANIC => {
+            let code = abi::decode_uint(args, 0).ok()?.as_u64();
+            Some(format!("panic: {} ({code:#04x})", panic(code)))
+        }
+        _ => Some(format!("custom error {}", selectors.function(data)?)),
+    }
+}
+
+/// Returns the revert reason of an error from the remote node, if any.
+pub fn error_reason(err: &jsonrpc::Error, selectors: &Selectors) -> Option<String> {
+    let data = serde_json::from_value::<Bytes<Vec<u8>>>(err.data.clone()?).ok()?;
+    reason(&data, selectors)
+}
+
+/// Adds the decoded revert reason to the data of an error from the remote
+/// node, as `{ "data": "0x...", "reason": "..." }`. Errors without decodable
+/// revert data are left untouched.
+pub fn annotate(err: &mut jsonrpc::Error, selectors: &Selectors) {
+    if let Some(reason) = error_reason(err, selectors) {
+        err.data = Some(json!({ "data": err.data.take(), "reason": reason }));
+    }
+}
+
+/// Annotates the error of a response from the remote node.
+pub fn annotate_response(method: &str, response: &mut jsonrpc::Response, selectors: &Selectors) {
+    if let (true, Err(err)) = (decodes(method), &mut response.result) {
+        annotate(err, selectors);
+    }
+}
+
+/// Describes a Solidity panic code.
+fn panic(code: u64) -> &'static str {
+    match code {
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum value",
+        0x22 => "invalid storage byte array encoding",
+        0x31 => "pop from empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to zero-initialized function",
+        _ => "generic panic",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethnum::U256;
+
+    #[test]
+    fn decodes_standard_reverts() {
+        let mut error = [
+            &ERROR[..],
+            &abi::uint(U256::new(0x20)),
+            &abi::uint(U256::new(5)),
+        ]
+        .concat();
+        error.extend_from_slice(&[b"oops!".as_slice(), &[0; 27]].concat());
+        let selectors = Selectors::default();
+        assert_eq!(reason(&error, &selectors).as_deref(), Some("oops!"));
+
+        let panic = [&PANIC[..], &abi::uint(U256::new(0x11))].concat();
+        assert_eq!(
+            reason(&panic, &selectors).as_deref(),
+            Some("panic: arithmetic overflow or underflow (0x11)"),
+        );
+
+        let mut err = jsonrpc::Error {
+            code: 3,
+            message: "execution reverted".to_owned(),
+            data: Some(json!(format!("0x{}", hex::encode(&panic)))),
+        };
+        annotate(&mut err, &selectors);
+        assert_eq!(
+            err.data.unwrap()["reason"],
+            "panic: arithmetic overflow or underflow (0x11)",
+        );
+
+        // Unknown selectors are left undecoded.
+        assert_eq!(reason(&[0xde, 0xad, 0xbe, 0xef], &selectors), None);
+    }
+}
@@ -0,0 +1,152 @@
+//! Per-connection state for long-lived client connections.
+//!
+//! HTTP requests are stateless, but WebSocket clients keep a connection open
+//! for as long as a dapp is connected, which is what wallets scope permissions
+//! and the selected chain to. A session tracks that state for a connection,
+//! and is torn down along with the connection's subscriptions when the client
+//! disconnects.
+
+use crate::serialization::{Quantity, Str};
+use ethnum::U256;
+use hdwallet::account::Address;
+use rocket::serde::Serialize;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+/// The ID of the next session.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The state of a client connection.
+#[derive(Debug)]
+pub struct Session {
+    id: u64,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The chain selected with `wallet_switchEthereumChain`.
+    chain_id: Option<U256>,
+    /// The accounts granted with `eth_requestAccounts` or
+    /// `wallet_requestPermissions`.
+    accounts: Option<Vec<Address>>,
+    /// The number of active subscriptions.
+    subscriptions: usize,
+}
+
+/// The state of a session, as returned by `hdnode_session`.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Status {
+    /// The session ID, which is unique for the lifetime of the node.
+    pub id: u64,
+    /// The chain selected by the client, if any.
+    pub chain_id: Option<Quantity>,
+    /// The accounts granted to the client, if it requested any.
+    pub accounts: Option<Vec<Str<Address>>>,
+    /// The number of active subscriptions.
+    pub subscriptions: usize,
+}
+
+impl Session {
+    /// Creates a new session.
+    pub fn new() -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        tracing::debug!(id, "session opened");
+        Self {
+            id,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Returns the state of the session.
+    pub fn status(&self) -> Status {
+        let state = self.state.lock().unwrap();
+        Status {
+            id: self.id,
+            chain_id: state.chain_id.map(Quantity),
+            accounts: state
+                .accounts
+                .as_ref()
+                .map(|accounts| accounts.iter().copied().map(Str).collect()),
+            subscriptions: state.subscriptions,
+        }
+    }
+
+    /// Records the chain selected by the client.
+    pub fn select_chain(&self, chain_id: U256) {
+        self.state.lock().unwrap().chain_id = Some(chain_id);
+    }
+
+    /// Returns the accounts granted to the client, or `None` if it never
+    /// requested any.
+    pub fn granted_accounts(&self) -> Option<Vec<Address>> {
+        self.state.lock().unwrap().accounts.clone()
+    }
+
+    /// Records the accounts granted to the client.
+    pub fn grant_accounts(&self, accounts: &[Address]) {
+        self.state.lock().unwrap().accounts = Some(accounts.to_vec());
+    }
+
+    /// Records that the client subscribed to remote node notifications.
+    pub fn subscribed(&self) {
+        self.state.lock().unwrap().subscriptions += 1;
+    }
+
+    /// Records that the client unsubscribed from remote node notifications.
+    pub fn unsubscribed(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.subscriptions = state.subscriptions.saturating_sub(1);
+    }
+
+    /// Tears down the session when the client disconnects, revoking anything
+    /// that was granted to it.
+    pub fn close(&self) {
+        let state = std::mem::take(&mut *self.state.lock().unwrap());
+        tracing::debug!(
+            id = self.id,
+            subscriptions = state.subscriptions,
+            "session closed"
+        );
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_and_tears_down_state() {
+        let (first, second) = (Session::new(), Session::new());
+        assert_ne!(first.status().id, second.status().id);
+
+        let account = Address([0x42; 20]);
+        first.select_chain(U256::new(100));
+        first.grant_accounts(&[account]);
+        first.subscribed();
+        first.subscribed();
+        first.unsubscribed();
+        let status = first.status();
+        assert_eq!(
+            status.chain_id.map(|chain_id| chain_id.0),
+            Some(U256::new(100))
+        );
+        assert_eq!(first.granted_accounts(), Some(vec![account]));
+        assert_eq!(status.subscriptions, 1);
+        assert_eq!(second.granted_accounts(), None);
+
+        first.close();
+        let status = first.status();
+        assert!(status.chain_id.is_none() && status.accounts.is_none());
+        assert_eq!(status.subscriptions, 0);
+    }
+}
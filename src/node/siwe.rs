@@ -0,0 +1,279 @@
+//! Sign-In with Ethereum (EIP-4361) messages.
+//!
+//! SIWE messages are plain text messages signed with `eth_sign` or
+//! `personal_sign`, so policies and validators would otherwise only see them
+//! as opaque bytes. This recognizes them and parses their fields, so that
+//! they can be restricted by domain, expiry and nonce.
+
+use crate::serialization::Str;
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use hdwallet::account::Address;
+use rocket::serde::Serialize;
+
+/// The end of the first line of a SIWE message, following the domain.
+const PREAMBLE: &str = " wants you to sign in with your Ethereum account:";
+
+/// A parsed SIWE message.
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct SiweMessage {
+    /// The URI scheme of the origin requesting the signature, if specified.
+    pub scheme: Option<String>,
+    /// The domain requesting the signature, including the port if any.
+    pub domain: String,
+    /// The account performing the sign-in.
+    pub address: Str<Address>,
+    /// The human-readable assertion the user signs, if any.
+    pub statement: Option<String>,
+    /// The URI of the resource that is the subject of the signing.
+    pub uri: String,
+    /// The version of the message, which must be `1`.
+    pub version: String,
+    /// The chain ID the session is bound to.
+    pub chain_id: u64,
+    /// The nonce used to prevent replay attacks.
+    pub nonce: Option<String>,
+    /// The RFC 3339 time at which the message was generated.
+    pub issued_at: String,
+    /// The RFC 3339 time at which the signed message expires, if any.
+    pub expiration_time: Option<String>,
+    /// The RFC 3339 time from which the signed message is valid, if any.
+    pub not_before: Option<String>,
+    /// A system-specific identifier for the sign-in request, if any.
+    pub request_id: Option<String>,
+    /// Resources the user wishes to have resolved as part of the sign-in.
+    pub resources: Vec<String>,
+}
+
+impl SiweMessage {
+    /// Parses a message as a SIWE message. Returns `None` for messages that
+    /// are not SIWE messages, and an error for malformed SIWE messages.
+    pub fn parse(message: &[u8]) -> Result<Option<Self>> {
+        let message = match std::str::from_utf8(message) {
+            Ok(message) => message,
+            Err(_) => return Ok(None),
+        };
+        let mut lines = message.lines();
+        let origin = match lines.next().and_then(|line| line.strip_suffix(PREAMBLE)) {
+            Some(origin) => origin,
+            None => return Ok(None),
+        };
+        let (scheme, domain) = match origin.split_once("://") {
+            Some((scheme, domain)) => (Some(scheme.to_owned()), domain),
+            None => (None, origin),
+        };
+        ensure!(!domain.is_empty(), "missing SIWE domain");
+        let address = lines
+            .next()
+            .context("missing SIWE address")?
+            .parse::<Address>()
+            .map_err(|err| anyhow!("invalid SIWE address: {err}"))?;
+
+        // The statement is optional and surrounded by empty lines, which are
+        // not always included by clients when it is omitted.
+        let mut lines = lines.filter(|line| !line.is_empty()).peekable();
+        let statement = lines
+            .next_if(|line| !line.starts_with("URI: "))
+            .map(str::to_owned);
+
+        let mut fields = Fields::default();
+        let mut resources = Vec::new();
+        while let Some(line) = lines.next() {
+            if line == "Resources:" {
+                for line in lines.by_ref() {
+                    let resource = line
+                        .strip_prefix("- ")
+                        .with_context(|| format!("invalid SIWE resource '{line}'"))?;
+                    resources.push(resource.to_owned());
+                }
+                break;
+            }
+            let (name, value) = line
+                .split_once(": ")
+                .with_context(|| format!("invalid SIWE field '{line}'"))?;
+            let field = match name {
+                "URI" => &mut fields.uri,
+                "Version" => &mut fields.version,
+                "Chain ID" => &mut fields.chain_id,
+                "Nonce" => &mut fields.nonce,
+                "Issued At" => &mut fields.issued_at,
+                "Expiration Time" => &mut fields.expiration_time,
+                "Not Before" => &mut fields.not_before,
+                "Request ID" => &mut fields.request_id,
+                _ => bail!("unknown SIWE field '{name}'"),
+            };
+            ensure!(field.is_none(), "duplicate SIWE field '{name}'");
+            *field = Some(value.to_owned());
+        }
+
+        let version = fields.version.context("missing SIWE version")?;
+        ensure!(version == "1", "unsupported SIWE version {version}");
+        let chain_id = fields
+            .chain_id
+            .context("missing SIWE chain ID")?
+            .parse()
+            .context("invalid SIWE chain ID")?;
+        let issued_at = fields.issued_at.context("missing SIWE issued at time")?;
+        for time in [
+            Some(&issued_at),
+            fields.expiration_time.as_ref(),
+            fields.not_before.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            timestamp(time)?;
+        }
+
+        Ok(Some(Self {
+            scheme,
+            domain: domain.to_owned(),
+            address: Str(address),
+            statement,
+            uri: fields.uri.context("missing SIWE URI")?,
+            version,
+            chain_id,
+            nonce: fields.nonce,
+            issued_at,
+            expiration_time: fields.expiration_time,
+            not_before: fields.not_before,
+            request_id: fields.request_id,
+            resources,
+        }))
+    }
+
+    /// Returns the UNIX timestamp at which the message expires, if any.
+    pub fn expires_at(&self) -> Option<u64> {
+        self.expiration_time
+            .as_deref()
+            .and_then(|time| timestamp(time).ok())
+    }
+
+    /// Returns the UNIX timestamp from which the message is valid, if any.
+    pub fn valid_from(&self) -> Option<u64> {
+        self.not_before
+            .as_deref()
+            .and_then(|time| timestamp(time).ok())
+    }
+}
+
+/// The raw values of the SIWE message fields.
+#[derive(Default)]
+struct Fields {
+    uri: Option<String>,
+    version: Option<String>,
+    chain_id: Option<String>,
+    nonce: Option<String>,
+    issued_at: Option<String>,
+    expiration_time: Option<String>,
+    not_before: Option<String>,
+    request_id: Option<String>,
+}
+
+/// Parses an RFC 3339 date-time into a UNIX timestamp.
+fn timestamp(time: &str) -> Result<u64> {
+    let invalid = || anyhow!("invalid SIWE time '{time}'");
+    let number = |start: usize, len: usize| -> Result<i64> {
+        time.get(start..start + len)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(invalid)
+    };
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    ensure!(
+        separators
+            .iter()
+            .all(|(i, separator)| time.as_bytes().get(*i) == Some(separator))
+            && matches!(time.as_bytes().get(10), Some(b'T' | b't')),
+        invalid(),
+    );
+    let (year, month, day) = (number(0, 4)?, number(5, 2)?, number(8, 2)?);
+    let (hour, minute, second) = (number(11, 2)?, number(14, 2)?, number(17, 2)?);
+    ensure!(
+        (1..=12).contains(&month)
+            && (1..=31).contains(&day)
+            && hour < 24
+            && minute < 60
+            && second <= 60,
+        invalid(),
+    );
+
+    let mut offset = &time[19..];
+    if let Some(fraction) = offset.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        ensure!(digits > 0, invalid());
+        offset = &fraction[digits..];
+    }
+    let offset = match offset {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match offset.as_bytes().first() {
+                Some(b'+') => 1,
+                Some(b'-') => -1,
+                _ => return Err(invalid()),
+            };
+            let (hours, minutes) = offset[1..].split_once(':').ok_or_else(invalid)?;
+            let component = |digits: &str| {
+                digits
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|_| digits.len() == 2 && digits.bytes().all(|b| b.is_ascii_digit()))
+                    .ok_or_else(invalid)
+            };
+            sign * (component(hours)? * 3600 + component(minutes)? * 60)
+        }
+    };
+
+    // Days since the UNIX epoch from a proleptic Gregorian calendar date.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second - offset).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_messages() {
+        let message = "\
+https://example.com wants you to sign in with your Ethereum account:
+0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266
+
+I accept the ExampleOrg Terms of Service: https://example.com/tos
+
+URI: https://example.com/login
+Version: 1
+Chain ID: 1
+Nonce: 32891756
+Issued At: 2021-09-30T16:25:24Z
+Expiration Time: 2021-10-01T18:25:24.123+02:00
+Resources:
+- ipfs://bafybeiemxf5abjwjbikoz4mc3a3dla6ual3jsgpdr4cjr3oz3evfyavhwq/
+- https://example.com/my-web2-claim.json";
+
+        let siwe = SiweMessage::parse(message.as_bytes()).unwrap().unwrap();
+        assert_eq!(siwe.scheme.as_deref(), Some("https"));
+        assert_eq!(siwe.domain, "example.com");
+        assert_eq!(
+            siwe.statement.as_deref(),
+            Some("I accept the ExampleOrg Terms of Service: https://example.com/tos"),
+        );
+        assert_eq!(siwe.chain_id, 1);
+        assert_eq!(siwe.nonce.as_deref(), Some("32891756"));
+        assert_eq!(siwe.resources.len(), 2);
+        assert_eq!(timestamp(&siwe.issued_at).unwrap(), 1633019124);
+        assert_eq!(siwe.expires_at(), Some(1633019124 + 86400));
+
+        assert!(SiweMessage::parse(b"hello").unwrap().is_none());
+        assert!(SiweMessage::parse(&message.as_bytes()[..200]).is_err());
+        assert!(
+            SiweMessage::parse(message.replace("Version: 1", "Version: 2").as_bytes()).is_err()
+        );
+    }
+}
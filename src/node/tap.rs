@@ -0,0 +1,203 @@
+//! A debug tap logging full requests and responses.
+//!
+//! Debugging client integrations often requires seeing the exact JSON that
+//! was exchanged, which is far too noisy to log for all proxied traffic. The
+//! tap writes selected request and response pairs, one JSON object per line,
+//! to a separate file. Requests are selected by method, by mentioning one of
+//! the tapped accounts in their parameters, or by random sampling.
+
+use crate::{
+    clock,
+    jsonrpc::{Request, Response},
+    serialization::Str,
+};
+use anyhow::{ensure, Context as _, Result};
+use hdwallet::account::Address;
+use rocket::serde::{
+    json::{serde_json, Value},
+    Deserialize, Serialize,
+};
+use std::{
+    collections::hash_map::RandomState,
+    fs::{File, OpenOptions},
+    hash::{BuildHasher as _, Hasher as _},
+    io::Write as _,
+    net::IpAddr,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+/// Debug tap options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Options {
+    /// The file that tapped requests and responses are appended to. The tap
+    /// is disabled when not specified.
+    #[serde(default)]
+    pub tap_file: Option<PathBuf>,
+
+    /// The fraction of requests, between 0 and 1, that are tapped at random.
+    #[serde(default)]
+    pub tap_sample_rate: f64,
+
+    /// Methods whose requests are always tapped.
+    #[serde(default)]
+    pub tap_methods: Vec<String>,
+
+    /// Accounts whose requests are always tapped. A request is considered to
+    /// be for an account if the account appears anywhere in its parameters.
+    #[serde(default)]
+    pub tap_accounts: Vec<Str<Address>>,
+}
+
+/// A tapped request and its response.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+struct Entry<'a> {
+    timestamp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_ip: Option<IpAddr>,
+    request: &'a Value,
+    response: &'a Response,
+}
+
+/// The debug tap.
+pub struct Tap {
+    sample_rate: f64,
+    methods: Vec<String>,
+    accounts: Vec<String>,
+    file: Mutex<File>,
+}
+
+impl Tap {
+    /// Creates a new tap from the specified options, or `None` if the tap is
+    /// not enabled.
+    pub fn new(options: &Options) -> Result<Option<Self>> {
+        let path = match &options.tap_file {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        ensure!(
+            (0.0..=1.0).contains(&options.tap_sample_rate),
+            "tap sample rate must be between 0 and 1",
+        );
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open tap file {}", path.display()))?;
+
+        Ok(Some(Self {
+            sample_rate: options.tap_sample_rate,
+            methods: options.tap_methods.clone(),
+            accounts: options
+                .tap_accounts
+                .iter()
+                .map(|account| hex::encode(account.0 .0))
+                .collect(),
+            file: Mutex::new(file),
+        }))
+    }
+
+    /// Captures a request if it is selected by the tap, so that it can be
+    /// recorded along with its response once it is handled.
+    pub fn capture(&self, request: &Request) -> Option<Value> {
+        if !self.selects(request) {
+            return None;
+        }
+        serde_json::to_value(request).ok()
+    }
+
+    /// Records a captured request with its response.
+    pub fn record(&self, request: &Value, response: &Response, client_ip: Option<IpAddr>) {
+        let entry = Entry {
+            timestamp: clock::now(),
+            client_ip,
+            request,
+            response,
+        };
+        let result = serde_json::to_string(&entry)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| Ok(writeln!(self.file.lock().unwrap(), "{line}")?));
+        if let Err(err) = result {
+            tracing::warn!(?err, "failed to write to tap file");
+        }
+    }
+
+    fn selects(&self, request: &Request) -> bool {
+        if self.methods.contains(&request.method) {
+            return true;
+        }
+        if let Some(params) = &request.params {
+            let params = params.get().to_lowercase();
+            if self
+                .accounts
+                .iter()
+                .any(|account| params.contains(account.as_str()))
+            {
+                return true;
+            }
+        }
+        self.sample_rate > 0.0 && sample() < self.sample_rate
+    }
+}
+
+/// Returns a random number between 0 and 1.
+fn sample() -> f64 {
+    // Each new `RandomState` is keyed differently, so hashing with it is a
+    // cheap source of randomness that is good enough for sampling.
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::{Id, JsonRpc, Params};
+    use rocket::serde::json::json;
+    use std::{env, fs, process};
+
+    #[test]
+    fn taps_selected_requests() {
+        let path = env::temp_dir().join(format!("hdnode-tap-{}.jsonl", process::id()));
+        let tap = Tap::new(&Options {
+            tap_file: Some(path.clone()),
+            tap_sample_rate: 0.0,
+            tap_methods: vec!["eth_sendTransaction".to_owned()],
+            tap_accounts: vec![Str(Address([0xab; 20]))],
+        })
+        .unwrap()
+        .unwrap();
+        let request = |method: &str, params: Value| Request {
+            jsonrpc: JsonRpc::V2,
+            method: method.to_owned(),
+            params: Some(Params::new(params).unwrap()),
+            id: Id::Number(1.into()),
+        };
+
+        assert!(tap
+            .capture(&request("eth_blockNumber", json!([])))
+            .is_none());
+        assert!(tap
+            .capture(&request("eth_sendTransaction", json!([{}])))
+            .is_some());
+        let captured = tap
+            .capture(&request(
+                "eth_getBalance",
+                json!(["0xABABABABABABABABABABABABABABABABABABABAB", "latest"]),
+            ))
+            .unwrap();
+
+        let response = Response {
+            jsonrpc: JsonRpc::V2,
+            result: Ok(serde_json::value::to_raw_value(&json!("0x0")).unwrap()),
+            id: Id::Number(1.into()),
+        };
+        tap.record(&captured, &response, None);
+        let entry =
+            serde_json::from_str::<Value>(fs::read_to_string(&path).unwrap().trim()).unwrap();
+        assert_eq!(entry["request"]["method"], "eth_getBalance");
+        assert_eq!(entry["response"]["result"], "0x0");
+        fs::remove_file(path).unwrap();
+    }
+}
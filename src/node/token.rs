@@ -0,0 +1,423 @@
+//! Token transfer and approval calldata decoding.
+//!
+//! Token transfers don't carry any native value, so they are invisible to
+//! checks on transaction values. This recognizes the standard ERC-20, ERC-721
+//! and ERC-1155 transfer and approval functions and decodes who they move
+//! tokens to, as well as EIP-2612 and Permit2 permits signed as typed data.
+
+use crate::{
+    abi,
+    node::typeddata::TypedData,
+    serialization::{Quantity, Str},
+};
+use anyhow::{bail, ensure, Context as _, Result};
+use ethnum::U256;
+use hdwallet::account::Address;
+use rocket::serde::{json::Value, Serialize};
+use std::slice;
+
+/// The recognized token functions.
+const FUNCTIONS: &[&str] = &[
+    "transfer(address,uint256)",
+    "transferFrom(address,address,uint256)",
+    "approve(address,uint256)",
+    "permit(address,address,uint256,uint256,uint8,bytes32,bytes32)",
+    "setApprovalForAll(address,bool)",
+    "safeTransferFrom(address,address,uint256)",
+    "safeTransferFrom(address,address,uint256,bytes)",
+    "safeTransferFrom(address,address,uint256,uint256,bytes)",
+    "safeBatchTransferFrom(address,address,uint256[],uint256[],bytes)",
+];
+
+/// The maximum Permit2 allowance, `type(uint160).max`.
+const PERMIT2_MAX_AMOUNT: U256 = U256::from_words(u32::MAX as u128, u128::MAX);
+
+/// The kind of a token operation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub enum Kind {
+    /// Tokens are transferred to a recipient.
+    Transfer,
+    /// A spender is allowed to transfer tokens.
+    Approval,
+    /// An operator is allowed to transfer all of the owner's tokens.
+    ApprovalForAll,
+}
+
+/// A token transfer or approval decoded from calldata.
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct TokenOperation {
+    /// The kind of operation.
+    pub kind: Kind,
+    /// The token contract.
+    pub token: Str<Address>,
+    /// The owner of the tokens, when it isn't the caller.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<Str<Address>>,
+    /// The recipient of a transfer, or the spender or operator of an approval.
+    pub to: Str<Address>,
+    /// The ERC-721 or ERC-1155 token ID, for functions specific to them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Quantity>,
+    /// The amount of tokens, or the token ID for ERC-721 `transferFrom` and
+    /// `approve` calls which share their selectors with ERC-20. Not set for
+    /// approvals for all.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<Quantity>,
+}
+
+/// Decodes the token operations of a call to the specified token contract.
+/// Calls to other functions or with invalid calldata have no operations.
+pub fn decode(token: Address, calldata: &[u8]) -> Vec<TokenOperation> {
+    try_decode(token, calldata).unwrap_or_default()
+}
+
+/// Decodes the approvals granted by permit typed data: EIP-2612 and DAI-style
+/// permits, where DAI-style permits allow an unlimited amount, and Permit2
+/// `PermitSingle` and `PermitBatch` allowances. Other typed data grants no
+/// approvals, while permits with fields that can't be parsed fail to decode.
+pub fn decode_permit(typed_data: &TypedData) -> Result<Vec<TokenOperation>> {
+    let value = typed_data.value();
+    let message = &value["message"];
+    match value["primaryType"].as_str() {
+        Some("Permit") => {
+            let amount = match (&message["value"], &message["allowed"]) {
+                (Value::Null, Value::Bool(true)) => U256::MAX,
+                (Value::Null, Value::Bool(false)) => U256::ZERO,
+                (amount, _) => uint(amount)?,
+            };
+            Ok(vec![TokenOperation {
+                kind: Kind::Approval,
+                token: address(&value["domain"]["verifyingContract"])?,
+                from: address(&message["owner"])
+                    .or_else(|_| address(&message["holder"]))
+                    .ok(),
+                to: address(&message["spender"])?,
+                id: None,
+                amount: Some(Quantity(amount)),
+            }])
+        }
+        Some("PermitSingle") => permit2(message, slice::from_ref(&message["details"])),
+        Some("PermitBatch") => match message["details"].as_array() {
+            Some(details) => permit2(message, details),
+            None => bail!("invalid Permit2 batch details"),
+        },
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Decodes the allowances of a Permit2 permit. Permit2 allowances are
+/// `uint160` amounts, with the maximum one being unlimited.
+fn permit2(message: &Value, details: &[Value]) -> Result<Vec<TokenOperation>> {
+    let spender = address(&message["spender"])?;
+    details
+        .iter()
+        .map(|details| {
+            let amount = uint(&details["amount"])?;
+            Ok(TokenOperation {
+                kind: Kind::Approval,
+                token: address(&details["token"])?,
+                from: None,
+                to: spender,
+                id: None,
+                amount: Some(Quantity(if amount >= PERMIT2_MAX_AMOUNT {
+                    U256::MAX
+                } else {
+                    amount
+                })),
+            })
+        })
+        .collect()
+}
+
+/// Parses an address field of permit typed data.
+fn address(value: &Value) -> Result<Str<Address>> {
+    value
+        .as_str()
+        .and_then(|value| value.parse().ok())
+        .map(Str)
+        .with_context(|| format!("invalid permit address {value}"))
+}
+
+/// Parses an amount field of permit typed data, from a decimal or hexadecimal
+/// string or from an integer. Numbers too large for JSON integers are rejected
+/// rather than rounded.
+fn uint(value: &Value) -> Result<U256> {
+    let amount = match value {
+        Value::String(value) => match value.strip_prefix("0x") {
+            Some(hex) => U256::from_str_radix(hex, 16).ok(),
+            None => value.parse().ok(),
+        },
+        Value::Number(value) => value.to_string().parse().ok(),
+        _ => None,
+    };
+    amount.with_context(|| format!("invalid permit amount {value}"))
+}
+
+/// Decodes the token operations of a call to the specified token contract,
+/// failing when the calldata of a recognized function is invalid. Calls to
+/// other functions have no operations.
+pub fn try_decode(token: Address, calldata: &[u8]) -> Result<Vec<TokenOperation>> {
+    let (selector, args) = match calldata.get(..4) {
+        Some(selector) => (selector, &calldata[4..]),
+        None => return Ok(Vec::new()),
+    };
+    let function = match FUNCTIONS
+        .iter()
+        .find(|function| abi::selector(function) == selector)
+    {
+        Some(function) => *function,
+        None => return Ok(Vec::new()),
+    };
+
+    let address = |index: usize| abi::decode_address(args, 32 * index).map(Str);
+    let uint = |index: usize| abi::decode_uint(args, 32 * index);
+    let operation = |kind: Kind,
+                     from: Option<Str<Address>>,
+                     to: Str<Address>,
+                     id: Option<U256>,
+                     amount: Option<U256>| TokenOperation {
+        kind,
+        token: Str(token),
+        from,
+        to,
+        id: id.map(Quantity),
+        amount: amount.map(Quantity),
+    };
+
+    Ok(match function {
+        "transfer(address,uint256)" => {
+            vec![operation(
+                Kind::Transfer,
+                None,
+                address(0)?,
+                None,
+                Some(uint(1)?),
+            )]
+        }
+        "transferFrom(address,address,uint256)" => vec![operation(
+            Kind::Transfer,
+            Some(address(0)?),
+            address(1)?,
+            None,
+            Some(uint(2)?),
+        )],
+        "approve(address,uint256)" => {
+            vec![operation(
+                Kind::Approval,
+                None,
+                address(0)?,
+                None,
+                Some(uint(1)?),
+            )]
+        }
+        "permit(address,address,uint256,uint256,uint8,bytes32,bytes32)" => vec![operation(
+            Kind::Approval,
+            Some(address(0)?),
+            address(1)?,
+            None,
+            Some(uint(2)?),
+        )],
+        "setApprovalForAll(address,bool)" => {
+            // Revoking approvals doesn't allow moving any tokens.
+            if uint(1)? == 0 {
+                return Ok(Vec::new());
+            }
+            vec![operation(
+                Kind::ApprovalForAll,
+                None,
+                address(0)?,
+                None,
+                None,
+            )]
+        }
+        "safeTransferFrom(address,address,uint256)"
+        | "safeTransferFrom(address,address,uint256,bytes)" => vec![operation(
+            Kind::Transfer,
+            Some(address(0)?),
+            address(1)?,
+            Some(uint(2)?),
+            Some(U256::ONE),
+        )],
+        "safeTransferFrom(address,address,uint256,uint256,bytes)" => vec![operation(
+            Kind::Transfer,
+            Some(address(0)?),
+            address(1)?,
+            Some(uint(2)?),
+            Some(uint(3)?),
+        )],
+        "safeBatchTransferFrom(address,address,uint256[],uint256[],bytes)" => {
+            let (from, to) = (address(0)?, address(1)?);
+            let ids = abi::decode_uint_array(args, 64)?;
+            let amounts = abi::decode_uint_array(args, 96)?;
+            ensure!(ids.len() == amounts.len(), "mismatched batch transfer");
+            ids.into_iter()
+                .zip(amounts)
+                .map(|(id, amount)| {
+                    operation(Kind::Transfer, Some(from), to, Some(id), Some(amount))
+                })
+                .collect()
+        }
+        _ => unreachable!("unhandled token function"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::{json, serde_json};
+
+    #[test]
+    fn decodes_token_operations() {
+        let token = Address([0x70; 20]);
+        let (owner, recipient) = (Address([0x11; 20]), Address([0x22; 20]));
+
+        let transfer = decode(
+            token,
+            &abi::call(
+                "transfer(address,uint256)",
+                &[abi::address(recipient), abi::uint(U256::new(42))],
+            ),
+        );
+        assert_eq!(transfer.len(), 1);
+        assert_eq!(transfer[0].kind, Kind::Transfer);
+        assert_eq!(transfer[0].token.0, token);
+        assert_eq!(transfer[0].to.0, recipient);
+        assert_eq!(transfer[0].amount.unwrap().0, 42);
+
+        let mut batch = abi::call(
+            "safeBatchTransferFrom(address,address,uint256[],uint256[],bytes)",
+            &[
+                abi::address(owner),
+                abi::address(recipient),
+                abi::uint(U256::new(0xa0)),
+                abi::uint(U256::new(0x100)),
+                abi::uint(U256::new(0x160)),
+            ],
+        );
+        for value in [2, 1, 2, 2, 10, 20, 0] {
+            batch.extend_from_slice(&abi::uint(U256::new(value)));
+        }
+        let batch = decode(token, &batch);
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[1].from.unwrap().0, owner);
+        assert_eq!(batch[1].id.unwrap().0, 2);
+        assert_eq!(batch[1].amount.unwrap().0, 20);
+
+        let revoke = abi::call(
+            "setApprovalForAll(address,bool)",
+            &[abi::address(recipient), abi::uint(U256::ZERO)],
+        );
+        assert!(decode(token, &revoke).is_empty());
+        assert!(decode(token, &[0xa9, 0x05, 0x9c, 0xbb]).is_empty());
+        assert!(try_decode(token, &[0xa9, 0x05, 0x9c, 0xbb]).is_err());
+        assert!(try_decode(token, &[0x12, 0x34, 0x56, 0x78])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn decodes_permits() {
+        let typed_data = serde_json::from_value::<TypedData>(json!({
+            "types": {
+                "EIP712Domain": [
+                    { "name": "verifyingContract", "type": "address" },
+                ],
+                "Permit": [
+                    { "name": "owner", "type": "address" },
+                    { "name": "spender", "type": "address" },
+                    { "name": "value", "type": "uint256" },
+                    { "name": "nonce", "type": "uint256" },
+                    { "name": "deadline", "type": "uint256" },
+                ],
+            },
+            "primaryType": "Permit",
+            "domain": {
+                "verifyingContract": "0x7070707070707070707070707070707070707070",
+            },
+            "message": {
+                "owner": "0x1111111111111111111111111111111111111111",
+                "spender": "0x2222222222222222222222222222222222222222",
+                "value": "0xffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+                "nonce": 0,
+                "deadline": 0,
+            },
+        }))
+        .unwrap();
+
+        let permit = decode_permit(&typed_data).unwrap().remove(0);
+        assert_eq!(permit.kind, Kind::Approval);
+        assert_eq!(permit.token.0, Address([0x70; 20]));
+        assert_eq!(permit.from.unwrap().0, Address([0x11; 20]));
+        assert_eq!(permit.to.0, Address([0x22; 20]));
+        assert_eq!(permit.amount.unwrap().0, U256::MAX);
+
+        let mut value = typed_data.value().clone();
+        value["message"]["value"] = json!(u64::MAX);
+        let permit = decode_permit(&TypedData::from_value(value.clone()).unwrap()).unwrap();
+        assert_eq!(permit[0].amount.unwrap().0, u64::MAX as u128);
+        // Permits without a spender can't be checked.
+        value["types"]["Permit"] = json!([{ "name": "value", "type": "uint256" }]);
+        value["message"] = json!({ "value": "1" });
+        let typed_data = TypedData::from_value(value).unwrap();
+        assert!(decode_permit(&typed_data).is_err());
+    }
+
+    #[test]
+    fn decodes_permit2_permits() {
+        let typed_data = serde_json::from_value::<TypedData>(json!({
+            "types": {
+                "EIP712Domain": [
+                    { "name": "name", "type": "string" },
+                    { "name": "chainId", "type": "uint256" },
+                    { "name": "verifyingContract", "type": "address" },
+                ],
+                "PermitBatch": [
+                    { "name": "details", "type": "PermitDetails[]" },
+                    { "name": "spender", "type": "address" },
+                    { "name": "sigDeadline", "type": "uint256" },
+                ],
+                "PermitDetails": [
+                    { "name": "token", "type": "address" },
+                    { "name": "amount", "type": "uint160" },
+                    { "name": "expiration", "type": "uint48" },
+                    { "name": "nonce", "type": "uint48" },
+                ],
+            },
+            "primaryType": "PermitBatch",
+            "domain": {
+                "name": "Permit2",
+                "chainId": 1,
+                "verifyingContract": "0x000000000022D473030F116dDEE9F6B43aC78BA3",
+            },
+            "message": {
+                "details": [
+                    {
+                        "token": "0x7070707070707070707070707070707070707070",
+                        "amount": "1000",
+                        "expiration": 0,
+                        "nonce": 0,
+                    },
+                    {
+                        "token": "0x7171717171717171717171717171717171717171",
+                        "amount": "0xffffffffffffffffffffffffffffffffffffffff",
+                        "expiration": 0,
+                        "nonce": 0,
+                    },
+                ],
+                "spender": "0x2222222222222222222222222222222222222222",
+                "sigDeadline": 0,
+            },
+        }))
+        .unwrap();
+
+        let permits = decode_permit(&typed_data).unwrap();
+        assert_eq!(permits.len(), 2);
+        assert_eq!(permits[0].token.0, Address([0x70; 20]));
+        assert_eq!(permits[0].to.0, Address([0x22; 20]));
+        assert_eq!(permits[0].amount.unwrap().0, 1000);
+        assert_eq!(permits[1].token.0, Address([0x71; 20]));
+        assert_eq!(permits[1].amount.unwrap().0, U256::MAX);
+    }
+}
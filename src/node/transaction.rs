@@ -1,10 +1,12 @@
 //! Partial transaction type for RPC calls.
 
 use crate::{
-    node::{eth::Eth, types::Block},
+    clock,
+    node::{eth::Eth, types::BlockTag},
     serialization::{Bytes, Quantity, Str},
 };
-use anyhow::{ensure, Result};
+use anyhow::{ensure, Context as _, Result};
+use ethnum::U256;
 use hdwallet::{
     account::Address,
     transaction::{Eip1559Transaction, Eip2930Transaction, LegacyTransaction},
@@ -13,7 +15,9 @@ use rocket::serde::{json::serde_json, Deserialize, Serialize, Serializer};
 use std::{
     fmt::{self, Debug, Formatter},
     ops::Deref,
+    time::Duration,
 };
+use thiserror::Error;
 
 /// Transaction request parameters uses for `eth_sendTransaction` and
 /// `eth_signTransaction` RPC calls.
@@ -31,7 +35,7 @@ pub struct TransactionRequest {
     pub to: Option<Str<Address>>,
     /// The gas limit for the transaction.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub gas: Option<Quantity>,
+    pub gas: Option<Quantity<u64>>,
     /// The gas price in Wei for the transaction.
     #[serde(rename = "gasPrice", skip_serializing_if = "Option::is_none")]
     pub gas_price: Option<Quantity>,
@@ -52,29 +56,69 @@ pub struct TransactionRequest {
     pub data: Bytes<Vec<u8>>,
     /// The nonce for the transaction.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub nonce: Option<Quantity>,
+    pub nonce: Option<Quantity<u64>>,
     /// List of addresses and storage keys that the transaction plans to access.
     #[serde(rename = "accessList", skip_serializing_if = "Option::is_none")]
     pub access_list: Option<AccessList>,
     /// The chain ID for the transaction.
     #[serde(rename = "chainId", skip_serializing_if = "Option::is_none")]
-    pub chain_id: Option<Quantity>,
+    pub chain_id: Option<Quantity<u64>>,
 }
 
 /// List of addresses and storage keys that the transaction plans to access.
 type AccessList = Vec<(Str<Address>, Vec<Bytes<[u8; 32]>>)>;
 
+/// Options for filling transactions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FillOptions {
+    /// The nonce following transactions from the same account that were
+    /// submitted but may not be included in a block yet.
+    pub next_nonce: Option<u64>,
+    /// Whether or not to verify that the account's balance covers the maximum
+    /// cost of the transaction.
+    pub check_balance: bool,
+    /// The maximum age of the remote node's latest block. When specified,
+    /// transactions are not filled while the remote node is syncing or its
+    /// latest block is older, since its nonce and fee data would be stale.
+    pub max_block_lag: Option<Duration>,
+}
+
+/// An error indicating that the remote node is not in sync with the chain.
+#[derive(Debug, Error)]
+pub enum OutOfSyncError {
+    #[error("remote node is syncing")]
+    Syncing,
+    #[error("remote node's latest block is {age}s old, exceeding the maximum lag of {max}s")]
+    Stale { age: u64, max: u64 },
+}
+
+/// An error indicating that an account's balance does not cover the maximum
+/// cost of a transaction.
+#[derive(Debug, Error)]
+#[error("insufficient funds for gas * price + value: address {account} have {balance} want {cost}")]
+pub struct InsufficientFundsError {
+    pub account: Address,
+    pub balance: U256,
+    pub cost: U256,
+}
+
 impl TransactionRequest {
     /// Fills a transaction by computing all unspecified fields.
-    pub async fn fill(mut self, eth: &Eth) -> Result<(Address, Transaction)> {
+    pub async fn fill(mut self, eth: &Eth, options: FillOptions) -> Result<(Address, Transaction)> {
         let account = self.from.0;
 
         let mut batch = eth.batch();
         let chain_id = batch.chain_id();
-        let nonce = batch.get_transaction_count(account, Block::Latest);
+        let nonce = batch.get_transaction_count(account, BlockTag::Latest);
+        let balance = options
+            .check_balance
+            .then(|| batch.get_balance(account, BlockTag::Latest));
+        let sync = options
+            .max_block_lag
+            .map(|max| (max, batch.syncing(), batch.get_latest_block()));
 
         let gas = match self.gas {
-            None => Some(batch.estimate_gas(self.clone(), Block::Pending)),
+            None => Some(batch.estimate_gas(self.clone(), BlockTag::Pending)),
             _ => None,
         };
 
@@ -102,12 +146,27 @@ impl TransactionRequest {
 
         batch.execute().await?;
 
-        let chain_id = chain_id.await?;
+        if let Some((max, syncing, header)) = sync {
+            if syncing.await? {
+                return Err(OutOfSyncError::Syncing.into());
+            }
+            let timestamp = header.await?.timestamp.0;
+            let age = Duration::from_secs(clock::now().saturating_sub(timestamp));
+            if age > max {
+                return Err(OutOfSyncError::Stale {
+                    age: age.as_secs(),
+                    max: max.as_secs(),
+                }
+                .into());
+            }
+        }
+
+        let chain_id = u64::try_from(chain_id.await?).context("chain ID out of range")?;
         ensure!(
             self.chain_id.get_or_insert(Quantity(chain_id)).0 == chain_id,
             "chain ID used for signing does not match node"
         );
-        let nonce = nonce.await?;
+        let nonce = nonce.await?.max(options.next_nonce.unwrap_or_default());
         ensure!(
             self.nonce.get_or_insert(Quantity(nonce)).0 == nonce,
             "only signing transactions for current nonce ({nonce:#x}) permitted",
@@ -146,9 +205,50 @@ impl TransactionRequest {
             }
         }
 
+        if let Some(balance) = balance {
+            let balance = balance.await?;
+            let price = self
+                .max_fee_per_gas
+                .or(self.gas_price)
+                .unwrap_or_default()
+                .0;
+            let cost = U256::from(self.gas.unwrap_or_default().0)
+                .saturating_mul(price)
+                .saturating_add(self.value.0);
+            if balance < cost {
+                return Err(InsufficientFundsError {
+                    account,
+                    balance,
+                    cost,
+                }
+                .into());
+            }
+        }
+
         Ok((account, Transaction::from_args(self)))
     }
 
+    /// Converts a fully specified transaction request into a transaction
+    /// without reading anything from a node, for offline signing.
+    pub fn into_filled(self) -> Result<(Address, Transaction)> {
+        ensure!(self.chain_id.is_some(), "missing transaction chain ID");
+        ensure!(self.nonce.is_some(), "missing transaction nonce");
+        ensure!(self.gas.is_some(), "missing transaction gas limit");
+        ensure!(
+            matches!(
+                (
+                    &self.gas_price,
+                    &self.max_fee_per_gas,
+                    &self.max_priority_fee_per_gas,
+                ),
+                (Some(_), None, None) | (None, Some(_), Some(_)),
+            ),
+            "transaction requires either a gas price or London gas parameters",
+        );
+
+        Ok((self.from.0, Transaction::from_args(self)))
+    }
+
     /// Returns the access list for this transaction request in the `hdwallet`
     /// format.
     fn hdwallet_access_list(&self) -> hdwallet::transaction::accesslist::AccessList {
@@ -191,39 +291,78 @@ impl Transaction {
     fn from_args(args: TransactionRequest) -> Self {
         let inner = match (&args.max_fee_per_gas, &args.access_list) {
             (Some(_), _) => Inner::Eip1559(Eip1559Transaction {
-                chain_id: args.chain_id.unwrap().0,
-                nonce: args.nonce.unwrap().0,
+                chain_id: args.chain_id.unwrap().0.into(),
+                nonce: args.nonce.unwrap().0.into(),
                 max_priority_fee_per_gas: args.max_priority_fee_per_gas.unwrap().0,
                 max_fee_per_gas: args.max_fee_per_gas.unwrap().0,
-                gas_limit: args.gas.unwrap().0,
+                gas_limit: args.gas.unwrap().0.into(),
                 to: args.to.map(|to| to.0),
                 value: args.value.0,
                 data: args.data.0.clone(),
                 access_list: args.hdwallet_access_list(),
             }),
             (None, Some(_)) => Inner::Eip2930(Eip2930Transaction {
-                chain_id: args.chain_id.unwrap().0,
-                nonce: args.nonce.unwrap().0,
+                chain_id: args.chain_id.unwrap().0.into(),
+                nonce: args.nonce.unwrap().0.into(),
                 gas_price: args.gas_price.unwrap().0,
-                gas_limit: args.gas.unwrap().0,
+                gas_limit: args.gas.unwrap().0.into(),
                 to: args.to.map(|to| to.0),
                 value: args.value.0,
                 data: args.data.0.clone(),
                 access_list: args.hdwallet_access_list(),
             }),
             (None, None) => Inner::Legacy(LegacyTransaction {
-                nonce: args.nonce.unwrap().0,
+                nonce: args.nonce.unwrap().0.into(),
                 gas_price: args.gas_price.unwrap().0,
-                gas_limit: args.gas.unwrap().0,
+                gas_limit: args.gas.unwrap().0.into(),
                 to: args.to.map(|to| to.0),
                 value: args.value.0,
                 data: args.data.0.clone(),
-                chain_id: Some(args.chain_id.unwrap().0),
+                chain_id: Some(args.chain_id.unwrap().0.into()),
             }),
         };
 
         Self { args, inner }
     }
+
+    /// Returns the filled transaction request.
+    pub fn request(&self) -> &TransactionRequest {
+        &self.args
+    }
+
+    /// Returns the EIP-2718 type of the transaction.
+    fn kind(&self) -> &'static str {
+        match &self.inner {
+            Inner::Legacy(_) => "0x0",
+            Inner::Eip2930(_) => "0x1",
+            Inner::Eip1559(_) => "0x2",
+        }
+    }
+}
+
+/// A filled transaction for `eth_fillTransaction`, previewing exactly what
+/// would be signed.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct FilledTransaction {
+    /// The account that would sign the transaction.
+    pub from: Str<Address>,
+    /// The EIP-2718 type of the transaction.
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(flatten)]
+    transaction: Transaction,
+}
+
+impl FilledTransaction {
+    /// Creates a new filled transaction for an account.
+    pub fn new(account: Address, transaction: Transaction) -> Self {
+        Self {
+            from: Str(account),
+            kind: transaction.kind(),
+            transaction,
+        }
+    }
 }
 
 impl Deref for Transaction {
@@ -254,3 +393,150 @@ impl Serialize for Transaction {
         self.args.serialize(serializer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{call, client_with, MockUpstream, ACCOUNT};
+    use proptest::{collection::vec, option, prelude::*};
+    use rocket::serde::json::{serde_json::json, Value};
+
+    fn address() -> impl Strategy<Value = Str<Address>> {
+        any::<[u8; 20]>().prop_map(|bytes| Str(Address(bytes)))
+    }
+
+    fn quantity() -> impl Strategy<Value = Quantity> {
+        any::<[u8; 32]>().prop_map(|bytes| Quantity(U256::from_be_bytes(bytes)))
+    }
+
+    /// Encodes a transaction request, including the `from` field which is
+    /// only deserialized, as serialized requests are filled transactions for a
+    /// known account.
+    fn encode(request: &TransactionRequest) -> Value {
+        let mut json = serde_json::to_value(request).unwrap();
+        json["from"] = serde_json::to_value(request.from).unwrap();
+        json
+    }
+
+    /// Returns a strategy for encoded transaction requests.
+    fn transaction_request() -> impl Strategy<Value = Value> {
+        let small = || option::of(any::<u64>().prop_map(Quantity));
+        (
+            (address(), option::of(address()), small()),
+            (
+                option::of(quantity()),
+                option::of(quantity()),
+                option::of(quantity()),
+            ),
+            (quantity(), vec(any::<u8>(), 0..64), small()),
+            option::of(vec(
+                (address(), vec(any::<[u8; 32]>().prop_map(Bytes), 0..3)),
+                0..3,
+            )),
+            small(),
+        )
+            .prop_map(
+                |(
+                    (from, to, gas),
+                    (gas_price, max_fee_per_gas, max_priority_fee_per_gas),
+                    (value, data, nonce),
+                    access_list,
+                    chain_id,
+                )| {
+                    encode(&TransactionRequest {
+                        from,
+                        to,
+                        gas,
+                        gas_price,
+                        max_fee_per_gas,
+                        max_priority_fee_per_gas,
+                        value,
+                        data: Bytes(data),
+                        nonce,
+                        access_list,
+                        chain_id,
+                    })
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn transaction_requests_roundtrip(encoded in transaction_request()) {
+            let decoded = serde_json::from_value::<TransactionRequest>(encoded.clone()).unwrap();
+            prop_assert_eq!(encode(&decoded), encoded);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn rejects_transactions_exceeding_balance() {
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| figment.merge(("balance_check", true))).await;
+        upstream.respond("eth_getTransactionCount", json!("0x0"));
+        upstream.respond("eth_getBalance", json!("0x5208"));
+
+        let transaction = |value: &str| {
+            json!([{
+                "from": ACCOUNT,
+                "to": "0x3535353535353535353535353535353535353535",
+                "gas": "0x5208",
+                "gasPrice": "0x1",
+                "value": value,
+            }])
+        };
+        let signed = call(&client, "eth_signTransaction", transaction("0x0")).await;
+        let rejected = call(&client, "eth_signTransaction", transaction("0x1")).await;
+
+        assert!(signed["result"].is_string());
+        assert_eq!(rejected["error"]["code"], -32000);
+        assert!(rejected["error"]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("insufficient funds for gas * price + value"));
+    }
+
+    #[rocket::async_test]
+    async fn refuses_to_sign_when_remote_is_behind() {
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| figment.merge(("max_block_lag", 60))).await;
+        upstream.respond("eth_getTransactionCount", json!("0x0"));
+        upstream.respond("eth_syncing", json!(false));
+        upstream.respond("eth_blockNumber", json!("0x1"));
+        upstream.respond(
+            "eth_getBlockByNumber",
+            json!({
+                "number": "0x1",
+                "hash": format!("0x{}", "42".repeat(32)),
+                "parentHash": format!("0x{}", "41".repeat(32)),
+                "timestamp": "0x5f5e100",
+                "miner": "0x0000000000000000000000000000000000000000",
+                "gasLimit": "0x1c9c380",
+                "gasUsed": "0x0",
+                "baseFeePerGas": "0x7",
+                "transactions": [],
+            }),
+        );
+
+        let transaction = json!([{
+            "from": ACCOUNT,
+            "to": "0x3535353535353535353535353535353535353535",
+            "gas": "0x5208",
+            "gasPrice": "0x1",
+        }]);
+        let stale = call(&client, "eth_signTransaction", transaction.clone()).await;
+        upstream.respond(
+            "eth_syncing",
+            json!({ "currentBlock": "0x1", "highestBlock": "0x2" }),
+        );
+        let syncing = call(&client, "eth_signTransaction", transaction).await;
+        let proxied = call(&client, "eth_blockNumber", json!([])).await;
+
+        assert_eq!(stale["error"]["code"], -32002);
+        assert!(stale["error"]["data"]
+            .as_str()
+            .unwrap()
+            .contains("exceeding the maximum lag of 60s"));
+        assert_eq!(syncing["error"]["data"], "remote node is syncing");
+        assert_eq!(proxied["result"], "0x1");
+    }
+}
@@ -2,10 +2,17 @@
 //!
 //! This is just a thin wrapper around `hdwallet::typeddata::TypedData` that
 //! keeps track of its original JSON blob so it can debug and re-serialize it.
+//! Typed data is validated against its declared types before being parsed.
 
-use crate::node::eth::Eth;
-use anyhow::{ensure, Result};
+pub mod validation;
+
+use crate::{
+    abi::{self, Word},
+    node::{eth::Eth, types::BlockTag},
+};
+use anyhow::{bail, ensure, Context as _, Result};
 use ethnum::U256;
+use hdwallet::account::Address;
 use rocket::serde::{
     de,
     json::{self, Value},
@@ -15,6 +22,7 @@ use std::{
     fmt::{self, Debug, Formatter},
     ops::Deref,
 };
+use thiserror::Error;
 
 /// Inner actual `TypedData` implementation.
 type Inner = hdwallet::typeddata::TypedData;
@@ -27,18 +35,187 @@ pub struct TypedData {
     chain_id: Option<U256>,
 }
 
+/// Options for verifying typed data domains against the connected chain.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VerifyOptions {
+    /// Whether or not to verify that the domain's `verifyingContract` has
+    /// code on the connected chain.
+    pub check_contract_code: bool,
+    /// Whether or not to verify that the domain separator matches the one
+    /// returned by the verifying contract's `DOMAIN_SEPARATOR()` function, for
+    /// contracts that implement it.
+    pub check_domain_separator: bool,
+}
+
+/// An error indicating that the typed data domain does not match the
+/// verifying contract deployed on the connected chain.
+#[derive(Debug, Error)]
+pub enum DomainMismatchError {
+    #[error("verifying contract {0} has no code on the connected chain")]
+    NoCode(Address),
+    #[error("domain separator does not match verifying contract {0}")]
+    Separator(Address),
+}
+
 impl TypedData {
+    /// Parses typed data from its JSON value, failing with an
+    /// [`validation::InvalidTypedDataError`] describing the offending type or
+    /// value if it does not match its declared types.
+    pub fn from_value(raw: Value) -> Result<Self> {
+        validation::validate(&raw)?;
+        let inner = json::from_value(raw.clone())?;
+
+        // Be extra permissive with `chainId` because EIP-712 doesn't really
+        // standardize its representation.
+        let chain_id = match &raw["domain"]["chainId"] {
+            Value::Null => None,
+            Value::Number(value) if value.is_u64() => value.as_u64().map(U256::from),
+            Value::String(value) => {
+                let (s, radix) = match value.strip_prefix("0x") {
+                    Some(s) => (s, 16),
+                    None => (&**value, 10),
+                };
+                Some(U256::from_str_radix(s, radix)?)
+            }
+            other => bail!("invalid chain ID value in domain '{other}'"),
+        };
+
+        Ok(Self {
+            raw,
+            inner,
+            chain_id,
+        })
+    }
+
     /// Verifies the typed data domain is compatible with the connected node.
-    pub async fn verify(&self, eth: &Eth) -> Result<()> {
-        if let Some(chain_id) = self.chain_id {
+    pub async fn verify(&self, eth: &Eth, options: VerifyOptions) -> Result<()> {
+        let contract = match &self.raw["domain"]["verifyingContract"] {
+            Value::String(contract) => Some(
+                contract
+                    .parse::<Address>()
+                    .context("invalid verifying contract")?,
+            ),
+            _ => None,
+        };
+        // Only check domain separators that can be computed from the declared
+        // domain type.
+        let separator = match (contract, &self.raw["types"]["EIP712Domain"]) {
+            (Some(_), Value::Array(_)) if options.check_domain_separator => {
+                Some(self.domain_separator()?)
+            }
+            _ => None,
+        };
+
+        let mut batch = eth.batch();
+        let chain_id = self.chain_id.map(|_| batch.chain_id());
+        let code = contract
+            .filter(|_| options.check_contract_code)
+            .map(|contract| batch.get_code(contract, BlockTag::Latest));
+        let onchain_separator = contract.filter(|_| separator.is_some()).map(|contract| {
+            batch.call_contract(
+                contract,
+                abi::call("DOMAIN_SEPARATOR()", &[]),
+                BlockTag::Latest,
+            )
+        });
+        batch.execute().await?;
+
+        if let (Some(expected), Some(chain_id)) = (self.chain_id, chain_id) {
             ensure!(
-                chain_id == eth.chain_id().await?,
+                expected == chain_id.await?,
                 "chain ID used for signing does not match node",
             );
         }
+        if let (Some(contract), Some(code)) = (contract, code) {
+            if code.await?.is_empty() {
+                return Err(DomainMismatchError::NoCode(contract).into());
+            }
+        }
+        if let (Some(contract), Some(separator), Some(onchain_separator)) =
+            (contract, separator, onchain_separator)
+        {
+            // Contracts that don't implement `DOMAIN_SEPARATOR()` revert or
+            // return nothing, in which case there is nothing to compare to.
+            if let Ok(onchain_separator) = onchain_separator.await {
+                if onchain_separator.len() == 32 && onchain_separator != separator {
+                    return Err(DomainMismatchError::Separator(contract).into());
+                }
+            }
+        }
 
         Ok(())
     }
+
+    /// Computes the EIP-712 domain separator from the declared domain type.
+    fn domain_separator(&self) -> Result<Word> {
+        let fields = self.raw["types"]["EIP712Domain"]
+            .as_array()
+            .context("missing domain type")?
+            .iter()
+            .map(|field| (field["name"].as_str(), field["type"].as_str()))
+            .map(|field| match field {
+                (Some(name), Some(kind)) => Ok((name, kind)),
+                _ => bail!("invalid domain type field"),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let encoded_type = format!(
+            "EIP712Domain({})",
+            fields
+                .iter()
+                .map(|(name, kind)| format!("{kind} {name}"))
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+        let mut words = vec![abi::keccak256(encoded_type)];
+        for (name, kind) in fields {
+            words.push(
+                encode_atomic(kind, &self.raw["domain"][name])
+                    .with_context(|| format!("failed to encode domain field {name}"))?,
+            );
+        }
+        Ok(abi::keccak256(abi::encode(&words)))
+    }
+
+    /// Returns the original JSON value of the typed data.
+    pub fn value(&self) -> &Value {
+        &self.raw
+    }
+}
+
+/// Encodes an atomic or dynamic EIP-712 value as a word for hashing.
+fn encode_atomic(kind: &str, value: &Value) -> Result<Word> {
+    let hex = || -> Result<Vec<u8>> {
+        let hex = value
+            .as_str()
+            .and_then(|value| value.strip_prefix("0x"))
+            .context("expected hex string")?;
+        Ok(hex::decode(hex)?)
+    };
+    let word = match kind {
+        "address" => abi::address(value.as_str().context("expected address")?.parse()?),
+        "bool" => abi::uint(U256::from(value.as_bool().context("expected boolean")?)),
+        "string" => abi::keccak256(value.as_str().context("expected string")?),
+        "bytes" => abi::keccak256(hex()?),
+        _ if kind.starts_with("bytes") => {
+            let bytes = hex()?;
+            ensure!(bytes.len() <= 32, "fixed bytes too long");
+            let mut word = [0_u8; 32];
+            word[..bytes.len()].copy_from_slice(&bytes);
+            word
+        }
+        _ if kind.starts_with("uint") || kind.starts_with("int") => {
+            let (negative, magnitude) = validation::integer(value).context("expected integer")?;
+            // Signed integers are encoded in two's complement.
+            abi::uint(if negative {
+                magnitude.wrapping_neg()
+            } else {
+                magnitude
+            })
+        }
+        _ => bail!("unsupported domain field type '{kind}'"),
+    };
+    Ok(word)
 }
 
 impl Deref for TypedData {
@@ -70,31 +247,84 @@ impl<'de> Deserialize<'de> for TypedData {
         D: Deserializer<'de>,
     {
         let raw = Value::deserialize(deserializer)?;
-        let inner = json::from_value(raw.clone()).map_err(de::Error::custom)?;
+        Self::from_value(raw).map_err(de::Error::custom)
+    }
+}
 
-        // Be extra permissive with `chainId` because EIP-712 doesn't really
-        // standardize its representation.
-        let chain_id = match &raw["domain"]["chainId"] {
-            Value::Null => None,
-            Value::Number(value) if value.is_u64() => value.as_u64().map(U256::from),
-            Value::String(value) => {
-                let (s, radix) = match value.strip_prefix("0x") {
-                    Some(s) => (s, 16),
-                    None => (&**value, 10),
-                };
-                Some(U256::from_str_radix(s, radix).map_err(de::Error::custom)?)
-            }
-            other => {
-                return Err(de::Error::custom(format!(
-                    "invalid chain ID value in domain '{other}'"
-                )))
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{jsonrpc, testing::MockUpstream};
+    use rocket::serde::json::json;
+
+    fn mail() -> TypedData {
+        TypedData::from_value(json!({
+            "types": {
+                "EIP712Domain": [
+                    { "name": "name", "type": "string" },
+                    { "name": "version", "type": "string" },
+                    { "name": "chainId", "type": "uint256" },
+                    { "name": "verifyingContract", "type": "address" },
+                ],
+                "Mail": [
+                    { "name": "contents", "type": "string" },
+                ],
+            },
+            "primaryType": "Mail",
+            "domain": {
+                "name": "Ether Mail",
+                "version": "1",
+                "chainId": 1,
+                "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC",
+            },
+            "message": { "contents": "Hello, Bob!" },
+        }))
+        .unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn verifies_domain_against_chain() {
+        let separator = "0xf2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f";
+        assert_eq!(
+            hex::encode(mail().domain_separator().unwrap()),
+            separator[2..]
+        );
+
+        let upstream = MockUpstream::start().await;
+        let eth = Eth::new(
+            jsonrpc::Client::new(upstream.url(), jsonrpc::ClientOptions::default()).unwrap(),
+        );
+        let options = VerifyOptions {
+            check_contract_code: true,
+            check_domain_separator: true,
         };
+        upstream.respond("eth_chainId", json!("0x1"));
+        upstream.respond("eth_getCode", json!("0x00"));
+        upstream.respond("eth_call", json!(separator));
+        mail().verify(&eth, options).await.unwrap();
 
-        Ok(Self {
-            raw,
-            inner,
-            chain_id,
-        })
+        // Contracts without `DOMAIN_SEPARATOR()` are not checked.
+        upstream.fail("eth_call", 3, "execution reverted");
+        mail().verify(&eth, options).await.unwrap();
+
+        upstream.respond("eth_call", json!(format!("0x{}", "00".repeat(32))));
+        assert!(matches!(
+            mail()
+                .verify(&eth, options)
+                .await
+                .unwrap_err()
+                .downcast_ref::<DomainMismatchError>(),
+            Some(DomainMismatchError::Separator(_)),
+        ));
+
+        upstream.respond("eth_getCode", json!("0x"));
+        assert!(matches!(
+            mail()
+                .verify(&eth, options)
+                .await
+                .unwrap_err()
+                .downcast_ref::<DomainMismatchError>(),
+            Some(DomainMismatchError::NoCode(_)),
+        ));
     }
 }
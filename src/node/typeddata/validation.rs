@@ -0,0 +1,351 @@
+//! EIP-712 typed data validation.
+//!
+//! The typed data parser from `hdwallet` only reports that a payload is
+//! invalid, and not what is wrong with it. Payloads are validated against
+//! their declared types first, so that errors include the path of the
+//! offending type or value.
+
+use ethnum::U256;
+use rocket::serde::json::{serde_json::Map, Value};
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// An error indicating that typed data does not match its declared types.
+#[derive(Debug, Error)]
+#[error("invalid typed data at {path}: {message}")]
+pub struct InvalidTypedDataError {
+    pub path: String,
+    pub message: String,
+}
+
+/// Declared struct types with their fields as name and type pairs.
+type Types<'a> = HashMap<&'a str, Vec<(&'a str, &'a str)>>;
+
+/// The atomic and dynamic types of EIP-712.
+enum Atomic {
+    Address,
+    Bool,
+    String,
+    Bytes,
+    FixedBytes(usize),
+    Uint(usize),
+    Int(usize),
+}
+
+/// Validates typed data against its declared types.
+pub fn validate(raw: &Value) -> Result<(), InvalidTypedDataError> {
+    let root = raw
+        .as_object()
+        .ok_or_else(|| error("$", "expected an object"))?;
+    let types = field(root, "types")?
+        .as_object()
+        .ok_or_else(|| error("types", "expected an object"))?;
+    let types = parse_types(types)?;
+
+    let primary_type = field(root, "primaryType")?
+        .as_str()
+        .ok_or_else(|| error("primaryType", "expected a string"))?;
+    if !types.contains_key(primary_type) {
+        return Err(error(
+            "primaryType",
+            format!("undeclared type '{primary_type}'"),
+        ));
+    }
+
+    let domain = field(root, "domain")?;
+    if types.contains_key("EIP712Domain") {
+        check(&types, "EIP712Domain", domain, "domain")?;
+    } else if !domain.is_object() {
+        return Err(error("domain", "expected an object"));
+    }
+    check(&types, primary_type, field(root, "message")?, "message")
+}
+
+fn field<'a>(
+    object: &'a Map<String, Value>,
+    name: &str,
+) -> Result<&'a Value, InvalidTypedDataError> {
+    object
+        .get(name)
+        .ok_or_else(|| error("$", format!("missing field '{name}'")))
+}
+
+/// Parses the declared struct types, verifying that all their field types
+/// are known.
+fn parse_types(types: &Map<String, Value>) -> Result<Types<'_>, InvalidTypedDataError> {
+    let mut parsed = HashMap::new();
+    for (name, fields) in types {
+        let path = format!("types.{name}");
+        let fields = fields
+            .as_array()
+            .ok_or_else(|| error(&path, "expected an array of fields"))?;
+        let fields = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let path = format!("{path}[{i}]");
+                let name = field["name"]
+                    .as_str()
+                    .ok_or_else(|| error(&path, "missing field name"))?;
+                let kind = field["type"]
+                    .as_str()
+                    .ok_or_else(|| error(&path, "missing field type"))?;
+                Ok((name, kind))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        parsed.insert(name.as_str(), fields);
+    }
+
+    for (name, fields) in &parsed {
+        for (i, (_, kind)) in fields.iter().enumerate() {
+            let path = format!("types.{name}[{i}].type");
+            let mut base = *kind;
+            while let Some((inner, _)) = split_array(base).map_err(|err| error(&path, err))? {
+                base = inner;
+            }
+            if atomic(base).is_none() && !parsed.contains_key(base) {
+                return Err(error(&path, format!("unknown type '{base}'")));
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+/// Checks that a value matches a type.
+fn check(
+    types: &Types,
+    kind: &str,
+    value: &Value,
+    path: &str,
+) -> Result<(), InvalidTypedDataError> {
+    if let Some((inner, len)) = split_array(kind).map_err(|err| error(path, err))? {
+        let elements = value
+            .as_array()
+            .ok_or_else(|| error(path, format!("expected an array for type '{kind}'")))?;
+        if let Some(len) = len {
+            if elements.len() != len {
+                return Err(error(
+                    path,
+                    format!("expected {len} elements, got {}", elements.len()),
+                ));
+            }
+        }
+        for (i, element) in elements.iter().enumerate() {
+            check(types, inner, element, &format!("{path}[{i}]"))?;
+        }
+        return Ok(());
+    }
+
+    if let Some(fields) = types.get(kind) {
+        let object = value
+            .as_object()
+            .ok_or_else(|| error(path, format!("expected an object for type '{kind}'")))?;
+        for (name, kind) in fields {
+            let value = object
+                .get(*name)
+                .ok_or_else(|| error(path, format!("missing field '{name}'")))?;
+            check(types, kind, value, &format!("{path}.{name}"))?;
+        }
+        return Ok(());
+    }
+
+    let valid = match atomic(kind) {
+        Some(Atomic::Address) => value
+            .as_str()
+            .and_then(|value| value.strip_prefix("0x"))
+            .map(|hex| hex.len() == 40 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .unwrap_or(false),
+        Some(Atomic::Bool) => value.is_boolean(),
+        Some(Atomic::String) => value.is_string(),
+        Some(Atomic::Bytes) => bytes(value).is_some(),
+        Some(Atomic::FixedBytes(size)) => bytes(value) == Some(size),
+        Some(Atomic::Uint(bits)) => match integer(value) {
+            Some((false, magnitude)) | Some((true, magnitude @ U256::ZERO)) => {
+                bits == 256 || magnitude >> bits == 0
+            }
+            _ => false,
+        },
+        Some(Atomic::Int(bits)) => match integer(value) {
+            Some((negative, magnitude)) => {
+                let limit = U256::ONE << (bits - 1);
+                if negative {
+                    magnitude <= limit
+                } else {
+                    magnitude < limit
+                }
+            }
+            None => false,
+        },
+        None => return Err(error(path, format!("unknown type '{kind}'"))),
+    };
+    if !valid {
+        return Err(error(path, format!("invalid value for type '{kind}'")));
+    }
+    Ok(())
+}
+
+/// Splits the outermost array dimension off a type, returning the element
+/// type and the fixed length, if any.
+fn split_array(kind: &str) -> Result<Option<(&str, Option<usize>)>, String> {
+    let inner = match kind.strip_suffix(']') {
+        Some(inner) => inner,
+        None => return Ok(None),
+    };
+    let (element, len) = inner
+        .rsplit_once('[')
+        .ok_or_else(|| format!("invalid array type '{kind}'"))?;
+    let len = match len {
+        "" => None,
+        len => Some(
+            len.parse::<usize>()
+                .ok()
+                .filter(|len| *len > 0)
+                .ok_or_else(|| format!("invalid array length in type '{kind}'"))?,
+        ),
+    };
+    Ok(Some((element, len)))
+}
+
+fn atomic(kind: &str) -> Option<Atomic> {
+    let bits = |size: &str| match size {
+        "" => Some(256),
+        size => size
+            .parse::<usize>()
+            .ok()
+            .filter(|bits| *bits > 0 && *bits <= 256 && bits % 8 == 0),
+    };
+    Some(match kind {
+        "address" => Atomic::Address,
+        "bool" => Atomic::Bool,
+        "string" => Atomic::String,
+        "bytes" => Atomic::Bytes,
+        _ => {
+            if let Some(size) = kind.strip_prefix("bytes") {
+                let size = size
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|size| (1..=32).contains(size))?;
+                Atomic::FixedBytes(size)
+            } else if let Some(size) = kind.strip_prefix("uint") {
+                Atomic::Uint(bits(size)?)
+            } else if let Some(size) = kind.strip_prefix("int") {
+                Atomic::Int(bits(size)?)
+            } else {
+                return None;
+            }
+        }
+    })
+}
+
+/// Returns the length of a hex-encoded bytes value.
+fn bytes(value: &Value) -> Option<usize> {
+    let hex = value.as_str()?.strip_prefix("0x")?;
+    (hex.len() % 2 == 0 && hex.bytes().all(|b| b.is_ascii_hexdigit())).then_some(hex.len() / 2)
+}
+
+/// Parses an integer value, given as a JSON number or a decimal or hex
+/// string, returning whether it is negative and its magnitude.
+pub fn integer(value: &Value) -> Option<(bool, U256)> {
+    match value {
+        Value::Number(number) => match (number.as_u64(), number.as_i64()) {
+            (Some(value), _) => Some((false, U256::from(value))),
+            (None, Some(value)) => Some((true, U256::from(value.unsigned_abs()))),
+            _ => None,
+        },
+        Value::String(value) => {
+            let (negative, value) = match value.strip_prefix('-') {
+                Some(value) => (true, value),
+                None => (false, value.as_str()),
+            };
+            let magnitude = match value.strip_prefix("0x") {
+                Some(hex) if !hex.is_empty() && hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                    U256::from_str_radix(hex, 16).ok()?
+                }
+                None if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                    U256::from_str_radix(value, 10).ok()?
+                }
+                _ => return None,
+            };
+            Some((negative, magnitude))
+        }
+        _ => None,
+    }
+}
+
+fn error(path: &str, message: impl Into<String>) -> InvalidTypedDataError {
+    InvalidTypedDataError {
+        path: path.to_owned(),
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::json;
+
+    #[test]
+    fn reports_error_paths() {
+        let mail = || {
+            json!({
+                "types": {
+                    "EIP712Domain": [
+                        { "name": "name", "type": "string" },
+                        { "name": "chainId", "type": "uint256" },
+                    ],
+                    "Person": [
+                        { "name": "name", "type": "string" },
+                        { "name": "wallets", "type": "address[2]" },
+                    ],
+                    "Mail": [
+                        { "name": "from", "type": "Person" },
+                        { "name": "to", "type": "Person[]" },
+                        { "name": "priority", "type": "int8" },
+                    ],
+                },
+                "primaryType": "Mail",
+                "domain": { "name": "Ether Mail", "chainId": 1 },
+                "message": {
+                    "from": {
+                        "name": "Cow",
+                        "wallets": [
+                            "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826",
+                            "0xDeaDbeefdEAdbeefdEadbEEFdeadbeEFdEaDbeeF",
+                        ],
+                    },
+                    "to": [],
+                    "priority": "-128",
+                },
+            })
+        };
+        let path = |typed_data: Value| validate(&typed_data).unwrap_err().path;
+
+        validate(&mail()).unwrap();
+
+        let mut typed_data = mail();
+        typed_data["message"]["from"]["wallets"][1] = json!("0x1234");
+        assert_eq!(path(typed_data), "message.from.wallets[1]");
+
+        let mut typed_data = mail();
+        typed_data["message"]["from"]["wallets"] = json!([]);
+        assert_eq!(path(typed_data), "message.from.wallets");
+
+        let mut typed_data = mail();
+        typed_data["message"]["priority"] = json!(128);
+        assert_eq!(path(typed_data), "message.priority");
+
+        let mut typed_data = mail();
+        typed_data["message"]["to"] = json!([{ "name": "Bob" }]);
+        let err = validate(&typed_data).unwrap_err();
+        assert_eq!(err.path, "message.to[0]");
+        assert_eq!(err.message, "missing field 'wallets'");
+
+        let mut typed_data = mail();
+        typed_data["types"]["Mail"][2]["type"] = json!("Priority");
+        assert_eq!(path(typed_data), "types.Mail[2].type");
+
+        let mut typed_data = mail();
+        typed_data["domain"]["chainId"] = json!("-1");
+        assert_eq!(path(typed_data), "domain.chainId");
+    }
+}
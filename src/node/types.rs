@@ -1,12 +1,16 @@
 //! Additional Ethereum RPC types.
 
-use crate::serialization::Quantity;
+use crate::{
+    node::transaction::TransactionRequest,
+    serialization::{Bytes, Quantity, Str},
+};
+use hdwallet::account::Address;
 use rocket::serde::{Deserialize, Serialize};
 
 /// A block reference.
 #[derive(Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
-pub enum Block {
+pub enum BlockTag {
     /// The latest block.
     #[serde(rename = "latest")]
     Latest,
@@ -15,6 +19,157 @@ pub enum Block {
     Pending,
 }
 
+/// A block, as returned by `eth_getBlockByNumber`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Block {
+    /// The block number.
+    pub number: Quantity<u64>,
+    /// The block hash.
+    pub hash: Bytes<[u8; 32]>,
+    /// The hash of the parent block.
+    pub parent_hash: Bytes<[u8; 32]>,
+    /// The UNIX timestamp of the block in seconds.
+    pub timestamp: Quantity<u64>,
+    /// The address receiving the block's priority fees.
+    pub miner: Str<Address>,
+    /// The gas limit of the block.
+    pub gas_limit: Quantity<u64>,
+    /// The gas used by the block's transactions.
+    pub gas_used: Quantity<u64>,
+    /// The base fee per gas of the block, for blocks after EIP-1559.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_fee_per_gas: Option<Quantity>,
+    /// The block's transactions.
+    pub transactions: BlockTransactions,
+}
+
+/// The transactions of a block, either as hashes or in full, depending on
+/// how the block was requested.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", untagged)]
+pub enum BlockTransactions {
+    /// The transaction hashes.
+    Hashes(Vec<Bytes<[u8; 32]>>),
+    /// The full transactions.
+    Full(Vec<Transaction>),
+}
+
+/// A transaction, as returned by `eth_getTransactionByHash` and in full
+/// blocks.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Transaction {
+    /// The transaction hash.
+    pub hash: Bytes<[u8; 32]>,
+    /// The EIP-2718 transaction type.
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<Quantity<u64>>,
+    /// The account that sent the transaction.
+    pub from: Str<Address>,
+    /// The recipient of the transaction, or `None` for contract creations.
+    pub to: Option<Str<Address>>,
+    /// The nonce of the transaction.
+    pub nonce: Quantity<u64>,
+    /// The gas limit of the transaction.
+    pub gas: Quantity<u64>,
+    /// The gas price of legacy transactions, or the effective gas price of
+    /// included EIP-1559 transactions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gas_price: Option<Quantity>,
+    /// The maximum fee per gas of EIP-1559 transactions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_fee_per_gas: Option<Quantity>,
+    /// The maximum priority fee per gas of EIP-1559 transactions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_priority_fee_per_gas: Option<Quantity>,
+    /// The amount of Ether sent with the transaction.
+    pub value: Quantity,
+    /// The calldata of the transaction.
+    pub input: Bytes<Vec<u8>>,
+    /// The chain ID of the transaction, if it is replay protected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<Quantity<u64>>,
+    /// The hash of the including block, or `None` while pending.
+    pub block_hash: Option<Bytes<[u8; 32]>>,
+    /// The number of the including block, or `None` while pending.
+    pub block_number: Option<Quantity<u64>>,
+    /// The index of the transaction in its block, or `None` while pending.
+    pub transaction_index: Option<Quantity<u64>>,
+}
+
+/// A transaction receipt, as returned by `eth_getTransactionReceipt`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Receipt {
+    /// The transaction hash.
+    pub transaction_hash: Bytes<[u8; 32]>,
+    /// The index of the transaction in its block.
+    pub transaction_index: Quantity<u64>,
+    /// The hash of the including block.
+    pub block_hash: Bytes<[u8; 32]>,
+    /// The number of the including block.
+    pub block_number: Quantity<u64>,
+    /// The account that sent the transaction.
+    pub from: Str<Address>,
+    /// The recipient of the transaction, or `None` for contract creations.
+    pub to: Option<Str<Address>>,
+    /// The gas used by the block's transactions up to and including this one.
+    pub cumulative_gas_used: Quantity<u64>,
+    /// The gas used by the transaction.
+    pub gas_used: Quantity<u64>,
+    /// The price per gas paid by the transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effective_gas_price: Option<Quantity>,
+    /// The address of the created contract, for contract creations.
+    #[serde(default)]
+    pub contract_address: Option<Str<Address>>,
+    /// The logs emitted by the transaction.
+    pub logs: Vec<Log>,
+    /// Whether the transaction succeeded (1) or reverted (0), for
+    /// transactions after the Byzantium hard fork.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Quantity<u64>>,
+}
+
+impl Receipt {
+    /// Returns whether or not the transaction reverted.
+    pub fn reverted(&self) -> bool {
+        self.status.map(|status| status.0 == 0).unwrap_or(false)
+    }
+}
+
+/// A log, as returned by `eth_getLogs` and in receipts.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Log {
+    /// The address of the contract that emitted the log.
+    pub address: Str<Address>,
+    /// The indexed topics of the log.
+    pub topics: Vec<Bytes<[u8; 32]>>,
+    /// The non-indexed data of the log.
+    pub data: Bytes<Vec<u8>>,
+    /// The number of the including block, or `None` while pending.
+    #[serde(default)]
+    pub block_number: Option<Quantity<u64>>,
+    /// The hash of the including block, or `None` while pending.
+    #[serde(default)]
+    pub block_hash: Option<Bytes<[u8; 32]>>,
+    /// The hash of the emitting transaction, or `None` while pending.
+    #[serde(default)]
+    pub transaction_hash: Option<Bytes<[u8; 32]>>,
+    /// The index of the emitting transaction in its block, or `None` while
+    /// pending.
+    #[serde(default)]
+    pub transaction_index: Option<Quantity<u64>>,
+    /// The index of the log in its block, or `None` while pending.
+    #[serde(default)]
+    pub log_index: Option<Quantity<u64>>,
+    /// Whether or not the log was removed because of a chain reorganization.
+    #[serde(default)]
+    pub removed: bool,
+}
+
 /// Fee history.
 #[derive(Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
@@ -31,3 +186,91 @@ pub struct FeeHistory {
     /// Effective priority fee reward percentiles.
     pub reward: Option<Vec<Vec<Quantity>>>,
 }
+
+/// Parameters for an `eth_call` message call.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CallRequest {
+    /// The contract to call.
+    pub to: Str<Address>,
+    /// The calldata to use for the call.
+    pub data: Bytes<Vec<u8>>,
+}
+
+/// An entry of an access list.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct AccessListItem {
+    /// The accessed account.
+    pub address: Str<Address>,
+    /// The accessed storage slots of the account.
+    pub storage_keys: Vec<Bytes<[u8; 32]>>,
+}
+
+/// The result of an `eth_createAccessList` call.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct AccessListResult {
+    /// The accounts and storage slots accessed by the transaction.
+    pub access_list: Vec<AccessListItem>,
+    /// The gas used by the transaction with the access list.
+    pub gas_used: Quantity,
+}
+
+/// Parameters for an `eth_call` simulating a transaction.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SimulationRequest<'a> {
+    /// The account sending the transaction.
+    pub from: Str<Address>,
+    /// The transaction.
+    #[serde(flatten)]
+    pub transaction: &'a TransactionRequest,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::{json, serde_json};
+
+    #[test]
+    fn full_block_transactions() {
+        let hash = format!("0x{}", "42".repeat(32));
+        let block = serde_json::from_value::<Block>(json!({
+            "number": "0x1",
+            "hash": hash,
+            "parentHash": hash,
+            "timestamp": "0x0",
+            "miner": "0x0000000000000000000000000000000000000000",
+            "gasLimit": "0x1c9c380",
+            "gasUsed": "0x5208",
+            "transactions": [{
+                "hash": hash,
+                "type": "0x0",
+                "from": "0x4242424242424242424242424242424242424242",
+                "to": null,
+                "nonce": "0x0",
+                "gas": "0x5208",
+                "gasPrice": "0x1",
+                "value": "0x0",
+                "input": "0x",
+                "blockHash": hash,
+                "blockNumber": "0x1",
+                "transactionIndex": "0x0",
+                "v": "0x1b",
+                "r": "0x1",
+                "s": "0x1",
+            }],
+        }))
+        .unwrap();
+
+        assert!(block.base_fee_per_gas.is_none());
+        match block.transactions {
+            BlockTransactions::Full(transactions) => {
+                assert_eq!(transactions[0].from.0, Address([0x42; 20]));
+                assert!(transactions[0].to.is_none());
+            }
+            BlockTransactions::Hashes(_) => panic!("expected full transactions"),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Module implementing load balancing across remote nodes.
+//!
+//! Filters are local to the node that created them, so filter IDs returned by
+//! remote nodes are replaced with hdnode-issued IDs that remember which remote
+//! node owns the filter. Subsequent filter calls are routed to that node.
+
+use crate::{
+    jsonrpc::{self, Id, JsonRpc, Params, Request},
+    node::{
+        eth::Eth,
+        filter::{filter_not_found, FILTER_TIMEOUT},
+    },
+};
+use anyhow::Result;
+use rocket::serde::json::serde_json::{self, value::RawValue};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::Instant,
+};
+
+/// A set of remote nodes that proxied requests are distributed across.
+pub struct Upstreams {
+    clients: Vec<Eth>,
+    next: AtomicUsize,
+    filters: Mutex<HashMap<String, Filter>>,
+    next_filter: AtomicU64,
+}
+
+/// A filter installed on a remote node.
+struct Filter {
+    /// The index of the remote node owning the filter.
+    upstream: usize,
+    /// The filter ID issued by the remote node.
+    id: Box<RawValue>,
+    /// The last time the filter was used.
+    last_poll: Instant,
+}
+
+impl Upstreams {
+    /// Creates a new set of upstreams from the specified clients.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no clients are specified.
+    pub fn new(clients: Vec<Eth>) -> Self {
+        assert!(!clients.is_empty(), "at least one upstream is required");
+        Self {
+            clients,
+            next: AtomicUsize::new(0),
+            filters: Mutex::new(HashMap::new()),
+            next_filter: AtomicU64::new(1),
+        }
+    }
+
+    /// Returns true if requests are distributed across more than one remote
+    /// node.
+    pub fn is_load_balanced(&self) -> bool {
+        self.clients.len() > 1
+    }
+
+    /// Selects the remote node for the next proxied request in round-robin
+    /// order.
+    pub fn select(&self) -> &Eth {
+        &self.clients[self.select_index()]
+    }
+
+    /// Creates a new filter on one of the remote nodes and returns an
+    /// hdnode-issued filter ID for it.
+    pub async fn new_filter(&self, method: &str, params: Option<Params>) -> Result<Box<RawValue>> {
+        let upstream = self.select_index();
+        let id = self.execute(upstream, method, params).await?;
+
+        let filter_id = format!("{:#x}", self.next_filter.fetch_add(1, Ordering::SeqCst));
+        tracing::debug!(%filter_id, upstream, remote_id = %id, "installed filter");
+        self.lock_filters().insert(
+            filter_id.clone(),
+            Filter {
+                upstream,
+                id,
+                last_poll: Instant::now(),
+            },
+        );
+
+        Ok(serde_json::value::to_raw_value(&filter_id)?)
+    }
+
+    /// Executes a filter method, such as `eth_getFilterChanges`, on the remote
+    /// node owning the filter.
+    pub async fn filter_call(&self, method: &str, params: Option<Params>) -> Result<Box<RawValue>> {
+        let (filter_id,) = params
+            .as_ref()
+            .and_then(|params| params.parse::<(String,)>().ok())
+            .ok_or_else(jsonrpc::Error::invalid_params)?;
+
+        let (upstream, params) = {
+            let mut filters = self.lock_filters();
+            let filter = filters.get_mut(&filter_id).ok_or_else(filter_not_found)?;
+            filter.last_poll = Instant::now();
+            (filter.upstream, Params::new([&filter.id])?)
+        };
+        let result = self.execute(upstream, method, Some(params)).await?;
+
+        if method == "eth_uninstallFilter" {
+            self.lock_filters().remove(&filter_id);
+        }
+        Ok(result)
+    }
+
+    /// Locks the filter table, forgetting any filters that timed out. The
+    /// remote nodes uninstall these filters on their own.
+    fn lock_filters(&self) -> MutexGuard<'_, HashMap<String, Filter>> {
+        let mut filters = self.filters.lock().unwrap();
+        filters.retain(|_, filter| filter.last_poll.elapsed() < FILTER_TIMEOUT);
+        filters
+    }
+
+    /// Returns the index of the next remote node in round-robin order.
+    fn select_index(&self) -> usize {
+        self.next.fetch_add(1, Ordering::Relaxed) % self.clients.len()
+    }
+
+    /// Executes a request on the specified remote node.
+    async fn execute(
+        &self,
+        upstream: usize,
+        method: &str,
+        params: Option<Params>,
+    ) -> Result<Box<RawValue>> {
+        let request = Request {
+            jsonrpc: JsonRpc::V2,
+            method: method.to_owned(),
+            params,
+            id: Id::Number(0.into()),
+        };
+        let response = self.clients[upstream].execute(&request).await?;
+        Ok(response.result?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[rocket::async_test]
+    async fn forgets_idle_filters() {
+        let eth = Eth::from_url("http://localhost:8545".parse().unwrap(), Default::default());
+        let upstreams = Upstreams::new(vec![eth.unwrap()]);
+        for (id, idle) in [("0x1", FILTER_TIMEOUT), ("0x2", Duration::ZERO)] {
+            upstreams.filters.lock().unwrap().insert(
+                id.to_owned(),
+                Filter {
+                    upstream: 0,
+                    id: serde_json::value::to_raw_value(id).unwrap(),
+                    last_poll: Instant::now() - idle,
+                },
+            );
+        }
+
+        let params = Params::new(["0x1"]).unwrap();
+        let err = upstreams
+            .filter_call("eth_getFilterChanges", Some(params))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("filter not found"));
+        assert!(upstreams.lock_filters().contains_key("0x2"));
+    }
+}
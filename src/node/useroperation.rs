@@ -0,0 +1,192 @@
+//! ERC-4337 user operation type for RPC calls.
+
+use crate::{
+    abi,
+    node::eth::Eth,
+    serialization::{Bytes, Quantity, Str},
+};
+use anyhow::Result;
+use ethnum::U256;
+use hdwallet::account::Address;
+use rocket::serde::{json::serde_json, Deserialize, Serialize, Serializer};
+use std::fmt::{self, Debug, Formatter};
+
+/// User operation parameters used for the `hdnode_signUserOperation` RPC call.
+///
+/// This uses the same JSON representation as the `eth_sendUserOperation`
+/// bundler RPC method.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", deny_unknown_fields)]
+pub struct UserOperationRequest {
+    /// The smart contract account making the operation.
+    pub sender: Str<Address>,
+    /// The anti-replay nonce of the account.
+    pub nonce: Quantity,
+    /// The account factory calldata, only used for account creation.
+    #[serde(rename = "initCode", default)]
+    pub init_code: Bytes<Vec<u8>>,
+    /// The calldata to execute on the account.
+    #[serde(rename = "callData")]
+    pub call_data: Bytes<Vec<u8>>,
+    /// The gas limit for the main execution call.
+    #[serde(rename = "callGasLimit")]
+    pub call_gas_limit: Quantity,
+    /// The gas limit for the verification step.
+    #[serde(rename = "verificationGasLimit")]
+    pub verification_gas_limit: Quantity,
+    /// The gas paid to the bundler for pre-verification execution.
+    #[serde(rename = "preVerificationGas")]
+    pub pre_verification_gas: Quantity,
+    /// The maximum gas price in Wei for the operation.
+    #[serde(rename = "maxFeePerGas")]
+    pub max_fee_per_gas: Quantity,
+    /// The maximum priority fee in Wei for the operation.
+    #[serde(rename = "maxPriorityFeePerGas")]
+    pub max_priority_fee_per_gas: Quantity,
+    /// The paymaster address and its data, if the operation is sponsored.
+    #[serde(rename = "paymasterAndData", default)]
+    pub paymaster_and_data: Bytes<Vec<u8>>,
+    /// The signature field. This is not part of the user operation hash and
+    /// is accepted so clients can send placeholder values.
+    #[serde(default)]
+    pub signature: Bytes<Vec<u8>>,
+}
+
+impl UserOperationRequest {
+    /// Prepares a user operation for signing for the specified entry point,
+    /// using the chain ID of the connected node.
+    pub async fn prepare(self, eth: &Eth, entry_point: Address) -> Result<UserOperation> {
+        let chain_id = eth.chain_id().await?;
+        Ok(UserOperation::new(self, entry_point, chain_id))
+    }
+
+    /// Computes the hash of the packed user operation fields.
+    fn packed_hash(&self) -> [u8; 32] {
+        abi::keccak256(abi::encode(&[
+            abi::address(self.sender.0),
+            abi::uint(self.nonce.0),
+            abi::keccak256(&*self.init_code),
+            abi::keccak256(&*self.call_data),
+            abi::uint(self.call_gas_limit.0),
+            abi::uint(self.verification_gas_limit.0),
+            abi::uint(self.pre_verification_gas.0),
+            abi::uint(self.max_fee_per_gas.0),
+            abi::uint(self.max_priority_fee_per_gas.0),
+            abi::keccak256(&*self.paymaster_and_data),
+        ]))
+    }
+}
+
+/// A user operation bound to an entry point and chain, ready to be signed.
+pub struct UserOperation {
+    args: UserOperationRequest,
+    entry_point: Address,
+    chain_id: U256,
+    hash: [u8; 32],
+}
+
+impl UserOperation {
+    /// Creates a new user operation for the specified entry point and chain.
+    fn new(args: UserOperationRequest, entry_point: Address, chain_id: U256) -> Self {
+        let hash = abi::keccak256(abi::encode(&[
+            args.packed_hash(),
+            abi::address(entry_point),
+            abi::uint(chain_id),
+        ]));
+
+        Self {
+            args,
+            entry_point,
+            chain_id,
+            hash,
+        }
+    }
+
+    /// Returns the user operation hash, as computed by the entry point's
+    /// `getUserOpHash` method.
+    pub fn hash(&self) -> [u8; 32] {
+        self.hash
+    }
+
+    /// Returns the user operation request.
+    pub fn request(&self) -> &UserOperationRequest {
+        &self.args
+    }
+}
+
+impl Debug for UserOperation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match serde_json::to_string(self) {
+            Ok(json) => f.write_str(&json),
+            Err(err) => {
+                tracing::error!(?err, "unexpected error formatting user operation");
+                f.write_str("UserOperation { FORMATTING_ERROR }")
+            }
+        }
+    }
+}
+
+impl Serialize for UserOperation {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        #[derive(Serialize)]
+        #[serde(crate = "rocket::serde")]
+        struct Repr<'a> {
+            #[serde(flatten)]
+            args: &'a UserOperationRequest,
+            #[serde(rename = "entryPoint")]
+            entry_point: Str<Address>,
+            #[serde(rename = "chainId")]
+            chain_id: Quantity,
+            #[serde(rename = "userOpHash")]
+            hash: Bytes<[u8; 32]>,
+        }
+
+        Repr {
+            args: &self.args,
+            entry_point: Str(self.entry_point),
+            chain_id: Quantity(self.chain_id),
+            hash: Bytes(self.hash),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::json;
+
+    #[test]
+    fn user_operation_hash() {
+        // `execute(0x4242..42, 0, 0x)` on a smart contract account, hashed for
+        // the v0.6 entry point on mainnet.
+        let request = serde_json::from_value::<UserOperationRequest>(json!({
+            "sender": "0x9406cc6185a346906296840746125a0e44976454",
+            "nonce": "0x7",
+            "callData": "0xb61d27f6\
+                         0000000000000000000000004242424242424242424242424242424242424242\
+                         0000000000000000000000000000000000000000000000000000000000000000\
+                         0000000000000000000000000000000000000000000000000000000000000060\
+                         0000000000000000000000000000000000000000000000000000000000000000",
+            "callGasLimit": "0x186a0",
+            "verificationGasLimit": "0x249f0",
+            "preVerificationGas": "0x5208",
+            "maxFeePerGas": "0x6fc23ac00",
+            "maxPriorityFeePerGas": "0x3b9aca00",
+            "signature": "0x",
+        }))
+        .unwrap();
+        let entry_point = "0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789"
+            .parse()
+            .unwrap();
+
+        let user_operation = UserOperation::new(request, entry_point, U256::ONE);
+        assert_eq!(
+            hex::encode(user_operation.hash()),
+            "651d21039af4e6bcd9f5ccabffd5539284101f56ab3bb8fdf95fdb4b854ac485",
+        );
+    }
+}
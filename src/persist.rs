@@ -0,0 +1,12 @@
+//! Helpers for persisting state to local files.
+
+use std::{fs, io, path::Path};
+
+/// Replaces the contents of a file. The contents are written to a temporary
+/// file next to it first, which is then renamed over the file, so that the
+/// file is never left partially written.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)
+}
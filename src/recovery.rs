@@ -0,0 +1,96 @@
+//! Module implementing ECDSA public key recovery for Ethereum signatures.
+
+use crate::abi;
+use anyhow::{bail, ensure, Result};
+use hdwallet::account::{Address, Signature};
+use secp256k1::{
+    ecdsa::{RecoverableSignature, RecoveryId},
+    Message, Secp256k1,
+};
+
+/// An uncompressed SEC1 encoded secp256k1 public key.
+pub type PublicKey = [u8; 65];
+
+/// Recovers the public key that produced a signature for a 32-byte digest.
+pub fn recover_public_key(hash: [u8; 32], signature: &Signature) -> Result<PublicKey> {
+    let mut compact = [0_u8; 64];
+    compact[..32].copy_from_slice(&signature.r);
+    compact[32..].copy_from_slice(&signature.s);
+    let v = signature.v();
+    let recovery_id = RecoveryId::from_i32(i32::from(if v >= 27 { v - 27 } else { v }))?;
+
+    let signature = RecoverableSignature::from_compact(&compact, recovery_id)?;
+    let message = Message::from_slice(&hash)?;
+    let public_key = Secp256k1::verification_only().recover_ecdsa(&message, &signature)?;
+
+    Ok(public_key.serialize_uncompressed())
+}
+
+/// Recovers the address of the account that produced a signature for a
+/// 32-byte digest.
+pub fn recover_address(hash: [u8; 32], signature: &Signature) -> Result<Address> {
+    Ok(address(&recover_public_key(hash, signature)?))
+}
+
+/// Parses a 65-byte `r || s || v` signature.
+pub fn parse_signature(bytes: &[u8]) -> Result<Signature> {
+    ensure!(bytes.len() == 65, "invalid signature length");
+    Ok(Signature {
+        r: bytes[..32].try_into()?,
+        s: bytes[32..64].try_into()?,
+        y_parity: y_parity(bytes[64].into())?,
+    })
+}
+
+/// Returns the y-parity for a signature `v` value, which is either the parity
+/// itself, 27 or 28, or an EIP-155 value.
+pub fn y_parity(v: u64) -> Result<bool> {
+    Ok(match v {
+        0 | 1 => v == 1,
+        27 | 28 => v == 28,
+        v if v >= 35 => (v - 35) % 2 == 1,
+        _ => bail!("invalid signature v value {v}"),
+    })
+}
+
+/// Computes the Ethereum address of a public key.
+pub fn address(public_key: &PublicKey) -> Address {
+    let hash = abi::keccak256(&public_key[1..]);
+    let mut address = [0_u8; 20];
+    address.copy_from_slice(&hash[12..]);
+    Address(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{call, client, MockUpstream, ACCOUNT};
+    use rocket::serde::json::serde_json::json;
+
+    #[rocket::async_test]
+    async fn recovers_message_signers() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+
+        let hash = call(&client, "hdnode_hashMessage", json!(["0x68656c6c6f"])).await;
+        assert_eq!(
+            hash["result"],
+            "0x50b2c43fd39106bafbba0da34fc430e1f91e3c96ea2acee2bc34119f92b37750",
+        );
+
+        let signature = call(&client, "eth_sign", json!([ACCOUNT, "0x68656c6c6f"])).await;
+        let signer = call(
+            &client,
+            "hdnode_recoverAddress",
+            json!(["0x68656c6c6f", signature["result"]]),
+        )
+        .await;
+        assert_eq!(signer["result"].as_str().unwrap().to_lowercase(), ACCOUNT);
+
+        let invalid = call(&client, "hdnode_recoverAddress", json!(["0x", "0x00"])).await;
+        assert!(invalid["error"].is_object());
+        assert!(upstream
+            .requests()
+            .iter()
+            .all(|request| request["method"] == "eth_chainId"));
+    }
+}
@@ -0,0 +1,118 @@
+//! Module implementing configuration reloading.
+//!
+//! Selected parts of the configuration can be reloaded on a running node,
+//! either on SIGHUP or with the `hdnode_reloadConfig` RPC method. This avoids
+//! dropping connections and re-deriving keys for routine policy changes.
+
+use crate::{
+    cli,
+    node::{Node, Options},
+    signer::validator,
+};
+use anyhow::{Context as _, Result};
+use rocket::{
+    serde::Deserialize,
+    tokio::signal::unix::{signal, SignalKind},
+};
+use std::{
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+use tracing_subscriber::{
+    layer::SubscriberExt as _, reload, util::SubscriberInitExt as _, EnvFilter, Registry,
+};
+
+/// The default log filter used when `RUST_LOG` is not set.
+const DEFAULT_LOG_FILTER: &str = "info";
+
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Initializes logging with a reloadable filter. The initial filter is read
+/// from the `RUST_LOG` environment variable.
+pub fn init_logging() {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER));
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+    let _ = LOG_FILTER.set(handle);
+}
+
+/// Parses log filter directives, in the same format as `RUST_LOG`.
+fn log_filter(directives: &str) -> Result<EnvFilter> {
+    EnvFilter::try_new(directives).with_context(|| format!("invalid log level {directives:?}"))
+}
+
+/// Replaces the current log filter.
+fn set_log_filter(filter: EnvFilter) -> Result<()> {
+    LOG_FILTER
+        .get()
+        .context("logging not initialized")?
+        .reload(filter)?;
+    Ok(())
+}
+
+/// Sets the log level from filter directives, in the same format as
+/// `RUST_LOG`.
+pub fn set_log_level(directives: &str) -> Result<()> {
+    set_log_filter(log_filter(directives)?)
+}
+
+/// The parts of the configuration that can be reloaded.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Config {
+    validator: Option<PathBuf>,
+    log_filter: Option<String>,
+    #[serde(flatten)]
+    node: Options,
+}
+
+/// Reloads the configuration of a running node.
+pub struct Reloader {
+    validators: Vec<(validator::Handle, Option<PathBuf>)>,
+    dev: bool,
+}
+
+impl Reloader {
+    /// Creates a new reloader for the specified validators. Validators with an
+    /// explicitly specified module reload it, while the others reload the
+    /// configured `validator` module. Nodes in development mode reload their
+    /// configuration with the development defaults, as on startup.
+    pub fn new(validators: Vec<(validator::Handle, Option<PathBuf>)>, dev: bool) -> Self {
+        Self { validators, dev }
+    }
+
+    /// Reads the current configuration and applies its reloadable parts to
+    /// the node. Nothing gets applied if the configuration is invalid.
+    pub fn reload(&self, node: &Node) -> Result<()> {
+        let config = cli::figment(self.dev)
+            .extract::<Config>()
+            .context("invalid configuration")?;
+        let log_filter = config.log_filter.as_deref().map(log_filter).transpose()?;
+
+        for (validator, module) in &self.validators {
+            validator.reload(module.as_deref().or(config.validator.as_deref()))?;
+        }
+        if let Some(log_filter) = log_filter {
+            set_log_filter(log_filter)?;
+        }
+        node.set_options(config.node);
+
+        tracing::info!("reloaded configuration");
+        Ok(())
+    }
+}
+
+/// Reloads the node's configuration whenever the process receives SIGHUP.
+pub async fn reload_on_sighup(node: Arc<Node>) -> Result<()> {
+    let mut hangups = signal(SignalKind::hangup())?;
+    while hangups.recv().await.is_some() {
+        if let Err(err) = node.reload_config() {
+            tracing::error!(?err, "failed to reload configuration");
+        }
+    }
+    Ok(())
+}
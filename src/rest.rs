@@ -0,0 +1,176 @@
+//! REST-style read endpoints.
+//!
+//! A couple of convenience `GET` endpoints for health dashboards and scripts
+//! that can't easily speak JSON RPC. They are translated to the corresponding
+//! JSON RPC calls and handled like any other request, responding with the
+//! bare JSON result. They are disabled unless `rest_api` is set.
+
+use crate::{
+    acl::RpcClient,
+    jsonrpc::{self, Id, JsonRpc, Params, Request},
+    node::{Caller, Node, RequestTimeout},
+};
+use rocket::{
+    http::Status,
+    request::{self, FromRequest},
+    serde::{
+        json::{
+            serde_json::{self, json},
+            Json, Value,
+        },
+        Deserialize,
+    },
+    State,
+};
+use std::sync::Arc;
+
+/// REST endpoint options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Options {
+    /// Whether or not to serve the REST-style read endpoints.
+    #[serde(default)]
+    pub rest_api: bool,
+}
+
+/// Request guard forwarding requests when the REST endpoints are disabled.
+pub struct Enabled;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Enabled {
+    type Error = ();
+
+    async fn from_request(request: &'r rocket::Request<'_>) -> request::Outcome<Self, ()> {
+        match request.rocket().state::<Options>() {
+            Some(options) if options.rest_api => request::Outcome::Success(Enabled),
+            _ => request::Outcome::Forward(Status::NotFound),
+        }
+    }
+}
+
+/// A REST response, with the JSON RPC result or error.
+type Reply = (Status, Json<Value>);
+
+/// Returns the balance of an account, at the `latest` block unless another
+/// block is specified.
+#[rocket::get("/eth/balance/<address>?<block>")]
+pub async fn balance(
+    _enabled: Enabled,
+    _client: RpcClient,
+    address: &str,
+    block: Option<&str>,
+    caller: Caller,
+    timeout: RequestTimeout,
+    node: &State<Arc<Node>>,
+) -> Reply {
+    let params = json!([address, block.unwrap_or("latest")]);
+    call(node, "eth_getBalance", params, &caller, timeout).await
+}
+
+/// Returns a transaction by its hash.
+#[rocket::get("/eth/tx/<hash>")]
+pub async fn transaction(
+    _enabled: Enabled,
+    _client: RpcClient,
+    hash: &str,
+    caller: Caller,
+    timeout: RequestTimeout,
+    node: &State<Arc<Node>>,
+) -> Reply {
+    call(
+        node,
+        "eth_getTransactionByHash",
+        json!([hash]),
+        &caller,
+        timeout,
+    )
+    .await
+}
+
+/// Handles a JSON RPC call for a REST request.
+async fn call(
+    node: &Node,
+    method: &str,
+    params: Value,
+    caller: &Caller,
+    timeout: RequestTimeout,
+) -> Reply {
+    let request = Request {
+        jsonrpc: JsonRpc::V2,
+        method: method.to_owned(),
+        params: Some(Params::new(params).expect("JSON value serializes")),
+        id: Id::Number(1.into()),
+    };
+    let handled = async {
+        let response = node.handle_request(request, caller).await;
+        match response.result {
+            Ok(result) => match serde_json::from_str::<Value>(result.get()) {
+                Ok(Value::Null) => (Status::NotFound, Json(Value::Null)),
+                Ok(result) => (Status::Ok, Json(result)),
+                Err(_) => error(jsonrpc::Error::internal_error()),
+            },
+            Err(err) => error(err),
+        }
+    };
+    node.within_timeout(timeout.0, handled, || {
+        (
+            Status::GatewayTimeout,
+            Json(json!({ "error": jsonrpc::Error::timed_out() })),
+        )
+    })
+    .await
+}
+
+/// Returns the response for a JSON RPC error.
+fn error(err: jsonrpc::Error) -> Reply {
+    let status = match err.code {
+        -32700 | -32600 | -32602 => Status::BadRequest,
+        -32601 => Status::NotFound,
+        -32005 => Status::TooManyRequests,
+        4001 | 4100 => Status::Forbidden,
+        _ => Status::BadGateway,
+    };
+    (status, Json(json!({ "error": err })))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{client, client_with, MockUpstream, ACCOUNT};
+    use rocket::serde::json::{serde_json::json, Value};
+
+    #[rocket::async_test]
+    async fn serves_rest_read_endpoints() {
+        let upstream = MockUpstream::start().await;
+        let client = client(&upstream).await;
+        let status = client
+            .get(format!("/eth/balance/{ACCOUNT}"))
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, rocket::http::Status::NotFound);
+
+        let client = client_with(&upstream, |figment| figment.merge(("rest_api", true))).await;
+        upstream.respond("eth_getBalance", json!("0xde0b6b3a7640000"));
+        upstream.respond("eth_getTransactionByHash", Value::Null);
+
+        let response = client
+            .get(format!("/eth/balance/{ACCOUNT}?block=0x2a"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), rocket::http::Status::Ok);
+        assert_eq!(
+            response.into_json::<Value>().await.unwrap(),
+            "0xde0b6b3a7640000"
+        );
+        let request = upstream.requests().pop().unwrap();
+        assert_eq!(request["params"], json!([ACCOUNT, "0x2a"]));
+
+        let hash = format!("0x{}", "00".repeat(32));
+        let status = client
+            .get(format!("/eth/tx/{hash}"))
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, rocket::http::Status::NotFound);
+    }
+}
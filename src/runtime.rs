@@ -0,0 +1,41 @@
+//! Helpers for blocking from synchronous code running on the async runtime.
+//!
+//! Signers and backends are synchronous but are called from request handlers
+//! on the runtime's worker threads. Blocking a worker thread stalls the tasks
+//! scheduled on it, so blocking work is moved off the runtime's workers where
+//! the runtime allows it.
+
+use anyhow::{bail, Result};
+use rocket::tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    task,
+};
+use std::future::Future;
+
+/// Runs blocking work, blocking in place when running on a multi-threaded
+/// runtime so that other tasks can make progress.
+pub fn blocking<T>(f: impl FnOnce() -> T) -> T {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
+/// Runs a future to completion from synchronous code running on the async
+/// runtime, blocking in place so that other tasks can make progress. This
+/// fails outside of a multi-threaded runtime, where the future could never
+/// complete, rather than panicking or deadlocking.
+pub fn block_on<F>(future: F) -> Result<F::Output>
+where
+    F: Future,
+{
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            Ok(task::block_in_place(|| handle.block_on(future)))
+        }
+        Ok(_) => bail!("blocking on async work requires a multi-threaded runtime"),
+        Err(_) => bail!("blocking on async work requires a runtime"),
+    }
+}
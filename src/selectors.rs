@@ -0,0 +1,151 @@
+//! Function signature database for naming the functions called by calldata.
+//!
+//! Calldata selectors are looked up in a bundled list of common function
+//! signatures, optionally extended with local signature files and a list
+//! refreshed periodically from a URL. Names are purely informational, as
+//! different functions can share a selector.
+
+use crate::{abi, serialization::Str};
+use anyhow::{Context as _, Result};
+use reqwest::Url;
+use rocket::{serde::Deserialize, tokio};
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, RwLock, Weak},
+    time::Duration,
+};
+
+/// The bundled function signatures.
+const BUNDLED: &str = include_str!("selectors.txt");
+
+/// Function signatures by selector.
+type Database = HashMap<[u8; 4], String>;
+
+/// Function signature database options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Options {
+    /// Whether or not to name the functions called by calldata.
+    #[serde(default)]
+    pub function_signatures: bool,
+
+    /// Additional files with one function signature per line.
+    #[serde(default)]
+    pub function_signature_files: Vec<PathBuf>,
+
+    /// A URL with one function signature per line that is periodically
+    /// fetched for additional signatures.
+    pub function_signature_url: Option<Str<Url>>,
+
+    /// The interval in seconds at which to refresh signatures from the URL.
+    /// Defaults to a day.
+    pub function_signature_refresh: Option<u64>,
+}
+
+/// A function signature database. Clones share the same signatures, and the
+/// default database is disabled and names no functions.
+#[derive(Clone, Default)]
+pub struct Selectors(Option<Arc<RwLock<Database>>>);
+
+impl Selectors {
+    /// Loads the function signature database configured by the specified
+    /// options. This must be called from within a Tokio runtime when
+    /// refreshing from a URL.
+    pub fn new(options: &Options) -> Result<Self> {
+        if !options.function_signatures {
+            return Ok(Self::default());
+        }
+
+        let mut database = HashMap::new();
+        insert(&mut database, BUNDLED);
+        for file in &options.function_signature_files {
+            let signatures = fs::read_to_string(file).with_context(|| {
+                format!("failed to read function signatures {}", file.display())
+            })?;
+            insert(&mut database, &signatures);
+        }
+        let database = Arc::new(RwLock::new(database));
+
+        if let Some(url) = &options.function_signature_url {
+            let interval = Duration::from_secs(options.function_signature_refresh.unwrap_or(86400));
+            tokio::spawn(refresh(Arc::downgrade(&database), url.0.clone(), interval));
+        }
+        Ok(Self(Some(database)))
+    }
+
+    /// Returns the signature of the function called by some calldata, if
+    /// known.
+    pub fn function(&self, calldata: &[u8]) -> Option<String> {
+        let selector: [u8; 4] = calldata.get(..4)?.try_into().ok()?;
+        self.0.as_ref()?.read().unwrap().get(&selector).cloned()
+    }
+}
+
+/// Adds function signatures, one per line, to the database. Known selectors
+/// are kept, so that bundled and local signatures take precedence.
+fn insert(database: &mut Database, signatures: &str) {
+    for line in signatures.lines() {
+        let signature = line.trim();
+        if signature.is_empty() || signature.starts_with('#') {
+            continue;
+        }
+        database
+            .entry(abi::selector(signature))
+            .or_insert_with(|| signature.to_owned());
+    }
+}
+
+/// Periodically fetches additional function signatures from a URL, until the
+/// database is dropped.
+async fn refresh(database: Weak<RwLock<Database>>, url: Url, interval: Duration) {
+    let client = reqwest::Client::new();
+    while database.strong_count() > 0 {
+        let result = async {
+            client
+                .get(url.clone())
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        }
+        .await;
+        match result {
+            Ok(signatures) => {
+                match database.upgrade() {
+                    Some(database) => insert(&mut database.write().unwrap(), &signatures),
+                    None => break,
+                }
+                tracing::debug!(%url, "refreshed function signatures");
+            }
+            Err(err) => tracing::warn!(?err, %url, "failed to refresh function signatures"),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_signatures() {
+        let mut database = HashMap::new();
+        insert(&mut database, BUNDLED);
+        assert_eq!(
+            database[&hex_literal("a9059cbb")],
+            "transfer(address,uint256)"
+        );
+        // The CoW Protocol settlement selector checked by the sample validator.
+        assert_eq!(
+            database[&hex_literal("13d79a0b")].split('(').next(),
+            Some("settle")
+        );
+    }
+
+    fn hex_literal(selector: &str) -> [u8; 4] {
+        hex::decode(selector).unwrap().try_into().unwrap()
+    }
+}
@@ -6,10 +6,22 @@ use rocket::serde::{de, ser::SerializeSeq as _, Deserialize, Deserializer, Seria
 use std::{
     borrow::Cow,
     fmt::{self, Debug, Display, Formatter},
+    marker::PhantomData,
     ops::Deref,
     str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
+/// Whether or not quantities are deserialized leniently.
+static LENIENT_QUANTITIES: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether or not quantities are deserialized leniently, accepting JSON
+/// numbers and decimal strings in addition to hex-encoded strings. Quantities
+/// are always serialized in their canonical hex encoding.
+pub fn set_lenient_quantities(lenient: bool) {
+    LENIENT_QUANTITIES.store(lenient, Ordering::Relaxed);
+}
+
 /// Type repesenting empty JSON RPC parameters.
 pub type NoParameters = [(); 0];
 
@@ -176,6 +188,7 @@ where
     T: FromStr,
     T::Err: Display,
 {
+    #[allow(clippy::explicit_auto_deref)]
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
@@ -185,17 +198,60 @@ where
     }
 }
 
-/// Wrapper type implementing serialization for 25b-bit unsigned intengers.
+/// Wrapper type implementing serialization for unsigned integer quantities.
+///
+/// Quantities are always serialized in their canonical form from the Ethereum
+/// JSON RPC specification: hex-encoded without leading zeros, and `0x0` for
+/// zero. Quantities are 256-bit by default, but smaller integer types can be
+/// used for fields that are semantically 64-bit, such as nonces, gas limits and
+/// chain IDs, so that out of range values are rejected when deserializing
+/// instead of being forwarded to the remote node.
 #[derive(Clone, Copy, Default, Eq, PartialEq)]
-pub struct Quantity(pub U256);
+pub struct Quantity<T = U256>(pub T);
+
+/// An unsigned integer type that can be used as a quantity.
+pub trait Unsigned: Copy + fmt::LowerHex + Sized {
+    /// The size of the integer type in bits.
+    const BITS: usize;
+
+    /// Parses an integer in the specified radix without prefix.
+    fn from_radix(s: &str, radix: u32) -> Option<Self>;
+
+    /// Converts from a 64-bit integer.
+    fn from_u64(value: u64) -> Self;
+}
+
+macro_rules! impl_unsigned {
+    ($($t:ty => $bits:expr),*) => {$(
+        impl Unsigned for $t {
+            const BITS: usize = $bits;
+
+            fn from_radix(s: &str, radix: u32) -> Option<Self> {
+                <$t>::from_str_radix(s, radix).ok()
+            }
 
-impl Debug for Quantity {
+            fn from_u64(value: u64) -> Self {
+                value.into()
+            }
+        }
+    )*};
+}
+
+impl_unsigned!(u64 => 64, u128 => 128, U256 => 256);
+
+impl<T> Debug for Quantity<T>
+where
+    T: Debug,
+{
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         Debug::fmt(&self.0, f)
     }
 }
 
-impl Serialize for Quantity {
+impl<T> Serialize for Quantity<T>
+where
+    T: Unsigned,
+{
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
@@ -204,17 +260,185 @@ impl Serialize for Quantity {
     }
 }
 
-impl<'de> Deserialize<'de> for Quantity {
+impl<'de, T> Deserialize<'de> for Quantity<T>
+where
+    T: Unsigned,
+{
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let s = Cow::<str>::deserialize(deserializer)?;
-        let s = s
-            .strip_prefix("0x")
-            .ok_or_else(|| de::Error::custom("missing '0x' prefix"))?;
-        U256::from_str_radix(s, 16)
-            .map(Quantity)
-            .map_err(de::Error::custom)
+        if LENIENT_QUANTITIES.load(Ordering::Relaxed) {
+            deserializer.deserialize_any(LenientQuantity(PhantomData))
+        } else {
+            let s = Cow::<str>::deserialize(deserializer)?;
+            parse_quantity(&s, false).map_err(de::Error::custom)
+        }
+    }
+}
+
+/// Parses a quantity string, additionally accepting decimal strings when
+/// lenient.
+fn parse_quantity<T>(s: &str, lenient: bool) -> Result<Quantity<T>, String>
+where
+    T: Unsigned,
+{
+    let digits = match s.strip_prefix("0x") {
+        Some(digits) => digits,
+        None if lenient && !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) => {
+            return T::from_radix(s, 10)
+                .map(Quantity)
+                .ok_or_else(|| format!("quantity {s} out of range for {}-bit integer", T::BITS));
+        }
+        None => return Err("missing '0x' prefix".to_owned()),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("invalid quantity {s}"));
+    }
+    // Leading zeros are not canonical, but are accepted since they are
+    // commonly sent by clients. Quantities are always re-encoded without
+    // them.
+    let significant = digits.trim_start_matches('0');
+    if significant.len() > T::BITS / 4 {
+        return Err(format!(
+            "quantity {s} out of range for {}-bit integer",
+            T::BITS
+        ));
+    }
+    T::from_radix(digits, 16)
+        .map(Quantity)
+        .ok_or_else(|| format!("invalid quantity {s}"))
+}
+
+/// Visitor for lenient quantities, accepting JSON numbers and strings.
+struct LenientQuantity<T>(PhantomData<T>);
+
+impl<'de, T> de::Visitor<'de> for LenientQuantity<T>
+where
+    T: Unsigned,
+{
+    type Value = Quantity<T>;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("a quantity as a hex or decimal string or a number")
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(Quantity(T::from_u64(value)))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        parse_quantity(s, true).map_err(E::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::{collection::vec, prelude::*};
+    use rocket::serde::json::{json, serde_json};
+
+    #[test]
+    fn canonical_quantities() {
+        let encode = |value| serde_json::to_value(value).unwrap();
+        assert_eq!(encode(Quantity(U256::ZERO)), json!("0x0"));
+        assert_eq!(encode(Quantity(U256::new(0x0102))), json!("0x102"));
+        assert_eq!(serde_json::to_value(Quantity(0_u64)).unwrap(), json!("0x0"));
+        assert_eq!(
+            encode(Quantity(U256::MAX)),
+            json!(format!("0x{}", "f".repeat(64)))
+        );
+
+        let decode = |value: &str| parse_quantity::<u64>(value, false).map(|q| q.0);
+        assert_eq!(decode("0x0"), Ok(0));
+        assert_eq!(decode("0x00ff"), Ok(0xff));
+        assert_eq!(decode("0xffffffffffffffff"), Ok(u64::MAX));
+        assert!(decode("0x10000000000000000").is_err());
+        assert!(decode("0x").is_err());
+        assert!(decode("ff").is_err());
+        assert!(decode("0xfg").is_err());
+        assert!(decode("42").is_err());
+    }
+
+    #[test]
+    fn lenient_quantities() {
+        let decode = |value: &str| parse_quantity::<U256>(value, true).map(|q| q.0);
+        assert_eq!(decode("42"), Ok(U256::new(42)));
+        assert_eq!(decode("0x2a"), Ok(U256::new(42)));
+        assert!(decode("-1").is_err());
+        assert!(decode("4.2").is_err());
+        assert!(parse_quantity::<u64>("18446744073709551616", true).is_err());
+
+        let number = json!(42)
+            .deserialize_any(LenientQuantity::<u64>(PhantomData))
+            .unwrap();
+        assert_eq!(number.0, 42);
+    }
+
+    proptest! {
+        #[test]
+        fn quantities_roundtrip(bytes in any::<[u8; 32]>(), small in any::<u64>(), zeros in 0..8_usize) {
+            let value = U256::from_be_bytes(bytes);
+            let encoded = serde_json::to_value(Quantity(value)).unwrap();
+            let digits = encoded.as_str().unwrap().strip_prefix("0x").unwrap();
+            prop_assert!(digits == "0" || !digits.starts_with('0'));
+            prop_assert_eq!(serde_json::from_value::<Quantity>(encoded.clone()).unwrap(), Quantity(value));
+
+            let padded = format!("0x{}{digits}", "0".repeat(zeros));
+            prop_assert_eq!(parse_quantity(&padded, false), Ok(Quantity(value)));
+
+            let encoded = serde_json::to_value(Quantity(small)).unwrap();
+            prop_assert_eq!(serde_json::from_value::<Quantity<u64>>(encoded).unwrap(), Quantity(small));
+            prop_assert_eq!(parse_quantity(&small.to_string(), true), Ok(Quantity(small)));
+        }
+
+        #[test]
+        fn parses_arbitrary_quantities(s in "(0x)?[0-9a-fA-F+_ -]{0,80}", lenient in any::<bool>()) {
+            if let Ok(quantity) = parse_quantity::<u64>(&s, lenient) {
+                let encoded = serde_json::to_value(quantity).unwrap();
+                prop_assert_eq!(parse_quantity(encoded.as_str().unwrap(), false), Ok(quantity));
+            }
+            if let Ok(quantity) = parse_quantity::<U256>(&s, lenient) {
+                let encoded = serde_json::to_value(quantity).unwrap();
+                prop_assert_eq!(parse_quantity(encoded.as_str().unwrap(), false), Ok(quantity));
+            }
+        }
+
+        #[test]
+        fn bytes_roundtrip(bytes in vec(any::<u8>(), 0..128), slot in any::<[u8; 32]>()) {
+            let encoded = serde_json::to_value(Bytes(bytes.clone())).unwrap();
+            prop_assert_eq!(serde_json::from_value::<Bytes<Vec<u8>>>(encoded).unwrap().0, bytes);
+
+            let encoded = serde_json::to_value(Bytes(slot)).unwrap();
+            prop_assert_eq!(serde_json::from_value::<Bytes<[u8; 32]>>(encoded).unwrap().0, slot);
+        }
+
+        #[test]
+        fn parses_arbitrary_bytes(s in "(0x)?[0-9a-fA-Fg]{0,70}") {
+            let decoded = serde_json::from_value::<Bytes<Vec<u8>>>(json!(s));
+            prop_assert_eq!(decoded.is_ok(), s.starts_with("0x") && hex::decode(&s[2..]).is_ok());
+            let decoded = serde_json::from_value::<Bytes<[u8; 32]>>(json!(s));
+            prop_assert_eq!(
+                decoded.is_ok(),
+                s.len() == 66 && s.starts_with("0x") && hex::decode(&s[2..]).is_ok()
+            );
+        }
+
+        #[test]
+        fn addresses_roundtrip(bytes in any::<[u8; 20]>()) {
+            let encoded = serde_json::to_value(Str(Address(bytes))).unwrap();
+            let decoded = serde_json::from_value::<Str<Address>>(encoded).unwrap();
+            prop_assert_eq!(decoded.0 .0, bytes);
+
+            let lowercase = json!(format!("0x{}", hex::encode(bytes)));
+            let decoded = serde_json::from_value::<Str<Address>>(lowercase).unwrap();
+            prop_assert_eq!(decoded.0 .0, bytes);
+        }
     }
 }
@@ -4,11 +4,33 @@
 //! methods, such as validating transaction signatures and recording them to a
 //! database.
 
+pub mod anomaly;
+pub mod approval;
+pub mod audit;
+pub mod canary;
+pub mod dryrun;
+pub mod email;
+pub mod identity;
+pub mod lock;
 pub mod log_recorder;
+pub mod notify;
+pub mod opa;
+pub mod pause;
+pub mod policy;
+pub mod quota;
+pub mod receipt;
+pub mod registry;
+pub mod replay;
+pub mod request;
 pub mod validator;
+pub mod verify;
 pub mod wallet;
+pub mod web3signer;
 
-use crate::node::{transaction::Transaction, typeddata::TypedData};
+use crate::{
+    node::{transaction::Transaction, typeddata::TypedData, useroperation::UserOperation},
+    recovery::PublicKey,
+};
 use anyhow::Result;
 use hdwallet::account::{Address, Signature};
 
@@ -17,6 +39,15 @@ pub trait Signing {
     /// Returns the list of addresses of the accounts managed by the signer.
     fn accounts(&self) -> &[Address];
 
+    /// Returns the public key of an account managed by the signer.
+    fn public_key(&self, account: Address) -> Result<PublicKey>;
+
+    /// Signs a raw 32-byte digest.
+    ///
+    /// Unlike messages, the digest is signed as is without the EIP-191 prefix,
+    /// meaning it can be any signing message, including a transaction's.
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature>;
+
     /// Signs an Ethereum message.
     fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature>;
 
@@ -25,7 +56,51 @@ pub trait Signing {
 
     /// Signs Ethereum EIP-712 typed data.
     fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature>;
+
+    /// Signs an ERC-4337 user operation.
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature>;
 }
 
 /// A boxed signer that is safe to send between threads.
 pub type BoxSigner = Box<dyn Signing + Send + Sync + 'static>;
+
+impl<S> Signing for Box<S>
+where
+    S: Signing + ?Sized,
+{
+    fn accounts(&self) -> &[Address] {
+        (**self).accounts()
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        (**self).public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        (**self).sign_hash(account, hash)
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        (**self).sign_message(account, message)
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        (**self).sign_transaction(account, transaction)
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        (**self).sign_typed_data(account, typed_data)
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        (**self).sign_user_operation(account, user_operation)
+    }
+}
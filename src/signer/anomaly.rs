@@ -0,0 +1,498 @@
+//! A signer flagging unusual signing patterns.
+//!
+//! Each account's signing activity is compared against its own rolling
+//! baseline to detect sudden spikes in signing rate, transactions to
+//! destinations the account has never sent to, and transaction values far
+//! above the account's average. Anomalies are logged and optionally posted to
+//! a webhook, and can additionally block the signing request or hold it until
+//! an operator approves it.
+
+use super::{
+    approval::Approver, lock::AccountLocks, request, PublicKey, Signing, Transaction, TypedData,
+    UserOperation,
+};
+use crate::{
+    selectors::Selectors,
+    serialization::{Bytes, Str},
+};
+use anyhow::{bail, Context as _, Result};
+use hdwallet::account::{Address, Signature};
+use reqwest::Url;
+use rocket::{
+    serde::{Deserialize, Serialize},
+    tokio,
+};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::{self, Display, Formatter},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The window over which the current signing rate is measured.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// The window over which the baseline signing rate is measured.
+const BASELINE_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// The minimum number of signatures within the rate window to be considered
+/// a spike, so that a handful of signatures from an idle account isn't.
+const MIN_RATE_SPIKE: usize = 5;
+
+/// The number of recent transaction values averaged for the value baseline.
+const VALUE_SAMPLES: usize = 100;
+
+/// The minimum number of recent transaction values required before values
+/// are checked for outliers.
+const MIN_VALUE_SAMPLES: usize = 10;
+
+/// Anomaly detection settings.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Anomalies {
+    /// The default detection settings for all accounts.
+    #[serde(flatten)]
+    pub default: Detection,
+
+    /// Detection settings for individual accounts, by address, replacing the
+    /// defaults.
+    #[serde(default)]
+    pub accounts: HashMap<String, Detection>,
+
+    /// A URL to post detected anomalies to.
+    pub webhook: Option<Str<Url>>,
+}
+
+/// Anomaly detection settings for an account.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Detection {
+    /// Flag signing rates over the last minute this many times above the
+    /// account's average rate over the last hour.
+    pub rate_factor: Option<f64>,
+
+    /// Flag transactions to destinations the account has never sent
+    /// transactions to.
+    #[serde(default)]
+    pub new_destinations: bool,
+
+    /// Destinations never flagged as new, such as the account's usual
+    /// counterparties, so that they aren't flagged after restarts.
+    #[serde(default)]
+    pub known_destinations: Vec<Str<Address>>,
+
+    /// Flag transaction values this many times above the account's average
+    /// recent transaction value.
+    pub value_factor: Option<f64>,
+
+    /// The action to take when an anomaly is detected.
+    #[serde(default)]
+    pub action: Action,
+}
+
+/// The action to take for anomalous signing requests.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum Action {
+    /// Only report the anomaly.
+    #[default]
+    Warn,
+    /// Report the anomaly and hold the request until an operator approves it.
+    Approve,
+    /// Report the anomaly and reject the request. The request still counts
+    /// towards the signing rate, and its destination is no longer new.
+    Block,
+}
+
+/// A detected anomaly.
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde", tag = "kind", rename_all = "camelCase")]
+enum Anomaly {
+    #[serde(rename_all = "camelCase")]
+    RateSpike { rate: usize, baseline: f64 },
+    #[serde(rename_all = "camelCase")]
+    NewDestination { destination: Str<Address> },
+    #[serde(rename_all = "camelCase")]
+    ValueOutlier { value: f64, average: f64 },
+}
+
+impl Display for Anomaly {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::RateSpike { rate, baseline } => write!(
+                f,
+                "{rate} signatures in the last minute, compared to an average of {baseline:.2}",
+            ),
+            Self::NewDestination { destination } => {
+                write!(f, "first transaction to {}", destination.0)
+            }
+            Self::ValueOutlier { value, average } => write!(
+                f,
+                "transaction value of {value} Wei, compared to an average of {average} Wei",
+            ),
+        }
+    }
+}
+
+/// An anomaly report posted to the webhook.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+struct Report<'a> {
+    account: Str<Address>,
+    operation: &'a str,
+    #[serde(flatten)]
+    anomaly: &'a Anomaly,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    function: Option<&'a str>,
+}
+
+/// The recent signing activity of an account.
+#[derive(Default)]
+struct Activity {
+    signatures: VecDeque<Instant>,
+    destinations: HashSet<[u8; 20]>,
+    values: VecDeque<f64>,
+}
+
+/// The destination and value of a transaction being signed.
+type Transfer = (Option<Address>, f64);
+
+impl Activity {
+    /// Returns the anomalies for a signing request at the specified time.
+    fn anomalies(
+        &mut self,
+        detection: &Detection,
+        now: Instant,
+        transfer: Option<Transfer>,
+    ) -> Vec<Anomaly> {
+        while matches!(
+            self.signatures.front(),
+            Some(time) if now.duration_since(*time) > BASELINE_WINDOW
+        ) {
+            self.signatures.pop_front();
+        }
+
+        let mut anomalies = Vec::new();
+        if let Some(factor) = detection.rate_factor {
+            // Include the current request in the rate.
+            let rate = 1 + self
+                .signatures
+                .iter()
+                .filter(|time| now.duration_since(**time) <= RATE_WINDOW)
+                .count();
+            let baseline = self.signatures.len() as f64 * RATE_WINDOW.as_secs_f64()
+                / BASELINE_WINDOW.as_secs_f64();
+            if rate >= MIN_RATE_SPIKE && rate as f64 > factor * baseline.max(1.0) {
+                anomalies.push(Anomaly::RateSpike { rate, baseline });
+            }
+        }
+
+        if let Some((destination, value)) = transfer {
+            if let Some(destination) = destination {
+                let known = self.destinations.contains(&destination.0)
+                    || detection
+                        .known_destinations
+                        .iter()
+                        .any(|known| known.0 == destination);
+                if detection.new_destinations && !known {
+                    anomalies.push(Anomaly::NewDestination {
+                        destination: Str(destination),
+                    });
+                }
+            }
+            if let Some(factor) = detection.value_factor {
+                if self.values.len() >= MIN_VALUE_SAMPLES {
+                    let average = self.values.iter().sum::<f64>() / self.values.len() as f64;
+                    if value > factor * average {
+                        anomalies.push(Anomaly::ValueOutlier { value, average });
+                    }
+                }
+            }
+        }
+
+        anomalies
+    }
+
+    /// Records a signature at the specified time.
+    fn record(&mut self, now: Instant, transfer: Option<Transfer>) {
+        self.signatures.push_back(now);
+        if let Some((destination, value)) = transfer {
+            if let Some(destination) = destination {
+                self.destinations.insert(destination.0);
+            }
+            if self.values.len() == VALUE_SAMPLES {
+                self.values.pop_front();
+            }
+            self.values.push_back(value);
+        }
+    }
+
+    /// Records a blocked signing request at the specified time, without its
+    /// value so that it doesn't skew the average.
+    fn record_blocked(&mut self, now: Instant, transfer: Option<Transfer>) {
+        self.signatures.push_back(now);
+        if let Some((Some(destination), _)) = transfer {
+            self.destinations.insert(destination.0);
+        }
+    }
+}
+
+/// Wrapping signer that detects anomalous signing requests.
+pub struct Anomalous<S> {
+    default: Detection,
+    accounts: HashMap<[u8; 20], Detection>,
+    webhook: Option<(reqwest::Client, Url)>,
+    approver: Option<Arc<Approver>>,
+    activity: Mutex<HashMap<[u8; 20], Activity>>,
+    locks: AccountLocks,
+    selectors: Selectors,
+    inner: S,
+}
+
+impl<S> Anomalous<S> {
+    /// Creates a new anomaly detecting signer wrapping the specified signer.
+    /// Anomalous requests are held for the operators of the specified
+    /// approver, which is required for accounts configured with the `approve`
+    /// action.
+    pub fn new(inner: S, anomalies: Anomalies, approver: Option<Arc<Approver>>) -> Result<Self> {
+        let accounts = anomalies
+            .accounts
+            .iter()
+            .map(|(account, detection)| {
+                let account = account
+                    .parse::<Address>()
+                    .with_context(|| format!("invalid anomaly detection address {account}"))?;
+                Ok((account.0, detection.clone()))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        let approver = match approver {
+            Some(approver) => Some(approver),
+            None if std::iter::once(&anomalies.default)
+                .chain(accounts.values())
+                .any(|detection| detection.action == Action::Approve) =>
+            {
+                bail!("anomaly approval requires an operator socket");
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            default: anomalies.default,
+            accounts,
+            webhook: anomalies.webhook.map(|url| (reqwest::Client::new(), url.0)),
+            approver,
+            activity: Mutex::default(),
+            locks: AccountLocks::default(),
+            selectors: Selectors::default(),
+            inner,
+        })
+    }
+
+    /// Names the functions called by signing requests in reports, from the
+    /// specified function signature database.
+    pub fn with_selectors(mut self, selectors: Selectors) -> Self {
+        self.selectors = selectors;
+        self
+    }
+
+    /// Checks a signing request for anomalies, reporting them and applying
+    /// the configured action, before signing it with `sign`.
+    fn sign(
+        &self,
+        operation: &str,
+        account: Address,
+        data: impl Serialize,
+        transfer: Option<Transfer>,
+        function: Option<String>,
+        sign: impl FnOnce(&S) -> Result<Signature>,
+    ) -> Result<Signature> {
+        let detection = self.accounts.get(&account.0).unwrap_or(&self.default);
+        // Concurrent requests for the same account are checked one at a time,
+        // each against the activity recorded by the previous ones.
+        let _lock = self.locks.lock(account);
+        let now = Instant::now();
+        let anomalies = self
+            .activity
+            .lock()
+            .unwrap()
+            .entry(account.0)
+            .or_default()
+            .anomalies(detection, now, transfer);
+        let record = |record: fn(&mut Activity, Instant, Option<Transfer>)| {
+            if !request::is_preview() {
+                let mut activity = self.activity.lock().unwrap();
+                record(activity.entry(account.0).or_default(), now, transfer);
+            }
+        };
+
+        for anomaly in &anomalies {
+            self.report(operation, account, anomaly, function.as_deref());
+        }
+        if anomalies.is_empty() {
+            request::decide("anomaly", "no anomalies detected");
+        }
+        if let Some(anomaly) = anomalies.first() {
+            match detection.action {
+                Action::Warn => {
+                    request::decide("anomaly", format!("allowed with warning: {anomaly}"))
+                }
+                Action::Approve => {
+                    // Checked when creating the signer.
+                    let approver = self.approver.as_ref().unwrap();
+                    approver.request(operation, account, data)?;
+                }
+                Action::Block => {
+                    record(Activity::record_blocked);
+                    bail!("anomalous signing request blocked: {anomaly}");
+                }
+            }
+        }
+
+        // Record the request once it is allowed, even if signing fails
+        // further down the pipeline, so that approved destinations don't
+        // need approval again.
+        record(Activity::record);
+        sign(&self.inner)
+    }
+
+    /// Logs an anomaly and posts it to the webhook, if configured.
+    fn report(&self, operation: &str, account: Address, anomaly: &Anomaly, function: Option<&str>) {
+        if request::is_preview() {
+            return;
+        }
+        tracing::warn!(%account, operation, function, %anomaly, "anomalous signing request");
+
+        let (client, url) = match &self.webhook {
+            Some(webhook) => webhook,
+            None => return,
+        };
+        let request = client.post(url.clone()).json(&Report {
+            account: Str(account),
+            operation,
+            anomaly,
+            message: anomaly.to_string(),
+            function,
+        });
+        tokio::spawn(async move {
+            if let Err(err) = request.send().await.and_then(|r| r.error_for_status()) {
+                tracing::warn!(?err, "failed to post anomaly to webhook");
+            }
+        });
+    }
+}
+
+impl<S> Signing for Anomalous<S>
+where
+    S: Signing,
+{
+    fn accounts(&self) -> &[Address] {
+        self.inner.accounts()
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.inner.public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.sign("signHash", account, Bytes(hash), None, None, |inner| {
+            inner.sign_hash(account, hash)
+        })
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        self.sign(
+            "signMessage",
+            account,
+            Bytes(message),
+            None,
+            None,
+            |inner| inner.sign_message(account, message),
+        )
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        let request = transaction.request();
+        let transfer = (request.to.as_ref().map(|to| to.0), request.value.0.as_f64());
+        self.sign(
+            "signTransaction",
+            account,
+            transaction,
+            Some(transfer),
+            self.selectors.function(&request.data.0),
+            |inner| inner.sign_transaction(account, transaction),
+        )
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        self.sign("signTypedData", account, typed_data, None, None, |inner| {
+            inner.sign_typed_data(account, typed_data)
+        })
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.sign(
+            "signUserOperation",
+            account,
+            user_operation,
+            None,
+            self.selectors
+                .function(&user_operation.request().call_data.0),
+            |inner| inner.sign_user_operation(account, user_operation),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_anomalies_against_baseline() {
+        let detection = Detection {
+            rate_factor: Some(3.0),
+            new_destinations: true,
+            known_destinations: vec![Str(Address([0x43; 20]))],
+            value_factor: Some(10.0),
+            action: Action::Warn,
+        };
+        let destination = Address([0x42; 20]);
+        let start = Instant::now();
+        let mut activity = Activity::default();
+
+        for i in 0..10 {
+            let now = start + Duration::from_secs(i * 5 * 60);
+            let transfer = Some((Some(destination), 100.0));
+            let anomalies = activity.anomalies(&detection, now, transfer);
+            assert_eq!(anomalies.is_empty(), i > 0);
+            activity.record(now, transfer);
+        }
+
+        let now = start + Duration::from_secs(50 * 60);
+        let known = Some((Some(Address([0x43; 20])), 100.0));
+        assert!(activity.anomalies(&detection, now, known).is_empty());
+        let blocked = Some((Some(Address([0x44; 20])), 1e6));
+        assert_eq!(activity.anomalies(&detection, now, blocked).len(), 2);
+        activity.record_blocked(now, blocked);
+        let anomalies = activity.anomalies(&detection, now, Some((Some(destination), 1001.0)));
+        assert!(matches!(
+            anomalies[..],
+            [Anomaly::ValueOutlier { average, .. }] if average == 100.0,
+        ));
+
+        // The blocked request counts towards the rate.
+        for _ in 0..3 {
+            activity.record(now, None);
+        }
+        let anomalies = activity.anomalies(&detection, now, None);
+        assert!(matches!(
+            anomalies[..],
+            [Anomaly::RateSpike { rate: 5, .. }]
+        ));
+    }
+}
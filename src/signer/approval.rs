@@ -0,0 +1,428 @@
+//! A signer requiring operator approval for every signing operation.
+//!
+//! Pending signing requests are pushed to operators connected to a local Unix
+//! socket, one JSON object per line. Operators reply with a JSON object per
+//! line containing the request ID and their decision:
+//!
+//! ```text
+//! < {"id":1,"operation":"signTransaction","account":"0x...","data":{...}}
+//! > {"id":1,"approved":true}
+//! ```
+//!
+//! Requests that are not approved in time are rejected. The `hdnode approve`
+//! command provides a terminal prompt for this protocol.
+
+use super::{request, PublicKey, Signing, Transaction, TypedData, UserOperation};
+use crate::{
+    runtime,
+    serialization::{Bytes, Str},
+};
+use anyhow::{bail, ensure, Context as _, Result};
+use hdwallet::account::{Address, Signature};
+use rocket::{
+    serde::{
+        json::{serde_json, Value},
+        Deserialize, Serialize,
+    },
+    tokio::{
+        self,
+        io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+        net::{UnixListener, UnixStream},
+        sync::{mpsc, oneshot},
+    },
+};
+use std::{
+    collections::HashMap,
+    fs, io,
+    os::unix::{
+        fs::{FileTypeExt as _, MetadataExt as _, PermissionsExt as _},
+        net,
+    },
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
+};
+use thiserror::Error;
+
+/// The approvers of the approval, anomaly and policy signers of a node, for
+/// listing and watching their pending requests. Clones share the same
+/// approvers.
+#[derive(Clone, Default)]
+pub struct Approvals(Arc<Mutex<Registered>>);
+
+#[derive(Default)]
+struct Registered {
+    approvers: Vec<Weak<Approver>>,
+    watchers: Vec<mpsc::UnboundedSender<String>>,
+}
+
+impl Approvals {
+    /// Creates a new approver listening for operators on the specified Unix
+    /// socket, whose requests are listed with these approvals. Requests are
+    /// rejected if no decision is made within `timeout`.
+    pub fn bind(&self, socket: &Path, timeout: Duration) -> Result<Arc<Approver>> {
+        let approver = Approver::bind(socket, timeout, self.clone())?;
+        self.0
+            .lock()
+            .unwrap()
+            .approvers
+            .push(Arc::downgrade(&approver));
+        Ok(approver)
+    }
+
+    /// Returns the signing requests pending approval across all approvers, in
+    /// the format they are pushed to operators.
+    pub fn pending(&self) -> Vec<Value> {
+        let mut registered = self.0.lock().unwrap();
+        registered
+            .approvers
+            .retain(|approver| approver.strong_count() > 0);
+        registered
+            .approvers
+            .iter()
+            .filter_map(Weak::upgrade)
+            .flat_map(|approver| {
+                let state = approver.state.lock().unwrap();
+                let mut pending = state.pending.iter().collect::<Vec<_>>();
+                pending.sort_by_key(|(id, _)| **id);
+                pending
+                    .into_iter()
+                    .filter_map(|(_, (line, _))| serde_json::from_str(line).ok())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Returns a channel receiving new signing requests pending approval
+    /// across all approvers, in the format they are pushed to operators.
+    /// Watchers can't make decisions.
+    pub fn watch(&self) -> mpsc::UnboundedReceiver<String> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.0.lock().unwrap().watchers.push(sender);
+        receiver
+    }
+
+    /// Notifies watchers of a new signing request pending approval.
+    fn notify(&self, line: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .watchers
+            .retain(|watcher| watcher.send(line.to_owned()).is_ok());
+    }
+}
+
+/// Wrapping signer that waits for operator approval before signing.
+pub struct Approval<S> {
+    approver: Arc<Approver>,
+    inner: S,
+}
+
+impl<S> Approval<S> {
+    /// Creates a new approval signer wrapping the specified signer, waiting
+    /// for decisions of the operators of the specified approver.
+    pub fn new(inner: S, approver: Arc<Approver>) -> Self {
+        Self { approver, inner }
+    }
+}
+
+/// A signing request pending approval.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+struct PendingRequest<'a> {
+    id: u64,
+    operation: &'a str,
+    account: Str<Address>,
+    data: Value,
+}
+
+/// An operator decision.
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Decision {
+    pub id: u64,
+    pub approved: bool,
+}
+
+/// An error indicating that a signing request was not approved.
+#[derive(Debug, Error)]
+pub enum NotApprovedError {
+    #[error("signing request rejected by operator")]
+    Rejected,
+    #[error("signing request approval timed out")]
+    TimedOut,
+}
+
+/// Shared approval state.
+pub struct Approver {
+    timeout: Duration,
+    next_id: AtomicU64,
+    state: Mutex<State>,
+    approvals: Approvals,
+}
+
+#[derive(Default)]
+struct State {
+    pending: HashMap<u64, (String, oneshot::Sender<bool>)>,
+    operators: Vec<mpsc::UnboundedSender<String>>,
+}
+
+impl Approver {
+    /// Creates a new approver listening for operators on the specified Unix
+    /// socket. Only processes of the user running the node can connect.
+    fn bind(socket: &Path, timeout: Duration, approvals: Approvals) -> Result<Arc<Self>> {
+        remove_stale(socket)?;
+        let listener = UnixListener::bind(socket)
+            .with_context(|| format!("failed to bind socket {}", socket.display()))?;
+        fs::set_permissions(socket, fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("failed to restrict socket {}", socket.display()))?;
+        // The socket is owned by the user running the node, which peers are
+        // checked against in case they connected before its permissions were
+        // restricted.
+        let owner = fs::metadata(socket)
+            .with_context(|| format!("failed to read socket {}", socket.display()))?
+            .uid();
+
+        let approver = Arc::new(Self {
+            timeout,
+            next_id: AtomicU64::new(1),
+            state: Mutex::default(),
+            approvals,
+        });
+        tokio::spawn(accept(listener, owner, approver.clone()));
+        Ok(approver)
+    }
+
+    /// Requests approval for a signing operation, blocking until a decision
+    /// is made or the request times out.
+    pub(super) fn request(
+        &self,
+        operation: &str,
+        account: Address,
+        data: impl Serialize,
+    ) -> Result<()> {
+        if request::is_preview() {
+            request::decide(
+                "approval",
+                format!("{operation} requires operator approval"),
+            );
+            return Ok(());
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let line = serde_json::to_string(&PendingRequest {
+            id,
+            operation,
+            account: Str(account),
+            data: serde_json::to_value(data)?,
+        })?;
+
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            state
+                .operators
+                .retain(|operator| operator.send(line.clone()).is_ok());
+            state.pending.insert(id, (line.clone(), sender));
+        }
+        self.approvals.notify(&line);
+        tracing::info!(id, operation, %account, "waiting for signing approval");
+
+        // The `Signing` trait is synchronous, so block in place to allow the
+        // runtime to keep making progress on other tasks in the meantime.
+        let decision = runtime::block_on(tokio::time::timeout(self.timeout, receiver));
+        self.state.lock().unwrap().pending.remove(&id);
+
+        match decision? {
+            Ok(Ok(true)) => {
+                tracing::info!(id, "signing request approved");
+                request::decide("approval", format!("{operation} approved by operator"));
+                Ok(())
+            }
+            Ok(_) => {
+                tracing::warn!(id, "signing request rejected");
+                Err(NotApprovedError::Rejected.into())
+            }
+            Err(_) => {
+                tracing::warn!(id, "signing request approval timed out");
+                Err(NotApprovedError::TimedOut.into())
+            }
+        }
+    }
+
+    /// Applies an operator decision to a pending request.
+    fn decide(&self, decision: Decision) {
+        let pending = self.state.lock().unwrap().pending.remove(&decision.id);
+        if let Some((_, sender)) = pending {
+            let _ = sender.send(decision.approved);
+        }
+    }
+}
+
+/// Removes a socket left behind by a previous run, refusing to remove other
+/// files and sockets that are still in use.
+fn remove_stale(socket: &Path) -> Result<()> {
+    let metadata = match fs::symlink_metadata(socket) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read socket {}", socket.display()))
+        }
+    };
+    ensure!(
+        metadata.file_type().is_socket(),
+        "{} already exists and is not a socket",
+        socket.display(),
+    );
+    match net::UnixStream::connect(socket) {
+        Ok(_) => bail!("socket {} is in use by another process", socket.display()),
+        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(socket)
+            .with_context(|| format!("failed to remove socket {}", socket.display())),
+        Err(err) => {
+            Err(err).with_context(|| format!("failed to connect to socket {}", socket.display()))
+        }
+    }
+}
+
+/// Accepts operator connections from processes of the socket's owner.
+async fn accept(listener: UnixListener, owner: u32, approver: Arc<Approver>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => match stream.peer_cred() {
+                Ok(peer) if peer.uid() == owner => {
+                    tokio::spawn(operator(stream, approver.clone()));
+                }
+                Ok(peer) => {
+                    tracing::warn!(
+                        uid = peer.uid(),
+                        "rejected approval operator of another user"
+                    );
+                }
+                Err(err) => tracing::warn!(?err, "failed to identify approval operator"),
+            },
+            Err(err) => {
+                tracing::error!(?err, "failed to accept approval operator connection");
+                return;
+            }
+        }
+    }
+}
+
+/// Handles an operator connection.
+async fn operator(stream: UnixStream, approver: Arc<Approver>) {
+    tracing::info!("approval operator connected");
+    let (reader, mut writer) = stream.into_split();
+
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    {
+        let mut state = approver.state.lock().unwrap();
+        for (line, _) in state.pending.values() {
+            let _ = sender.send(line.clone());
+        }
+        state.operators.push(sender);
+    }
+    let writer = tokio::spawn(async move {
+        while let Some(line) = receiver.recv().await {
+            if writer
+                .write_all(format!("{line}\n").as_bytes())
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        match serde_json::from_str::<Decision>(&line) {
+            Ok(decision) => approver.decide(decision),
+            Err(err) => tracing::debug!(?err, %line, "invalid approval decision"),
+        }
+    }
+
+    writer.abort();
+    tracing::info!("approval operator disconnected");
+}
+
+impl<S> Signing for Approval<S>
+where
+    S: Signing,
+{
+    fn accounts(&self) -> &[Address] {
+        self.inner.accounts()
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.inner.public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.approver.request("signHash", account, Bytes(hash))?;
+        self.inner.sign_hash(account, hash)
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        self.approver
+            .request("signMessage", account, Bytes(message))?;
+        self.inner.sign_message(account, message)
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        self.approver
+            .request("signTransaction", account, transaction)?;
+        self.inner.sign_transaction(account, transaction)
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        self.approver
+            .request("signTypedData", account, typed_data)?;
+        self.inner.sign_typed_data(account, typed_data)
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.approver
+            .request("signUserOperation", account, user_operation)?;
+        self.inner.sign_user_operation(account, user_operation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn binds_sockets_safely() {
+        let socket = env::temp_dir().join(format!("hdnode-approval-{}.sock", process::id()));
+        let timeout = Duration::from_secs(1);
+        let approvals = Approvals::default();
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                fs::write(&socket, "").unwrap();
+                assert!(approvals.bind(&socket, timeout).is_err());
+                fs::remove_file(&socket).unwrap();
+
+                let approver = approvals.bind(&socket, timeout).unwrap();
+                assert_eq!(fs::metadata(&socket).unwrap().mode() & 0o777, 0o600);
+                assert!(approvals.bind(&socket, timeout).is_err());
+
+                // Waiting for approval would block the only worker thread.
+                assert!(approver
+                    .request("signHash", Address([0; 20]), Bytes([0; 32]))
+                    .is_err());
+                assert!(approvals.pending().is_empty());
+                fs::remove_file(&socket).unwrap();
+            });
+    }
+}
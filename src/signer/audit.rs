@@ -0,0 +1,624 @@
+//! A signer keeping a tamper-evident audit log of signing operations.
+//!
+//! Every signing operation, including rejected ones, is appended to the audit
+//! log as a JSON line. Entries are hash-chained: each one contains the hash of
+//! the line before it, so that modifying, inserting or removing an entry
+//! breaks the chain. Periodically, a checkpoint entry signs the hash of the
+//! chain so far with a dedicated audit key, so that the log can't be rewritten
+//! from scratch without the key either.
+//!
+//! Entries are written by a dedicated thread, which syncs batches of entries
+//! to disk at once, so that concurrent signing operations don't wait for each
+//! other's disk syncs. An incomplete last line, left behind by a crash while
+//! writing, is removed on startup: its signature was never returned.
+//!
+//! Signing operations can additionally be shipped to syslog and journald
+//! sinks, for environments with centralized compliance logging.
+
+pub mod sink;
+
+use super::{
+    request::{self, RequestContext},
+    PublicKey, Signing, Transaction, TypedData, UserOperation,
+};
+use crate::{
+    abi, clock, recovery, runtime,
+    serialization::{Bytes, Str},
+};
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use hdwallet::{
+    account::{Address, PrivateKey, Signature},
+    message::EthereumMessage,
+};
+use rocket::serde::{
+    json::{serde_json, Value},
+    Deserialize, Serialize,
+};
+use sink::{Event, Severity, Sink};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+/// The number of entries between checkpoints when not configured.
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 100;
+
+/// An entry of the audit log.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+struct Entry {
+    /// The position of the entry in the log.
+    sequence: u64,
+    /// The hash of the previous line of the log, or zero for the first entry.
+    previous_hash: Bytes<[u8; 32]>,
+    /// The UNIX timestamp in seconds at which the entry was recorded.
+    timestamp: u64,
+    #[serde(flatten)]
+    record: Record,
+}
+
+/// An audit log record.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(
+    crate = "rocket::serde",
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+enum Record {
+    /// A signing operation.
+    Signing {
+        operation: String,
+        account: Str<Address>,
+        payload_hash: Bytes<[u8; 32]>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<Bytes<[u8; 65]>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request: Option<Value>,
+    },
+    /// A checkpoint, signing the previous hash with the audit key.
+    Checkpoint {
+        signer: Str<Address>,
+        signature: Bytes<[u8; 65]>,
+    },
+}
+
+impl Record {
+    /// Returns the sink event for a signing operation.
+    fn event(&self) -> Option<Event> {
+        let Record::Signing {
+            operation,
+            account,
+            payload_hash,
+            signature,
+            error,
+            request,
+        } = self
+        else {
+            return None;
+        };
+        let mut fields = vec![
+            ("operation", operation.clone()),
+            ("account", account.0.to_string()),
+            ("payloadHash", format!("{payload_hash:?}")),
+        ];
+        fields.extend(signature.as_ref().map(|s| ("signature", format!("{s:?}"))));
+        fields.extend(error.clone().map(|error| ("error", error)));
+        fields.extend(
+            request
+                .as_ref()
+                .map(|request| ("request", request.to_string())),
+        );
+        let (severity, message) = match error {
+            Some(error) => (
+                Severity::Warning,
+                format!("{operation} for {} failed: {error}", account.0),
+            ),
+            None => (
+                Severity::Info,
+                format!("{operation} for {} succeeded", account.0),
+            ),
+        };
+        Some(Event {
+            severity,
+            message,
+            fields,
+        })
+    }
+}
+
+/// The result of verifying an audit log.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Verification {
+    /// Whether or not the whole log is valid.
+    pub valid: bool,
+    /// The number of valid entries, including checkpoints.
+    pub entries: u64,
+    /// The number of valid checkpoints.
+    pub checkpoints: u64,
+    /// The number of entries covered by the last valid checkpoint. Entries
+    /// after it are only protected by the hash chain.
+    pub checkpointed_entries: u64,
+    /// The hash of the last valid entry.
+    pub head: Bytes<[u8; 32]>,
+    /// The reason the log is invalid, if it is.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Verifies the hash chain of an audit log and its checkpoint signatures by
+/// the specified audit key address.
+pub fn verify(log: &str, signer: Address) -> Verification {
+    let mut verification = Verification {
+        valid: true,
+        entries: 0,
+        checkpoints: 0,
+        checkpointed_entries: 0,
+        head: Bytes([0; 32]),
+        error: None,
+    };
+    for line in log.lines() {
+        match verify_entry(line, signer, &verification) {
+            Ok(Record::Checkpoint { .. }) => {
+                verification.checkpoints += 1;
+                verification.checkpointed_entries = verification.entries;
+            }
+            Ok(Record::Signing { .. }) => {}
+            Err(err) => {
+                verification.valid = false;
+                verification.error = Some(format!("entry {}: {err:#}", verification.entries));
+                break;
+            }
+        }
+        verification.entries += 1;
+        verification.head = Bytes(abi::keccak256(line));
+    }
+    verification
+}
+
+/// Verifies a single entry against the log verified so far, returning its
+/// record.
+fn verify_entry(line: &str, signer: Address, verified: &Verification) -> Result<Record> {
+    let entry = serde_json::from_str::<Entry>(line).context("malformed entry")?;
+    ensure!(
+        entry.sequence == verified.entries,
+        "unexpected sequence number {}",
+        entry.sequence,
+    );
+    ensure!(
+        entry.previous_hash.0 == verified.head.0,
+        "previous hash does not match, the log was modified",
+    );
+    if let Record::Checkpoint {
+        signer: checkpoint_signer,
+        signature,
+    } = &entry.record
+    {
+        ensure!(
+            checkpoint_signer.0 == signer,
+            "checkpoint signed by unexpected key {}",
+            checkpoint_signer.0,
+        );
+        let hash = EthereumMessage(&entry.previous_hash.0).signing_message();
+        let signature = recovery::parse_signature(&signature.0)?;
+        ensure!(
+            recovery::recover_address(hash, &signature)? == signer,
+            "invalid checkpoint signature",
+        );
+    }
+    Ok(entry.record)
+}
+
+/// The open audit log.
+struct Log {
+    path: PathBuf,
+    file: File,
+    key: PrivateKey,
+    checkpoint_interval: u64,
+    sequence: u64,
+    head: [u8; 32],
+    since_checkpoint: u64,
+}
+
+impl Log {
+    /// Appends an entry, followed by a checkpoint when one is due.
+    fn append(&mut self, record: Record) -> Result<()> {
+        self.write(record)?;
+        self.since_checkpoint += 1;
+        if self.since_checkpoint >= self.checkpoint_interval {
+            let hash = EthereumMessage(&self.head).signing_message();
+            self.write(Record::Checkpoint {
+                signer: Str(self.key.address()),
+                signature: Bytes::from_signature(self.key.sign(hash)),
+            })?;
+            self.since_checkpoint = 0;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, record: Record) -> Result<()> {
+        let line = serde_json::to_string(&Entry {
+            sequence: self.sequence,
+            previous_hash: Bytes(self.head),
+            timestamp: clock::now(),
+            record,
+        })?;
+        self.file
+            .write_all(format!("{line}\n").as_bytes())
+            .with_context(|| format!("failed to write audit log {}", self.path.display()))?;
+        self.sequence += 1;
+        self.head = abi::keccak256(&line);
+        Ok(())
+    }
+
+    /// Syncs the entries written so far to disk.
+    fn sync(&self) -> Result<()> {
+        self.file
+            .sync_data()
+            .with_context(|| format!("failed to sync audit log {}", self.path.display()))
+    }
+
+    /// Verifies the log as it currently is on disk.
+    fn verify(&self) -> Result<Verification> {
+        let contents = fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read audit log {}", self.path.display()))?;
+        Ok(verify(&contents, self.key.address()))
+    }
+}
+
+/// A command for the audit log writer.
+enum Command {
+    /// Appends a record, replying once it is synced to disk.
+    Append(Record, mpsc::SyncSender<Result<()>>),
+    /// Verifies the log, replying with the result.
+    Verify(mpsc::SyncSender<Result<Verification>>),
+}
+
+/// Runs the audit log writer until all senders of commands are dropped.
+/// Records received while syncing are appended and synced together.
+fn run(mut log: Log, commands: mpsc::Receiver<Command>) {
+    while let Ok(command) = commands.recv() {
+        let mut appended = Vec::new();
+        for command in [command].into_iter().chain(commands.try_iter()) {
+            match command {
+                Command::Append(record, reply) => appended.push((reply, log.append(record))),
+                Command::Verify(reply) => {
+                    let _ = reply.send(log.verify());
+                }
+            }
+        }
+        if appended.is_empty() {
+            continue;
+        }
+        let synced = log.sync().map_err(|err| format!("{err:#}"));
+        for (reply, result) in appended {
+            let result = result.and_then(|_| synced.clone().map_err(|err| anyhow!(err)));
+            let _ = reply.send(result);
+        }
+    }
+}
+
+/// Sends a command to the audit log writer, blocking until it replies.
+fn request<T>(
+    writer: &mpsc::Sender<Command>,
+    command: impl FnOnce(mpsc::SyncSender<Result<T>>) -> Command,
+) -> Result<T> {
+    let (reply, receiver) = mpsc::sync_channel(1);
+    writer
+        .send(command(reply))
+        .map_err(|_| anyhow!("audit log writer stopped"))?;
+    runtime::blocking(|| receiver.recv()).map_err(|_| anyhow!("audit log writer stopped"))?
+}
+
+/// Wrapping signer that records signing operations to an audit log and audit
+/// sinks.
+pub struct Audit<S> {
+    log: Option<mpsc::Sender<Command>>,
+    sinks: Vec<Sink>,
+    inner: S,
+}
+
+impl<S> Audit<S> {
+    /// Creates a new auditing signer wrapping the specified signer, without
+    /// any audit log or sinks.
+    pub fn new(inner: S) -> Self {
+        Self {
+            log: None,
+            sinks: Vec::new(),
+            inner,
+        }
+    }
+
+    /// Appends signing operations to the audit log at the specified path,
+    /// signing a checkpoint with the audit key every `checkpoint_interval`
+    /// entries. Existing logs are verified first, and are never appended to
+    /// when they are invalid.
+    pub fn with_log(
+        mut self,
+        path: &Path,
+        key: PrivateKey,
+        checkpoint_interval: u64,
+    ) -> Result<Self> {
+        ensure!(
+            checkpoint_interval > 0,
+            "audit checkpoint interval must be positive",
+        );
+        let mut existing = match path.exists() {
+            true => fs::read_to_string(path)
+                .with_context(|| format!("failed to read audit log {}", path.display()))?,
+            false => String::new(),
+        };
+        if !existing.is_empty() && !existing.ends_with('\n') {
+            let complete = existing.rfind('\n').map_or(0, |newline| newline + 1);
+            tracing::warn!(
+                path = %path.display(),
+                line = &existing[complete..],
+                "removing incomplete last line of audit log",
+            );
+            OpenOptions::new()
+                .write(true)
+                .open(path)
+                .and_then(|file| file.set_len(complete as u64))
+                .with_context(|| format!("failed to truncate audit log {}", path.display()))?;
+            existing.truncate(complete);
+        }
+        let verification = verify(&existing, key.address());
+        if let Some(err) = verification.error {
+            bail!("invalid audit log {}: {err}", path.display());
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open audit log {}", path.display()))?;
+
+        let log = Log {
+            path: path.to_owned(),
+            file,
+            key,
+            checkpoint_interval,
+            sequence: verification.entries,
+            head: verification.head.0,
+            since_checkpoint: verification.entries - verification.checkpointed_entries,
+        };
+        let (writer, commands) = mpsc::channel();
+        thread::Builder::new()
+            .name("audit-log".to_owned())
+            .spawn(move || run(log, commands))
+            .context("failed to start audit log writer")?;
+        self.log = Some(writer);
+        Ok(self)
+    }
+
+    /// Additionally sends signing operations to the specified audit sink.
+    pub fn with_sink(mut self, sink: Sink) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Returns a handle for verifying the audit log, if any.
+    pub fn handle(&self) -> Option<Handle> {
+        self.log.clone().map(Handle)
+    }
+
+    /// Signs a payload with `sign`, recording the outcome to the audit log
+    /// and sinks. Signatures are only returned once they are written to the
+    /// audit log.
+    fn sign(
+        &self,
+        operation: &str,
+        account: Address,
+        payload_hash: [u8; 32],
+        sign: impl FnOnce(&S) -> Result<Signature>,
+    ) -> Result<Signature> {
+        if request::is_preview() {
+            return sign(&self.inner);
+        }
+        let result = sign(&self.inner);
+        let record = Record::Signing {
+            operation: operation.to_owned(),
+            account: Str(account),
+            payload_hash: Bytes(payload_hash),
+            signature: result.as_ref().ok().map(|s| Bytes::from_signature(*s)),
+            error: result.as_ref().err().map(|err| format!("{err:#}")),
+            request: RequestContext::current()
+                .and_then(|context| serde_json::to_value(context).ok()),
+        };
+        let event = record.event();
+        if let Some(writer) = &self.log {
+            request(writer, |reply| Command::Append(record, reply))?;
+        }
+        if let Some(event) = event {
+            for sink in &self.sinks {
+                sink.send(&event);
+            }
+        }
+        result
+    }
+}
+
+/// A handle for verifying the audit log of a running auditing signer.
+#[derive(Clone)]
+pub struct Handle(mpsc::Sender<Command>);
+
+impl Handle {
+    /// Verifies the audit log as it currently is on disk. The log is verified
+    /// by its writer, so that no entry is being appended while reading.
+    pub fn verify(&self) -> Result<Verification> {
+        request(&self.0, Command::Verify)
+    }
+}
+
+impl<S> Signing for Audit<S>
+where
+    S: Signing,
+{
+    fn accounts(&self) -> &[Address] {
+        self.inner.accounts()
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.inner.public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.sign("signHash", account, hash, |inner| {
+            inner.sign_hash(account, hash)
+        })
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        let hash = EthereumMessage(message).signing_message();
+        self.sign("signMessage", account, hash, |inner| {
+            inner.sign_message(account, message)
+        })
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        self.sign(
+            "signTransaction",
+            account,
+            transaction.signing_message(),
+            |inner| inner.sign_transaction(account, transaction),
+        )
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        self.sign(
+            "signTypedData",
+            account,
+            typed_data.signing_message(),
+            |inner| inner.sign_typed_data(account, typed_data),
+        )
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.sign(
+            "signUserOperation",
+            account,
+            user_operation.hash(),
+            |inner| inner.sign_user_operation(account, user_operation),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        signer::wallet::Wallet,
+        testing::{call, call_as_admin, client_with, MockUpstream, ACCOUNT},
+    };
+    use rocket::serde::json::serde_json::json;
+    use std::{env, process};
+
+    #[test]
+    fn detects_tampering() {
+        let path = env::temp_dir().join(format!("hdnode-audit-{}.jsonl", process::id()));
+        let mnemonic = "test test test test test test test test test test test junk"
+            .parse()
+            .unwrap();
+        let key = || {
+            "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d"
+                .parse::<PrivateKey>()
+                .unwrap()
+        };
+        let signer = Audit::new(Wallet::new(&mnemonic, "", 1).unwrap())
+            .with_log(&path, key(), 2)
+            .unwrap();
+        let account = signer.accounts()[0];
+        for message in [b"a", b"b", b"c"] {
+            signer.sign_message(account, message).unwrap();
+        }
+
+        let verification = signer.handle().unwrap().verify().unwrap();
+        assert!(verification.valid);
+        assert_eq!((verification.entries, verification.checkpoints), (4, 1));
+        assert_eq!(verification.checkpointed_entries, 2);
+
+        let log = fs::read_to_string(&path).unwrap();
+        let tampered = log.replacen(r#""signMessage""#, r#""signHash""#, 1);
+        let verification = verify(&tampered, key().address());
+        assert!(!verification.valid);
+        assert_eq!(verification.entries, 1);
+        let removed = log.lines().skip(1).collect::<Vec<_>>().join("\n");
+        assert!(!verify(&removed, key().address()).valid);
+
+        // Logs are never appended to when they are invalid.
+        fs::write(&path, tampered).unwrap();
+        let wallet = Wallet::new(&mnemonic, "", 1).unwrap();
+        assert!(Audit::new(wallet).with_log(&path, key(), 2).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn removes_incomplete_last_line() {
+        let path = env::temp_dir().join(format!("hdnode-audit-torn-{}.jsonl", process::id()));
+        let mnemonic = "test test test test test test test test test test test junk"
+            .parse()
+            .unwrap();
+        let key = || {
+            "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d"
+                .parse::<PrivateKey>()
+                .unwrap()
+        };
+        let signer = Audit::new(Wallet::new(&mnemonic, "", 1).unwrap())
+            .with_log(&path, key(), 10)
+            .unwrap();
+        let account = signer.accounts()[0];
+        signer.sign_message(account, b"a").unwrap();
+        drop(signer);
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"sequence":1,"previ"#).unwrap();
+        let signer = Audit::new(Wallet::new(&mnemonic, "", 1).unwrap())
+            .with_log(&path, key(), 10)
+            .unwrap();
+        signer.sign_message(account, b"b").unwrap();
+
+        let verification = signer.handle().unwrap().verify().unwrap();
+        assert!(verification.valid);
+        assert_eq!(verification.entries, 2);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn verifies_audit_logs() {
+        let path = std::env::temp_dir().join(format!("hdnode-audit-{}.jsonl", std::process::id()));
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment
+                .merge(("signers", json!(["wallet", "audit"])))
+                .merge(("audit_log", &path))
+                .merge((
+                    "audit_key",
+                    "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d",
+                ))
+                .merge(("audit_checkpoint_interval", 2))
+                .merge(("admin_token", "secret"))
+        })
+        .await;
+
+        for message in ["0x61", "0x62", "0x63"] {
+            call(&client, "eth_sign", json!([ACCOUNT, message])).await;
+        }
+        let verification = call_as_admin(&client, "hdnode_verifyAuditLog", json!([])).await;
+        std::fs::remove_file(&path).unwrap();
+
+        let verification = &verification["result"];
+        assert_eq!(verification["valid"], true);
+        assert_eq!(verification["entries"], 4);
+        assert_eq!(verification["checkpoints"], 1);
+    }
+}
@@ -0,0 +1,279 @@
+//! Audit sinks shipping signing events to syslog and journald.
+//!
+//! Events are sent as datagrams, either over Unix sockets to the local syslog
+//! daemon or journald, or over UDP to a remote syslog collector. Syslog events
+//! use the RFC 5424 format with the event fields as structured data, and
+//! journald events use its native protocol with one field per event field.
+
+use anyhow::{Context as _, Result};
+use rocket::serde::Deserialize;
+use std::{
+    fmt::Write as _,
+    net::{ToSocketAddrs as _, UdpSocket},
+    os::unix::net::UnixDatagram,
+    path::PathBuf,
+};
+
+/// The default path of the local syslog socket.
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// The default path of the journald native protocol socket.
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// The syslog structured data ID of audit events. It uses the enterprise
+/// number reserved for documentation by RFC 5612.
+const SD_ID: &str = "hdnode@32473";
+
+/// Audit sink settings.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "lowercase")]
+pub enum Config {
+    /// Sends events to syslog, either to a local socket path (`/dev/log` by
+    /// default) or to a remote collector with a `udp://host:port` address.
+    Syslog {
+        address: Option<String>,
+        #[serde(default)]
+        facility: Facility,
+    },
+    /// Sends events to journald, over its native protocol socket.
+    Journald { socket: Option<PathBuf> },
+}
+
+/// A syslog facility.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Facility {
+    User,
+    Daemon,
+    Auth,
+    #[default]
+    Authpriv,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl Facility {
+    /// Returns the numerical code of the facility.
+    fn code(self) -> u8 {
+        match self {
+            Self::User => 1,
+            Self::Daemon => 3,
+            Self::Auth => 4,
+            Self::Authpriv => 10,
+            Self::Local0 => 16,
+            Self::Local1 => 17,
+            Self::Local2 => 18,
+            Self::Local3 => 19,
+            Self::Local4 => 20,
+            Self::Local5 => 21,
+            Self::Local6 => 22,
+            Self::Local7 => 23,
+        }
+    }
+}
+
+/// The severity of an audit event, with syslog severity levels.
+#[derive(Clone, Copy, Debug)]
+pub enum Severity {
+    Warning = 4,
+    Info = 6,
+}
+
+/// An audit event.
+pub struct Event {
+    /// The severity of the event.
+    pub severity: Severity,
+    /// A human readable description of the event.
+    pub message: String,
+    /// The structured fields of the event, by camel case name.
+    pub fields: Vec<(&'static str, String)>,
+}
+
+/// The transport of a sink.
+enum Transport {
+    Unix(UnixDatagram, PathBuf),
+    Udp(UdpSocket),
+}
+
+impl Transport {
+    fn send(&self, datagram: &[u8]) -> std::io::Result<()> {
+        match self {
+            Self::Unix(socket, path) => socket.send_to(datagram, path)?,
+            Self::Udp(socket) => socket.send(datagram)?,
+        };
+        Ok(())
+    }
+}
+
+/// The event encoding of a sink.
+enum Format {
+    Syslog(Facility),
+    Journald,
+}
+
+/// An audit sink.
+pub struct Sink {
+    format: Format,
+    transport: Transport,
+}
+
+impl Sink {
+    /// Creates a new audit sink.
+    pub fn new(config: &Config) -> Result<Self> {
+        let (format, transport) = match config {
+            Config::Syslog { address, facility } => {
+                let address = address.as_deref().unwrap_or(SYSLOG_SOCKET);
+                let transport = match address.strip_prefix("udp://") {
+                    Some(address) => {
+                        let remote = address
+                            .to_socket_addrs()
+                            .ok()
+                            .and_then(|mut addresses| addresses.next())
+                            .with_context(|| format!("invalid syslog address {address}"))?;
+                        let local = match remote.is_ipv4() {
+                            true => "0.0.0.0:0",
+                            false => "[::]:0",
+                        };
+                        let socket = UdpSocket::bind(local)?;
+                        socket.connect(remote)?;
+                        Transport::Udp(socket)
+                    }
+                    None => Transport::Unix(UnixDatagram::unbound()?, address.into()),
+                };
+                (Format::Syslog(*facility), transport)
+            }
+            Config::Journald { socket } => {
+                let path = socket.clone().unwrap_or_else(|| JOURNALD_SOCKET.into());
+                (
+                    Format::Journald,
+                    Transport::Unix(UnixDatagram::unbound()?, path),
+                )
+            }
+        };
+        Ok(Self { format, transport })
+    }
+
+    /// Sends an event. Datagram delivery isn't guaranteed, so failures are
+    /// only logged.
+    pub fn send(&self, event: &Event) {
+        let datagram = match self.format {
+            Format::Syslog(facility) => syslog(facility, event),
+            Format::Journald => journald(event),
+        };
+        if let Err(err) = self.transport.send(&datagram) {
+            tracing::warn!(?err, "failed to send audit event");
+        }
+    }
+}
+
+/// Encodes an event as an RFC 5424 syslog message, leaving the timestamp and
+/// host name for the syslog daemon to fill in.
+fn syslog(facility: Facility, event: &Event) -> Vec<u8> {
+    let priority = facility.code() * 8 + event.severity as u8;
+    let mut message = format!(
+        "<{priority}>1 - - hdnode {} audit [{SD_ID}",
+        std::process::id()
+    );
+    for (name, value) in &event.fields {
+        let value = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace(']', "\\]");
+        let _ = write!(message, " {name}=\"{value}\"");
+    }
+    let _ = write!(message, "] {}", event.message);
+    message.into_bytes()
+}
+
+/// Encodes an event with the journald native protocol. Field names are
+/// converted to upper snake case and prefixed with `HDNODE_`.
+fn journald(event: &Event) -> Vec<u8> {
+    let mut datagram = Vec::new();
+    let mut field = |name: &str, value: &str| {
+        datagram.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            datagram.push(b'=');
+        }
+        datagram.extend_from_slice(value.as_bytes());
+        datagram.push(b'\n');
+    };
+
+    field("MESSAGE", &event.message);
+    field("PRIORITY", &(event.severity as u8).to_string());
+    field("SYSLOG_IDENTIFIER", "hdnode");
+    for (name, value) in &event.fields {
+        let mut key = String::from("HDNODE_");
+        for c in name.chars() {
+            if c.is_ascii_uppercase() {
+                key.push('_');
+            }
+            key.push(c.to_ascii_uppercase());
+        }
+        field(&key, value);
+    }
+    datagram
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    #[test]
+    fn encodes_events() {
+        let path = env::temp_dir().join(format!("hdnode-audit-sink-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        let event = Event {
+            severity: Severity::Warning,
+            message: "signMessage failed".to_owned(),
+            fields: vec![
+                ("operation", "signMessage".to_owned()),
+                ("error", "bad \"value\"]\nsecond line".to_owned()),
+            ],
+        };
+        let mut buffer = [0; 1024];
+        let sinks = [
+            Config::Syslog {
+                address: Some(path.display().to_string()),
+                facility: Facility::Auth,
+            },
+            Config::Journald {
+                socket: Some(path.clone()),
+            },
+        ];
+        let [syslog, journald] = sinks.map(|config| {
+            Sink::new(&config).unwrap().send(&event);
+            let len = receiver.recv(&mut buffer).unwrap();
+            buffer[..len].to_vec()
+        });
+
+        let syslog = String::from_utf8(syslog).unwrap();
+        assert!(syslog.starts_with("<36>1 - - hdnode "));
+        assert!(syslog.ends_with(
+            "[hdnode@32473 operation=\"signMessage\" \
+             error=\"bad \\\"value\\\"\\]\nsecond line\"] signMessage failed"
+        ));
+
+        let error = "bad \"value\"]\nsecond line";
+        let mut expected = b"MESSAGE=signMessage failed\nPRIORITY=4\n\
+            SYSLOG_IDENTIFIER=hdnode\nHDNODE_OPERATION=signMessage\nHDNODE_ERROR\n"
+            .to_vec();
+        expected.extend_from_slice(&(error.len() as u64).to_le_bytes());
+        expected.extend_from_slice(error.as_bytes());
+        expected.push(b'\n');
+        assert_eq!(journald, expected);
+
+        fs::remove_file(path).unwrap();
+    }
+}
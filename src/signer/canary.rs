@@ -0,0 +1,184 @@
+//! A signer guarding canary accounts.
+//!
+//! Canary accounts are managed accounts that are never meant to sign, such as
+//! an unused derived account. Legitimate clients have no reason to use them,
+//! so any signing request naming one is rejected and raises a high-severity
+//! alert, acting as a tripwire for compromised clients probing the node.
+
+use super::{request::RequestContext, PublicKey, Signing, Transaction, TypedData, UserOperation};
+use crate::serialization::Str;
+use anyhow::{ensure, Result};
+use hdwallet::account::{Address, Signature};
+use reqwest::Url;
+use rocket::{
+    serde::{Deserialize, Serialize},
+    tokio,
+};
+use std::collections::HashSet;
+use thiserror::Error;
+
+/// Canary account settings.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Canaries {
+    /// The canary accounts.
+    #[serde(default)]
+    pub accounts: Vec<Str<Address>>,
+
+    /// A URL to post alerts to.
+    pub webhook: Option<Str<Url>>,
+}
+
+/// An alert posted to the webhook.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+struct Alert<'a> {
+    account: Str<Address>,
+    operation: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request: Option<RequestContext>,
+}
+
+/// Wrapping signer that rejects signing requests for canary accounts.
+pub struct Canary<S> {
+    accounts: HashSet<[u8; 20]>,
+    webhook: Option<(reqwest::Client, Url)>,
+    inner: S,
+}
+
+impl<S> Canary<S>
+where
+    S: Signing,
+{
+    /// Creates a new canary signer wrapping the specified signer. Canary
+    /// accounts must be managed by the wrapped signer, so that they are
+    /// indistinguishable from other accounts.
+    pub fn new(inner: S, canaries: Canaries) -> Result<Self> {
+        for account in &canaries.accounts {
+            ensure!(
+                inner.accounts().contains(&account.0),
+                "canary account {} is not managed by the signer",
+                account.0,
+            );
+        }
+
+        Ok(Self {
+            accounts: canaries
+                .accounts
+                .iter()
+                .map(|account| account.0 .0)
+                .collect(),
+            webhook: canaries.webhook.map(|url| (reqwest::Client::new(), url.0)),
+            inner,
+        })
+    }
+}
+
+impl<S> Canary<S> {
+    /// Ensures that the account is no canary, raising an alert otherwise.
+    fn ensure_not_canary(&self, operation: &str, account: Address) -> Result<()> {
+        if !self.accounts.contains(&account.0) {
+            return Ok(());
+        }
+
+        let request = RequestContext::current();
+        tracing::error!(
+            %account, operation, ?request,
+            "signing request for canary account, the client may be compromised"
+        );
+        if let Some((client, url)) = &self.webhook {
+            let alert = client.post(url.clone()).json(&Alert {
+                account: Str(account),
+                operation,
+                request,
+            });
+            tokio::spawn(async move {
+                if let Err(err) = alert.send().await.and_then(|r| r.error_for_status()) {
+                    tracing::error!(?err, "failed to post canary alert to webhook");
+                }
+            });
+        }
+        Err(SigningRejectedError.into())
+    }
+}
+
+/// An error indicating that a signing request was rejected. It intentionally
+/// doesn't mention the canary, so that probing clients aren't tipped off.
+#[derive(Debug, Error)]
+#[error("signing request rejected")]
+pub struct SigningRejectedError;
+
+impl<S> Signing for Canary<S>
+where
+    S: Signing,
+{
+    fn accounts(&self) -> &[Address] {
+        self.inner.accounts()
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.inner.public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.ensure_not_canary("signHash", account)?;
+        self.inner.sign_hash(account, hash)
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        self.ensure_not_canary("signMessage", account)?;
+        self.inner.sign_message(account, message)
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        self.ensure_not_canary("signTransaction", account)?;
+        self.inner.sign_transaction(account, transaction)
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        self.ensure_not_canary("signTypedData", account)?;
+        self.inner.sign_typed_data(account, typed_data)
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.ensure_not_canary("signUserOperation", account)?;
+        self.inner.sign_user_operation(account, user_operation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::wallet::Wallet;
+
+    #[test]
+    fn rejects_canary_accounts() {
+        let mnemonic = "test test test test test test test test test test test junk"
+            .parse()
+            .unwrap();
+        let wallet = Wallet::new(&mnemonic, "", 2).unwrap();
+        let [account, canary] = [wallet.accounts()[0], wallet.accounts()[1]];
+        let signer = Canary::new(
+            wallet,
+            Canaries {
+                accounts: vec![Str(canary)],
+                webhook: None,
+            },
+        )
+        .unwrap();
+
+        assert!(signer.sign_message(account, b"hello").is_ok());
+        let err = signer.sign_message(canary, b"hello").unwrap_err();
+        assert!(err.is::<SigningRejectedError>());
+
+        let unmanaged = Canaries {
+            accounts: vec![Str(Address([1; 20]))],
+            webhook: None,
+        };
+        assert!(Canary::new(signer, unmanaged).is_err());
+    }
+}
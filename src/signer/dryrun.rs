@@ -0,0 +1,136 @@
+//! A signing backend producing fake signatures for dry runs.
+//!
+//! This allows CI pipelines and staging environments to exercise full request
+//! flows without any private keys. Signatures are derived deterministically
+//! from the account and the signed payload, so that they are stable across
+//! runs. They are flagged with a marker in their `r` value and always have a
+//! high `s` value, which Ethereum rejects for transactions since EIP-2, so they
+//! can never be valid transaction signatures.
+
+use super::{
+    request, wallet::UnknownSignerError, PublicKey, Signing, Transaction, TypedData, UserOperation,
+};
+use crate::abi;
+use anyhow::{bail, Result};
+use hdwallet::{
+    account::{Address, Signature},
+    message::EthereumMessage,
+};
+
+/// The marker that the `r` value of dry run signatures starts with.
+pub const MARKER: &[u8; 12] = b"hdnodedryrun";
+
+/// Signing backend producing fake signatures for a set of accounts.
+pub struct DryRun {
+    accounts: Vec<Address>,
+}
+
+impl DryRun {
+    /// Creates a new dry run signer for the specified accounts.
+    pub fn new(accounts: Vec<Address>) -> Self {
+        Self { accounts }
+    }
+
+    /// Produces the fake signature of a 32-byte digest.
+    fn sign(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        if !self.accounts.contains(&account) {
+            return Err(UnknownSignerError {
+                account,
+                suggestion: None,
+            }
+            .into());
+        }
+        request::ensure_not_preview()?;
+
+        let digest = abi::keccak256([&account.0[..], &hash].concat());
+        let mut r = [0_u8; 32];
+        r[..MARKER.len()].copy_from_slice(MARKER);
+        r[MARKER.len()..].copy_from_slice(&digest[..32 - MARKER.len()]);
+        // Keep `s` between half the curve order and the curve order.
+        let mut s = abi::keccak256(digest);
+        s[0] = 0x80 | (s[0] & 0x3f);
+
+        let signature = Signature {
+            r,
+            s,
+            y_parity: false,
+        };
+        tracing::warn!(%account, %signature, "produced dry run signature");
+        Ok(signature)
+    }
+}
+
+impl Signing for DryRun {
+    fn accounts(&self) -> &[Address] {
+        &self.accounts
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        bail!("dry run account {account} has no public key")
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.sign(account, hash)
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        self.sign(account, EthereumMessage(message).signing_message())
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        self.sign(account, transaction.signing_message())
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        self.sign(account, typed_data.signing_message())
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.sign_message(account, &user_operation.hash())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{call, client_with, MockUpstream, ACCOUNT};
+    use rocket::serde::json::serde_json::json;
+
+    #[test]
+    fn signs_deterministically_with_flagged_signatures() {
+        let account = Address([1; 20]);
+        let signer = DryRun::new(vec![account]);
+
+        let signature = signer.sign_message(account, b"hello").unwrap();
+        assert!(signature.r.starts_with(MARKER));
+        assert!(signature.s[0] >= 0x80);
+        assert_eq!(signer.sign_message(account, b"hello").unwrap(), signature);
+        assert_ne!(signer.sign_message(account, b"world").unwrap(), signature);
+
+        assert!(signer.sign_message(Address([2; 20]), b"hello").is_err());
+    }
+
+    #[rocket::async_test]
+    async fn signs_with_dry_run_signatures() {
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment
+                .merge(("signers", json!(["dryrun", "log", "validator", "policy"])))
+                .merge(("dry_run_accounts", json!([ACCOUNT])))
+        })
+        .await;
+
+        let accounts = call(&client, "eth_accounts", json!([])).await;
+        assert_eq!(accounts["result"], json!([ACCOUNT]));
+
+        let params = json!([ACCOUNT, "0x68656c6c6f"]);
+        let signed = call(&client, "eth_sign", params.clone()).await;
+        let signature = signed["result"].as_str().unwrap();
+        assert!(signature.starts_with(&format!("0x{}", hex::encode("hdnodedryrun"))));
+        assert_eq!(call(&client, "eth_sign", params).await["result"], signature);
+    }
+}
@@ -0,0 +1,445 @@
+//! A signer scoping signing operations to client identities.
+//!
+//! On zero-trust networks, clients authenticate with TLS client certificates
+//! and the common names of their subjects are mapped to identities. Each
+//! identity can be limited to a subset of the accounts and to signature quotas
+//! of its own, and signing operations are labelled with the identity they are
+//! performed for, so that they can be attributed in audit logs and receipts.
+
+use super::{
+    quota::{Limits, Store},
+    request::{self, RequestContext},
+    PublicKey, Signing, Transaction, TypedData, UserOperation,
+};
+use crate::{backend::SharedBackend, serialization::Str};
+use anyhow::{ensure, Result};
+use hdwallet::account::{Address, Signature};
+use rocket::serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+use thiserror::Error;
+
+/// Client identity options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Options {
+    /// Client identities, by name.
+    #[serde(default)]
+    pub identities: HashMap<String, Identity>,
+
+    /// Whether or not to reject HTTP requests without a client certificate
+    /// mapping to one of the identities.
+    #[serde(default)]
+    pub require_client_identity: bool,
+}
+
+impl Options {
+    /// Returns the name of the identity of a client certificate, from the
+    /// common names of its subject.
+    pub fn identify<'a>(&self, mut common_names: impl Iterator<Item = &'a str>) -> Option<&str> {
+        common_names.find_map(|common_name| {
+            self.identities
+                .iter()
+                .find(|(_, identity)| identity.common_name == common_name)
+                .map(|(name, _)| name.as_str())
+        })
+    }
+}
+
+/// Settings for a client identity.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Identity {
+    /// The common name of the subject of the identity's client certificates.
+    pub common_name: String,
+
+    /// The accounts available to the identity. All accounts are available
+    /// when not specified.
+    pub accounts: Option<Vec<Str<Address>>>,
+
+    /// The signature limits of the identity, across all of its accounts.
+    #[serde(flatten)]
+    pub limits: Limits,
+}
+
+/// An error indicating that an account is not available to an identity.
+#[derive(Debug, Error)]
+#[error("account {account} is not available to identity {identity}")]
+pub struct NotInScopeError {
+    pub account: Address,
+    pub identity: String,
+}
+
+/// An error indicating that a request has no client identity, while client
+/// identities are required.
+#[derive(Debug, Error)]
+#[error("signing requires a client identity")]
+pub struct IdentityRequiredError;
+
+/// An error indicating that an identity's signing quota is exhausted.
+#[derive(Debug, Error)]
+#[error("{period} signing quota of {limit} exceeded for identity {identity}")]
+pub struct IdentityQuotaExceededError {
+    pub identity: String,
+    pub period: &'static str,
+    pub limit: u64,
+}
+
+/// The scope of an identity.
+struct Scope {
+    accounts: Option<Vec<Address>>,
+    limits: Limits,
+}
+
+/// Wrapping signer that enforces the account scopes and quotas of client
+/// identities.
+pub struct Identified<S> {
+    scopes: HashMap<String, Scope>,
+    require: bool,
+    counters: Store,
+    inner: S,
+}
+
+impl<S> Identified<S>
+where
+    S: Signing,
+{
+    /// Creates a new identity scoping signer wrapping the specified signer.
+    /// When client identities are required, requests without one can't use
+    /// any account. Signing operations made by the node itself, outside of
+    /// any request, are never scoped. Quota counters are loaded from and
+    /// persisted to the specified file, if any, or kept in the specified
+    /// shared backend in cluster mode.
+    pub fn new(
+        inner: S,
+        identities: HashMap<String, Identity>,
+        require: bool,
+        path: Option<&Path>,
+        cluster: Option<SharedBackend>,
+    ) -> Result<Self> {
+        let mut scopes = HashMap::new();
+        for (name, identity) in identities {
+            let accounts = identity
+                .accounts
+                .map(|accounts| accounts.into_iter().map(|account| account.0).collect());
+            if let Some(accounts) = &accounts {
+                for account in accounts {
+                    ensure!(
+                        inner.accounts().contains(account),
+                        "account {account} of identity {name} is not managed by the signer",
+                    );
+                }
+            }
+            let scope = Scope {
+                accounts,
+                limits: identity.limits,
+            };
+            scopes.insert(name, scope);
+        }
+
+        Ok(Self {
+            scopes,
+            require,
+            counters: Store::open(path, cluster)?,
+            inner,
+        })
+    }
+}
+
+impl<S> Identified<S> {
+    /// Returns the identity of the current request along with its scope, if
+    /// any.
+    fn scope(&self) -> Option<(String, &Scope)> {
+        let identity = RequestContext::current()?.identity?;
+        let scope = self.scopes.get(&identity)?;
+        Some((identity, scope))
+    }
+
+    /// Returns whether or not the current request is missing a required
+    /// client identity.
+    fn missing_identity(&self) -> bool {
+        self.require && RequestContext::current().is_some_and(|request| request.identity.is_none())
+    }
+
+    /// Checks that the account is available to the identity of the current
+    /// request.
+    fn check_scope(&self, account: Address) -> Result<()> {
+        if self.missing_identity() {
+            return Err(IdentityRequiredError.into());
+        }
+        let (identity, scope) = match self.scope() {
+            Some(scope) => scope,
+            None => return Ok(()),
+        };
+        if matches!(&scope.accounts, Some(accounts) if !accounts.contains(&account)) {
+            return Err(NotInScopeError { account, identity }.into());
+        }
+        Ok(())
+    }
+
+    /// Signs with the inner signer if the account is available to the
+    /// identity of the current request and its quota allows it.
+    fn sign(
+        &self,
+        account: Address,
+        sign: impl FnOnce(&S) -> Result<Signature>,
+    ) -> Result<Signature> {
+        self.check_scope(account)?;
+        let (identity, scope) = match self.scope() {
+            Some(scope) => scope,
+            None => return sign(&self.inner),
+        };
+
+        // Identity counters share the store format of account quotas, so
+        // they are keyed apart from account addresses.
+        let key = format!("identity:{identity}");
+        let reserved = self.counters.reserve(&key, scope.limits, |period, limit| {
+            IdentityQuotaExceededError {
+                identity: identity.clone(),
+                period,
+                limit,
+            }
+            .into()
+        })?;
+        request::decide("identity", format!("signing for identity {identity}"));
+        tracing::info!(%account, %identity, "signing for client identity");
+        let result = sign(&self.inner);
+        if result.is_err() {
+            self.counters.release(&key, reserved);
+        }
+        result
+    }
+}
+
+impl<S> Signing for Identified<S>
+where
+    S: Signing,
+{
+    fn accounts(&self) -> &[Address] {
+        if self.missing_identity() {
+            return &[];
+        }
+        match self
+            .scope()
+            .and_then(|(_, scope)| scope.accounts.as_deref())
+        {
+            Some(accounts) => accounts,
+            None => self.inner.accounts(),
+        }
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.check_scope(account)?;
+        self.inner.public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.sign(account, |inner| inner.sign_hash(account, hash))
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        self.sign(account, |inner| inner.sign_message(account, message))
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        self.sign(account, |inner| {
+            inner.sign_transaction(account, transaction)
+        })
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        self.sign(account, |inner| inner.sign_typed_data(account, typed_data))
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.sign(account, |inner| {
+            inner.sign_user_operation(account, user_operation)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        jsonrpc::Id,
+        signer::wallet::Wallet,
+        testing::{client_with, MockUpstream, ACCOUNT},
+    };
+    use rocket::{
+        error::ErrorKind,
+        local::asynchronous::Client,
+        serde::json::{serde_json::json, Value},
+    };
+
+    #[rocket::async_test]
+    async fn requires_client_identities() {
+        let mnemonic = "test test test test test test test test test test test junk"
+            .parse()
+            .unwrap();
+        let wallet = Wallet::new(&mnemonic, "", 1).unwrap();
+        let account = wallet.accounts()[0];
+        let identities = HashMap::from([(
+            "payments".to_owned(),
+            Identity {
+                common_name: "payments.internal".to_owned(),
+                accounts: None,
+                limits: Limits::default(),
+            },
+        )]);
+        let signer = Identified::new(wallet, identities, true, None, None).unwrap();
+        let context = |identity: Option<&str>| RequestContext {
+            method: "eth_sign".to_owned(),
+            id: Id::Number(1.into()),
+            client_ip: None,
+            origin: None,
+            identity: identity.map(String::from),
+            timestamp: 0,
+        };
+
+        context(None)
+            .scope(async {
+                assert!(signer.accounts().is_empty());
+                let err = signer.sign_message(account, b"hello").unwrap_err();
+                assert!(err.is::<IdentityRequiredError>());
+            })
+            .await;
+        context(Some("payments"))
+            .scope(async {
+                assert_eq!(signer.accounts(), [account]);
+                assert!(signer.sign_message(account, b"hello").is_ok());
+            })
+            .await;
+        // Signing operations of the node itself are not requests.
+        assert!(signer.sign_message(account, b"hello").is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn persists_identity_quotas() {
+        let path = std::env::temp_dir().join(format!(
+            "hdnode-identity-quotas-{}.json",
+            std::process::id()
+        ));
+        let mnemonic = "test test test test test test test test test test test junk"
+            .parse()
+            .unwrap();
+        let open = || {
+            let wallet = Wallet::new(&mnemonic, "", 1).unwrap();
+            let identities = HashMap::from([(
+                "payments".to_owned(),
+                Identity {
+                    common_name: "payments.internal".to_owned(),
+                    accounts: None,
+                    limits: Limits {
+                        hourly: None,
+                        daily: Some(1),
+                    },
+                },
+            )]);
+            Identified::new(wallet, identities, false, Some(&path), None).unwrap()
+        };
+        let context = RequestContext {
+            method: "eth_sign".to_owned(),
+            id: Id::Number(1.into()),
+            client_ip: None,
+            origin: None,
+            identity: Some("payments".to_owned()),
+            timestamp: 0,
+        };
+
+        let signer = open();
+        let account = signer.accounts()[0];
+        let signed = context
+            .clone()
+            .scope(async { signer.sign_message(account, b"hello") })
+            .await;
+        // A restarted node keeps counting signatures against the quota.
+        let restarted = open();
+        let denied = context
+            .scope(async { restarted.sign_message(account, b"hello") })
+            .await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(signed.is_ok());
+        assert!(denied.unwrap_err().is::<IdentityQuotaExceededError>());
+    }
+
+    #[rocket::async_test]
+    async fn requires_identity_signer_for_identities() {
+        let upstream = MockUpstream::start().await;
+        upstream.respond("eth_chainId", json!("0x1"));
+        let figment = crate::cli::dev_figment(rocket::Config::figment())
+            .merge(("remote_node_url", upstream.url().as_str()))
+            .merge((
+                "identities",
+                json!({ "payments": { "common_name": "payments.internal" } }),
+            ));
+
+        let error = Client::tracked(crate::rocket(figment)).await.err().unwrap();
+        assert!(matches!(error.kind(), ErrorKind::FailedFairings(_)));
+    }
+
+    #[rocket::async_test]
+    async fn scopes_client_identities() {
+        let path = std::env::temp_dir().join(format!(
+            "hdnode-identity-scopes-{}.json",
+            std::process::id()
+        ));
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment
+                .merge((
+                    "signers",
+                    json!(["wallet", "log", "identity", "validator", "policy"]),
+                ))
+                .merge(("require_client_identity", true))
+                .merge(("identity_quota_file", &path))
+                .merge((
+                    "identities",
+                    json!({
+                        "payments": {
+                            "common_name": "payments.internal",
+                            "accounts": [ACCOUNT],
+                            "daily": 1,
+                        },
+                    }),
+                ))
+        })
+        .await;
+        let call = |method: &str, params: Value, certificate: bool| {
+            let mut request = client.post("/").json(&json!({
+                "jsonrpc": "2.0",
+                "method": method,
+                "params": params,
+                "id": 1,
+            }));
+            if certificate {
+                request = request.identity(&include_bytes!("../../fixtures/client.pem")[..]);
+            }
+            request.dispatch()
+        };
+
+        let anonymous = call("eth_accounts", json!([]), false).await;
+        assert_eq!(anonymous.status(), rocket::http::Status::Forbidden);
+
+        let accounts = call("eth_accounts", json!([]), true).await;
+        let accounts = accounts.into_json::<Value>().await.unwrap();
+        assert_eq!(accounts["result"], json!([ACCOUNT]));
+
+        let other = "0x70997970c51812dc3a010c7d01b50e0d17dc79c8";
+        let signed = call("eth_sign", json!([other, "0x"]), true).await;
+        let signed = signed.into_json::<Value>().await.unwrap();
+        assert_eq!(signed["error"]["code"], 4100);
+
+        let signed = call("eth_sign", json!([ACCOUNT, "0x"]), true).await;
+        let signed = signed.into_json::<Value>().await.unwrap();
+        assert!(signed["result"].is_string());
+        let signed = call("eth_sign", json!([ACCOUNT, "0x"]), true).await;
+        let signed = signed.into_json::<Value>().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(signed["error"]["code"], -32005);
+    }
+}
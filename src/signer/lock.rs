@@ -0,0 +1,115 @@
+//! Per-account locks for signers.
+//!
+//! Signers are shared by all requests and sign for different accounts
+//! concurrently. Decorators whose decisions depend on earlier signing requests
+//! of the same account lock the account while deciding, so that concurrent
+//! requests for it are handled one at a time without blocking other accounts.
+//! Locks can be held for a long time, for example while waiting for operator
+//! approval, so waiting for them blocks in place instead of stalling the
+//! runtime's worker thread.
+
+use crate::runtime;
+use hdwallet::account::Address;
+use std::{
+    collections::HashSet,
+    sync::{Condvar, Mutex},
+};
+
+/// A set of per-account locks.
+#[derive(Default)]
+pub struct AccountLocks {
+    locked: Mutex<HashSet<[u8; 20]>>,
+    unlocked: Condvar,
+}
+
+impl AccountLocks {
+    /// Locks an account, blocking while it is locked by another request.
+    pub fn lock(&self, account: Address) -> AccountGuard<'_> {
+        if !self.locked.lock().unwrap().insert(account.0) {
+            runtime::blocking(|| {
+                let mut locked = self.locked.lock().unwrap();
+                while !locked.insert(account.0) {
+                    locked = self.unlocked.wait(locked).unwrap();
+                }
+            });
+        }
+        AccountGuard {
+            locks: self,
+            account,
+        }
+    }
+}
+
+/// A guard unlocking an account when dropped.
+pub struct AccountGuard<'a> {
+    locks: &'a AccountLocks,
+    account: Address,
+}
+
+impl Drop for AccountGuard<'_> {
+    fn drop(&mut self) {
+        self.locks.locked.lock().unwrap().remove(&self.account.0);
+        self.locks.unlocked.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::tokio::{self, sync::oneshot};
+    use std::{
+        sync::{mpsc, Arc},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn locks_accounts_independently() {
+        let locks = &AccountLocks::default();
+        let guard = locks.lock(Address([1; 20]));
+
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            scope.spawn(move || {
+                let _other = locks.lock(Address([2; 20]));
+                sender.send("other").unwrap();
+                let _same = locks.lock(Address([1; 20]));
+                sender.send("same").unwrap();
+            });
+
+            let timeout = Duration::from_secs(5);
+            assert_eq!(receiver.recv_timeout(timeout), Ok("other"));
+            assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+            drop(guard);
+            assert_eq!(receiver.recv_timeout(timeout), Ok("same"));
+        });
+    }
+
+    #[rocket::tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn waits_without_stalling_the_runtime() {
+        let locks = Arc::new(AccountLocks::default());
+        let guard = locks.lock(Address([1; 20]));
+
+        let (started, waiting) = oneshot::channel();
+        let waiter = tokio::spawn({
+            let locks = locks.clone();
+            async move {
+                started.send(()).unwrap();
+                let _guard = locks.lock(Address([1; 20]));
+            }
+        });
+        waiting.await.unwrap();
+
+        // The only worker thread is waiting for the lock, so other tasks only
+        // make progress if the wait doesn't stall it.
+        let (sender, receiver) = oneshot::channel();
+        tokio::spawn(async move { sender.send(()).unwrap() });
+        tokio::time::timeout(Duration::from_secs(5), receiver)
+            .await
+            .unwrap()
+            .unwrap();
+
+        drop(guard);
+        waiter.await.unwrap();
+    }
+}
@@ -1,36 +1,80 @@
 //! A signer that just logs all signing operations.
 
-use super::{Signing, Transaction, TypedData};
+use super::{PublicKey, Signing, Transaction, TypedData, UserOperation};
+use crate::selectors::Selectors;
 use anyhow::Result;
 use hdwallet::account::{Address, Signature};
 
 /// Wrapping signer that logs all signing operations to the global logger.
-pub struct LogRecorder<S>(pub S);
+pub struct LogRecorder<S> {
+    inner: S,
+    selectors: Selectors,
+}
+
+impl<S> LogRecorder<S> {
+    /// Creates a new logging signer wrapping the specified signer, naming the
+    /// functions called by transactions with the specified function signature
+    /// database.
+    pub fn new(inner: S, selectors: Selectors) -> Self {
+        Self { inner, selectors }
+    }
+}
 
 impl<S> Signing for LogRecorder<S>
 where
     S: Signing,
 {
     fn accounts(&self) -> &[Address] {
-        self.0.accounts()
+        self.inner.accounts()
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.inner.public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        let signature = self.inner.sign_hash(account, hash)?;
+        let hash = format!("0x{}", hex::encode(hash));
+        tracing::warn!(%account, %hash, %signature, "signed raw hash");
+        Ok(signature)
     }
 
     fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
-        let signature = self.0.sign_message(account, message)?;
+        let signature = self.inner.sign_message(account, message)?;
         let ethmessage = format!("0x{}", hex::encode(message));
         tracing::info!(%account, %ethmessage, %signature, "signed message");
         Ok(signature)
     }
 
     fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
-        let signature = self.0.sign_transaction(account, transaction)?;
-        tracing::info!(%account, ?transaction, %signature, "signed transaction");
+        let signature = self.inner.sign_transaction(account, transaction)?;
+        let function = self.selectors.function(&transaction.request().data.0);
+        tracing::info!(
+            %account, ?transaction, function = function.as_deref(), %signature,
+            "signed transaction"
+        );
         Ok(signature)
     }
 
     fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
-        let signature = self.0.sign_typed_data(account, typed_data)?;
+        let signature = self.inner.sign_typed_data(account, typed_data)?;
         tracing::info!(%account, ?typed_data, %signature, "signed typed data");
         Ok(signature)
     }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        let signature = self.inner.sign_user_operation(account, user_operation)?;
+        let function = self
+            .selectors
+            .function(&user_operation.request().call_data.0);
+        tracing::info!(
+            %account, ?user_operation, function = function.as_deref(), %signature,
+            "signed user operation"
+        );
+        Ok(signature)
+    }
 }
@@ -0,0 +1,307 @@
+//! A signer posting signing notifications to chat channels.
+//!
+//! Notifiers post concise, human readable messages with the account, the
+//! action, the value and destination of transactions, and the decision to
+//! Slack, Discord or Telegram, so that operators can follow signing activity
+//! without watching logs. Each notifier can be limited to denied signing
+//! requests or to high value transactions.
+
+use super::{request, PublicKey, Signing, Transaction, TypedData, UserOperation};
+use crate::{dashboard, serialization::Str};
+use anyhow::{ensure, Result};
+use ethnum::U256;
+use hdwallet::account::{Address, Signature};
+use reqwest::Url;
+use rocket::{
+    serde::{json::serde_json::json, Deserialize},
+    tokio,
+};
+use std::fmt::Write as _;
+
+/// Notifier settings.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Notifier {
+    /// The chat channel to post to.
+    #[serde(flatten)]
+    pub channel: Channel,
+
+    /// The signing events to notify about.
+    #[serde(default)]
+    pub events: Events,
+
+    /// The minimum value in Wei of high value transactions.
+    pub min_value: Option<Str<U256>>,
+}
+
+/// A chat channel.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "lowercase")]
+pub enum Channel {
+    /// A Slack incoming webhook.
+    Slack { webhook: Str<Url> },
+    /// A Discord webhook.
+    Discord { webhook: Str<Url> },
+    /// A Telegram chat, posted to by a bot.
+    Telegram { bot_token: String, chat_id: String },
+}
+
+/// Classes of signing events.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum Events {
+    /// All signing requests.
+    #[default]
+    All,
+    /// Denied signing requests only.
+    Denials,
+    /// Transactions with a value of at least the minimum value only.
+    HighValue,
+}
+
+/// A signing event.
+struct Event<'a> {
+    action: &'a str,
+    account: Address,
+    transaction: Option<&'a Transaction>,
+    error: Option<&'a anyhow::Error>,
+}
+
+impl Event<'_> {
+    /// Formats the event as a chat message.
+    fn message(&self) -> String {
+        let mut message = format!("hdnode: {} for {}", self.action, self.account);
+        if let Some(transaction) = self.transaction {
+            let request = transaction.request();
+            let _ = write!(
+                message,
+                "\nValue: {} ETH",
+                dashboard::ether(request.value.0)
+            );
+            match &request.to {
+                Some(to) => {
+                    let _ = write!(message, "\nDestination: {}", to.0);
+                }
+                None => message.push_str("\nDestination: contract creation"),
+            }
+        }
+        match self.error {
+            Some(err) => {
+                let _ = write!(message, "\nDecision: denied ({err})");
+            }
+            None => message.push_str("\nDecision: signed"),
+        }
+        message
+    }
+}
+
+/// A configured notifier.
+struct Target {
+    channel: Channel,
+    events: Events,
+    min_value: U256,
+}
+
+impl Target {
+    /// Returns whether or not the event should be notified.
+    fn matches(&self, event: &Event) -> bool {
+        match self.events {
+            Events::All => true,
+            Events::Denials => event.error.is_some(),
+            Events::HighValue => event
+                .transaction
+                .is_some_and(|transaction| transaction.request().value.0 >= self.min_value),
+        }
+    }
+}
+
+/// Wrapping signer that posts signing notifications to chat channels.
+pub struct Notify<S> {
+    client: reqwest::Client,
+    targets: Vec<Target>,
+    inner: S,
+}
+
+impl<S> Notify<S> {
+    /// Creates a new notifying signer wrapping the specified signer.
+    pub fn new(inner: S, notifiers: Vec<Notifier>) -> Result<Self> {
+        let targets = notifiers
+            .into_iter()
+            .map(|notifier| {
+                ensure!(
+                    notifier.events != Events::HighValue || notifier.min_value.is_some(),
+                    "high value notifiers require a minimum value",
+                );
+                Ok(Target {
+                    channel: notifier.channel,
+                    events: notifier.events,
+                    min_value: notifier.min_value.map(|value| value.0).unwrap_or_default(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            client: reqwest::Client::new(),
+            targets,
+            inner,
+        })
+    }
+
+    /// Signs with `sign`, notifying the matching chat channels of the outcome.
+    fn sign(
+        &self,
+        action: &str,
+        account: Address,
+        transaction: Option<&Transaction>,
+        sign: impl FnOnce(&S) -> Result<Signature>,
+    ) -> Result<Signature> {
+        if request::is_preview() {
+            return sign(&self.inner);
+        }
+        let result = sign(&self.inner);
+        let event = Event {
+            action,
+            account,
+            transaction,
+            error: result.as_ref().err(),
+        };
+        for target in self.targets.iter().filter(|target| target.matches(&event)) {
+            self.post(&target.channel, event.message());
+        }
+        result
+    }
+
+    /// Posts a message to a chat channel in the background.
+    fn post(&self, channel: &Channel, text: String) {
+        let request = match channel {
+            Channel::Slack { webhook } => self
+                .client
+                .post(webhook.0.clone())
+                .json(&json!({ "text": text })),
+            Channel::Discord { webhook } => self
+                .client
+                .post(webhook.0.clone())
+                .json(&json!({ "content": text })),
+            Channel::Telegram { bot_token, chat_id } => self
+                .client
+                .post(format!(
+                    "https://api.telegram.org/bot{bot_token}/sendMessage"
+                ))
+                .json(&json!({ "chat_id": chat_id, "text": text })),
+        };
+        tokio::spawn(async move {
+            if let Err(err) = request.send().await.and_then(|r| r.error_for_status()) {
+                tracing::warn!(?err, "failed to post signing notification");
+            }
+        });
+    }
+}
+
+impl<S> Signing for Notify<S>
+where
+    S: Signing,
+{
+    fn accounts(&self) -> &[Address] {
+        self.inner.accounts()
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.inner.public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.sign("sign hash", account, None, |inner| {
+            inner.sign_hash(account, hash)
+        })
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        self.sign("sign message", account, None, |inner| {
+            inner.sign_message(account, message)
+        })
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        self.sign("sign transaction", account, Some(transaction), |inner| {
+            inner.sign_transaction(account, transaction)
+        })
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        self.sign("sign typed data", account, None, |inner| {
+            inner.sign_typed_data(account, typed_data)
+        })
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.sign("sign user operation", account, None, |inner| {
+            inner.sign_user_operation(account, user_operation)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::transaction::TransactionRequest;
+    use anyhow::anyhow;
+    use rocket::serde::json::serde_json;
+
+    #[test]
+    fn formats_and_filters_events() {
+        let (account, transaction) = serde_json::from_value::<TransactionRequest>(json!({
+            "from": "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266",
+            "to": "0x70997970c51812dc3a010c7d01b50e0d17dc79c8",
+            "value": "0x14d1120d7b160000",
+            "gas": "0x5208",
+            "gasPrice": "0x1",
+            "nonce": "0x0",
+            "chainId": "0x1",
+        }))
+        .unwrap()
+        .into_filled()
+        .unwrap();
+        let error = anyhow!("transaction value exceeds maximum");
+        let denied = Event {
+            action: "sign transaction",
+            account,
+            transaction: Some(&transaction),
+            error: Some(&error),
+        };
+        let to = transaction.request().to.as_ref().unwrap().0;
+        assert_eq!(
+            denied.message(),
+            format!(
+                "hdnode: sign transaction for {account}\n\
+                 Value: 1.500000 ETH\n\
+                 Destination: {to}\n\
+                 Decision: denied (transaction value exceeds maximum)"
+            ),
+        );
+        let signed = Event {
+            action: "sign message",
+            account,
+            transaction: None,
+            error: None,
+        };
+
+        let target = |events, min_value: u64| Target {
+            channel: Channel::Telegram {
+                bot_token: String::new(),
+                chat_id: String::new(),
+            },
+            events,
+            min_value: U256::from(min_value),
+        };
+        assert!(target(Events::All, 0).matches(&signed));
+        assert!(target(Events::Denials, 0).matches(&denied));
+        assert!(!target(Events::Denials, 0).matches(&signed));
+        assert!(target(Events::HighValue, 1_500_000_000_000_000_000).matches(&denied));
+        assert!(!target(Events::HighValue, 1_500_000_000_000_000_001).matches(&denied));
+        assert!(!target(Events::HighValue, 0).matches(&signed));
+    }
+}
@@ -0,0 +1,144 @@
+//! A signer validating signing operations with Open Policy Agent.
+//!
+//! Signing operations are evaluated by querying an OPA server's data API, for
+//! example one running as a sidecar, with the operation as input:
+//!
+//! ```text
+//! POST /v1/data/hdnode/allow
+//! {"input":{"operation":"signTransaction","account":"0x...","data":{...},"context":{...}}}
+//! ```
+//!
+//! The operation is allowed only if the result is `true`. An undefined result
+//! or an unreachable OPA server denies it.
+
+use super::{
+    request::{self, RequestContext},
+    PublicKey, Signing, Transaction, TypedData, UserOperation,
+};
+use crate::{
+    runtime,
+    serialization::{Bytes, Str},
+};
+use anyhow::{bail, Context as _, Result};
+use hdwallet::account::{Address, Signature};
+use reqwest::Url;
+use rocket::serde::{json::Value, Deserialize, Serialize};
+use std::time::Duration;
+
+/// Wrapping signer that only signs operations allowed by an OPA policy.
+pub struct Opa<S> {
+    client: reqwest::Client,
+    url: Url,
+    inner: S,
+}
+
+/// An OPA data API query.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Query<'a, T> {
+    input: Input<'a, T>,
+}
+
+/// The OPA query input.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Input<'a, T> {
+    operation: &'a str,
+    account: Str<Address>,
+    data: T,
+    context: Option<RequestContext>,
+}
+
+/// The OPA query result.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Output {
+    result: Option<Value>,
+}
+
+impl<S> Opa<S> {
+    /// Creates a new OPA signer wrapping the specified signer, evaluating
+    /// signing operations with the policy decision at the specified OPA data
+    /// API URL. Policy queries that don't complete within `timeout` deny the
+    /// operation.
+    pub fn new(inner: S, url: Url, timeout: Duration) -> Result<Self> {
+        let client = reqwest::Client::builder().timeout(timeout).build()?;
+        Ok(Self { client, url, inner })
+    }
+
+    /// Evaluates the policy for a signing operation.
+    fn evaluate(&self, operation: &str, account: Address, data: impl Serialize) -> Result<()> {
+        let request = self.client.post(self.url.clone()).json(&Query {
+            input: Input {
+                operation,
+                account: Str(account),
+                data,
+                context: RequestContext::current(),
+            },
+        });
+
+        // The `Signing` trait is synchronous, so block in place to allow the
+        // runtime to keep making progress on other tasks in the meantime.
+        let output = runtime::block_on(async {
+            request
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<Output>()
+                .await
+        })?
+        .context("failed to query OPA policy")?;
+
+        match output.result {
+            Some(Value::Bool(true)) => {
+                request::decide("opa", format!("{operation} allowed by policy"));
+                Ok(())
+            }
+            Some(Value::Bool(false)) => bail!("OPA policy denied {operation} for {account}"),
+            Some(_) => bail!("OPA policy decision is not a boolean"),
+            None => bail!("OPA policy decision is undefined"),
+        }
+    }
+}
+
+impl<S> Signing for Opa<S>
+where
+    S: Signing,
+{
+    fn accounts(&self) -> &[Address] {
+        self.inner.accounts()
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.inner.public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.evaluate("signHash", account, Bytes(hash))?;
+        self.inner.sign_hash(account, hash)
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        self.evaluate("signMessage", account, Bytes(message))?;
+        self.inner.sign_message(account, message)
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        self.evaluate("signTransaction", account, transaction)?;
+        self.inner.sign_transaction(account, transaction)
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        self.evaluate("signTypedData", account, typed_data)?;
+        self.inner.sign_typed_data(account, typed_data)
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.evaluate("signUserOperation", account, user_operation)?;
+        self.inner.sign_user_operation(account, user_operation)
+    }
+}
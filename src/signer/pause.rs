@@ -0,0 +1,87 @@
+//! A signer that can be paused at runtime.
+
+use super::{PublicKey, Signing, Transaction, TypedData, UserOperation};
+use anyhow::Result;
+use hdwallet::account::{Address, Signature};
+use std::sync::atomic::{AtomicBool, Ordering};
+use thiserror::Error;
+
+/// Wrapping signer that rejects all signing operations while paused.
+pub struct Pausable<S> {
+    paused: AtomicBool,
+    inner: S,
+}
+
+impl<S> Pausable<S> {
+    /// Creates a new pausable signer wrapping the specified signer.
+    pub fn new(inner: S, paused: bool) -> Self {
+        Self {
+            paused: AtomicBool::new(paused),
+            inner,
+        }
+    }
+
+    /// Pauses or resumes signing.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+    }
+
+    /// Returns whether signing is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    fn ensure_not_paused(&self) -> Result<()> {
+        if self.paused.load(Ordering::SeqCst) {
+            return Err(SigningPausedError.into());
+        }
+        Ok(())
+    }
+}
+
+/// An error indicating that signing is paused.
+#[derive(Debug, Error)]
+#[error("signing is paused")]
+pub struct SigningPausedError;
+
+impl<S> Signing for Pausable<S>
+where
+    S: Signing,
+{
+    fn accounts(&self) -> &[Address] {
+        self.inner.accounts()
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.inner.public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.ensure_not_paused()?;
+        self.inner.sign_hash(account, hash)
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        self.ensure_not_paused()?;
+        self.inner.sign_message(account, message)
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        self.ensure_not_paused()?;
+        self.inner.sign_transaction(account, transaction)
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        self.ensure_not_paused()?;
+        self.inner.sign_typed_data(account, typed_data)
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.ensure_not_paused()?;
+        self.inner.sign_user_operation(account, user_operation)
+    }
+}
@@ -0,0 +1,938 @@
+//! A signer enforcing static signing policies from the configuration.
+//!
+//! Unlike validators, policies don't require writing any Lua, and cover
+//! common restrictions for deployments that only interact with known
+//! contracts.
+
+use super::{
+    approval::Approver,
+    request::{self, RequestContext},
+    wallet::UnknownSignerError,
+    PublicKey, Signing, Transaction, TypedData, UserOperation,
+};
+use crate::{
+    clock,
+    node::{
+        siwe::SiweMessage,
+        token::{self, Kind, TokenOperation},
+    },
+    serialization::Str,
+};
+use anyhow::{bail, Context as _, Result};
+use ethnum::U256;
+use hdwallet::account::{Address, Signature};
+use rocket::serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+};
+
+/// Signing policies.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Policies {
+    /// The only addresses transactions are allowed to be sent to. All
+    /// destinations are allowed when not specified.
+    #[serde(default)]
+    pub allowed_destinations: Option<Vec<Str<Address>>>,
+
+    /// Whether or not contract creation transactions are allowed when
+    /// destinations are restricted.
+    #[serde(default)]
+    pub allow_contract_creation: bool,
+
+    /// Policies for individual accounts, by address. Accounts without a
+    /// policy are not restricted any further.
+    #[serde(default)]
+    pub account_policies: HashMap<String, AccountPolicy>,
+
+    /// Policies for individual dapps, by the web origin making the request,
+    /// such as `https://app.example.com`. The `*` policy applies to origins
+    /// without a policy. Requests without an origin are not restricted any
+    /// further.
+    #[serde(default)]
+    pub origin_policies: HashMap<String, OriginPolicy>,
+
+    /// Limits on token transfers, by token address. Tokens without limits
+    /// are not restricted any further.
+    #[serde(default)]
+    pub token_limits: HashMap<String, TokenLimit>,
+
+    /// Guards against token approvals with large allowances. Approvals are
+    /// not guarded when not specified.
+    pub approval_guard: Option<ApprovalGuard>,
+
+    /// The maximum fee per gas in Wei of EIP-1559 transactions.
+    pub max_fee_per_gas: Option<Str<U256>>,
+
+    /// The maximum gas price in Wei of legacy and EIP-2930 transactions.
+    pub max_gas_price: Option<Str<U256>>,
+
+    /// Policy for Sign-In with Ethereum messages. SIWE messages are not
+    /// restricted any further when not specified.
+    pub siwe: Option<SiwePolicy>,
+}
+
+/// Signing policy for a single account.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AccountPolicy {
+    /// The methods the account is allowed to sign with. All methods are
+    /// allowed when not specified.
+    pub methods: Option<Vec<Method>>,
+
+    /// The maximum value in Wei of transactions signed by the account.
+    pub max_value: Option<Str<U256>>,
+
+    /// The maximum fee per gas in Wei of EIP-1559 transactions signed by the
+    /// account, replacing the global maximum.
+    pub max_fee_per_gas: Option<Str<U256>>,
+
+    /// The maximum gas price in Wei of legacy and EIP-2930 transactions
+    /// signed by the account, replacing the global maximum.
+    pub max_gas_price: Option<Str<U256>>,
+}
+
+/// Signing policy for a single dapp origin.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct OriginPolicy {
+    /// The only accounts that are visible to the origin and that it may sign
+    /// with. All accounts are available when not specified.
+    pub accounts: Option<Vec<Str<Address>>>,
+
+    /// The methods the origin is allowed to sign with. All methods are
+    /// allowed when not specified.
+    pub methods: Option<Vec<Method>>,
+
+    /// The maximum value in Wei of transactions signed for the origin.
+    pub max_value: Option<Str<U256>>,
+}
+
+/// Limits on transfers of a single token.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TokenLimit {
+    /// The maximum amount of tokens a single transfer may move, or a single
+    /// approval may allow. Approvals for all are rejected when set.
+    pub max_amount: Option<Str<U256>>,
+
+    /// The only addresses tokens are allowed to be transferred to, and the
+    /// only spenders and operators approvals may be granted to. All
+    /// recipients are allowed when not specified.
+    pub allowed_recipients: Option<Vec<Str<Address>>>,
+}
+
+/// Guard against token approvals with large allowances.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ApprovalGuard {
+    /// The allowance above which approvals are guarded. Unlimited approvals,
+    /// including approvals for all tokens, are always guarded.
+    pub max_allowance: Option<Str<U256>>,
+
+    /// The action to take for guarded approvals.
+    #[serde(default)]
+    pub action: GuardAction,
+}
+
+/// Policy for Sign-In with Ethereum (EIP-4361) messages.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SiwePolicy {
+    /// The only domains SIWE messages may be signed for. All domains are
+    /// allowed when not specified.
+    pub allowed_domains: Option<Vec<String>>,
+
+    /// Whether or not SIWE messages must have an expiration time.
+    #[serde(default)]
+    pub require_expiration: bool,
+
+    /// Whether or not SIWE messages must have a nonce.
+    #[serde(default)]
+    pub require_nonce: bool,
+}
+
+/// The action to take for a guarded approval.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum GuardAction {
+    /// Reject the signing request.
+    #[default]
+    Reject,
+    /// Hold the signing request until an operator approves it.
+    Approve,
+}
+
+/// A signing method.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum Method {
+    Hash,
+    Message,
+    Transaction,
+    TypedData,
+    UserOperation,
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Hash => "hashes",
+            Self::Message => "messages",
+            Self::Transaction => "transactions",
+            Self::TypedData => "typed data",
+            Self::UserOperation => "user operations",
+        })
+    }
+}
+
+/// Wrapping signer that rejects signing operations not allowed by policies.
+pub struct Policy<S> {
+    policies: Policies,
+    accounts: HashMap<[u8; 20], AccountPolicy>,
+    origins: HashMap<String, (OriginPolicy, Option<Vec<Address>>)>,
+    tokens: HashMap<[u8; 20], TokenLimit>,
+    approver: Option<Arc<Approver>>,
+    inner: S,
+}
+
+impl<S> Policy<S> {
+    /// Creates a new policy signer wrapping the specified signer. Guarded
+    /// approvals are held for the operators of the specified approver, which
+    /// is required when the approval guard uses the `approve` action.
+    pub fn new(inner: S, policies: Policies, approver: Option<Arc<Approver>>) -> Result<Self> {
+        let accounts = policies
+            .account_policies
+            .iter()
+            .map(|(account, policy)| {
+                let account = account
+                    .parse::<Address>()
+                    .with_context(|| format!("invalid account policy address {account}"))?;
+                Ok((account.0, policy.clone()))
+            })
+            .collect::<Result<_>>()?;
+        let origins = policies
+            .origin_policies
+            .iter()
+            .map(|(origin, policy)| {
+                let accounts = policy
+                    .accounts
+                    .as_ref()
+                    .map(|accounts| accounts.iter().map(|account| account.0).collect());
+                let origin = origin.trim().trim_end_matches('/').to_lowercase();
+                (origin, (policy.clone(), accounts))
+            })
+            .collect();
+        let tokens = policies
+            .token_limits
+            .iter()
+            .map(|(token, limit)| {
+                let token = token
+                    .parse::<Address>()
+                    .with_context(|| format!("invalid token limit address {token}"))?;
+                Ok((token.0, limit.clone()))
+            })
+            .collect::<Result<_>>()?;
+
+        let approver = match approver {
+            Some(approver) => Some(approver),
+            None if policies
+                .approval_guard
+                .as_ref()
+                .is_some_and(|guard| guard.action == GuardAction::Approve) =>
+            {
+                bail!("approval guard requires an operator socket");
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            policies,
+            accounts,
+            origins,
+            tokens,
+            approver,
+            inner,
+        })
+    }
+
+    /// Returns the origin of the current request along with its policy and
+    /// visible accounts, if there is one.
+    fn origin_policy(&self) -> Option<(String, &OriginPolicy, Option<&[Address]>)> {
+        let origin = RequestContext::current()?.origin?;
+        let (policy, accounts) = self
+            .origins
+            .get(&origin)
+            .or_else(|| self.origins.get("*"))?;
+        Some((origin, policy, accounts.as_deref()))
+    }
+
+    /// Checks that the account is visible to the origin of the current
+    /// request.
+    fn check_visible(&self, account: Address) -> Result<()> {
+        if let Some((origin, _, Some(accounts))) = self.origin_policy() {
+            if !accounts.contains(&account) {
+                bail!("account {account} is not available to origin {origin}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the account is allowed to sign with the method.
+    fn check_method(&self, account: Address, method: Method) -> Result<()> {
+        self.check_visible(account)?;
+        if let Some((origin, policy, _)) = self.origin_policy() {
+            if let Some(allowed) = &policy.methods {
+                if !allowed.contains(&method) {
+                    bail!("origin {origin} is not allowed to sign {method}");
+                }
+            }
+        }
+        let allowed = match self
+            .accounts
+            .get(&account.0)
+            .and_then(|policy| policy.methods.as_ref())
+        {
+            Some(allowed) => allowed,
+            None => return Ok(()),
+        };
+        if !allowed.contains(&method) {
+            bail!("account {account} is not allowed to sign {method}");
+        }
+        Ok(())
+    }
+
+    /// Checks that a message is allowed by the SIWE policy, if it is a SIWE
+    /// message.
+    fn check_siwe(&self, account: Address, message: &[u8]) -> Result<()> {
+        let policy = match &self.policies.siwe {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        let siwe = match SiweMessage::parse(message)? {
+            Some(siwe) => siwe,
+            None => return Ok(()),
+        };
+
+        if siwe.address.0 != account {
+            bail!("SIWE message is for {}, not {account}", siwe.address.0);
+        }
+        if let Some(allowed) = &policy.allowed_domains {
+            if !allowed
+                .iter()
+                .any(|domain| domain.eq_ignore_ascii_case(&siwe.domain))
+            {
+                bail!("SIWE domain {} is not allowed", siwe.domain);
+            }
+        }
+        if policy.require_nonce && siwe.nonce.is_none() {
+            bail!("SIWE message has no nonce");
+        }
+        let now = clock::now();
+        match siwe.expires_at() {
+            Some(expires_at) if expires_at <= now => bail!("SIWE message has expired"),
+            None if policy.require_expiration => bail!("SIWE message has no expiration time"),
+            _ => {}
+        }
+        if siwe.valid_from().is_some_and(|valid_from| valid_from > now) {
+            bail!("SIWE message is not valid yet");
+        }
+        Ok(())
+    }
+
+    /// Checks that a transaction is allowed by the policies.
+    fn check_transaction(&self, account: Address, transaction: &Transaction) -> Result<()> {
+        self.check_method(account, Method::Transaction)?;
+
+        let request = transaction.request();
+        if let Some(max_value) = self
+            .accounts
+            .get(&account.0)
+            .and_then(|policy| policy.max_value.as_ref())
+        {
+            if request.value.0 > max_value.0 {
+                bail!(
+                    "transaction value exceeds maximum of {} Wei for account {account}",
+                    max_value.0,
+                );
+            }
+        }
+        if let Some((origin, policy, _)) = self.origin_policy() {
+            if let Some(max_value) = &policy.max_value {
+                if request.value.0 > max_value.0 {
+                    bail!(
+                        "transaction value exceeds maximum of {} Wei for origin {origin}",
+                        max_value.0,
+                    );
+                }
+            }
+        }
+        self.check_fees(account, transaction)?;
+
+        if let Some(to) = &request.to {
+            self.check_tokens(to.0, &request.data.0)?;
+            self.guard_approvals(
+                "signTransaction",
+                account,
+                transaction,
+                token::decode(to.0, &request.data.0),
+            )?;
+        }
+
+        let allowed = match &self.policies.allowed_destinations {
+            Some(allowed) => allowed,
+            None => return Ok(()),
+        };
+        match &request.to {
+            Some(to) if !allowed.iter().any(|allowed| allowed.0 == to.0) => {
+                bail!("transaction destination {} is not allowed", to.0)
+            }
+            None if !self.policies.allow_contract_creation => {
+                bail!("contract creation is not allowed")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the fees of a transaction are within the ceilings, which
+    /// protects against fee spikes and fat-fingered gas prices.
+    fn check_fees(&self, account: Address, transaction: &Transaction) -> Result<()> {
+        let policy = self.accounts.get(&account.0);
+        let request = transaction.request();
+        let (fee, name, ceiling) = match (request.max_fee_per_gas, request.gas_price) {
+            (Some(fee), _) => (
+                fee,
+                "maximum fee per gas",
+                policy
+                    .and_then(|policy| policy.max_fee_per_gas)
+                    .or(self.policies.max_fee_per_gas),
+            ),
+            (None, Some(fee)) => (
+                fee,
+                "gas price",
+                policy
+                    .and_then(|policy| policy.max_gas_price)
+                    .or(self.policies.max_gas_price),
+            ),
+            (None, None) => return Ok(()),
+        };
+        if let Some(ceiling) = ceiling {
+            if fee.0 > ceiling.0 {
+                bail!(
+                    "transaction {name} of {} Wei exceeds maximum of {} Wei for account {account}",
+                    fee.0,
+                    ceiling.0,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the token transfers and approvals of a call are within the
+    /// limits. Calls to limited tokens with undecodable calldata for one of
+    /// the token functions are rejected, as their effect can't be checked.
+    fn check_tokens(&self, to: Address, calldata: &[u8]) -> Result<()> {
+        if !self.tokens.contains_key(&to.0) {
+            return Ok(());
+        }
+        let operations = token::try_decode(to, calldata)
+            .with_context(|| format!("invalid calldata for limited token {to}"))?;
+        for operation in &operations {
+            self.check_token_operation(operation)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that a token operation is within the limits of its token.
+    fn check_token_operation(&self, operation: &TokenOperation) -> Result<()> {
+        let token = operation.token.0;
+        let limit = match self.tokens.get(&token.0) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let name = match operation.kind {
+            Kind::Transfer => "transfer",
+            Kind::Approval | Kind::ApprovalForAll => "approval",
+        };
+        if let Some(max_amount) = &limit.max_amount {
+            // Approvals for all have no amount, and allow moving all tokens.
+            if operation
+                .amount
+                .is_none_or(|amount| amount.0 > max_amount.0)
+            {
+                bail!(
+                    "token {name} exceeds maximum of {} for token {token}",
+                    max_amount.0
+                );
+            }
+        }
+        if let Some(allowed) = &limit.allowed_recipients {
+            if !allowed.iter().any(|allowed| allowed.0 == operation.to.0) {
+                match operation.kind {
+                    Kind::Transfer => bail!("token recipient {} is not allowed", operation.to.0),
+                    _ => bail!("token spender {} is not allowed", operation.to.0),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects or requests operator approval for token approvals with
+    /// allowances over the guard's maximum.
+    fn guard_approvals(
+        &self,
+        operation: &str,
+        account: Address,
+        data: impl Serialize,
+        operations: impl IntoIterator<Item = TokenOperation>,
+    ) -> Result<()> {
+        let guard = match &self.policies.approval_guard {
+            Some(guard) => guard,
+            None => return Ok(()),
+        };
+        let max_allowance = guard.max_allowance.map_or(U256::MAX, |max| max.0);
+        let guarded = operations.into_iter().find(|approval| match approval.kind {
+            Kind::Transfer => false,
+            Kind::Approval => approval
+                .amount
+                .is_some_and(|amount| amount.0 == U256::MAX || amount.0 > max_allowance),
+            Kind::ApprovalForAll => true,
+        });
+        let approval = match guarded {
+            Some(approval) => approval,
+            None => return Ok(()),
+        };
+
+        match guard.action {
+            GuardAction::Reject => bail!(
+                "approval of {} for token {} exceeds the maximum allowance",
+                approval.to.0,
+                approval.token.0,
+            ),
+            GuardAction::Approve => {
+                // Checked when creating the signer.
+                let approver = self.approver.as_ref().unwrap();
+                approver.request(operation, account, data)
+            }
+        }
+    }
+}
+
+impl<S> Signing for Policy<S>
+where
+    S: Signing,
+{
+    fn accounts(&self) -> &[Address] {
+        match self.origin_policy() {
+            Some((_, _, Some(accounts))) => accounts,
+            _ => self.inner.accounts(),
+        }
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.check_visible(account)?;
+        UnknownSignerError::restrict(self.inner.public_key(account), self.accounts())
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.check_method(account, Method::Hash)?;
+        request::decide("policy", "allowed by signing policies");
+        UnknownSignerError::restrict(self.inner.sign_hash(account, hash), self.accounts())
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        self.check_method(account, Method::Message)?;
+        self.check_siwe(account, message)?;
+        request::decide("policy", "allowed by signing policies");
+        UnknownSignerError::restrict(self.inner.sign_message(account, message), self.accounts())
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        self.check_transaction(account, transaction)?;
+        request::decide("policy", "allowed by signing policies");
+        UnknownSignerError::restrict(
+            self.inner.sign_transaction(account, transaction),
+            self.accounts(),
+        )
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        self.check_method(account, Method::TypedData)?;
+        if self.policies.approval_guard.is_some() || !self.tokens.is_empty() {
+            let permits = token::decode_permit(typed_data)?;
+            for permit in &permits {
+                self.check_token_operation(permit)?;
+            }
+            self.guard_approvals("signTypedData", account, typed_data, permits)?;
+        }
+        request::decide("policy", "allowed by signing policies");
+        UnknownSignerError::restrict(
+            self.inner.sign_typed_data(account, typed_data),
+            self.accounts(),
+        )
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.check_method(account, Method::UserOperation)?;
+        request::decide("policy", "allowed by signing policies");
+        UnknownSignerError::restrict(
+            self.inner.sign_user_operation(account, user_operation),
+            self.accounts(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        abi, jsonrpc::Id, node::transaction::TransactionRequest, serialization::Quantity,
+        signer::wallet::Wallet,
+    };
+    use rocket::serde::json::{json, serde_json, Value};
+
+    #[test]
+    fn account_policies() {
+        let account = "0x1111111111111111111111111111111111111111";
+        let policies = serde_json::from_value::<Policies>(json!({
+            "account_policies": {
+                account: {
+                    "methods": ["transaction"],
+                    "max_value": "1000000000000000000",
+                },
+            },
+        }))
+        .unwrap();
+        let policy = Policy::new((), policies, None).unwrap();
+
+        let transaction = |value: &str| {
+            serde_json::from_value::<TransactionRequest>(json!({
+                "from": account,
+                "to": account,
+                "value": value,
+                "gas": "0x5208",
+                "gasPrice": "0x1",
+                "nonce": "0x0",
+                "chainId": "0x1",
+            }))
+            .unwrap()
+            .into_filled()
+            .unwrap()
+        };
+
+        let (account, allowed) = transaction("0xde0b6b3a7640000");
+        assert!(policy.check_transaction(account, &allowed).is_ok());
+        let (_, denied) = transaction("0xde0b6b3a7640001");
+        assert!(policy.check_transaction(account, &denied).is_err());
+        assert!(policy.check_method(account, Method::Message).is_err());
+
+        let other = "0x2222222222222222222222222222222222222222"
+            .parse()
+            .unwrap();
+        assert!(policy.check_method(other, Method::Message).is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn origin_policies() {
+        let (visible, hidden) = (Address([0x11; 20]), Address([0x22; 20]));
+        let policies = serde_json::from_value::<Policies>(json!({
+            "origin_policies": {
+                "https://app.example.com/": {
+                    "accounts": [visible.to_string()],
+                    "methods": ["transaction"],
+                    "max_value": "1000",
+                },
+                "*": { "methods": [] },
+            },
+        }))
+        .unwrap();
+        let policy = Policy::new((), policies, None).unwrap();
+        let transaction = |value: u64| {
+            serde_json::from_value::<TransactionRequest>(json!({
+                "from": visible.to_string(),
+                "to": visible.to_string(),
+                "value": Quantity(U256::from(value)),
+                "gas": "0x5208",
+                "gasPrice": "0x1",
+                "nonce": "0x0",
+                "chainId": "0x1",
+            }))
+            .unwrap()
+            .into_filled()
+            .unwrap()
+            .1
+        };
+        let context = |origin: Option<&str>| RequestContext {
+            method: "eth_signTransaction".to_owned(),
+            id: Id::Number(1.into()),
+            client_ip: None,
+            origin: origin.map(String::from),
+            identity: None,
+            timestamp: 0,
+        };
+
+        context(Some("https://app.example.com"))
+            .scope(async {
+                assert_eq!(policy.origin_policy().unwrap().2, Some(&[visible][..]));
+                assert!(policy
+                    .check_transaction(visible, &transaction(1000))
+                    .is_ok());
+                assert!(policy
+                    .check_transaction(visible, &transaction(1001))
+                    .is_err());
+                assert!(policy.check_method(visible, Method::Message).is_err());
+                assert!(policy.check_method(hidden, Method::Transaction).is_err());
+            })
+            .await;
+        context(Some("https://other.example.com"))
+            .scope(async {
+                assert!(policy.check_method(visible, Method::Transaction).is_err());
+            })
+            .await;
+        context(None)
+            .scope(async {
+                assert!(policy.origin_policy().is_none());
+                assert!(policy.check_method(hidden, Method::Message).is_ok());
+            })
+            .await;
+    }
+
+    #[rocket::async_test]
+    async fn hides_suggestions_outside_origin_scope() {
+        let mnemonic = "test test test test test test test test test test test junk"
+            .parse()
+            .unwrap();
+        let wallet = Wallet::new(&mnemonic, "", 1).unwrap();
+        let account = wallet.accounts()[0];
+        // A typo of the managed account, which the origin is scoped to.
+        let typo = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92267"
+            .parse::<Address>()
+            .unwrap();
+        let policies = serde_json::from_value::<Policies>(json!({
+            "origin_policies": {
+                "https://app.example.com": { "accounts": [typo.to_string()] },
+            },
+        }))
+        .unwrap();
+        let policy = Policy::new(wallet, policies, None).unwrap();
+        let context = |origin: Option<&str>| RequestContext {
+            method: "eth_sign".to_owned(),
+            id: Id::Number(1.into()),
+            client_ip: None,
+            origin: origin.map(String::from),
+            identity: None,
+            timestamp: 0,
+        };
+        let suggestion = || {
+            let err = policy.sign_message(typo, b"hello").unwrap_err();
+            err.downcast::<UnknownSignerError>().unwrap().suggestion
+        };
+
+        context(Some("https://app.example.com"))
+            .scope(async { assert_eq!(suggestion(), None) })
+            .await;
+        context(None)
+            .scope(async { assert_eq!(suggestion(), Some(account)) })
+            .await;
+    }
+
+    #[test]
+    fn fee_ceilings() {
+        let account = "0x1111111111111111111111111111111111111111";
+        let other = "0x2222222222222222222222222222222222222222";
+        let policies = serde_json::from_value::<Policies>(json!({
+            "max_fee_per_gas": "100",
+            "max_gas_price": "50",
+            "account_policies": {
+                account: { "max_fee_per_gas": "200" },
+            },
+        }))
+        .unwrap();
+        let policy = Policy::new((), policies, None).unwrap();
+
+        let transaction = |from: &str, fees: Value| {
+            let mut request = json!({
+                "from": from,
+                "to": from,
+                "gas": "0x5208",
+                "nonce": "0x0",
+                "chainId": "0x1",
+            });
+            request
+                .as_object_mut()
+                .unwrap()
+                .extend(fees.as_object().unwrap().clone());
+            serde_json::from_value::<TransactionRequest>(request)
+                .unwrap()
+                .into_filled()
+                .unwrap()
+        };
+        let eip1559 = |from, fee: u64| {
+            transaction(
+                from,
+                json!({ "maxFeePerGas": Quantity(U256::from(fee)), "maxPriorityFeePerGas": "0x1" }),
+            )
+        };
+        let legacy = |from, price: u64| {
+            transaction(from, json!({ "gasPrice": Quantity(U256::from(price)) }))
+        };
+
+        for ((from, transaction), allowed) in [
+            (eip1559(account, 200), true),
+            (eip1559(account, 201), false),
+            (eip1559(other, 100), true),
+            (eip1559(other, 101), false),
+            (legacy(account, 50), true),
+            (legacy(other, 51), false),
+        ] {
+            assert_eq!(policy.check_fees(from, &transaction).is_ok(), allowed);
+        }
+    }
+
+    #[test]
+    fn token_limits() {
+        let token = "0x7070707070707070707070707070707070707070";
+        let recipient = "0x2222222222222222222222222222222222222222";
+        let policies = serde_json::from_value::<Policies>(json!({
+            "token_limits": {
+                token: {
+                    "max_amount": "1000",
+                    "allowed_recipients": [recipient],
+                },
+            },
+        }))
+        .unwrap();
+        let policy = Policy::new((), policies, None).unwrap();
+
+        let (token, recipient) = (token.parse().unwrap(), recipient.parse().unwrap());
+        let transfer = |to, amount| {
+            abi::call(
+                "transfer(address,uint256)",
+                &[abi::address(to), abi::uint(U256::new(amount))],
+            )
+        };
+        assert!(policy
+            .check_tokens(token, &transfer(recipient, 1000))
+            .is_ok());
+        assert!(policy
+            .check_tokens(token, &transfer(recipient, 1001))
+            .is_err());
+        let other = Address([0x33; 20]);
+        assert!(policy.check_tokens(token, &transfer(other, 1)).is_err());
+        assert!(policy.check_tokens(other, &transfer(other, 1001)).is_ok());
+
+        let call =
+            |function, args: &[abi::Word]| policy.check_tokens(token, &abi::call(function, args));
+        let (amount, spender) = (abi::uint(U256::new(1000)), abi::address(recipient));
+        assert!(call("approve(address,uint256)", &[spender, amount]).is_ok());
+        assert!(call("approve(address,uint256)", &[spender, abi::uint(U256::MAX)]).is_err());
+        assert!(call("approve(address,uint256)", &[abi::address(other), amount]).is_err());
+        assert!(call(
+            "setApprovalForAll(address,bool)",
+            &[spender, abi::uint(U256::ONE)]
+        )
+        .is_err());
+        let from = abi::address(other);
+        assert!(call(
+            "transferFrom(address,address,uint256)",
+            &[from, spender, amount]
+        )
+        .is_ok());
+        assert!(call(
+            "transferFrom(address,address,uint256)",
+            &[from, abi::address(other), amount]
+        )
+        .is_err());
+        assert!(call("transfer(address,uint256)", &[spender]).is_err());
+    }
+
+    #[test]
+    fn approval_guard() {
+        let policies = serde_json::from_value::<Policies>(json!({
+            "approval_guard": {
+                "max_allowance": "1000",
+            },
+        }))
+        .unwrap();
+        let policy = Policy::new((), policies, None).unwrap();
+
+        let (account, token) = (Address([0x11; 20]), Address([0x70; 20]));
+        let guard = |allowance| {
+            let approve = abi::call(
+                "approve(address,uint256)",
+                &[abi::address(Address([0x22; 20])), abi::uint(allowance)],
+            );
+            policy.guard_approvals(
+                "signTransaction",
+                account,
+                (),
+                token::decode(token, &approve),
+            )
+        };
+        assert!(guard(U256::new(1000)).is_ok());
+        assert!(guard(U256::new(1001)).is_err());
+        assert!(guard(U256::MAX).is_err());
+
+        let policies = serde_json::from_value::<Policies>(json!({
+            "approval_guard": { "action": "approve" },
+        }))
+        .unwrap();
+        assert!(Policy::new((), policies, None).is_err());
+    }
+
+    #[test]
+    fn siwe_policy() {
+        let account = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266";
+        let policies = serde_json::from_value::<Policies>(json!({
+            "siwe": {
+                "allowed_domains": ["example.com"],
+                "require_expiration": true,
+                "require_nonce": true,
+            },
+        }))
+        .unwrap();
+        let policy = Policy::new((), policies, None).unwrap();
+        let account = account.parse().unwrap();
+
+        let message = |domain: &str, fields: &str| {
+            format!(
+                "{domain} wants you to sign in with your Ethereum account:\n\
+                 0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266\n\n\
+                 URI: https://{domain}\n\
+                 Version: 1\n\
+                 Chain ID: 1\n\
+                 Issued At: 2021-09-30T16:25:24Z\n\
+                 {fields}"
+            )
+        };
+        let check = |message: String| policy.check_siwe(account, message.as_bytes());
+
+        let fields = "Nonce: 1\nExpiration Time: 2100-01-01T00:00:00Z";
+        assert!(check(message("example.com", fields)).is_ok());
+        assert!(check(message("evil.com", fields)).is_err());
+        assert!(check(message("example.com", "Nonce: 1")).is_err());
+        assert!(check(message(
+            "example.com",
+            "Expiration Time: 2100-01-01T00:00:00Z"
+        ))
+        .is_err());
+        assert!(check(message(
+            "example.com",
+            "Nonce: 1\nExpiration Time: 2022-01-01T00:00:00Z"
+        ))
+        .is_err());
+        assert!(policy
+            .check_siwe(
+                Address([0x42; 20]),
+                message("example.com", fields).as_bytes()
+            )
+            .is_err());
+        assert!(policy.check_siwe(account, b"hello").is_ok());
+    }
+}
@@ -0,0 +1,631 @@
+//! A signer enforcing per-account signing quotas.
+//!
+//! Signatures are counted in fixed hourly and daily windows. Counters are
+//! persisted to disk after every signature, so restarting the node does not
+//! reset them.
+//!
+//! In cluster mode, counters are kept in the shared backend instead, so that
+//! instances serving the same accounts enforce quotas together. The identity
+//! signer uses the same counter store for the quotas of client identities.
+
+use super::{request, PublicKey, Signing, Transaction, TypedData, UserOperation};
+use crate::{backend::SharedBackend, clock, persist};
+use anyhow::{Context as _, Result};
+use hdwallet::account::{Address, Signature};
+use rocket::serde::{json::serde_json, Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use thiserror::Error;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+
+/// Signing quotas.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Quotas {
+    /// The default limits for all accounts.
+    #[serde(flatten)]
+    pub default: Limits,
+
+    /// Limits for individual accounts, by address, replacing the defaults.
+    #[serde(default)]
+    pub accounts: HashMap<String, Limits>,
+}
+
+impl Quotas {
+    /// Returns whether or not any limits are configured.
+    pub fn is_limited(&self) -> bool {
+        self.default.is_limited() || self.accounts.values().any(Limits::is_limited)
+    }
+}
+
+/// Signature count limits.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Limits {
+    /// The maximum number of signatures per hour.
+    pub hourly: Option<u64>,
+    /// The maximum number of signatures per day.
+    pub daily: Option<u64>,
+}
+
+impl Limits {
+    /// Returns whether or not any limit is set.
+    pub fn is_limited(&self) -> bool {
+        self.hourly.is_some() || self.daily.is_some()
+    }
+}
+
+/// Signature counters for an account.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub(super) struct Counters {
+    pub(super) hour: u64,
+    pub(super) hourly: u64,
+    pub(super) day: u64,
+    pub(super) daily: u64,
+}
+
+impl Counters {
+    /// Returns the counters for the current time windows.
+    pub(super) fn current(self, now: u64) -> Self {
+        let (hour, day) = (now / HOUR, now / DAY);
+        Self {
+            hour,
+            hourly: if self.hour == hour { self.hourly } else { 0 },
+            day,
+            daily: if self.day == day { self.daily } else { 0 },
+        }
+    }
+}
+
+/// Current quota usage for an account.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Usage {
+    pub hourly: Count,
+    pub daily: Count,
+}
+
+/// A signature count along with its limit.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Count {
+    pub used: u64,
+    pub limit: Option<u64>,
+}
+
+/// An error indicating that an account's signing quota is exhausted.
+#[derive(Debug, Error)]
+#[error("{period} signing quota of {limit} exceeded for account {account}")]
+pub struct QuotaExceededError {
+    pub account: Address,
+    pub period: &'static str,
+    pub limit: u64,
+}
+
+/// Shared quota state.
+struct State {
+    quotas: HashMap<[u8; 20], Limits>,
+    default: Limits,
+    accounts: Vec<Address>,
+    store: Store,
+}
+
+/// Signature counters by key, persisted to disk or kept in the shared backend
+/// in cluster mode. Keys are account addresses for account quotas, so other
+/// users of the store need to use distinct keys.
+pub(super) struct Store {
+    path: Option<PathBuf>,
+    counters: Mutex<Local>,
+    /// The version of the counters last written to disk. Counters are written
+    /// while holding this lock rather than the counters lock, so that quota
+    /// checks don't wait for the disk.
+    written: Mutex<u64>,
+    cluster: Option<SharedBackend>,
+}
+
+/// Signature counters kept by this node, by key.
+#[derive(Default)]
+struct Local {
+    counters: HashMap<String, Counters>,
+    /// Incremented on every change, so that older snapshots of the counters
+    /// never overwrite newer ones on disk.
+    version: u64,
+}
+
+/// Serialized counters to write to disk.
+struct Snapshot {
+    version: u64,
+    data: Vec<u8>,
+}
+
+impl State {
+    fn limits(&self, account: Address) -> Limits {
+        self.quotas.get(&account.0).copied().unwrap_or(self.default)
+    }
+
+    /// Reserves a signature for the account, failing if its quota is
+    /// exhausted. Returns the time of the reservation, for releasing it.
+    fn reserve(&self, account: Address) -> Result<u64> {
+        self.store.reserve(
+            &account.to_string(),
+            self.limits(account),
+            |period, limit| {
+                QuotaExceededError {
+                    account,
+                    period,
+                    limit,
+                }
+                .into()
+            },
+        )
+    }
+
+    /// Releases a signature reserved at the specified time after a signing
+    /// failure.
+    fn release(&self, account: Address, reserved: u64) {
+        self.store.release(&account.to_string(), reserved);
+    }
+
+    /// Returns the current counters of an account.
+    fn current(&self, account: Address, now: u64) -> Counters {
+        self.store.current(&account.to_string(), now)
+    }
+}
+
+impl Store {
+    /// Opens a counter store, loading counters from and persisting them to the
+    /// specified file, if any, or keeping them in the specified shared backend
+    /// in cluster mode.
+    pub(super) fn open(path: Option<&Path>, cluster: Option<SharedBackend>) -> Result<Self> {
+        let counters = match path {
+            Some(path) if path.exists() => serde_json::from_slice(
+                &fs::read(path)
+                    .with_context(|| format!("failed to read quota file {}", path.display()))?,
+            )
+            .with_context(|| format!("invalid quota file {}", path.display()))?,
+            _ => HashMap::new(),
+        };
+        Ok(Self {
+            path: path.map(Path::to_owned),
+            counters: Mutex::new(Local {
+                counters,
+                version: 0,
+            }),
+            written: Mutex::new(0),
+            cluster,
+        })
+    }
+
+    /// Reserves a signature for the key, failing with the error returned by
+    /// `exceeded` for the period and limit if its quota is exhausted. Returns
+    /// the time of the reservation, for releasing it.
+    pub(super) fn reserve(
+        &self,
+        key: &str,
+        limits: Limits,
+        exceeded: impl FnOnce(&'static str, u64) -> anyhow::Error,
+    ) -> Result<u64> {
+        let now = clock::now();
+        if let Some(backend) = &self.cluster {
+            reserve_shared(backend, key, limits, now, exceeded)?;
+            return Ok(now);
+        }
+        let mut local = self.counters.lock().unwrap();
+        let counter = local.counters.entry(key.to_owned()).or_default();
+        let current = counter.current(now);
+
+        for (period, used, limit) in [
+            ("hourly", current.hourly, limits.hourly),
+            ("daily", current.daily, limits.daily),
+        ] {
+            if let Some(limit) = limit {
+                if used >= limit {
+                    return Err(exceeded(period, limit));
+                }
+            }
+        }
+
+        *counter = Counters {
+            hourly: current.hourly + 1,
+            daily: current.daily + 1,
+            ..current
+        };
+        let snapshot = self.snapshot(&mut local);
+        drop(local);
+
+        // Don't count signatures that could be forgotten on restart.
+        if let Err(err) = self.persist(snapshot) {
+            self.release(key, now);
+            return Err(err);
+        }
+        Ok(now)
+    }
+
+    /// Releases a signature reserved at the specified time after a signing
+    /// failure, from the windows it was counted in.
+    pub(super) fn release(&self, key: &str, reserved: u64) {
+        if let Some(backend) = &self.cluster {
+            release_shared(backend, key, &windows(reserved));
+            return;
+        }
+        let mut local = self.counters.lock().unwrap();
+        if let Some(counter) = local.counters.get_mut(key) {
+            if counter.hour == reserved / HOUR {
+                counter.hourly = counter.hourly.saturating_sub(1);
+            }
+            if counter.day == reserved / DAY {
+                counter.daily = counter.daily.saturating_sub(1);
+            }
+        }
+        let snapshot = self.snapshot(&mut local);
+        drop(local);
+        if let Err(err) = self.persist(snapshot) {
+            tracing::warn!(?err, "failed to persist signing quota counters");
+        }
+    }
+
+    /// Returns the current counters of a key.
+    pub(super) fn current(&self, key: &str, now: u64) -> Counters {
+        let backend = match &self.cluster {
+            Some(backend) => backend,
+            None => {
+                return self
+                    .counters
+                    .lock()
+                    .unwrap()
+                    .counters
+                    .get(key)
+                    .copied()
+                    .unwrap_or_default()
+                    .current(now)
+            }
+        };
+        let [hourly, daily] = windows(now).map(|window| match backend.get(&window.key(key)) {
+            Ok(used) => used.and_then(|used| used.parse().ok()).unwrap_or_default(),
+            Err(err) => {
+                tracing::warn!(?err, "failed to read shared signing quota");
+                0
+            }
+        });
+        Counters {
+            hourly,
+            daily,
+            ..Counters::default().current(now)
+        }
+    }
+
+    /// Takes a snapshot of changed counters for writing them to disk, if
+    /// persistence is enabled.
+    fn snapshot(&self, local: &mut Local) -> Option<Snapshot> {
+        self.path.as_ref()?;
+        local.version += 1;
+        Some(Snapshot {
+            version: local.version,
+            data: serde_json::to_vec(&local.counters).ok()?,
+        })
+    }
+
+    /// Writes a snapshot of the counters to disk, unless a newer one was
+    /// already written.
+    fn persist(&self, snapshot: Option<Snapshot>) -> Result<()> {
+        let (path, snapshot) = match (&self.path, snapshot) {
+            (Some(path), Some(snapshot)) => (path, snapshot),
+            _ => return Ok(()),
+        };
+        let mut written = self.written.lock().unwrap();
+        if snapshot.version <= *written {
+            return Ok(());
+        }
+        persist::write_atomic(path, snapshot.data)
+            .with_context(|| format!("failed to write quota file {}", path.display()))?;
+        *written = snapshot.version;
+        Ok(())
+    }
+}
+
+/// Wrapping signer that enforces signing quotas.
+pub struct Quota<S> {
+    state: Arc<State>,
+    inner: S,
+}
+
+impl<S> Quota<S>
+where
+    S: Signing,
+{
+    /// Creates a new quota signer wrapping the specified signer. Counters are
+    /// loaded from and persisted to the specified file, if any, or kept in
+    /// the specified shared backend in cluster mode.
+    pub fn new(
+        inner: S,
+        quotas: Quotas,
+        path: Option<&Path>,
+        cluster: Option<SharedBackend>,
+    ) -> Result<Self> {
+        let accounts = quotas
+            .accounts
+            .iter()
+            .map(|(account, limits)| {
+                let account = account
+                    .parse::<Address>()
+                    .with_context(|| format!("invalid quota account address {account}"))?;
+                Ok((account.0, *limits))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            state: Arc::new(State {
+                quotas: accounts,
+                default: quotas.default,
+                accounts: inner.accounts().to_vec(),
+                store: Store::open(path, cluster)?,
+            }),
+            inner,
+        })
+    }
+
+    /// Returns a handle for reading current quota usage.
+    pub fn handle(&self) -> Handle {
+        Handle(self.state.clone())
+    }
+
+    /// Signs with the inner signer if the account's quota allows it.
+    fn sign(
+        &self,
+        account: Address,
+        sign: impl FnOnce(&S) -> Result<Signature>,
+    ) -> Result<Signature> {
+        let reserved = self.state.reserve(account)?;
+        request::decide("quota", "within signing quota");
+        let result = sign(&self.inner);
+        if result.is_err() {
+            self.state.release(account, reserved);
+        }
+        result
+    }
+}
+
+/// A handle for reading the current quota usage of a running quota signer.
+#[derive(Clone)]
+pub struct Handle(Arc<State>);
+
+impl Handle {
+    /// Returns the current quota usage of all accounts.
+    pub fn usage(&self) -> HashMap<String, Usage> {
+        let now = clock::now();
+        self.0
+            .accounts
+            .iter()
+            .map(|&account| {
+                let limits = self.0.limits(account);
+                let current = self.0.current(account, now);
+                (
+                    account.to_string(),
+                    Usage {
+                        hourly: Count {
+                            used: current.hourly,
+                            limit: limits.hourly,
+                        },
+                        daily: Count {
+                            used: current.daily,
+                            limit: limits.daily,
+                        },
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+/// A time window of the shared counters.
+#[derive(Clone, Copy)]
+struct Window {
+    period: &'static str,
+    index: u64,
+    length: u64,
+}
+
+impl Window {
+    /// Returns the backend key of a counter for this window.
+    fn key(self, key: &str) -> String {
+        format!("hdnode:quota:{key}:{}:{}", self.period, self.index)
+    }
+}
+
+/// Returns the current hourly and daily windows.
+fn windows(now: u64) -> [Window; 2] {
+    [("hourly", HOUR), ("daily", DAY)].map(|(period, length)| Window {
+        period,
+        index: now / length,
+        length,
+    })
+}
+
+/// Reserves a signature for the key in the shared counters of the windows at
+/// `now`, failing if its quota is exhausted.
+fn reserve_shared(
+    backend: &SharedBackend,
+    key: &str,
+    limits: Limits,
+    now: u64,
+    exceeded: impl FnOnce(&'static str, u64) -> anyhow::Error,
+) -> Result<()> {
+    let mut reserved = Vec::new();
+    for (window, limit) in windows(now).into_iter().zip([limits.hourly, limits.daily]) {
+        let used = backend.increment(
+            &window.key(key),
+            1,
+            Some(Duration::from_secs(window.length)),
+        )?;
+        reserved.push(window);
+        if let Some(limit) = limit {
+            if u64::try_from(used).is_ok_and(|used| used > limit) {
+                release_shared(backend, key, &reserved);
+                return Err(exceeded(window.period, limit));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Releases a signature reserved in the shared counters of the specified
+/// windows.
+fn release_shared(backend: &SharedBackend, key: &str, windows: &[Window]) {
+    for window in windows {
+        // Counters that expired in the meantime are recreated by decrementing
+        // them, so they need to expire again.
+        let ttl = Duration::from_secs(window.length);
+        if let Err(err) = backend.increment(&window.key(key), -1, Some(ttl)) {
+            tracing::warn!(?err, "failed to release shared signing quota");
+        }
+    }
+}
+
+impl<S> Signing for Quota<S>
+where
+    S: Signing,
+{
+    fn accounts(&self) -> &[Address] {
+        self.inner.accounts()
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.inner.public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.sign(account, |inner| inner.sign_hash(account, hash))
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        self.sign(account, |inner| inner.sign_message(account, message))
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        self.sign(account, |inner| {
+            inner.sign_transaction(account, transaction)
+        })
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        self.sign(account, |inner| inner.sign_typed_data(account, typed_data))
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.sign(account, |inner| {
+            inner.sign_user_operation(account, user_operation)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{call, call_as_admin, client_with, MockUpstream, ACCOUNT};
+    use rocket::serde::json::serde_json::json;
+
+    #[test]
+    fn counters_reset_with_windows() {
+        let counters = Counters {
+            hour: 10,
+            hourly: 3,
+            day: 0,
+            daily: 7,
+        };
+        let current = counters.current(10 * HOUR + 1);
+        assert_eq!((current.hourly, current.daily), (3, 7));
+        let current = counters.current(11 * HOUR);
+        assert_eq!((current.hourly, current.daily), (0, 7));
+        let current = counters.current(DAY);
+        assert_eq!((current.hourly, current.daily), (0, 0));
+    }
+
+    #[test]
+    fn shares_counters_in_cluster() {
+        let backend: SharedBackend = Arc::new(crate::backend::memory::Memory::new());
+        let account = Address([0x42; 20]);
+        let state = || State {
+            quotas: HashMap::new(),
+            default: Limits {
+                hourly: Some(2),
+                daily: None,
+            },
+            accounts: vec![account],
+            store: Store::open(None, Some(backend.clone())).unwrap(),
+        };
+        let (first, second) = (state(), state());
+
+        first.reserve(account).unwrap();
+        second.reserve(account).unwrap();
+        assert!(first
+            .reserve(account)
+            .unwrap_err()
+            .is::<QuotaExceededError>());
+        assert_eq!(second.current(account, clock::now()).hourly, 2);
+
+        // Releasing a reservation from earlier windows leaves the current
+        // windows alone.
+        second.release(account, clock::now() - DAY);
+        assert_eq!(second.current(account, clock::now()).hourly, 2);
+        second.release(account, clock::now());
+        first.reserve(account).unwrap();
+        assert_eq!(first.current(account, clock::now()).daily, 2);
+    }
+
+    #[test]
+    fn rolls_back_unpersisted_counters() {
+        let account = Address([0x42; 20]);
+        let state = State {
+            quotas: HashMap::new(),
+            default: Limits::default(),
+            accounts: vec![account],
+            store: Store::open(Some(Path::new("/nonexistent/hdnode-quotas.json")), None).unwrap(),
+        };
+
+        assert!(state.reserve(account).is_err());
+        assert_eq!(state.current(account, clock::now()).hourly, 0);
+    }
+
+    #[rocket::async_test]
+    async fn enforces_signing_quotas() {
+        let path = std::env::temp_dir().join(format!("hdnode-quotas-{}.json", std::process::id()));
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment
+                .merge(("quotas", json!({ "hourly": 1 })))
+                .merge(("quota_file", &path))
+                .merge(("admin_token", "secret"))
+        })
+        .await;
+
+        let message = json!([ACCOUNT, "0x68656c6c6f"]);
+        let signed = call(&client, "eth_sign", message.clone()).await;
+        let denied = call(&client, "eth_sign", message).await;
+        let unauthorized = call(&client, "hdnode_quotas", json!([])).await;
+        let quotas = call_as_admin(&client, "hdnode_quotas", json!([])).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(signed["result"].is_string());
+        assert_eq!(denied["error"]["code"], -32005);
+        assert_eq!(unauthorized["error"]["code"], 4100);
+        let (_, usage) = quotas["result"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .find(|(account, _)| account.eq_ignore_ascii_case(ACCOUNT))
+            .unwrap();
+        assert_eq!(usage["hourly"], json!({ "used": 1, "limit": 1 }));
+    }
+}
@@ -0,0 +1,272 @@
+//! A signer issuing signed receipts for signing operations.
+//!
+//! Receipts record the hash of the signed payload, the account, the time and
+//! the decisions made by the other signers in the pipeline, and are signed
+//! with a dedicated receipt key. This gives downstream systems an attestable
+//! record of why a signature exists. Receipts are kept in memory and can be
+//! looked up by signature with the `hdnode_getSigningReceipt` RPC method.
+
+use super::{
+    request::{self, Decision},
+    PublicKey, Signing, Transaction, TypedData, UserOperation,
+};
+use crate::{
+    clock,
+    serialization::{Bytes, Str},
+};
+use anyhow::Result;
+use hdwallet::{
+    account::{Address, PrivateKey, Signature},
+    message::EthereumMessage,
+};
+use rocket::serde::{json::serde_json, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+/// The number of receipts kept when not configured.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// A record of a signing operation.
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Receipt {
+    /// The signing operation.
+    pub operation: &'static str,
+    /// The hash of the signed payload.
+    pub payload_hash: Bytes<[u8; 32]>,
+    /// The account that signed the payload.
+    pub account: Str<Address>,
+    /// The signature of the payload.
+    pub signature: String,
+    /// The UNIX timestamp in seconds at which the payload was signed.
+    pub timestamp: u64,
+    /// The decisions made by the signers in the pipeline.
+    pub decisions: Vec<Decision>,
+    /// The version of hdnode that signed the payload.
+    pub version: &'static str,
+}
+
+/// A receipt signed with the receipt key.
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct SignedReceipt {
+    /// The receipt.
+    pub receipt: Receipt,
+    /// The address of the receipt key.
+    pub signer: Str<Address>,
+    /// The EIP-191 signature of the receipt's JSON encoding by the receipt
+    /// key.
+    pub signature: String,
+}
+
+/// Issued receipts, by signature.
+struct Receipts {
+    capacity: usize,
+    order: VecDeque<String>,
+    receipts: HashMap<String, SignedReceipt>,
+}
+
+/// Wrapping signer that issues signed receipts for signing operations.
+pub struct ReceiptSigner<S> {
+    key: PrivateKey,
+    receipts: Arc<Mutex<Receipts>>,
+    inner: S,
+}
+
+impl<S> ReceiptSigner<S> {
+    /// Creates a new receipt issuing signer wrapping the specified signer,
+    /// signing receipts with the specified key and keeping up to `capacity` of
+    /// the most recent receipts.
+    pub fn new(inner: S, key: PrivateKey, capacity: usize) -> Self {
+        Self {
+            key,
+            receipts: Arc::new(Mutex::new(Receipts {
+                capacity,
+                order: VecDeque::new(),
+                receipts: HashMap::new(),
+            })),
+            inner,
+        }
+    }
+
+    /// Returns a handle for looking up issued receipts.
+    pub fn handle(&self) -> Handle {
+        Handle(self.receipts.clone())
+    }
+
+    /// Signs a payload with `sign`, issuing a receipt for the signature.
+    fn sign(
+        &self,
+        operation: &'static str,
+        account: Address,
+        payload_hash: [u8; 32],
+        sign: impl FnOnce(&S) -> Result<Signature>,
+    ) -> Result<Signature> {
+        // Previews keep their decisions, as they are reported to the caller.
+        if request::is_preview() {
+            return sign(&self.inner);
+        }
+        let result = sign(&self.inner);
+        // Take the decisions even if signing fails, so that they don't end up
+        // in the receipt of a later signing operation in the same request.
+        let decisions = request::take_decisions();
+        let signature = result?;
+
+        let receipt = Receipt {
+            operation,
+            payload_hash: Bytes(payload_hash),
+            account: Str(account),
+            signature: signature.to_string(),
+            timestamp: clock::now(),
+            decisions,
+            version: env!("CARGO_PKG_VERSION"),
+        };
+        let encoded = serde_json::to_vec(&receipt)?;
+        let receipt = SignedReceipt {
+            receipt,
+            signer: Str(self.key.address()),
+            signature: self
+                .key
+                .sign(EthereumMessage(&encoded).signing_message())
+                .to_string(),
+        };
+
+        let mut receipts = self.receipts.lock().unwrap();
+        if receipts.order.len() >= receipts.capacity {
+            if let Some(oldest) = receipts.order.pop_front() {
+                receipts.receipts.remove(&oldest);
+            }
+        }
+        let key = receipt.receipt.signature.clone();
+        receipts.order.push_back(key.clone());
+        receipts.receipts.insert(key, receipt);
+
+        Ok(signature)
+    }
+}
+
+/// A handle for looking up issued receipts.
+#[derive(Clone)]
+pub struct Handle(Arc<Mutex<Receipts>>);
+
+impl Handle {
+    /// Returns the receipt issued for a signature, if it is still kept.
+    pub fn get(&self, signature: &str) -> Option<SignedReceipt> {
+        self.0
+            .lock()
+            .unwrap()
+            .receipts
+            .get(&signature.to_lowercase())
+            .cloned()
+    }
+
+    /// Returns up to `count` of the most recently issued receipts, newest
+    /// first.
+    pub fn recent(&self, count: usize) -> Vec<SignedReceipt> {
+        let receipts = self.0.lock().unwrap();
+        receipts
+            .order
+            .iter()
+            .rev()
+            .take(count)
+            .filter_map(|signature| receipts.receipts.get(signature).cloned())
+            .collect()
+    }
+}
+
+impl<S> Signing for ReceiptSigner<S>
+where
+    S: Signing,
+{
+    fn accounts(&self) -> &[Address] {
+        self.inner.accounts()
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.inner.public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.sign("signHash", account, hash, |inner| {
+            inner.sign_hash(account, hash)
+        })
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        let hash = EthereumMessage(message).signing_message();
+        self.sign("signMessage", account, hash, |inner| {
+            inner.sign_message(account, message)
+        })
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        self.sign(
+            "signTransaction",
+            account,
+            transaction.signing_message(),
+            |inner| inner.sign_transaction(account, transaction),
+        )
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        self.sign(
+            "signTypedData",
+            account,
+            typed_data.signing_message(),
+            |inner| inner.sign_typed_data(account, typed_data),
+        )
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.sign(
+            "signUserOperation",
+            account,
+            user_operation.hash(),
+            |inner| inner.sign_user_operation(account, user_operation),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{call, client_with, MockUpstream, ACCOUNT};
+    use rocket::serde::json::serde_json::json;
+
+    #[rocket::async_test]
+    async fn issues_signing_receipts() {
+        let upstream = MockUpstream::start().await;
+        let client = client_with(&upstream, |figment| {
+            figment
+                .merge((
+                    "signers",
+                    json!(["wallet", "log", "quota", "validator", "policy", "receipt"]),
+                ))
+                .merge((
+                    "receipt_key",
+                    "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d",
+                ))
+        })
+        .await;
+
+        let signed = call(&client, "eth_sign", json!([ACCOUNT, "0x68656c6c6f"])).await;
+        let signature = signed["result"].clone();
+        let receipt = call(&client, "hdnode_getSigningReceipt", json!([signature])).await;
+        let unknown = call(&client, "hdnode_getSigningReceipt", json!(["0x"])).await;
+
+        let receipt = &receipt["result"];
+        assert_eq!(receipt["receipt"]["signature"], signature);
+        assert_eq!(receipt["receipt"]["operation"], "signMessage");
+        let decisions = receipt["receipt"]["decisions"].as_array().unwrap();
+        assert!(decisions
+            .iter()
+            .any(|decision| decision["signer"] == "policy"));
+        assert!(receipt["signature"].is_string());
+        assert!(unknown["result"].is_null());
+    }
+}
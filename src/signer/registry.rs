@@ -0,0 +1,707 @@
+//! A registry of signer factories.
+//!
+//! The signer is configured as a pipeline of signer specifications of the form
+//! `kind` or `kind:argument`. The first specification creates the signing
+//! backend, and each following specification creates a decorator that wraps
+//! the signer built so far. For example, `["wallet", "log", "validator"]`
+//! creates a validating signer around a logging signer around an HD wallet.
+
+use super::{
+    anomaly::{Anomalies, Anomalous},
+    approval::{Approval, Approvals, Approver},
+    audit::{self, sink::Sink, Audit},
+    canary::{Canaries, Canary},
+    dryrun::DryRun,
+    email::{Email, Emailer},
+    identity::{self, Identified},
+    log_recorder::LogRecorder,
+    notify::{Notifier, Notify},
+    opa::Opa,
+    policy::{Policies, Policy},
+    quota::{self, Quota, Quotas},
+    receipt::{self, ReceiptSigner},
+    replay::{Replay, ReplayProtection},
+    validator::{self, store::Store, Validator},
+    verify::Verifying,
+    wallet::Wallet,
+    web3signer::Web3Signer,
+    BoxSigner, Signing as _,
+};
+use crate::{backend::SharedBackend, node::eth::Eth, selectors::Selectors, serialization::Str};
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use hdwallet::{
+    account::{Address, PrivateKey},
+    mnemonic::Mnemonic,
+};
+use rocket::{
+    figment::Figment,
+    futures::future::BoxFuture,
+    serde::{Deserialize, DeserializeOwned},
+};
+use secp256k1::SecretKey;
+use std::{collections::HashMap, fs, path::PathBuf, sync::Arc, time::Duration};
+
+/// Returns the signer pipeline used when none is configured.
+pub fn default_pipeline() -> Vec<String> {
+    ["wallet", "log", "quota", "validator", "policy"]
+        .map(String::from)
+        .to_vec()
+}
+
+/// A factory for a signing backend.
+pub type Backend = for<'a> fn(&'a Context<'a>, Option<&'a str>) -> BoxFuture<'a, Result<BoxSigner>>;
+
+/// A factory for a signer decorator, wrapping the signer built so far.
+pub type Decorator = fn(&mut Context<'_>, Option<&str>, BoxSigner) -> Result<BoxSigner>;
+
+/// The context in which signers are built.
+pub struct Context<'a> {
+    /// The node configuration, from which factories read their settings.
+    pub figment: &'a Figment,
+    /// The remote node.
+    pub remote: &'a Eth,
+    /// Validators created by the pipeline, along with their explicitly
+    /// specified Lua module, if any.
+    pub validators: Vec<(validator::Handle, Option<PathBuf>)>,
+    /// The quota signer created by the pipeline, if any.
+    pub quotas: Option<quota::Handle>,
+    /// The receipt signer created by the pipeline, if any.
+    pub receipts: Option<receipt::Handle>,
+    /// The audit log of the pipeline, if any.
+    pub audit: Option<audit::Handle>,
+    /// The key-value store shared by validators, opened by the first one.
+    pub store: Option<Store>,
+    /// The backend shared with other instances in cluster mode.
+    pub cluster: Option<SharedBackend>,
+    /// The function signature database of the node.
+    pub selectors: Selectors,
+    /// The approvals of the approvers bound by the pipeline.
+    pub approvals: Approvals,
+}
+
+impl<'a> Context<'a> {
+    /// Creates a new context for building signers.
+    pub fn new(figment: &'a Figment, remote: &'a Eth) -> Self {
+        Self {
+            figment,
+            remote,
+            validators: Vec::new(),
+            quotas: None,
+            receipts: None,
+            audit: None,
+            store: None,
+            cluster: None,
+            selectors: Selectors::default(),
+            approvals: Approvals::default(),
+        }
+    }
+
+    /// Extracts factory settings from the node configuration.
+    pub fn config<T>(&self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.figment
+            .extract()
+            .context("invalid signer configuration")
+    }
+}
+
+/// A registry of signer factories by kind.
+pub struct Registry {
+    backends: HashMap<&'static str, Backend>,
+    decorators: HashMap<&'static str, Decorator>,
+}
+
+impl Registry {
+    /// Creates a new empty registry.
+    pub fn new() -> Self {
+        Self {
+            backends: HashMap::new(),
+            decorators: HashMap::new(),
+        }
+    }
+
+    /// Registers a signing backend factory.
+    pub fn register_backend(&mut self, kind: &'static str, factory: Backend) -> &mut Self {
+        self.backends.insert(kind, factory);
+        self
+    }
+
+    /// Registers a signer decorator factory.
+    pub fn register_decorator(&mut self, kind: &'static str, factory: Decorator) -> &mut Self {
+        self.decorators.insert(kind, factory);
+        self
+    }
+
+    /// Builds a signer from a pipeline of signer specifications.
+    pub async fn build<S>(&self, pipeline: &[S], context: &mut Context<'_>) -> Result<BoxSigner>
+    where
+        S: AsRef<str>,
+    {
+        let (backend, decorators) = pipeline.split_first().context("empty signer pipeline")?;
+
+        let (kind, argument) = parse(backend.as_ref());
+        let factory = match self.backends.get(kind) {
+            Some(factory) => factory,
+            None if self.decorators.contains_key(kind) => {
+                bail!("signer pipeline must start with a backend, found decorator {kind:?}")
+            }
+            None => bail!("unknown signer backend {kind:?}"),
+        };
+        let mut signer = factory(context, argument)
+            .await
+            .with_context(|| format!("failed to create {kind:?} signer"))?;
+
+        for decorator in decorators {
+            let (kind, argument) = parse(decorator.as_ref());
+            let factory = self
+                .decorators
+                .get(kind)
+                .with_context(|| format!("unknown signer decorator {kind:?}"))?;
+            signer = factory(context, argument, signer)
+                .with_context(|| format!("failed to create {kind:?} signer"))?;
+        }
+
+        Ok(signer)
+    }
+}
+
+impl Default for Registry {
+    /// Creates a registry with the built-in signers.
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry
+            .register_backend("wallet", wallet)
+            .register_backend("web3signer", web3signer)
+            .register_backend("dryrun", dryrun)
+            .register_decorator("log", log)
+            .register_decorator("notify", notify)
+            .register_decorator("opa", opa)
+            .register_decorator("anomaly", anomaly)
+            .register_decorator("approval", approval)
+            .register_decorator("audit", audit)
+            .register_decorator("canary", canary)
+            .register_decorator("email", email)
+            .register_decorator("identity", identity)
+            .register_decorator("policy", policy)
+            .register_decorator("quota", quota)
+            .register_decorator("receipt", receipt)
+            .register_decorator("replay", replay)
+            .register_decorator("validator", validator)
+            .register_decorator("verify", verify);
+        registry
+    }
+}
+
+/// Parses a signer specification into its kind and optional argument.
+fn parse(spec: &str) -> (&str, Option<&str>) {
+    match spec.split_once(':') {
+        Some((kind, argument)) => (kind, Some(argument)),
+        None => (spec, None),
+    }
+}
+
+/// Creates a wallet signer with HD accounts derived from the `mnemonic`,
+/// `password`, `account_count` and `account_gap_limit` settings and from each
+/// group of these settings in `mnemonics`, and accounts imported from the
+/// `private_keys` and `private_key_files` settings.
+fn wallet<'a>(context: &'a Context<'a>, _: Option<&'a str>) -> BoxFuture<'a, Result<BoxSigner>> {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Config {
+        mnemonic: Option<Str<Mnemonic>>,
+        #[serde(default)]
+        password: String,
+        account_count: Option<usize>,
+        account_gap_limit: Option<usize>,
+        #[serde(default)]
+        mnemonics: Vec<MnemonicConfig>,
+        #[serde(default)]
+        private_keys: Vec<String>,
+        #[serde(default)]
+        private_key_files: Vec<PathBuf>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct MnemonicConfig {
+        mnemonic: Str<Mnemonic>,
+        #[serde(default)]
+        password: String,
+        account_count: usize,
+        account_gap_limit: Option<usize>,
+    }
+
+    Box::pin(async move {
+        let config = context.config::<Config>()?;
+        let mut groups = Vec::new();
+        if let Some(mnemonic) = config.mnemonic {
+            groups.push(MnemonicConfig {
+                mnemonic,
+                password: config.password,
+                account_count: config
+                    .account_count
+                    .context("missing account count for mnemonic")?,
+                account_gap_limit: config.account_gap_limit,
+            });
+        }
+        groups.extend(config.mnemonics);
+
+        let mut wallet = Wallet::from_private_keys([]);
+        for group in groups {
+            wallet.extend(match group.account_gap_limit {
+                Some(gap_limit) => {
+                    Wallet::discover(
+                        &group.mnemonic,
+                        &group.password,
+                        group.account_count,
+                        gap_limit,
+                        context.remote,
+                    )
+                    .await?
+                }
+                None => Wallet::new(&group.mnemonic, &group.password, group.account_count)?,
+            });
+        }
+        wallet.extend(Wallet::from_secret_keys(private_keys(
+            &config.private_keys,
+            &config.private_key_files,
+        )?));
+
+        ensure!(
+            !wallet.accounts().is_empty(),
+            "wallet has no accounts, configure a mnemonic or private keys",
+        );
+        Ok(Box::new(wallet) as BoxSigner)
+    })
+}
+
+/// Reads raw private keys, either specified as hex strings or from files
+/// containing one hex private key per line. Empty lines and lines starting
+/// with `#` are ignored in files.
+fn private_keys(keys: &[String], files: &[PathBuf]) -> Result<Vec<SecretKey>> {
+    // Never include the key material in errors, since they get logged.
+    let parse = |key: &str| {
+        let key = key.trim();
+        key.strip_prefix("0x").unwrap_or(key).parse::<SecretKey>()
+    };
+    let mut private_keys = keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| parse(key).map_err(|_| anyhow!("invalid private key at index {i}")))
+        .collect::<Result<Vec<_>>>()?;
+
+    for file in files {
+        let contents = fs::read_to_string(file)
+            .with_context(|| format!("failed to read private key file {}", file.display()))?;
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            private_keys.push(parse(line).map_err(|_| {
+                anyhow!("invalid private key in {} line {}", file.display(), i + 1)
+            })?);
+        }
+    }
+
+    Ok(private_keys)
+}
+
+/// Creates a signer forwarding to a remote Web3Signer, with its URL specified
+/// as the argument.
+fn web3signer<'a>(
+    context: &'a Context<'a>,
+    argument: Option<&'a str>,
+) -> BoxFuture<'a, Result<BoxSigner>> {
+    Box::pin(async move {
+        let url = argument
+            .context("missing remote signer URL")?
+            .parse()
+            .context("invalid remote signer URL")?;
+        let signer = Web3Signer::connect(url, context.remote.options().clone()).await?;
+        Ok(Box::new(signer) as BoxSigner)
+    })
+}
+
+/// Creates a signer producing fake signatures for dry runs, for the accounts
+/// from the `dry_run_accounts` setting.
+fn dryrun<'a>(context: &'a Context<'a>, _: Option<&'a str>) -> BoxFuture<'a, Result<BoxSigner>> {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Config {
+        #[serde(default)]
+        dry_run_accounts: Vec<Str<Address>>,
+    }
+
+    Box::pin(async move {
+        let config = context.config::<Config>()?;
+        ensure!(
+            !config.dry_run_accounts.is_empty(),
+            "dry run signer has no accounts, configure dry_run_accounts",
+        );
+        tracing::warn!("signing in dry run mode, signatures are fake");
+        let accounts = config.dry_run_accounts.into_iter().map(|a| a.0).collect();
+        Ok(Box::new(DryRun::new(accounts)) as BoxSigner)
+    })
+}
+
+/// Wraps a signer to log all signing operations.
+fn log(context: &mut Context<'_>, _: Option<&str>, inner: BoxSigner) -> Result<BoxSigner> {
+    Ok(Box::new(LogRecorder::new(inner, context.selectors.clone())))
+}
+
+/// Wraps a signer to verify that its signatures recover to the requested
+/// account before returning them.
+fn verify(_: &mut Context<'_>, _: Option<&str>, inner: BoxSigner) -> Result<BoxSigner> {
+    Ok(Box::new(Verifying(inner)))
+}
+
+/// Approval settings.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct ApprovalConfig {
+    #[serde(default = "default_approval_timeout")]
+    approval_timeout: u64,
+}
+
+fn default_approval_timeout() -> u64 {
+    60
+}
+
+/// Binds an approver on the operator socket specified as the argument, if any,
+/// with the configured `approval_timeout`.
+fn approver(context: &Context<'_>, argument: Option<&str>) -> Result<Option<Arc<Approver>>> {
+    let socket = match argument {
+        Some(socket) => socket,
+        None => return Ok(None),
+    };
+    let timeout = Duration::from_secs(context.config::<ApprovalConfig>()?.approval_timeout);
+    Ok(Some(context.approvals.bind(socket.as_ref(), timeout)?))
+}
+
+/// Wraps a signer to require operator approval, with the path of the operator
+/// socket specified as the argument. Requests are rejected if they are not
+/// approved within `approval_timeout` seconds, defaulting to a minute.
+fn approval(
+    context: &mut Context<'_>,
+    argument: Option<&str>,
+    inner: BoxSigner,
+) -> Result<BoxSigner> {
+    let socket = argument.context("missing approval socket path")?;
+    let config = context.config::<ApprovalConfig>()?;
+    let approver = context.approvals.bind(
+        socket.as_ref(),
+        Duration::from_secs(config.approval_timeout),
+    )?;
+    Ok(Box::new(Approval::new(inner, approver)))
+}
+
+/// Wraps a signer to detect anomalous signing requests with the `anomalies`
+/// setting. Anomalous requests can be held for operator approval on a Unix
+/// socket specified as the argument, in the same way as the approval signer.
+fn anomaly(
+    context: &mut Context<'_>,
+    argument: Option<&str>,
+    inner: BoxSigner,
+) -> Result<BoxSigner> {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Config {
+        #[serde(default)]
+        anomalies: Anomalies,
+    }
+
+    let config = context.config::<Config>()?;
+    let approver = approver(context, argument)?;
+    Ok(Box::new(
+        Anomalous::new(inner, config.anomalies, approver)?
+            .with_selectors(context.selectors.clone()),
+    ))
+}
+
+/// Wraps a signer to reject and alert on signing requests for the canary
+/// accounts from the `canaries` setting.
+fn canary(context: &mut Context<'_>, _: Option<&str>, inner: BoxSigner) -> Result<BoxSigner> {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Config {
+        #[serde(default)]
+        canaries: Canaries,
+    }
+
+    let config = context.config::<Config>()?;
+    Ok(Box::new(Canary::new(inner, config.canaries)?))
+}
+
+/// Wraps a signer to enforce the account scopes and signing quotas of the
+/// client identities from the `identities` setting. Quota counters are
+/// persisted to the `identity_quota_file`.
+fn identity(context: &mut Context<'_>, _: Option<&str>, inner: BoxSigner) -> Result<BoxSigner> {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Config {
+        identity_quota_file: Option<PathBuf>,
+    }
+
+    let options = context.config::<identity::Options>()?;
+    let config = context.config::<Config>()?;
+    ensure!(
+        !options
+            .identities
+            .values()
+            .any(|identity| identity.limits.is_limited())
+            || config.identity_quota_file.is_some()
+            || context.cluster.is_some(),
+        "identity quotas require an identity_quota_file so that counters survive restarts",
+    );
+    Ok(Box::new(Identified::new(
+        inner,
+        options.identities,
+        options.require_client_identity,
+        config.identity_quota_file.as_deref(),
+        context.cluster.clone(),
+    )?))
+}
+
+/// Wraps a signer to enforce the signing policies from the configuration.
+/// Approvals flagged by the approval guard can be held for operator approval
+/// on a Unix socket specified as the argument.
+fn policy(
+    context: &mut Context<'_>,
+    argument: Option<&str>,
+    inner: BoxSigner,
+) -> Result<BoxSigner> {
+    let approver = approver(context, argument)?;
+    Ok(Box::new(Policy::new(
+        inner,
+        context.config::<Policies>()?,
+        approver,
+    )?))
+}
+
+/// Wraps a signer to enforce the signing quotas from the `quotas` setting,
+/// persisting counters to the `quota_file`, which is required with quotas
+/// unless counters are kept in the cluster backend.
+fn quota(context: &mut Context<'_>, _: Option<&str>, inner: BoxSigner) -> Result<BoxSigner> {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Config {
+        #[serde(default)]
+        quotas: Quotas,
+        quota_file: Option<PathBuf>,
+    }
+
+    let config = context.config::<Config>()?;
+    ensure!(
+        context.quotas.is_none(),
+        "only one quota signer is supported"
+    );
+    ensure!(
+        !config.quotas.is_limited() || config.quota_file.is_some() || context.cluster.is_some(),
+        "signing quotas require a quota_file so that counters survive restarts",
+    );
+    let quota = Quota::new(
+        inner,
+        config.quotas,
+        config.quota_file.as_deref(),
+        context.cluster.clone(),
+    )?;
+    context.quotas = Some(quota.handle());
+    Ok(Box::new(quota))
+}
+
+/// Wraps a signer to record signing operations to the hash-chained
+/// `audit_log` file, signing a checkpoint with the `audit_key` private key
+/// every `audit_checkpoint_interval` entries, and to the `audit_sinks`.
+fn audit(context: &mut Context<'_>, _: Option<&str>, inner: BoxSigner) -> Result<BoxSigner> {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Config {
+        audit_log: Option<PathBuf>,
+        audit_key: Option<String>,
+        audit_checkpoint_interval: Option<u64>,
+        #[serde(default)]
+        audit_sinks: Vec<audit::sink::Config>,
+    }
+
+    let config = context.config::<Config>()?;
+    ensure!(
+        config.audit_log.is_some() || !config.audit_sinks.is_empty(),
+        "missing audit log or sinks"
+    );
+    let mut signer = Audit::new(inner);
+    if let Some(path) = &config.audit_log {
+        ensure!(context.audit.is_none(), "only one audit log is supported");
+        let key = config
+            .audit_key
+            .context("missing audit key")?
+            .parse::<PrivateKey>()
+            .context("invalid audit key")?;
+        signer = signer.with_log(
+            path,
+            key,
+            config
+                .audit_checkpoint_interval
+                .unwrap_or(audit::DEFAULT_CHECKPOINT_INTERVAL),
+        )?;
+        context.audit = signer.handle();
+    }
+    for sink in &config.audit_sinks {
+        signer = signer.with_sink(Sink::new(sink)?);
+    }
+    Ok(Box::new(signer))
+}
+
+/// Wraps a signer to email operators about pending approvals and denied
+/// signing requests with the `email` setting.
+fn email(context: &mut Context<'_>, _: Option<&str>, inner: BoxSigner) -> Result<BoxSigner> {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Config {
+        email: Option<Email>,
+    }
+
+    let config = context.config::<Config>()?;
+    let email = config.email.context("missing email settings")?;
+    Ok(Box::new(Emailer::new(inner, email, &context.approvals)?))
+}
+
+/// Wraps a signer to post signing notifications to the chat channels of the
+/// `notifiers` setting.
+fn notify(context: &mut Context<'_>, _: Option<&str>, inner: BoxSigner) -> Result<BoxSigner> {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Config {
+        #[serde(default)]
+        notifiers: Vec<Notifier>,
+    }
+
+    let config = context.config::<Config>()?;
+    ensure!(!config.notifiers.is_empty(), "missing notifiers");
+    Ok(Box::new(Notify::new(inner, config.notifiers)?))
+}
+
+/// Wraps a signer to issue receipts for signing operations, signed with the
+/// `receipt_key` private key. The `receipt_capacity` most recent receipts are
+/// kept for lookup.
+fn receipt(context: &mut Context<'_>, _: Option<&str>, inner: BoxSigner) -> Result<BoxSigner> {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Config {
+        receipt_key: Option<String>,
+        receipt_capacity: Option<usize>,
+    }
+
+    let config = context.config::<Config>()?;
+    ensure!(
+        context.receipts.is_none(),
+        "only one receipt signer is supported"
+    );
+    let key = config
+        .receipt_key
+        .context("missing receipt key")?
+        .parse::<PrivateKey>()
+        .context("invalid receipt key")?;
+    let signer = ReceiptSigner::new(
+        inner,
+        key,
+        config.receipt_capacity.unwrap_or(receipt::DEFAULT_CAPACITY),
+    );
+    context.receipts = Some(signer.handle());
+    Ok(Box::new(signer))
+}
+
+/// Wraps a signer to record signed messages and typed data with the
+/// `replay_protection` setting, persisting the ledger to the `replay_ledger`
+/// file if specified.
+fn replay(context: &mut Context<'_>, _: Option<&str>, inner: BoxSigner) -> Result<BoxSigner> {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Config {
+        #[serde(default)]
+        replay_protection: ReplayProtection,
+        replay_ledger: Option<PathBuf>,
+    }
+
+    let config = context.config::<Config>()?;
+    Ok(Box::new(Replay::new(
+        inner,
+        config.replay_protection,
+        config.replay_ledger.as_deref(),
+    )?))
+}
+
+/// Wraps a signer with a Lua validator. The module is either specified as the
+/// argument or with the `validator` setting, and runs within the resource
+/// limits from the `validator_limits` setting. Validators share a key-value
+/// store, persisted to the `validator_store` file if specified.
+fn validator(
+    context: &mut Context<'_>,
+    argument: Option<&str>,
+    inner: BoxSigner,
+) -> Result<BoxSigner> {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Config {
+        validator: Option<PathBuf>,
+        #[serde(default)]
+        validator_limits: validator::Limits,
+        validator_store: Option<PathBuf>,
+    }
+
+    let config = context.config::<Config>()?;
+    let explicit = argument.map(PathBuf::from);
+    let module = explicit.clone().or(config.validator);
+    let store = match (&context.store, &config.validator_store) {
+        (Some(store), _) => store.clone(),
+        (None, Some(path)) => Store::open(path)?,
+        (None, None) => Store::memory(),
+    };
+    context.store = Some(store.clone());
+
+    let validator = Validator::new(inner, module.as_deref(), config.validator_limits, store)?
+        .with_selectors(context.selectors.clone());
+    context.validators.push((validator.handle(), explicit));
+    Ok(Box::new(validator))
+}
+
+/// Wraps a signer to validate signing operations with an Open Policy Agent
+/// policy, with the URL of the policy decision in the OPA data API specified
+/// as the argument. Queries time out after the `timeout` of the
+/// `validator_limits` setting.
+fn opa(context: &mut Context<'_>, argument: Option<&str>, inner: BoxSigner) -> Result<BoxSigner> {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Config {
+        #[serde(default)]
+        validator_limits: validator::Limits,
+    }
+
+    let url = argument
+        .context("missing OPA policy URL")?
+        .parse()
+        .context("invalid OPA policy URL")?;
+    let config = context.config::<Config>()?;
+    Ok(Box::new(Opa::new(
+        inner,
+        url,
+        Duration::from_millis(config.validator_limits.timeout),
+    )?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_signer_specifications() {
+        assert_eq!(parse("wallet"), ("wallet", None));
+        assert_eq!(
+            parse("validator:policy.lua"),
+            ("validator", Some("policy.lua"))
+        );
+        assert_eq!(parse("kms:arn:aws:kms"), ("kms", Some("arn:aws:kms")));
+    }
+}
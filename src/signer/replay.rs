@@ -0,0 +1,340 @@
+//! A signer keeping a ledger of signed payloads for replay protection.
+//!
+//! The hashes of all signed raw hashes, messages and typed data are recorded,
+//! and signing an identical payload for the same account again can be
+//! rejected, either within a time window or ever. This protects against
+//! callers accidentally signing in a loop, and against signatures being
+//! harvested by replaying requests.
+//!
+//! The ledger is persisted as an append-only file with one hash and UNIX
+//! timestamp per line, which is compacted on startup and whenever it is full.
+//! Signing a raw hash is recorded like signing a message with the same signing
+//! message, as both produce the same signature.
+
+use super::{request, PublicKey, Signing, Transaction, TypedData, UserOperation};
+use crate::{abi, clock, persist};
+use anyhow::{bail, Context as _, Result};
+use hdwallet::{
+    account::{Address, Signature},
+    message::EthereumMessage,
+};
+use rocket::serde::Deserialize;
+use std::{
+    cmp,
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// The default maximum number of payloads kept in the ledger.
+pub const DEFAULT_MAX_ENTRIES: usize = 1_000_000;
+
+/// Replay protection settings.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ReplayProtection {
+    /// Whether or not to reject signing a payload that was already signed
+    /// for the same account.
+    #[serde(default)]
+    pub reject: bool,
+    /// The window in seconds within which signing a payload again is
+    /// rejected. Payloads are never signed again when not specified.
+    pub window: Option<u64>,
+    /// The maximum number of payloads kept in the ledger. When it is full,
+    /// the oldest payloads are evicted and can be signed again.
+    pub max_entries: Option<usize>,
+}
+
+/// The ledger of signed payloads.
+struct Ledger {
+    /// The last time each payload was signed, by hash.
+    signed: HashMap<[u8; 32], u64>,
+    /// The number of records in the ledger file since it was last compacted.
+    records: usize,
+    path: Option<PathBuf>,
+    file: Option<File>,
+}
+
+impl Ledger {
+    /// Loads the ledger from a file, if any, and compacts it.
+    fn open(path: Option<&Path>, protection: ReplayProtection, now: u64) -> Result<Self> {
+        let mut signed = HashMap::<_, u64>::new();
+        if let Some(path) = path.filter(|path| path.exists()) {
+            let context = || format!("invalid replay ledger {}", path.display());
+            let ledger = fs::read_to_string(path)
+                .with_context(|| format!("failed to read replay ledger {}", path.display()))?;
+            for line in ledger.lines() {
+                let (hash, timestamp) = line.split_once(' ').with_context(context)?;
+                let hash = hex::decode(hash.trim_start_matches("0x"))
+                    .ok()
+                    .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+                    .with_context(context)?;
+                let timestamp = timestamp.parse::<u64>().with_context(context)?;
+                let entry = signed.entry(hash).or_default();
+                *entry = (*entry).max(timestamp);
+            }
+        }
+
+        let mut ledger = Self {
+            signed,
+            records: 0,
+            path: path.map(Path::to_owned),
+            file: None,
+        };
+        ledger.compact(protection, now)?;
+        Ok(ledger)
+    }
+
+    /// Appends a signed payload to the ledger file, compacting the ledger when
+    /// it is full.
+    fn append(&mut self, hash: [u8; 32], protection: ReplayProtection, now: u64) -> Result<()> {
+        let signed = self.signed[&hash];
+        if let Some(file) = &mut self.file {
+            writeln!(file, "0x{} {signed}", hex::encode(hash))
+                .context("failed to write replay ledger")?;
+        }
+        self.records += 1;
+
+        let max_entries = protection.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES);
+        if self.records > max_entries || self.signed.len() > max_entries {
+            self.compact(protection, now)?;
+        }
+        Ok(())
+    }
+
+    /// Drops expired and, when more than three quarters full, the oldest
+    /// payloads from the ledger, and rewrites its file.
+    fn compact(&mut self, protection: ReplayProtection, now: u64) -> Result<()> {
+        self.signed
+            .retain(|_, signed| !expired(*signed, now, protection));
+        let max_entries = protection.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES);
+        let retained = max_entries - max_entries / 4;
+        if self.signed.len() > retained {
+            let mut entries = self.signed.drain().collect::<Vec<_>>();
+            entries.sort_unstable_by_key(|(_, signed)| cmp::Reverse(*signed));
+            let evicted = entries.len() - retained;
+            entries.truncate(retained);
+            self.signed.extend(entries);
+            tracing::warn!(
+                evicted,
+                "replay ledger is full, evicted the oldest payloads"
+            );
+        }
+        self.records = self.signed.len();
+
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let compacted = self
+            .signed
+            .iter()
+            .map(|(hash, signed)| format!("0x{} {signed}\n", hex::encode(hash)))
+            .collect::<String>();
+        persist::write_atomic(path, compacted)
+            .with_context(|| format!("failed to write replay ledger {}", path.display()))?;
+        self.file = Some(
+            OpenOptions::new()
+                .append(true)
+                .open(path)
+                .with_context(|| format!("failed to open replay ledger {}", path.display()))?,
+        );
+        Ok(())
+    }
+}
+
+/// Wrapping signer that records signed payloads and optionally rejects
+/// signing them again.
+pub struct Replay<S> {
+    protection: ReplayProtection,
+    ledger: Mutex<Ledger>,
+    inner: S,
+}
+
+impl<S> Replay<S> {
+    /// Creates a new replay protecting signer wrapping the specified signer.
+    /// The ledger is loaded from and persisted to the specified file, if any.
+    pub fn new(inner: S, protection: ReplayProtection, path: Option<&Path>) -> Result<Self> {
+        Ok(Self {
+            protection,
+            ledger: Mutex::new(Ledger::open(path, protection, clock::now())?),
+            inner,
+        })
+    }
+
+    /// Records a payload as signed, failing if signing it again is rejected.
+    /// The previous record is returned so that it can be restored if signing
+    /// fails.
+    fn record(&self, account: Address, signing_message: [u8; 32]) -> Result<Record> {
+        let hash = abi::keccak256([&account.0[..], &signing_message].concat());
+        let now = clock::now();
+
+        let mut ledger = self.ledger.lock().unwrap();
+        let previous = ledger.signed.get(&hash).copied();
+        if let Some(signed) = previous {
+            if self.protection.reject && !expired(signed, now, self.protection) {
+                bail!("payload was already signed for account {account} at {signed}");
+            }
+        }
+        ledger.signed.insert(hash, now);
+        if self.protection.reject {
+            request::decide("replay", "payload not signed before");
+        }
+        Ok(Record { hash, previous })
+    }
+
+    /// Persists a record after a successful signature, or restores the
+    /// previous one after a signing failure.
+    fn finish(&self, record: Record, result: &Result<Signature>) -> Result<()> {
+        let mut ledger = self.ledger.lock().unwrap();
+        if result.is_err() {
+            match record.previous {
+                Some(previous) => ledger.signed.insert(record.hash, previous),
+                None => ledger.signed.remove(&record.hash),
+            };
+            return Ok(());
+        }
+        ledger.append(record.hash, self.protection, clock::now())
+    }
+
+    fn sign(
+        &self,
+        account: Address,
+        signing_message: [u8; 32],
+        sign: impl FnOnce(&S) -> Result<Signature>,
+    ) -> Result<Signature> {
+        let record = self.record(account, signing_message)?;
+        let result = sign(&self.inner);
+        // The payload stays recorded in memory, so only a restart can lose
+        // it, which doesn't warrant discarding an already produced signature.
+        if let Err(err) = self.finish(record, &result) {
+            tracing::error!(?err, "failed to persist signed payload to replay ledger");
+        }
+        result
+    }
+}
+
+/// A payload recorded in the ledger.
+struct Record {
+    hash: [u8; 32],
+    previous: Option<u64>,
+}
+
+/// Returns whether a payload signed at the specified time is outside of the
+/// replay protection window.
+fn expired(signed: u64, now: u64, protection: ReplayProtection) -> bool {
+    protection
+        .window
+        .is_some_and(|window| now.saturating_sub(signed) >= window)
+}
+
+impl<S> Signing for Replay<S>
+where
+    S: Signing,
+{
+    fn accounts(&self) -> &[Address] {
+        self.inner.accounts()
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.inner.public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.sign(account, hash, |inner| inner.sign_hash(account, hash))
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        let signing_message = EthereumMessage(message).signing_message();
+        self.sign(account, signing_message, |inner| {
+            inner.sign_message(account, message)
+        })
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        self.inner.sign_transaction(account, transaction)
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        self.sign(account, typed_data.signing_message(), |inner| {
+            inner.sign_typed_data(account, typed_data)
+        })
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.inner.sign_user_operation(account, user_operation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::wallet::Wallet;
+    use std::{env, process};
+
+    #[test]
+    fn rejects_replayed_payloads() {
+        let path = env::temp_dir().join(format!("hdnode-replay-{}.txt", process::id()));
+        let protection = ReplayProtection {
+            reject: true,
+            window: None,
+            max_entries: None,
+        };
+        let replay = Replay::new((), protection, Some(&path)).unwrap();
+        let account = Address([0x42; 20]);
+
+        let record = replay.record(account, [1; 32]).unwrap();
+        replay
+            .finish(record, &Err(anyhow::anyhow!("failed")))
+            .unwrap();
+        let record = replay.record(account, [1; 32]).unwrap();
+        let signature = Signature {
+            r: [0; 32],
+            s: [0; 32],
+            y_parity: false,
+        };
+        replay.finish(record, &Ok(signature)).unwrap();
+        assert!(replay.record(account, [1; 32]).is_err());
+        assert!(replay.record(Address([0x43; 20]), [1; 32]).is_ok());
+
+        let reopened = Replay::new((), protection, Some(&path)).unwrap();
+        assert!(reopened.record(account, [1; 32]).is_err());
+        let windowed = ReplayProtection {
+            window: Some(0),
+            ..protection
+        };
+        let reopened = Replay::new((), windowed, Some(&path)).unwrap();
+        assert!(reopened.record(account, [1; 32]).is_ok());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bounds_ledger_and_protects_hashes() {
+        let mnemonic = "test test test test test test test test test test test junk"
+            .parse()
+            .unwrap();
+        let protection = ReplayProtection {
+            reject: true,
+            window: None,
+            max_entries: Some(4),
+        };
+        let replay = Replay::new(Wallet::new(&mnemonic, "", 1).unwrap(), protection, None).unwrap();
+        let account = replay.accounts()[0];
+
+        let signing_message = EthereumMessage(b"hello").signing_message();
+        replay.sign_hash(account, signing_message).unwrap();
+        assert!(replay.sign_message(account, b"hello").is_err());
+        assert!(replay.sign_hash(account, signing_message).is_err());
+
+        for hash in 0..8 {
+            replay.sign_hash(account, [hash; 32]).unwrap();
+            assert!(replay.ledger.lock().unwrap().signed.len() <= 4);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Metadata about the RPC request that a signing operation is performed for.
+//!
+//! The `Signing` trait only deals with signing payloads, so the node makes the
+//! request metadata available to signers for the duration of the request
+//! instead of threading it through every signing method.
+//!
+//! Signing operations can also be previewed, in which case signers make all
+//! of their decisions as usual, but skip side effects such as prompting for
+//! approval and signing backends stop short of producing a signature.
+
+use crate::jsonrpc::Id;
+use anyhow::Result;
+use rocket::{serde::Serialize, tokio};
+use std::{cell::RefCell, future::Future, net::IpAddr};
+use thiserror::Error;
+
+tokio::task_local! {
+    static CURRENT: RequestContext;
+    static DECISIONS: RefCell<Vec<Decision>>;
+    static PREVIEW: ();
+}
+
+/// Metadata about an RPC request.
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RequestContext {
+    /// The RPC method.
+    pub method: String,
+    /// The RPC request ID.
+    pub id: Id,
+    /// The IP address of the client making the request, if known.
+    pub client_ip: Option<IpAddr>,
+    /// The origin of the web page making the request, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// The identity of the client, from its TLS client certificate, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    /// The UNIX timestamp in seconds at which the request was received.
+    pub timestamp: u64,
+}
+
+impl RequestContext {
+    /// Returns the metadata of the request currently being handled, if any.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+
+    /// Runs a future with this as the current request metadata.
+    pub async fn scope<F>(self, f: F) -> F::Output
+    where
+        F: Future,
+    {
+        CURRENT
+            .scope(self, DECISIONS.scope(RefCell::default(), f))
+            .await
+    }
+}
+
+/// A decision made by a signer about a signing operation.
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Decision {
+    /// The kind of signer making the decision.
+    pub signer: &'static str,
+    /// The decision.
+    pub decision: String,
+}
+
+/// Records a decision about the signing operation of the current request.
+/// Decisions outside of requests are ignored.
+pub fn decide(signer: &'static str, decision: impl Into<String>) {
+    let _ = DECISIONS.try_with(|decisions| {
+        decisions.borrow_mut().push(Decision {
+            signer,
+            decision: decision.into(),
+        })
+    });
+}
+
+/// Takes the decisions recorded so far for the current request.
+pub fn take_decisions() -> Vec<Decision> {
+    DECISIONS
+        .try_with(|decisions| decisions.take())
+        .unwrap_or_default()
+}
+
+/// Runs a future previewing the signing operations it performs.
+pub async fn preview<F>(f: F) -> F::Output
+where
+    F: Future,
+{
+    PREVIEW.scope((), f).await
+}
+
+/// Returns whether or not signing operations are being previewed.
+pub fn is_preview() -> bool {
+    PREVIEW.try_with(|_| ()).is_ok()
+}
+
+/// Fails with a [`PreviewError`] when signing operations are being previewed.
+/// Signing backends call this right before producing a signature.
+pub fn ensure_not_preview() -> Result<()> {
+    if is_preview() {
+        return Err(PreviewError.into());
+    }
+    Ok(())
+}
+
+/// An error indicating that a signature was not produced because the signing
+/// operation is being previewed.
+#[derive(Debug, Error)]
+#[error("signing skipped for preview")]
+pub struct PreviewError;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn request_context_is_scoped() {
+        assert!(RequestContext::current().is_none());
+        let context = RequestContext {
+            method: "eth_sendTransaction".to_owned(),
+            id: Id::Number(1.into()),
+            client_ip: "127.0.0.1".parse().ok(),
+            origin: None,
+            identity: None,
+            timestamp: 0,
+        };
+        let (method, decisions) = context
+            .scope(async {
+                decide("policy", "allowed");
+                (
+                    RequestContext::current().map(|context| context.method),
+                    take_decisions(),
+                )
+            })
+            .await;
+        assert_eq!(method.as_deref(), Some("eth_sendTransaction"));
+        assert_eq!(decisions.len(), 1);
+        assert!(RequestContext::current().is_none());
+        assert!(take_decisions().is_empty());
+
+        assert!(!is_preview());
+        assert!(preview(async { ensure_not_preview() }).await.is_err());
+    }
+}
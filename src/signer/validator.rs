@@ -1,80 +1,307 @@
 //! Signature validation.
+//!
+//! Validators are Lua modules, or Rhai scripts for files with a `.rhai`
+//! extension. Each engine is behind a cargo feature of the same name.
 
-use crate::serialization::Bytes;
+#[cfg(feature = "lua")]
+pub mod lua;
+#[cfg(feature = "rhai")]
+pub mod rhai;
+pub mod store;
 
-use super::{Signing, Transaction, TypedData};
+use self::store::{Changes, Store};
+use crate::{
+    node::{
+        siwe::SiweMessage,
+        token::{self, TokenOperation},
+    },
+    selectors::Selectors,
+    serialization::Bytes,
+};
+
+use super::{
+    lock::AccountLocks,
+    request::{self, RequestContext},
+    PublicKey, Signing, Transaction, TypedData, UserOperation,
+};
 use anyhow::{ensure, Context as _, Result};
 use hdwallet::account::{Address, Signature};
-use mlua::{Function, Lua, LuaSerdeExt as _, StdLib, Value, Variadic};
-use rocket::serde::Serialize;
-use std::{fs, path::Path, sync::Mutex};
+use rocket::serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// Resource limits for running validator Lua code.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct Limits {
+    /// The maximum time in milliseconds a handler may run for.
+    pub timeout: u64,
+    /// The maximum number of Lua instructions a handler may execute.
+    pub max_instructions: Option<u64>,
+    /// The maximum memory in bytes the Lua state may allocate.
+    pub max_memory: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            timeout: 1000,
+            max_instructions: None,
+            max_memory: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// The context passed to validator handlers.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct HandlerContext {
+    /// The metadata of the request being handled, if any.
+    #[serde(flatten)]
+    request: Option<RequestContext>,
+    /// The signature of the function called by the payload's calldata, if
+    /// known.
+    function: Option<String>,
+    /// The token transfers and approvals made by the payload's calldata.
+    tokens: Vec<TokenOperation>,
+    /// The parsed Sign-In with Ethereum message, if the payload is one.
+    siwe: Option<SiweMessage>,
+}
+
+impl HandlerContext {
+    /// Returns the context for a payload without calldata.
+    fn current() -> Self {
+        Self {
+            request: RequestContext::current(),
+            function: None,
+            tokens: Vec::new(),
+            siwe: None,
+        }
+    }
+
+    /// Returns the context for a message, which may be a SIWE message.
+    fn message(message: &[u8]) -> Self {
+        Self {
+            siwe: SiweMessage::parse(message).ok().flatten(),
+            ..Self::current()
+        }
+    }
+
+    /// Returns the context for a payload calling a contract, if known, with
+    /// the specified calldata.
+    fn call(to: Option<Address>, calldata: &[u8], selectors: &Selectors) -> Self {
+        Self {
+            function: selectors.function(calldata),
+            tokens: to.map(|to| token::decode(to, calldata)).unwrap_or_default(),
+            ..Self::current()
+        }
+    }
 
-/// A validating signer whose logic is defined by a Lua module.
+    /// Returns the context for a transaction.
+    fn transaction(transaction: &Transaction, selectors: &Selectors) -> Self {
+        Self::call(
+            transaction.request().to.map(|to| to.0),
+            &transaction.request().data.0,
+            selectors,
+        )
+    }
+}
+
+/// A validating signer whose logic is defined by a Lua or Rhai module.
 pub struct Validator<S> {
-    lua: Mutex<Lua>,
+    module: Arc<Mutex<Option<Arc<Module>>>>,
+    limits: Limits,
+    store: Store,
+    locks: AccountLocks,
+    selectors: Selectors,
     inner: S,
 }
 
 impl<S> Validator<S> {
     /// Creates a new validator wrapping the specified signer and using the
-    /// specified path as a Lua module for validation logic.
-    pub fn new(inner: S, module: &Path) -> Result<Self> {
-        let lua = Lua::new_with(
-            StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::MATH,
-            Default::default(),
-        )?;
-
-        // Override `print` function and forward it to logs.
-        let print = lua.create_function(|lua, values: Variadic<Value>| {
-            let mut buffer = String::new();
-            for (i, value) in values.iter().enumerate() {
-                if i > 0 {
-                    buffer.push('\t');
-                }
-                if let Some(string) = lua.coerce_string(value.clone())? {
-                    buffer.push_str(&string.to_string_lossy());
-                }
-            }
-            tracing::debug!("{buffer}");
-            Ok(())
-        })?;
-        lua.globals().set("print", print)?;
-
-        let src = fs::read_to_string(module)?;
-        lua.load(&src).set_name("validator")?.exec()?;
-
+    /// specified path as a module for validation logic. All signatures are
+    /// allowed when no module is specified. Code that exceeds the specified
+    /// limits is aborted and the signature denied. The module can keep state
+    /// in the specified store.
+    pub fn new(inner: S, module: Option<&Path>, limits: Limits, store: Store) -> Result<Self> {
+        let module = module
+            .map(|module| Module::load(module, limits, store.clone()))
+            .transpose()?;
         Ok(Self {
-            lua: Mutex::new(lua),
+            module: Arc::new(Mutex::new(module.map(Arc::new))),
+            limits,
+            store,
+            locks: AccountLocks::default(),
+            selectors: Selectors::default(),
             inner,
         })
     }
 
-    fn validate<T>(&self, name: &str, account: Address, data: &T) -> Result<()>
+    /// Passes the names of the functions called by payloads to handlers, from
+    /// the specified function signature database.
+    pub fn with_selectors(mut self, selectors: Selectors) -> Self {
+        self.selectors = selectors;
+        self
+    }
+
+    /// Returns a handle for reloading the validator's module.
+    pub fn handle(&self) -> Handle {
+        Handle {
+            module: self.module.clone(),
+            limits: self.limits,
+            store: self.store.clone(),
+        }
+    }
+
+    /// Returns the current module, if any.
+    fn module(&self) -> Option<Arc<Module>> {
+        // Only hold the lock long enough to clone the module, so that
+        // validation runs concurrently and reloading doesn't wait for it.
+        self.module.lock().unwrap().clone()
+    }
+
+    /// Runs a handler with writes to the store staged, returning them if the
+    /// handler allows the signature.
+    #[cfg_attr(not(any(feature = "lua", feature = "rhai")), allow(unused_variables))]
+    fn validate<T>(
+        &self,
+        module: &Module,
+        name: &str,
+        account: Address,
+        data: &T,
+        context: HandlerContext,
+    ) -> Result<Changes>
     where
         T: Serialize,
     {
-        let lua = self.lua.lock().unwrap();
-        let handler = lua
-            .globals()
-            .get::<_, Function>(name)
-            .with_context(|| format!("missing '{name}' handler in module"))?;
-        let input = (account.to_string(), lua.to_value(data)?);
-        let ok = handler.call::<_, bool>(input)?;
-        ensure!(ok, "handler '{name}' denied signature");
+        let (ok, changes) = self.store.stage(|| -> Result<bool> {
+            match *module {
+                #[cfg(feature = "lua")]
+                Module::Lua(ref pool) => pool.validate(name, account, data, &context),
+                #[cfg(feature = "rhai")]
+                Module::Rhai(ref script) => script.validate(name, account, data, &context),
+            }
+        });
+        ensure!(ok?, "handler '{name}' denied signature");
+        request::decide("validator", format!("allowed by handler '{name}'"));
 
+        Ok(changes)
+    }
+
+    /// Validates a signing request and signs it with `sign`, committing the
+    /// handler's writes to the store once signed.
+    fn sign<T>(
+        &self,
+        name: &str,
+        account: Address,
+        data: &T,
+        context: HandlerContext,
+        sign: impl FnOnce(&S) -> Result<Signature>,
+    ) -> Result<Signature>
+    where
+        T: Serialize,
+    {
+        let module = match self.module() {
+            Some(module) => module,
+            None => return sign(&self.inner),
+        };
+        // Handlers for the same account run one at a time, with the writes of
+        // each committed before the next one starts, so that they can update
+        // state in the store based on earlier requests without racing.
+        let _lock = self.locks.lock(account);
+        let changes = self.validate(&module, name, account, data, context)?;
+        let signature = sign(&self.inner)?;
+        if !request::is_preview() {
+            // The signature is already produced, so keep it even if the store
+            // can't be written.
+            if let Err(err) = changes.commit() {
+                tracing::warn!(?err, "failed to commit validator store writes");
+            }
+        }
+        Ok(signature)
+    }
+
+    /// Validates a transaction without signing it. Writes to the store are
+    /// discarded.
+    pub fn validate_transaction(&self, account: Address, transaction: &Transaction) -> Result<()> {
+        match self.module() {
+            Some(module) => self
+                .validate(
+                    &module,
+                    "validate_transaction",
+                    account,
+                    transaction,
+                    HandlerContext::transaction(transaction, &self.selectors),
+                )
+                .map(drop),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A handle for reloading the module of a running validator.
+#[derive(Clone)]
+pub struct Handle {
+    module: Arc<Mutex<Option<Arc<Module>>>>,
+    limits: Limits,
+    store: Store,
+}
+
+impl Handle {
+    /// Replaces the validator's Lua module. The current module is kept if the
+    /// new one fails to load. Validations that are already running finish
+    /// with the previous module.
+    pub fn reload(&self, module: Option<&Path>) -> Result<()> {
+        let module = module
+            .map(|module| Module::load(module, self.limits, self.store.clone()))
+            .transpose()?;
+        *self.module.lock().unwrap() = module.map(Arc::new);
         Ok(())
     }
+}
+
+/// A loaded validator module.
+enum Module {
+    #[cfg(feature = "lua")]
+    Lua(lua::Pool),
+    #[cfg(feature = "rhai")]
+    Rhai(Box<rhai::Script>),
+}
+
+impl Module {
+    /// Loads a validator module, selecting the engine by file extension.
+    fn load(path: &Path, limits: Limits, store: Store) -> Result<Self> {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("failed to read validator {}", path.display()))?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("rhai") => Self::rhai(source, limits, store),
+            _ => Self::lua(source, limits, store),
+        }
+    }
+
+    #[cfg(feature = "lua")]
+    fn lua(source: String, limits: Limits, store: Store) -> Result<Self> {
+        Ok(Self::Lua(lua::Pool::new(source, limits, store)?))
+    }
 
-    fn validate_message(&self, account: Address, message: &[u8]) -> Result<()> {
-        self.validate("validate_message", account, &Bytes(message))
+    #[cfg(not(feature = "lua"))]
+    fn lua(_: String, _: Limits, _: Store) -> Result<Self> {
+        anyhow::bail!("Lua validators are not supported without the `lua` feature")
     }
 
-    fn validate_transaction(&self, account: Address, transaction: &Transaction) -> Result<()> {
-        self.validate("validate_transaction", account, transaction)
+    #[cfg(feature = "rhai")]
+    fn rhai(source: String, limits: Limits, store: Store) -> Result<Self> {
+        Ok(Self::Rhai(Box::new(rhai::Script::new(
+            &source, limits, store,
+        )?)))
     }
 
-    fn validate_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<()> {
-        self.validate("validate_typed_data", account, typed_data)
+    #[cfg(not(feature = "rhai"))]
+    fn rhai(_: String, _: Limits, _: Store) -> Result<Self> {
+        anyhow::bail!("Rhai validators are not supported without the `rhai` feature")
     }
 }
 
@@ -86,18 +313,61 @@ where
         self.inner.accounts()
     }
 
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.inner.public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.sign(
+            "validate_hash",
+            account,
+            &Bytes(hash),
+            HandlerContext::current(),
+            |inner| inner.sign_hash(account, hash),
+        )
+    }
+
     fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
-        self.validate_message(account, message)?;
-        self.inner.sign_message(account, message)
+        self.sign(
+            "validate_message",
+            account,
+            &Bytes(message),
+            HandlerContext::message(message),
+            |inner| inner.sign_message(account, message),
+        )
     }
 
     fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
-        self.validate_transaction(account, transaction)?;
-        self.inner.sign_transaction(account, transaction)
+        self.sign(
+            "validate_transaction",
+            account,
+            transaction,
+            HandlerContext::transaction(transaction, &self.selectors),
+            |inner| inner.sign_transaction(account, transaction),
+        )
     }
 
     fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
-        self.validate_typed_data(account, typed_data)?;
-        self.inner.sign_typed_data(account, typed_data)
+        self.sign(
+            "validate_typed_data",
+            account,
+            typed_data,
+            HandlerContext::current(),
+            |inner| inner.sign_typed_data(account, typed_data),
+        )
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.sign(
+            "validate_user_operation",
+            account,
+            user_operation,
+            HandlerContext::call(None, &user_operation.request().call_data.0, &self.selectors),
+            |inner| inner.sign_user_operation(account, user_operation),
+        )
     }
 }
@@ -0,0 +1,214 @@
+//! Validator modules written in Lua.
+//!
+//! Lua modules run in an embedded Lua 5.4 interpreter with only the `table`,
+//! `string`, `utf8` and `math` standard libraries available.
+
+use super::{store::Store, HandlerContext, Limits};
+use anyhow::{anyhow, Context as _, Result};
+use hdwallet::account::Address;
+use mlua::{Function, HookTriggers, Lua, LuaSerdeExt as _, StdLib, Table, Value, Variadic};
+use rocket::serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The number of Lua instructions between execution limit checks.
+const HOOK_INSTRUCTIONS: u32 = 1000;
+
+/// A pool of Lua states loaded with the same validator module.
+///
+/// A Lua state can only run one handler at a time, so concurrent validations
+/// each use their own state. States are created on demand and kept around for
+/// reuse, up to the available parallelism. This means that global variables
+/// set by handlers diverge between states, and are lost when a state is
+/// discarded after an error, which is why handlers keep state in the store.
+pub struct Pool {
+    source: String,
+    limits: Limits,
+    store: Store,
+    capacity: usize,
+    idle: Mutex<Vec<Lua>>,
+}
+
+impl Pool {
+    /// Creates a new pool for the specified module source. The module is
+    /// loaded once upfront to ensure that it is valid.
+    pub fn new(source: String, limits: Limits, store: Store) -> Result<Self> {
+        let lua = load(&source, &limits, &store)?;
+        Ok(Self {
+            source,
+            limits,
+            store,
+            capacity: thread::available_parallelism().map_or(1, usize::from),
+            idle: Mutex::new(vec![lua]),
+        })
+    }
+
+    /// Runs a function with a Lua state from the pool.
+    fn run<T>(&self, f: impl FnOnce(&Lua) -> Result<T>) -> Result<T> {
+        let idle = self.idle.lock().unwrap().pop();
+        let lua = match idle {
+            Some(lua) => lua,
+            None => load(&self.source, &self.limits, &self.store)?,
+        };
+
+        let result = f(&lua);
+
+        // Discard states after errors, as they may have been aborted at any
+        // point and be left in an inconsistent state.
+        if result.is_ok() {
+            let mut idle = self.idle.lock().unwrap();
+            if idle.len() < self.capacity {
+                idle.push(lua);
+            }
+        }
+        result
+    }
+
+    /// Runs a handler, returning whether or not it allows the signature.
+    pub(super) fn validate<T>(
+        &self,
+        name: &str,
+        account: Address,
+        data: &T,
+        context: &HandlerContext,
+    ) -> Result<bool>
+    where
+        T: Serialize,
+    {
+        self.run(|lua| {
+            let handler = lua
+                .globals()
+                .get::<_, Function>(name)
+                .with_context(|| format!("missing '{name}' handler in module"))?;
+            let input = (
+                account.to_string(),
+                lua.to_value(data)?,
+                lua.to_value(context)?,
+            );
+            limited(lua, &self.limits, || handler.call::<_, bool>(input))
+                .with_context(|| format!("handler '{name}' failed"))
+        })
+    }
+}
+
+/// Runs Lua code, aborting it if it exceeds the time or instruction limits.
+fn limited<T>(lua: &Lua, limits: &Limits, f: impl FnOnce() -> mlua::Result<T>) -> Result<T> {
+    let exceeded = Arc::new(Mutex::new(None));
+    let deadline = Instant::now() + Duration::from_millis(limits.timeout);
+    let max_instructions = limits.max_instructions;
+    let instructions = AtomicU64::new(0);
+    lua.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(HOOK_INSTRUCTIONS),
+            ..Default::default()
+        },
+        {
+            let exceeded = exceeded.clone();
+            move |_, _| {
+                let count = instructions.fetch_add(HOOK_INSTRUCTIONS.into(), Ordering::Relaxed)
+                    + u64::from(HOOK_INSTRUCTIONS);
+                let limit = if Instant::now() > deadline {
+                    "time limit"
+                } else if max_instructions.is_some_and(|max| count > max) {
+                    "instruction limit"
+                } else {
+                    return Ok(());
+                };
+                *exceeded.lock().unwrap() = Some(limit);
+                Err(mlua::Error::RuntimeError(format!(
+                    "validator exceeded its {limit}"
+                )))
+            }
+        },
+    )?;
+    let result = f();
+    lua.remove_hook();
+
+    // Report exceeded limits explicitly, as the Lua errors can be wrapped or
+    // even caught by the Lua code itself.
+    let exceeded = *exceeded.lock().unwrap();
+    match (result, exceeded) {
+        (_, Some(limit)) => Err(anyhow!("validator exceeded its {limit}")),
+        (Err(mlua::Error::MemoryError(_)), None) => {
+            Err(anyhow!("validator exceeded its memory limit"))
+        }
+        (result, None) => Ok(result?),
+    }
+}
+
+/// Creates a Lua state with a module for validation logic.
+fn load(source: &str, limits: &Limits, store: &Store) -> Result<Lua> {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::MATH,
+        Default::default(),
+    )?;
+    lua.set_memory_limit(limits.max_memory)?;
+
+    // Override `print` function and forward it to logs.
+    let print = lua.create_function(|lua, values: Variadic<Value>| {
+        let mut buffer = String::new();
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                buffer.push('\t');
+            }
+            if let Some(string) = lua.coerce_string(value.clone())? {
+                buffer.push_str(&string.to_string_lossy());
+            }
+        }
+        tracing::debug!("{buffer}");
+        Ok(())
+    })?;
+    lua.globals().set("print", print)?;
+
+    // Expose the key-value store as `store.get` and `store.set`.
+    let table = lua.create_table()?;
+    table.set(
+        "get",
+        lua.create_function({
+            let store = store.clone();
+            move |lua, key: String| match store.get(&key) {
+                Some(value) => lua.to_value(&value),
+                None => Ok(Value::Nil),
+            }
+        })?,
+    )?;
+    table.set(
+        "set",
+        lua.create_function({
+            let store = store.clone();
+            move |lua, (key, value): (String, Value)| {
+                let value = lua.from_value(value)?;
+                store.set(key, value).map_err(mlua::Error::external)
+            }
+        })?,
+    )?;
+    lua.globals().set("store", table)?;
+
+    limited(&lua, limits, || {
+        lua.load(source).set_name("validator")?.exec()
+    })?;
+
+    // Globals that handlers assign only exist in the pooled state that ran
+    // them, so warn about them and point to the store instead.
+    let metatable = lua.create_table()?;
+    metatable.set(
+        "__newindex",
+        lua.create_function(|_, (globals, key, value): (Table, Value, Value)| {
+            tracing::warn!(
+                key = ?key,
+                "validator handler set a global variable, which is not shared between \
+                 concurrent validations and is lost after errors; use the store instead"
+            );
+            globals.raw_set(key, value)
+        })?,
+    )?;
+    lua.globals().set_metatable(Some(metatable));
+
+    Ok(lua)
+}
@@ -0,0 +1,186 @@
+//! Validator modules written in Rhai.
+//!
+//! Rhai is a pure Rust scripting language, so unlike Lua it doesn't need a C
+//! interpreter, and scripts are sandboxed by default. Scripts define the same
+//! handlers as Lua modules, taking the account, the payload and optionally the
+//! request context.
+
+use super::{store::Store, HandlerContext, Limits};
+use anyhow::{anyhow, Context as _, Result};
+use hdwallet::account::Address;
+use rhai::{Dynamic, Engine, EvalAltResult, ImmutableString, Module, Scope, AST};
+use rocket::serde::{json::Value, Serialize};
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+thread_local! {
+    /// The deadline of the handler running on the current thread.
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// A compiled Rhai validator script.
+///
+/// The engine and script can be shared by concurrent validations, so no pool
+/// is needed.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    limits: Limits,
+}
+
+impl Script {
+    /// Compiles a Rhai validator script.
+    pub fn new(source: &str, limits: Limits, store: Store) -> Result<Self> {
+        let mut engine = Engine::new();
+        if let Some(max_instructions) = limits.max_instructions {
+            engine.set_max_operations(max_instructions);
+        }
+        // Rhai doesn't track memory usage, so approximate the memory limit by
+        // limiting the size of values.
+        engine
+            .set_max_string_size(limits.max_memory)
+            .set_max_array_size(limits.max_memory / 16)
+            .set_max_map_size(limits.max_memory / 16)
+            .on_progress(|_| {
+                let expired = DEADLINE.with(|deadline| {
+                    deadline
+                        .get()
+                        .is_some_and(|deadline| Instant::now() > deadline)
+                });
+                expired.then(|| Dynamic::from("time limit"))
+            })
+            .on_print(|message| tracing::debug!("{message}"));
+
+        // Expose the key-value store as `store::get` and `store::set`.
+        let mut module = Module::new();
+        module.set_native_fn("get", {
+            let store = store.clone();
+            move |key: ImmutableString| match store.get(&key) {
+                Some(value) => rhai::serde::to_dynamic(value),
+                None => Ok(Dynamic::UNIT),
+            }
+        });
+        module.set_native_fn("set", move |key: ImmutableString, value: Dynamic| {
+            let value = rhai::serde::from_dynamic::<Value>(&value)?;
+            store
+                .set(key.to_string(), value)
+                .map_err(|err| err.to_string().into())
+        });
+        engine.register_static_module("store", module.into());
+
+        let ast = engine.compile(source).context("invalid Rhai validator")?;
+        Ok(Self {
+            engine,
+            ast,
+            limits,
+        })
+    }
+
+    /// Runs a handler, returning whether or not it allows the signature.
+    pub(super) fn validate<T>(
+        &self,
+        name: &str,
+        account: Address,
+        data: &T,
+        context: &HandlerContext,
+    ) -> Result<bool>
+    where
+        T: Serialize,
+    {
+        let params = self
+            .ast
+            .iter_functions()
+            .find(|function| function.name == name)
+            .map(|function| function.params.len())
+            .with_context(|| format!("missing '{name}' handler in module"))?;
+
+        let account = Dynamic::from(account.to_string());
+        let data = rhai::serde::to_dynamic(data)?;
+        let mut scope = Scope::new();
+
+        let deadline = Instant::now() + Duration::from_millis(self.limits.timeout);
+        DEADLINE.with(|cell| cell.set(Some(deadline)));
+        let result = if params >= 3 {
+            let context = rhai::serde::to_dynamic(context)?;
+            self.engine
+                .call_fn::<bool>(&mut scope, &self.ast, name, (account, data, context))
+        } else {
+            self.engine
+                .call_fn::<bool>(&mut scope, &self.ast, name, (account, data))
+        };
+        DEADLINE.with(|cell| cell.set(None));
+
+        result.map_err(|err| match err.unwrap_inner() {
+            EvalAltResult::ErrorTerminated(..) => anyhow!("validator exceeded its time limit"),
+            EvalAltResult::ErrorTooManyOperations(..) => {
+                anyhow!("validator exceeded its instruction limit")
+            }
+            EvalAltResult::ErrorDataTooLarge(..) => anyhow!("validator exceeded its memory limit"),
+            _ => anyhow!("handler '{name}' failed: {err}"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::json;
+
+    #[test]
+    fn runs_handlers_within_limits() {
+        let limits = Limits {
+            max_instructions: Some(10_000),
+            ..Default::default()
+        };
+        let script = Script::new(
+            r#"
+                fn validate_message(account, message) {
+                    let count = store::get(account) ?? 0;
+                    store::set(account, count + 1);
+                    count < 1
+                }
+                fn validate_transaction(account, transaction, context) {
+                    transaction.value == "0x0" && context.function == "transfer(address,uint256)"
+                }
+                fn validate_hash(account, hash) {
+                    loop {}
+                }
+            "#,
+            limits,
+            Store::memory(),
+        )
+        .unwrap();
+        let account = Address([0x42; 20]);
+        let context = HandlerContext::current();
+
+        assert!(script
+            .validate("validate_message", account, &"0x", &context)
+            .unwrap());
+        assert!(!script
+            .validate("validate_message", account, &"0x", &context)
+            .unwrap());
+        assert!(script
+            .validate(
+                "validate_transaction",
+                account,
+                &json!({ "value": "0x0" }),
+                &HandlerContext {
+                    function: Some("transfer(address,uint256)".to_owned()),
+                    ..HandlerContext::current()
+                },
+            )
+            .unwrap());
+        assert_eq!(
+            script
+                .validate("validate_hash", account, &"0x", &context)
+                .unwrap_err()
+                .to_string(),
+            "validator exceeded its instruction limit",
+        );
+        assert!(script
+            .validate("validate_typed_data", account, &json!({}), &context)
+            .is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//! A persistent key-value store for validator modules.
+//!
+//! This allows validators to implement stateful rules, such as cumulative
+//! limits per destination, that survive node restarts. The store is shared by
+//! all Lua states of a validator and is exposed to Lua as `store.get(key)` and
+//! `store.set(key, value)`.
+//!
+//! Writes made while validating a signing request are staged and only
+//! committed once the request is signed, so that denied, failed and preview
+//! requests leave the store unchanged.
+
+use crate::persist;
+use anyhow::{Context as _, Result};
+use rocket::serde::json::{serde_json, Value};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// A key-value store, optionally persisted to a file.
+#[derive(Clone, Default)]
+pub struct Store(Arc<Inner>);
+
+#[derive(Default)]
+struct Inner {
+    path: Option<PathBuf>,
+    data: Mutex<HashMap<String, Value>>,
+}
+
+thread_local! {
+    /// The writes staged by the validation running on this thread, along with
+    /// the store they belong to. Removed keys are staged as `null`.
+    static STAGED: RefCell<Option<(*const Inner, HashMap<String, Value>)>> =
+        const { RefCell::new(None) };
+}
+
+/// Writes staged by a validation, to be committed to the store.
+#[must_use]
+pub struct Changes {
+    store: Store,
+    writes: HashMap<String, Value>,
+}
+
+impl Changes {
+    /// Applies the staged writes to the store, persisting them.
+    pub fn commit(self) -> Result<()> {
+        if self.writes.is_empty() {
+            return Ok(());
+        }
+        let mut data = self.store.0.data.lock().unwrap();
+        for (key, value) in self.writes {
+            apply(&mut data, key, value);
+        }
+        self.store.persist(&data)
+    }
+}
+
+impl Store {
+    /// Creates a new in-memory store.
+    pub fn memory() -> Self {
+        Self::default()
+    }
+
+    /// Opens a store persisted to the specified file, creating it on the
+    /// first write if it doesn't exist.
+    pub fn open(path: &Path) -> Result<Self> {
+        let data = if path.exists() {
+            serde_json::from_slice(
+                &fs::read(path)
+                    .with_context(|| format!("failed to read store {}", path.display()))?,
+            )
+            .with_context(|| format!("invalid store {}", path.display()))?
+        } else {
+            HashMap::new()
+        };
+        Ok(Self(Arc::new(Inner {
+            path: Some(path.to_owned()),
+            data: Mutex::new(data),
+        })))
+    }
+
+    /// Returns an in-memory copy of the store, so that changes to it are not
+    /// persisted.
+    pub fn detached(&self) -> Self {
+        Self(Arc::new(Inner {
+            path: None,
+            data: Mutex::new(self.0.data.lock().unwrap().clone()),
+        }))
+    }
+
+    /// Runs `f` with writes to the store staged rather than applied, returning
+    /// them as changes to commit. Reads within `f` see the staged writes.
+    pub fn stage<T>(&self, f: impl FnOnce() -> T) -> (T, Changes) {
+        let previous = STAGED.with(|staged| {
+            staged
+                .borrow_mut()
+                .replace((Arc::as_ptr(&self.0), HashMap::new()))
+        });
+        let result = f();
+        let (_, writes) = STAGED
+            .with(|staged| staged.replace(previous))
+            .expect("staged writes removed during validation");
+        let changes = Changes {
+            store: self.clone(),
+            writes,
+        };
+        (result, changes)
+    }
+
+    /// Calls `f` with the writes staged for this store on the current thread,
+    /// if any.
+    #[cfg_attr(not(any(feature = "lua", feature = "rhai")), allow(dead_code))]
+    fn staged<T>(&self, f: impl FnOnce(&mut HashMap<String, Value>) -> T) -> Option<T> {
+        STAGED.with(|staged| match &mut *staged.borrow_mut() {
+            Some((inner, writes)) if *inner == Arc::as_ptr(&self.0) => Some(f(writes)),
+            _ => None,
+        })
+    }
+
+    /// Returns the value for a key.
+    #[cfg_attr(not(any(feature = "lua", feature = "rhai")), allow(dead_code))]
+    pub fn get(&self, key: &str) -> Option<Value> {
+        match self.staged(|writes| writes.get(key).cloned()).flatten() {
+            Some(value) => Some(value).filter(|value| !value.is_null()),
+            None => self.0.data.lock().unwrap().get(key).cloned(),
+        }
+    }
+
+    /// Sets the value for a key, removing it when the value is `null`. The
+    /// change is staged during validations, and otherwise persisted before
+    /// returning.
+    #[cfg_attr(not(any(feature = "lua", feature = "rhai")), allow(dead_code))]
+    pub fn set(&self, key: String, value: Value) -> Result<()> {
+        if self.staged(|_| ()).is_some() {
+            self.staged(|writes| writes.insert(key, value));
+            return Ok(());
+        }
+        let mut data = self.0.data.lock().unwrap();
+        apply(&mut data, key, value);
+        self.persist(&data)
+    }
+
+    /// Writes the data to disk, if persistence is enabled.
+    fn persist(&self, data: &HashMap<String, Value>) -> Result<()> {
+        let path = match &self.0.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        persist::write_atomic(path, serde_json::to_vec(data)?)
+            .with_context(|| format!("failed to write store {}", path.display()))
+    }
+}
+
+/// Sets the value for a key, removing it when the value is `null`.
+fn apply(data: &mut HashMap<String, Value>, key: String, value: Value) {
+    if value.is_null() {
+        data.remove(&key);
+    } else {
+        data.insert(key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::json;
+    use std::{env, process};
+
+    #[test]
+    fn persists_values() {
+        let path = env::temp_dir().join(format!("hdnode-store-{}.json", process::id()));
+        let store = Store::open(&path).unwrap();
+        store.set("limit".to_owned(), json!({ "used": 1 })).unwrap();
+        store.set("other".to_owned(), json!(42)).unwrap();
+        store.set("other".to_owned(), Value::Null).unwrap();
+
+        let detached = Store::open(&path).unwrap().detached();
+        detached.set("limit".to_owned(), json!(0)).unwrap();
+
+        let reopened = Store::open(&path).unwrap();
+        assert_eq!(reopened.get("limit"), Some(json!({ "used": 1 })));
+        assert_eq!(reopened.get("other"), None);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn stages_writes() {
+        let store = Store::memory();
+        store.set("kept".to_owned(), json!(1)).unwrap();
+
+        let (_, changes) = store.stage(|| {
+            store.set("kept".to_owned(), Value::Null).unwrap();
+            store.set("added".to_owned(), json!(2)).unwrap();
+            assert_eq!(store.get("kept"), None);
+            assert_eq!(store.get("added"), Some(json!(2)));
+        });
+        assert_eq!(store.get("kept"), Some(json!(1)));
+        assert_eq!(store.get("added"), None);
+        drop(changes);
+
+        let (_, changes) = store.stage(|| store.set("added".to_owned(), json!(3)).unwrap());
+        changes.commit().unwrap();
+        assert_eq!(store.get("kept"), Some(json!(1)));
+        assert_eq!(store.get("added"), Some(json!(3)));
+    }
+}
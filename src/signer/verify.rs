@@ -0,0 +1,126 @@
+//! A signer verifying produced signatures.
+//!
+//! Signatures are only returned once the address recovered from them matches
+//! the requested account. This guards against faulty signing backends, such as
+//! misconfigured HSMs or KMS keys, and against bit-flips producing signatures
+//! that are attributable to the wrong key.
+
+use super::{PublicKey, Signing, Transaction, TypedData, UserOperation};
+use crate::recovery;
+use anyhow::Result;
+use hdwallet::{
+    account::{Address, Signature},
+    message::EthereumMessage,
+};
+use thiserror::Error;
+
+/// Wrapping signer that verifies the signatures of the inner signer.
+pub struct Verifying<S>(pub S);
+
+impl<S> Verifying<S> {
+    /// Verifies that a signature for a digest recovers to the account.
+    fn verify(&self, account: Address, hash: [u8; 32], signature: Signature) -> Result<Signature> {
+        let signer = recovery::recover_address(hash, &signature)?;
+        if signer != account {
+            tracing::error!(%account, %signer, %signature, "signature verification failed");
+            return Err(SignatureMismatchError { account, signer }.into());
+        }
+        Ok(signature)
+    }
+}
+
+/// An error indicating that a produced signature does not recover to the
+/// requested account.
+#[derive(Debug, Error)]
+#[error("signature for {account} recovers to {signer}")]
+pub struct SignatureMismatchError {
+    /// The requested account.
+    pub account: Address,
+    /// The account that the signature recovers to.
+    pub signer: Address,
+}
+
+impl<S> Signing for Verifying<S>
+where
+    S: Signing,
+{
+    fn accounts(&self) -> &[Address] {
+        self.0.accounts()
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        self.0.public_key(account)
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        let signature = self.0.sign_hash(account, hash)?;
+        self.verify(account, hash, signature)
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        let signature = self.0.sign_message(account, message)?;
+        let hash = EthereumMessage(message).signing_message();
+        self.verify(account, hash, signature)
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        let signature = self.0.sign_transaction(account, transaction)?;
+        self.verify(account, transaction.signing_message(), signature)
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        let signature = self.0.sign_typed_data(account, typed_data)?;
+        self.verify(account, typed_data.signing_message(), signature)
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        let signature = self.0.sign_user_operation(account, user_operation)?;
+        let hash = EthereumMessage(&user_operation.hash()).signing_message();
+        self.verify(account, hash, signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        signer::wallet::Wallet,
+        testing::{call, client, client_with, MockUpstream, ACCOUNT},
+    };
+    use rocket::serde::json::serde_json::json;
+
+    #[test]
+    fn rejects_signatures_of_other_accounts() {
+        let mnemonic = "test test test test test test test test test test test junk"
+            .parse()
+            .unwrap();
+        let signer = Verifying(Wallet::new(&mnemonic, "", 2).unwrap());
+        let [account, other] = [signer.accounts()[0], signer.accounts()[1]];
+
+        let signature = signer.sign_message(account, b"hello").unwrap();
+        let hash = EthereumMessage(b"hello").signing_message();
+        assert!(signer.verify(account, hash, signature).is_ok());
+
+        let err = signer.verify(other, hash, signature).unwrap_err();
+        let err = err.downcast_ref::<SignatureMismatchError>().unwrap();
+        assert_eq!((err.account, err.signer), (other, account));
+    }
+
+    #[rocket::async_test]
+    async fn verifies_produced_signatures() {
+        let upstream = MockUpstream::start().await;
+        let params = json!([ACCOUNT, "0x68656c6c6f"]);
+        let expected = call(&client(&upstream).await, "eth_sign", params.clone()).await;
+
+        let client = client_with(&upstream, |figment| {
+            figment.merge(("signers", json!(["wallet", "verify", "log"])))
+        })
+        .await;
+        let signed = call(&client, "eth_sign", params).await;
+        assert_eq!(signed["result"], expected["result"]);
+    }
+}
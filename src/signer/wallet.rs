@@ -1,20 +1,46 @@
 //! The wallet used for performing HD node operations.
 
-use super::{Signing, Transaction, TypedData};
-use anyhow::{Context as _, Result};
+use super::{request, PublicKey, Signing, Transaction, TypedData, UserOperation};
+use crate::{
+    node::{eth::Eth, types::BlockTag},
+    recovery,
+};
+use anyhow::{ensure, Context as _, Result};
 use hdwallet::{
     account::{Address, PrivateKey, Signature},
     hdk,
     message::EthereumMessage,
     mnemonic::Mnemonic,
 };
-use std::collections::HashMap;
+use secp256k1::{Message, Secp256k1, SecretKey, SignOnly};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::OnceLock,
+};
 use thiserror::Error;
 
 /// A collection of accounts that can perform Ethereum ECDSA operations.
 pub struct Wallet {
     addresses: Vec<Address>,
-    accounts: HashMap<[u8; 20], PrivateKey>,
+    accounts: HashMap<[u8; 20], Account>,
+    /// The signing context for imported keys, which is expensive to create
+    /// and so is shared by all of their signatures.
+    context: Secp256k1<SignOnly>,
+}
+
+/// An account of the wallet.
+struct Account {
+    key: Key,
+    /// The public key of the account, computed on first use.
+    public_key: OnceLock<PublicKey>,
+}
+
+/// The private key of an account.
+enum Key {
+    /// A key derived from a mnemonic.
+    Derived(PrivateKey),
+    /// An imported key, signed with the precomputed context of the wallet.
+    Imported(SecretKey),
 }
 
 impl Wallet {
@@ -27,25 +53,180 @@ impl Wallet {
             .collect::<Result<Vec<_>, _>>()
             .context("key derivation error")?;
 
-        let addresses = private_keys.iter().map(|key| key.address()).collect();
-        let accounts = private_keys
-            .into_iter()
-            .map(|account| (account.address().0, account))
-            .collect();
+        Ok(Self::from_private_keys(private_keys))
+    }
+
+    /// Creates a new wallet from raw private keys.
+    pub fn from_private_keys(private_keys: impl IntoIterator<Item = PrivateKey>) -> Self {
+        let mut wallet = Self::empty();
+        for private_key in private_keys {
+            wallet.insert(private_key.address(), Key::Derived(private_key), None);
+        }
+        wallet
+    }
+
+    /// Creates a new wallet from imported secret keys. Their public keys are
+    /// computed upfront, and signing reuses a single context instead of
+    /// creating one for every signature.
+    pub fn from_secret_keys(secret_keys: impl IntoIterator<Item = SecretKey>) -> Self {
+        let mut wallet = Self::empty();
+        for secret_key in secret_keys {
+            let public_key = secp256k1::PublicKey::from_secret_key(&wallet.context, &secret_key)
+                .serialize_uncompressed();
+            wallet.insert(
+                recovery::address(&public_key),
+                Key::Imported(secret_key),
+                Some(public_key),
+            );
+        }
+        wallet
+    }
+
+    /// Creates a new wallet without any accounts.
+    fn empty() -> Self {
+        Self {
+            addresses: Vec::new(),
+            accounts: HashMap::new(),
+            context: Secp256k1::signing_only(),
+        }
+    }
+
+    /// Adds the accounts of another wallet to this one. Accounts that are
+    /// already part of the wallet are ignored.
+    pub fn extend(&mut self, mut other: Wallet) {
+        for address in other.addresses {
+            if let Some(account) = other.accounts.remove(&address.0) {
+                if let Entry::Vacant(entry) = self.accounts.entry(address.0) {
+                    entry.insert(account);
+                    self.addresses.push(address);
+                }
+            }
+        }
+    }
+
+    /// Adds an account to the wallet, unless it is already part of it.
+    fn insert(&mut self, address: Address, key: Key, public_key: Option<PublicKey>) {
+        if let Entry::Vacant(entry) = self.accounts.entry(address.0) {
+            entry.insert(Account {
+                key,
+                public_key: public_key.map(OnceLock::from).unwrap_or_default(),
+            });
+            self.addresses.push(address);
+        }
+    }
+
+    /// Creates a new wallet from a mnemonic, discovering previously used
+    /// accounts on the remote node.
+    ///
+    /// Accounts are scanned in order until `gap_limit` consecutive accounts
+    /// without any transactions or balance are found, similar to BIP-44 account
+    /// discovery. The wallet contains at least `min_count` accounts.
+    pub async fn discover(
+        mnemonic: &Mnemonic,
+        password: &str,
+        min_count: usize,
+        gap_limit: usize,
+        eth: &Eth,
+    ) -> Result<Self> {
+        ensure!(
+            gap_limit > 0,
+            "account discovery gap limit must be positive"
+        );
+
+        let seed = mnemonic.seed(password);
+        let mut count = 0;
+        let mut start = 0;
+        while start < count + gap_limit {
+            let end = count + gap_limit;
+            let addresses = (start..end)
+                .map(|i| Ok(hdk::derive_index(&seed, i)?.address()))
+                .collect::<Result<Vec<_>>>()
+                .context("key derivation error")?;
+
+            let mut batch = eth.batch();
+            let activity = addresses
+                .iter()
+                .map(|&address| {
+                    (
+                        batch.get_transaction_count(address, BlockTag::Latest),
+                        batch.get_balance(address, BlockTag::Latest),
+                    )
+                })
+                .collect::<Vec<_>>();
+            batch.execute().await?;
+
+            for (i, (nonce, balance)) in (start..end).zip(activity) {
+                if nonce.await? > 0 || balance.await? > 0 {
+                    count = i + 1;
+                }
+            }
+            start = end;
+        }
+
+        tracing::debug!(count, "discovered used accounts");
+        Self::new(mnemonic, password, count.max(min_count))
+    }
+
+    /// Suggests a managed account for an unknown one.
+    ///
+    /// This picks the managed account whose hex representation differs in the
+    /// fewest digits, as long as it is close enough to likely be a typo.
+    fn suggest(&self, account: Address) -> Option<Address> {
+        const MAX_DIFFERING_DIGITS: usize = 4;
 
-        Ok(Self {
-            addresses,
-            accounts,
+        let digits = |address: &Address| {
+            address
+                .0
+                .iter()
+                .flat_map(|byte| [byte >> 4, byte & 0xf])
+                .collect::<Vec<_>>()
+        };
+        let target = digits(&account);
+        self.addresses
+            .iter()
+            .map(|address| {
+                let distance = digits(address)
+                    .iter()
+                    .zip(&target)
+                    .filter(|(a, b)| a != b)
+                    .count();
+                (distance, *address)
+            })
+            .filter(|(distance, _)| *distance <= MAX_DIFFERING_DIGITS)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, address)| address)
+    }
+
+    /// Returns a managed account.
+    fn account(&self, account: Address) -> Result<&Account> {
+        self.accounts.get(&account.0).ok_or_else(|| {
+            UnknownSignerError {
+                account,
+                suggestion: self.suggest(account),
+            }
+            .into()
         })
     }
 
     /// Signs a raw message with the specified account.
     fn sign(&self, account: Address, signing_message: [u8; 32]) -> Result<Signature> {
-        let private_key = self
-            .accounts
-            .get(&account.0)
-            .ok_or(UnknownSignerError(account))?;
-        Ok(private_key.sign(signing_message))
+        let key = &self.account(account)?.key;
+        request::ensure_not_preview()?;
+        match key {
+            Key::Derived(private_key) => Ok(private_key.sign(signing_message)),
+            Key::Imported(secret_key) => {
+                let message = Message::from_slice(&signing_message)?;
+                let (recovery_id, bytes) = self
+                    .context
+                    .sign_ecdsa_recoverable(&message, secret_key)
+                    .serialize_compact();
+                Ok(Signature {
+                    r: bytes[..32].try_into()?,
+                    s: bytes[32..].try_into()?,
+                    y_parity: recovery_id.to_i32() == 1,
+                })
+            }
+        }
     }
 }
 
@@ -54,6 +235,36 @@ impl Signing for Wallet {
         &self.addresses
     }
 
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        let entry = self.account(account)?;
+        if let Some(public_key) = entry.public_key.get() {
+            return Ok(*public_key);
+        }
+
+        // Derived keys only keep their private key around, so recover the
+        // public key from a signature over an arbitrary digest. This is no
+        // signing operation, so it is allowed when previewing.
+        let public_key = match &entry.key {
+            Key::Derived(private_key) => {
+                let digest = [1_u8; 32];
+                recovery::recover_public_key(digest, &private_key.sign(digest))?
+            }
+            Key::Imported(secret_key) => {
+                secp256k1::PublicKey::from_secret_key(&self.context, secret_key)
+                    .serialize_uncompressed()
+            }
+        };
+        ensure!(
+            recovery::address(&public_key) == account,
+            "recovered public key does not match account {account}",
+        );
+        Ok(*entry.public_key.get_or_init(|| public_key))
+    }
+
+    fn sign_hash(&self, account: Address, hash: [u8; 32]) -> Result<Signature> {
+        self.sign(account, hash)
+    }
+
     fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
         let message = EthereumMessage(message);
         self.sign(account, message.signing_message())
@@ -66,9 +277,107 @@ impl Signing for Wallet {
     fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
         self.sign(account, typed_data.signing_message())
     }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.sign_message(account, &user_operation.hash())
+    }
 }
 
 /// An error indicating that the signer is unknown.
 #[derive(Debug, Error)]
-#[error("unknown signer {0}")]
-pub struct UnknownSignerError(pub Address);
+#[error(
+    "unknown signer {account}{}",
+    .suggestion.map(|s| format!(", did you mean {s}?")).unwrap_or_default(),
+)]
+pub struct UnknownSignerError {
+    /// The unknown account.
+    pub account: Address,
+    /// A managed account that was likely meant instead.
+    pub suggestion: Option<Address>,
+}
+
+impl UnknownSignerError {
+    /// Drops the suggestion of an unknown signer error returned by a signer,
+    /// unless it is one of the specified visible accounts. Signers that hide
+    /// accounts from a request use this so that suggestions don't reveal them.
+    pub fn restrict<T>(result: Result<T>, visible: &[Address]) -> Result<T> {
+        result.map_err(|err| match err.downcast::<Self>() {
+            Ok(mut err) => {
+                if !matches!(err.suggestion, Some(suggestion) if visible.contains(&suggestion)) {
+                    err.suggestion = None;
+                }
+                err.into()
+            }
+            Err(err) => err,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_accounts_without_duplicates() {
+        let mnemonic = "test test test test test test test test test test test junk"
+            .parse()
+            .unwrap();
+        let mut wallet = Wallet::new(&mnemonic, "", 2).unwrap();
+        wallet.extend(Wallet::from_private_keys(
+            [
+                "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d",
+                "0x4646464646464646464646464646464646464646464646464646464646464646",
+            ]
+            .map(|key| key.parse().unwrap()),
+        ));
+
+        assert_eq!(
+            wallet.accounts(),
+            [
+                "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+                "0x70997970C51812dc3A010C7d01b50e0d17dc79C8",
+                "0x9d8A62f656a8d1615C1294fd71e9CFb3E4855A4F",
+            ]
+            .map(|address| address.parse().unwrap()),
+        );
+    }
+
+    #[test]
+    fn caches_public_keys() {
+        let mnemonic = "test test test test test test test test test test test junk"
+            .parse()
+            .unwrap();
+        let wallet = Wallet::new(&mnemonic, "", 1).unwrap();
+        let account = wallet.accounts()[0];
+
+        let public_key = wallet.public_key(account).unwrap();
+        assert_eq!(recovery::address(&public_key), account);
+        assert_eq!(
+            wallet.accounts[&account.0].public_key.get(),
+            Some(&public_key)
+        );
+        assert_eq!(wallet.public_key(account).unwrap(), public_key);
+    }
+
+    #[test]
+    fn signs_with_imported_keys() {
+        let key = "59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d";
+        let imported = Wallet::from_secret_keys([key.parse().unwrap()]);
+        let derived = Wallet::from_private_keys([key.parse().unwrap()]);
+        let account = derived.accounts()[0];
+
+        assert_eq!(imported.accounts(), [account]);
+        assert_eq!(
+            imported.public_key(account).unwrap(),
+            derived.public_key(account).unwrap(),
+        );
+        assert_eq!(
+            imported.sign_message(account, b"hello").unwrap(),
+            derived.sign_message(account, b"hello").unwrap(),
+        );
+    }
+}
@@ -0,0 +1,258 @@
+//! A signer backed by a remote Web3Signer instance.
+//!
+//! This allows hdnode to act as the policy, filling and proxying layer in front
+//! of an existing managed signer. Requests use the Web3Signer Ethereum JSON RPC
+//! API, which is also implemented by Clef-compatible signers.
+
+use super::{
+    request, wallet::UnknownSignerError, PublicKey, Signing, Transaction, TypedData, UserOperation,
+};
+use crate::{
+    jsonrpc::{self, Id, JsonRpc, Params, Request},
+    recovery, runtime,
+    serialization::{Bytes, Str},
+};
+use anyhow::{bail, ensure, Context as _, Result};
+use hdwallet::{
+    account::{Address, Signature},
+    message::EthereumMessage,
+};
+use reqwest::Url;
+use rocket::serde::{json::serde_json, DeserializeOwned, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static ID: AtomicU64 = AtomicU64::new(1);
+
+/// A signer that forwards signing requests to a remote Web3Signer.
+pub struct Web3Signer {
+    client: jsonrpc::Client,
+    accounts: Vec<Address>,
+}
+
+impl Web3Signer {
+    /// Connects to a remote signer, reading the accounts it manages.
+    pub async fn connect(url: Url, options: jsonrpc::ClientOptions) -> Result<Self> {
+        let client = jsonrpc::Client::new(url, options)?;
+        let accounts = call::<_, Vec<Str<Address>>>(&client, "eth_accounts", [(); 0])
+            .await
+            .context("failed to read remote signer accounts")?
+            .into_iter()
+            .map(|account| account.0)
+            .collect();
+
+        Ok(Self { client, accounts })
+    }
+
+    /// Performs a remote signer call, blocking the current thread until it
+    /// completes.
+    fn call<I, O>(&self, method: &'static str, params: I) -> Result<O>
+    where
+        I: Serialize,
+        O: DeserializeOwned,
+    {
+        // The `Signing` trait is synchronous, so block in place to allow the
+        // runtime to keep making progress on other tasks in the meantime.
+        runtime::block_on(call(&self.client, method, params))?
+    }
+
+    /// Ensures the account is managed by the remote signer.
+    fn ensure_account(&self, account: Address) -> Result<()> {
+        if !self.accounts.contains(&account) {
+            return Err(UnknownSignerError {
+                account,
+                suggestion: None,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Signs a message with the remote signer's `eth_sign`, returning the
+    /// signature over its EIP-191 signing message.
+    fn eth_sign(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        self.ensure_account(account)?;
+        request::ensure_not_preview()?;
+        let signature =
+            self.call::<_, Bytes<Vec<u8>>>("eth_sign", (Str(account), Bytes(message.to_vec())))?;
+        verified(
+            account,
+            EthereumMessage(message).signing_message(),
+            recovery::parse_signature(&signature.0)?,
+        )
+    }
+}
+
+impl Signing for Web3Signer {
+    fn accounts(&self) -> &[Address] {
+        &self.accounts
+    }
+
+    fn public_key(&self, account: Address) -> Result<PublicKey> {
+        // Remote signers don't expose public keys over JSON RPC, so recover it
+        // from a signature over an arbitrary message.
+        let message = b"hdnode public key";
+        let signature = self.eth_sign(account, message)?;
+        recovery::recover_public_key(EthereumMessage(message).signing_message(), &signature)
+    }
+
+    fn sign_hash(&self, _: Address, _: [u8; 32]) -> Result<Signature> {
+        bail!("remote signer does not support signing raw hashes")
+    }
+
+    fn sign_message(&self, account: Address, message: &[u8]) -> Result<Signature> {
+        self.eth_sign(account, message)
+    }
+
+    fn sign_transaction(&self, account: Address, transaction: &Transaction) -> Result<Signature> {
+        self.ensure_account(account)?;
+        request::ensure_not_preview()?;
+        let signed = self.call::<_, Bytes<Vec<u8>>>("eth_signTransaction", (transaction,))?;
+        verified(
+            account,
+            transaction.signing_message(),
+            transaction_signature(&signed.0)?,
+        )
+    }
+
+    fn sign_typed_data(&self, account: Address, typed_data: &TypedData) -> Result<Signature> {
+        self.ensure_account(account)?;
+        request::ensure_not_preview()?;
+        let signature =
+            self.call::<_, Bytes<Vec<u8>>>("eth_signTypedData", (Str(account), typed_data))?;
+        verified(
+            account,
+            typed_data.signing_message(),
+            recovery::parse_signature(&signature.0)?,
+        )
+    }
+
+    fn sign_user_operation(
+        &self,
+        account: Address,
+        user_operation: &UserOperation,
+    ) -> Result<Signature> {
+        self.eth_sign(account, &user_operation.hash())
+    }
+}
+
+/// Performs a JSON RPC call.
+async fn call<I, O>(client: &jsonrpc::Client, method: &'static str, params: I) -> Result<O>
+where
+    I: Serialize,
+    O: DeserializeOwned,
+{
+    let request = Request {
+        jsonrpc: JsonRpc::V2,
+        method: method.to_owned(),
+        params: Some(Params::new(params)?),
+        id: Id::Number(ID.fetch_add(1, Ordering::SeqCst).into()),
+    };
+    let response = client.execute(&request).await?;
+    Ok(serde_json::from_str(response.result?.get())?)
+}
+
+/// Ensures that a signature returned by the remote signer is from the expected
+/// account over the expected signing message.
+fn verified(account: Address, message: [u8; 32], signature: Signature) -> Result<Signature> {
+    let public_key = recovery::recover_public_key(message, &signature)?;
+    ensure!(
+        recovery::address(&public_key) == account,
+        "remote signer returned a signature from the wrong account",
+    );
+    Ok(signature)
+}
+
+/// Extracts the signature from an RLP encoded signed transaction, from the
+/// trailing `v`, `r` and `s` fields.
+fn transaction_signature(signed: &[u8]) -> Result<Signature> {
+    // Typed transactions are prefixed with their type.
+    let list = match signed.first() {
+        Some(0..=0x7f) => &signed[1..],
+        _ => signed,
+    };
+    let (payload, _) = rlp_item(list)?;
+    let mut fields = Vec::new();
+    let mut rest = payload;
+    while !rest.is_empty() {
+        let (field, remaining) = rlp_item(rest)?;
+        fields.push(field);
+        rest = remaining;
+    }
+    ensure!(fields.len() >= 3, "invalid signed transaction");
+
+    let word = |bytes: &[u8]| -> Result<[u8; 32]> {
+        ensure!(bytes.len() <= 32, "invalid signature component");
+        let mut word = [0; 32];
+        word[32 - bytes.len()..].copy_from_slice(bytes);
+        Ok(word)
+    };
+    let v = fields[fields.len() - 3]
+        .iter()
+        .try_fold(0_u64, |v, byte| {
+            v.checked_mul(256).map(|v| v + u64::from(*byte))
+        })
+        .context("invalid signature v value")?;
+    Ok(Signature {
+        r: word(fields[fields.len() - 2])?,
+        s: word(fields[fields.len() - 1])?,
+        y_parity: recovery::y_parity(v)?,
+    })
+}
+
+/// Decodes a single RLP item, returning its payload and the remaining data.
+fn rlp_item(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    let prefix = *data.first().context("unexpected end of RLP data")?;
+    let (offset, len) = match prefix {
+        0x00..=0x7f => (0, 1),
+        0x80..=0xb7 => (1, usize::from(prefix - 0x80)),
+        0xc0..=0xf7 => (1, usize::from(prefix - 0xc0)),
+        0xb8..=0xbf | 0xf8..=0xff => {
+            let size = usize::from(if prefix >= 0xf8 {
+                prefix - 0xf7
+            } else {
+                prefix - 0xb7
+            });
+            let bytes = data
+                .get(1..1 + size)
+                .context("unexpected end of RLP data")?;
+            let len = bytes
+                .iter()
+                .try_fold(0_usize, |len, byte| {
+                    len.checked_mul(256).map(|len| len + usize::from(*byte))
+                })
+                .context("RLP length overflow")?;
+            (1 + size, len)
+        }
+    };
+    let end = offset
+        .checked_add(len)
+        .filter(|end| *end <= data.len())
+        .context("unexpected end of RLP data")?;
+    Ok((&data[offset..end], &data[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_transaction_signature() {
+        // Signed transaction from the EIP-155 example.
+        let signed = hex::decode(
+            "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000\
+             8025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f\
+             761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83",
+        )
+        .unwrap();
+        let signature = transaction_signature(&signed).unwrap();
+        assert_eq!(
+            hex::encode(signature.r),
+            "28ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276",
+        );
+        assert_eq!(
+            hex::encode(signature.s),
+            "67cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83",
+        );
+        assert!(!signature.y_parity);
+    }
+}
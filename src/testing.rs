@@ -0,0 +1,407 @@
+//! Module with test support utilities.
+//!
+//! This provides an in-process mock of a remote node, so that tests can run
+//! the full request pipeline without a real Ethereum node. Golden signed
+//! transaction fixtures guard against regressions in transaction encoding,
+//! and the `anvil-tests` feature additionally runs them against Anvil.
+
+#[cfg(feature = "anvil-tests")]
+mod anvil;
+
+use crate::cli;
+use reqwest::Url;
+use rocket::{
+    figment::Figment,
+    http::Header,
+    local::asynchronous::Client,
+    serde::{
+        json::{serde_json, Value},
+        Deserialize,
+    },
+    tokio::{
+        self,
+        io::{AsyncReadExt as _, AsyncWriteExt as _},
+        net::{TcpListener, TcpStream},
+        task::JoinHandle,
+    },
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A programmed reply to a mock remote node method.
+#[derive(Clone, Debug)]
+pub enum Reply {
+    /// A successful result.
+    Result(Value),
+    /// A JSON RPC error with the specified code and message.
+    Error(i64, String),
+}
+
+/// An in-process mock of a remote node JSON RPC HTTP endpoint.
+///
+/// Methods return programmed replies, and calls to methods without one fail
+/// with a "method not found" error. All requests are recorded so tests can
+/// assert on the calls made to the remote node.
+pub struct MockUpstream {
+    url: Url,
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct State {
+    replies: HashMap<String, Reply>,
+    latency: Duration,
+    http_failure: Option<u16>,
+    reverse_batches: bool,
+    requests: Vec<Value>,
+    batch_sizes: Vec<usize>,
+}
+
+impl MockUpstream {
+    /// Starts a new mock remote node listening on a local port.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let task = tokio::spawn({
+            let state = state.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(connection(stream, state.clone()));
+                }
+            }
+        });
+
+        Self { url, state, task }
+    }
+
+    /// Returns the URL of the mock remote node.
+    pub fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    /// Programs a successful result for a method.
+    pub fn respond(&self, method: &str, result: Value) {
+        self.reply(method, Reply::Result(result));
+    }
+
+    /// Programs an error for a method.
+    pub fn fail(&self, method: &str, code: i64, message: &str) {
+        self.reply(method, Reply::Error(code, message.to_owned()));
+    }
+
+    /// Programs a reply for a method.
+    pub fn reply(&self, method: &str, reply: Reply) {
+        self.state
+            .lock()
+            .unwrap()
+            .replies
+            .insert(method.to_owned(), reply);
+    }
+
+    /// Sets the latency added to every HTTP response.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    /// Makes every HTTP request fail with the specified status code, or
+    /// clears the failure when `None`.
+    pub fn set_http_failure(&self, status: Option<u16>) {
+        self.state.lock().unwrap().http_failure = status;
+    }
+
+    /// Makes batch responses come back in reverse order, like some providers
+    /// that don't preserve the order of batches.
+    pub fn set_reverse_batches(&self, reverse: bool) {
+        self.state.lock().unwrap().reverse_batches = reverse;
+    }
+
+    /// Returns all individual JSON RPC requests received so far, with
+    /// batches flattened.
+    pub fn requests(&self) -> Vec<Value> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Returns the sizes of all batches received so far.
+    pub fn batch_sizes(&self) -> Vec<usize> {
+        self.state.lock().unwrap().batch_sizes.clone()
+    }
+
+    /// Returns the methods of all requests received so far.
+    pub fn methods(&self) -> Vec<String> {
+        self.requests()
+            .iter()
+            .map(|request| request["method"].as_str().unwrap_or_default().to_owned())
+            .collect()
+    }
+}
+
+impl Drop for MockUpstream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// The first development account.
+pub const ACCOUNT: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+
+/// Starts a development node using the specified mock remote node.
+pub async fn client(upstream: &MockUpstream) -> Client {
+    client_with(upstream, |figment| figment).await
+}
+
+/// Starts a development node using the specified mock remote node, with
+/// additional configuration.
+pub async fn client_with(
+    upstream: &MockUpstream,
+    configure: impl FnOnce(Figment) -> Figment,
+) -> Client {
+    upstream.respond("eth_chainId", serde_json::json!("0x1"));
+    let figment = cli::dev_figment(rocket::Config::figment())
+        .merge(("remote_node_url", upstream.url().as_str()));
+    Client::tracked(crate::rocket(configure(figment)))
+        .await
+        .unwrap()
+}
+
+/// Calls an RPC method on a node, returning the JSON RPC response.
+pub async fn call(client: &Client, method: &str, params: Value) -> Value {
+    client
+        .post("/")
+        .json(&serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": 1,
+        }))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap()
+}
+
+/// Calls an admin RPC method on a node configured with the `secret` admin
+/// token, returning the JSON RPC response.
+pub async fn call_as_admin(client: &Client, method: &str, params: Value) -> Value {
+    client
+        .post("/")
+        .header(Header::new("Authorization", "Bearer secret"))
+        .json(&serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": 1,
+        }))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap()
+}
+
+/// A golden signed transaction fixture.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TransactionFixture {
+    /// A short description of the transaction.
+    pub name: String,
+    /// The fully specified `eth_signTransaction` request.
+    pub transaction: Value,
+    /// The expected raw signed transaction.
+    pub raw: String,
+}
+
+/// Returns the golden signed transaction fixtures. They are signed by the
+/// first development account for Anvil's default chain ID, and use
+/// consecutive nonces so they can be submitted to a fresh Anvil node in order.
+pub fn transaction_fixtures() -> Vec<TransactionFixture> {
+    serde_json::from_str(include_str!("../fixtures/transactions.json")).unwrap()
+}
+
+/// Serves a single HTTP request on a connection.
+async fn connection(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+    let body = match read_request(&mut stream).await {
+        Some(body) => body,
+        None => return,
+    };
+
+    let (latency, status, body) = {
+        let mut state = state.lock().unwrap();
+        let (status, body) = match state.http_failure {
+            Some(status) => (status, String::new()),
+            None => (200, handle(&mut state, &body)),
+        };
+        (state.latency, status, body)
+    };
+    tokio::time::sleep(latency).await;
+
+    let response = format!(
+        "HTTP/1.1 {status} Mock\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {body}",
+        body.len(),
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Reads an HTTP request from the stream, returning its body.
+async fn read_request(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut buffer = Vec::new();
+    let header_end = loop {
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let mut chunk = [0; 4096];
+        let len = stream.read(&mut chunk).await.ok()?;
+        if len == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..len]);
+    };
+
+    let headers = String::from_utf8_lossy(&buffer[..header_end]).to_lowercase();
+    let content_length = headers
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let mut body = buffer.split_off(header_end);
+    while body.len() < content_length {
+        let mut chunk = [0; 4096];
+        let len = stream.read(&mut chunk).await.ok()?;
+        if len == 0 {
+            return None;
+        }
+        body.extend_from_slice(&chunk[..len]);
+    }
+    Some(body)
+}
+
+/// Handles a JSON RPC request or batch, returning the serialized response.
+fn handle(state: &mut State, body: &[u8]) -> String {
+    let response = match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Array(requests)) => {
+            state.batch_sizes.push(requests.len());
+            let mut responses = requests
+                .into_iter()
+                .map(|request| reply(state, request))
+                .collect::<Vec<_>>();
+            if state.reverse_batches {
+                responses.reverse();
+            }
+            Value::Array(responses)
+        }
+        Ok(request) => reply(state, request),
+        Err(_) => serde_json::json!({
+            "jsonrpc": "2.0",
+            "error": { "code": -32700, "message": "Parse error" },
+            "id": null,
+        }),
+    };
+    response.to_string()
+}
+
+/// Handles a single JSON RPC request.
+fn reply(state: &mut State, request: Value) -> Value {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let reply = request["method"]
+        .as_str()
+        .and_then(|method| state.replies.get(method))
+        .cloned()
+        .unwrap_or_else(|| Reply::Error(-32601, "Method not found".to_owned()));
+    state.requests.push(request);
+
+    match reply {
+        Reply::Result(result) => serde_json::json!({
+            "jsonrpc": "2.0",
+            "result": result,
+            "id": id,
+        }),
+        Reply::Error(code, message) => serde_json::json!({
+            "jsonrpc": "2.0",
+            "error": { "code": code, "message": message },
+            "id": id,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::{Client, ClientOptions, Id, JsonRpc, Params, Request};
+    use std::time::Instant;
+
+    #[rocket::async_test]
+    async fn mock_upstream_replies() {
+        let upstream = MockUpstream::start().await;
+        upstream.respond("eth_chainId", serde_json::json!("0x1"));
+        upstream.set_latency(Duration::from_millis(50));
+
+        let client = Client::new(upstream.url(), ClientOptions::default()).unwrap();
+        let request = |method: &str, id: u64| Request {
+            jsonrpc: JsonRpc::V2,
+            method: method.to_owned(),
+            params: Some(Params::new(Vec::<()>::new()).unwrap()),
+            id: Id::Number(id.into()),
+        };
+
+        let start = Instant::now();
+        let responses = client
+            .execute_many(&[request("eth_chainId", 1), request("eth_call", 2)])
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(responses[0].result.as_ref().unwrap().get(), r#""0x1""#);
+        assert_eq!(responses[1].result.as_ref().unwrap_err().code, -32601);
+        assert_eq!(upstream.methods(), ["eth_chainId", "eth_call"]);
+    }
+
+    #[rocket::async_test]
+    async fn limits_upstream_batch_size() {
+        let upstream = MockUpstream::start().await;
+        upstream.respond("eth_chainId", serde_json::json!("0x1"));
+        let requests = (0..5)
+            .map(|id| Request {
+                jsonrpc: JsonRpc::V2,
+                method: "eth_chainId".to_owned(),
+                params: None,
+                id: Id::Number(id.into()),
+            })
+            .collect::<Vec<_>>();
+        let client = |max_batch_size| {
+            let options = ClientOptions {
+                max_batch_size: Some(max_batch_size),
+                ..Default::default()
+            };
+            Client::new(upstream.url(), options).unwrap()
+        };
+
+        let responses = client(2).execute_many(&requests).await.unwrap();
+        assert_eq!(responses.len(), 5);
+        assert!(responses
+            .iter()
+            .zip(&requests)
+            .all(|(response, request)| response.id == request.id));
+        let mut sizes = upstream.batch_sizes();
+        sizes.sort();
+        assert_eq!(sizes, [1, 2, 2]);
+
+        let responses = client(1).execute_many(&requests).await.unwrap();
+        assert_eq!(responses.len(), 5);
+        assert_eq!(upstream.batch_sizes().len(), 3);
+        assert_eq!(upstream.requests().len(), 10);
+    }
+}
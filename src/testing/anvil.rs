@@ -0,0 +1,287 @@
+//! Integration tests against an Anvil node.
+//!
+//! These run hdnode in front of a real development node, so that the signed
+//! transactions are checked by an independent implementation. They require
+//! Foundry's `anvil` binary, either on the `PATH` or at the path set by the
+//! `ANVIL` environment variable, and only run with the `anvil-tests` feature.
+
+use super::transaction_fixtures;
+use crate::cli;
+use rocket::{
+    figment::Figment,
+    local::asynchronous::Client,
+    serde::json::{serde_json::json, Value},
+    tokio,
+};
+use std::{
+    env,
+    net::TcpListener,
+    process::{self, Child, Command, Stdio},
+    time::Duration,
+};
+
+/// The first development account.
+const ACCOUNT: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+
+/// The second development account, receiving transfers.
+const RECIPIENT: &str = "0x70997970c51812dc3a010c7d01b50e0d17dc79c8";
+
+/// The third development account, which the test validator policy denies
+/// transactions to.
+const DENIED: &str = "0x3c44cdddb6a900fa2b585dd299e03d12fa4293bc";
+
+/// A fresh Anvil node, killed when dropped.
+struct Anvil {
+    process: Child,
+    url: String,
+}
+
+impl Anvil {
+    /// Spawns an Anvil node on a free local port, and waits for it to serve
+    /// requests.
+    async fn spawn() -> Self {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let program = env::var("ANVIL").unwrap_or_else(|_| "anvil".to_owned());
+        let process = Command::new(&program)
+            .args(["--port", &port.to_string()])
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap_or_else(|err| panic!("failed to spawn '{program}': {err}"));
+        let anvil = Self {
+            process,
+            url: format!("http://127.0.0.1:{port}"),
+        };
+
+        for _ in 0..100 {
+            if anvil.call("eth_chainId", json!([])).await.is_ok() {
+                return anvil;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("Anvil did not start serving requests");
+    }
+
+    /// Calls a method on the Anvil node directly, bypassing hdnode.
+    async fn call(&self, method: &str, params: Value) -> reqwest::Result<Value> {
+        reqwest::Client::new()
+            .post(&self.url)
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "method": method,
+                "params": params,
+                "id": 1,
+            }))
+            .send()
+            .await?
+            .json()
+            .await
+    }
+
+    /// Starts hdnode in development mode, proxying the Anvil node.
+    async fn hdnode(&self, configure: impl FnOnce(Figment) -> Figment) -> Client {
+        let figment = cli::dev_figment(rocket::Config::figment())
+            .merge(("remote_node_url", self.url.as_str()));
+        Client::tracked(crate::rocket(configure(figment)))
+            .await
+            .unwrap()
+    }
+}
+
+impl Drop for Anvil {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+async fn call(client: &Client, method: &str, params: Value) -> Value {
+    client
+        .post("/")
+        .json(&json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": 1,
+        }))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap()
+}
+
+/// Returns the receipt of a transaction, waiting for it to be mined.
+async fn receipt(client: &Client, hash: &Value) -> Value {
+    for _ in 0..50 {
+        let response = call(client, "eth_getTransactionReceipt", json!([hash])).await;
+        if !response["result"].is_null() {
+            return response["result"].clone();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("transaction {hash} was not mined");
+}
+
+#[rocket::async_test]
+async fn accepts_golden_transactions() {
+    let anvil = Anvil::spawn().await;
+    let client = anvil.hdnode(|figment| figment).await;
+
+    for fixture in transaction_fixtures() {
+        let response = call(&client, "eth_sendRawTransaction", json!([fixture.raw])).await;
+        let receipt = receipt(&client, &response["result"]).await;
+        assert_eq!(receipt["status"], "0x1", "{}", fixture.name);
+    }
+}
+
+#[rocket::async_test]
+async fn sends_all_transaction_types() {
+    let anvil = Anvil::spawn().await;
+    let client = anvil.hdnode(|figment| figment).await;
+
+    let transactions = [
+        (
+            "0x0",
+            json!({ "from": ACCOUNT, "to": RECIPIENT, "value": "0x1", "gasPrice": "0x2540be400" }),
+        ),
+        (
+            "0x1",
+            json!({
+                "from": ACCOUNT,
+                "to": RECIPIENT,
+                "value": "0x1",
+                "gasPrice": "0x2540be400",
+                "accessList": [[DENIED, []]],
+            }),
+        ),
+        (
+            "0x2",
+            json!({ "from": ACCOUNT, "to": RECIPIENT, "value": "0x1" }),
+        ),
+    ];
+    for (kind, transaction) in transactions {
+        let response = call(&client, "eth_sendTransaction", json!([transaction])).await;
+        let hash = &response["result"];
+        assert_eq!(receipt(&client, hash).await["status"], "0x1", "type {kind}");
+
+        let mined = call(&client, "eth_getTransactionByHash", json!([hash])).await;
+        assert_eq!(mined["result"]["type"], kind);
+        assert_eq!(mined["result"]["from"], ACCOUNT);
+    }
+}
+
+#[rocket::async_test]
+async fn signs_messages_like_anvil() {
+    let anvil = Anvil::spawn().await;
+    let client = anvil.hdnode(|figment| figment).await;
+
+    let params = json!([ACCOUNT, "0x68656c6c6f"]);
+    assert_eq!(
+        call(&client, "eth_sign", params.clone()).await["result"],
+        anvil.call("eth_sign", params).await.unwrap()["result"],
+    );
+
+    let typed_data = json!({
+        "types": {
+            "EIP712Domain": [
+                { "name": "name", "type": "string" },
+                { "name": "chainId", "type": "uint256" },
+            ],
+            "Mail": [
+                { "name": "to", "type": "address" },
+                { "name": "contents", "type": "string" },
+            ],
+        },
+        "primaryType": "Mail",
+        "domain": { "name": "hdnode", "chainId": 31337 },
+        "message": { "to": RECIPIENT, "contents": "Hello, Anvil!" },
+    });
+    assert_eq!(
+        call(&client, "eth_signTypedData", json!([ACCOUNT, typed_data])).await["result"],
+        anvil
+            .call("eth_signTypedData_v4", json!([ACCOUNT, typed_data]))
+            .await
+            .unwrap()["result"],
+    );
+}
+
+#[rocket::async_test]
+async fn handles_batches() {
+    let anvil = Anvil::spawn().await;
+    let client = anvil.hdnode(|figment| figment).await;
+
+    let batch = [
+        ("eth_chainId", json!([])),
+        ("eth_accounts", json!([])),
+        ("eth_getBalance", json!([RECIPIENT, "latest"])),
+        ("eth_sign", json!([ACCOUNT, "0x"])),
+    ];
+    let responses: Value = client
+        .post("/")
+        .json(
+            &batch
+                .iter()
+                .enumerate()
+                .map(|(id, (method, params))| {
+                    json!({ "jsonrpc": "2.0", "method": method, "params": params, "id": id })
+                })
+                .collect::<Vec<_>>(),
+        )
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+
+    for (id, response) in responses.as_array().unwrap().iter().enumerate() {
+        assert_eq!(response["id"], id);
+    }
+    assert_eq!(responses[0]["result"], "0x7a69");
+    assert_eq!(responses[1]["result"][0], ACCOUNT);
+    assert_eq!(responses[2]["result"], "0x21e19e0c9bab2400000");
+    assert!(responses[3]["result"].is_string());
+}
+
+#[rocket::async_test]
+async fn enforces_validator_policies() {
+    let anvil = Anvil::spawn().await;
+    let path = env::temp_dir().join(format!("hdnode-anvil-{}.lua", process::id()));
+    std::fs::write(
+        &path,
+        format!(
+            "function validate_transaction(account, transaction)\n    \
+                 return string.lower(transaction.to or \"\") ~= \"{DENIED}\"\n\
+             end\n"
+        ),
+    )
+    .unwrap();
+    let client = anvil
+        .hdnode(|figment| figment.merge(("validator", &path)))
+        .await;
+
+    let send = |to| {
+        call(
+            &client,
+            "eth_sendTransaction",
+            json!([{ "from": ACCOUNT, "to": to }]),
+        )
+    };
+    let allowed = send(RECIPIENT).await;
+    assert_eq!(receipt(&client, &allowed["result"]).await["status"], "0x1");
+    let denied = send(DENIED).await;
+    assert!(denied["error"].is_object());
+
+    let nonce = call(
+        &client,
+        "eth_getTransactionCount",
+        json!([ACCOUNT, "latest"]),
+    )
+    .await;
+    assert_eq!(nonce["result"], "0x1");
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,403 @@
+//! Module implementing the WebSocket RPC endpoint.
+//!
+//! WebSocket clients can make the same requests as HTTP clients, and can
+//! additionally use `eth_subscribe` to receive notifications from the remote
+//! node.
+
+pub mod subscription;
+
+use self::subscription::{Hub, Subscription};
+use crate::{
+    acl::RpcClient,
+    jsonrpc::{self, Id, JsonRpc, Params, Request, Response},
+    node::{self, session::Session as ClientSession, Caller, Input, InputLimits, Node, Output},
+};
+use anyhow::Result;
+use rocket::{
+    data::{IoHandler, IoStream},
+    futures::{SinkExt as _, StreamExt as _},
+    http::Status,
+    request::{self, FromRequest},
+    response::{self, Responder},
+    serde::json::serde_json::{self, value::RawValue},
+    tokio::{self, io, sync::mpsc},
+    State,
+};
+use std::{collections::HashMap, pin::Pin, sync::Arc};
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::derive_accept_key,
+        protocol::{Role, WebSocketConfig},
+        Message,
+    },
+    WebSocketStream,
+};
+
+/// The WebSocket endpoint state, managed when WebSocket connections are
+/// enabled.
+pub struct Endpoint {
+    /// The subscription hub, if a remote node WebSocket URL is configured.
+    pub hub: Option<Arc<Hub>>,
+}
+
+/// Upgrades HTTP requests to WebSocket RPC connections. The upgrade request
+/// goes through the same guards as HTTP RPC requests, so the RPC allowlist
+/// and client identities apply, and messages are subject to the same input
+/// limits as HTTP request bodies.
+#[rocket::get("/")]
+pub fn handler(
+    _client: RpcClient,
+    handshake: Handshake,
+    caller: Caller,
+    node: &State<Arc<Node>>,
+    endpoint: &State<Endpoint>,
+) -> Upgrade {
+    Upgrade {
+        accept: derive_accept_key(handshake.key.as_bytes()),
+        connection: Connection {
+            caller,
+            limits: handshake.limits,
+            node: node.inner().clone(),
+            hub: endpoint.hub.clone(),
+        },
+    }
+}
+
+/// Request guard for WebSocket upgrade requests. Other requests are
+/// forwarded.
+pub struct Handshake {
+    key: String,
+    limits: InputLimits,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Handshake {
+    type Error = ();
+
+    async fn from_request(request: &'r rocket::Request<'_>) -> request::Outcome<Self, ()> {
+        let headers = request.headers();
+        let upgrade = headers
+            .get_one("Upgrade")
+            .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+        match headers.get_one("Sec-WebSocket-Key") {
+            Some(key) if upgrade => request::Outcome::Success(Self {
+                key: key.to_owned(),
+                limits: InputLimits::of(request),
+            }),
+            _ => request::Outcome::Forward(Status::BadRequest),
+        }
+    }
+}
+
+/// A response upgrading the connection to a WebSocket.
+pub struct Upgrade {
+    accept: String,
+    connection: Connection,
+}
+
+impl<'r> Responder<'r, 'static> for Upgrade {
+    fn respond_to(self, _: &'r rocket::Request<'_>) -> response::Result<'static> {
+        rocket::Response::build()
+            .status(Status::SwitchingProtocols)
+            .raw_header("Sec-WebSocket-Accept", self.accept)
+            .upgrade("websocket", self.connection)
+            .ok()
+    }
+}
+
+/// A WebSocket connection, handled once the HTTP connection is upgraded.
+struct Connection {
+    caller: Caller,
+    limits: InputLimits,
+    node: Arc<Node>,
+    hub: Option<Arc<Hub>>,
+}
+
+#[rocket::async_trait]
+impl IoHandler for Connection {
+    async fn io(self: Pin<Box<Self>>, io: IoStream) -> io::Result<()> {
+        let Connection {
+            caller,
+            limits,
+            node,
+            hub,
+        } = *Pin::into_inner(self);
+        let peer = caller.ip;
+        if let Err(err) = connection(io, caller, limits, node, hub).await {
+            tracing::debug!(?err, ?peer, "WebSocket connection error");
+        }
+        Ok(())
+    }
+}
+
+/// Handles a single WebSocket connection.
+async fn connection(
+    io: IoStream,
+    caller: Caller,
+    limits: InputLimits,
+    node: Arc<Node>,
+    hub: Option<Arc<Hub>>,
+) -> Result<()> {
+    // Messages exceeding the size limit are rejected by the WebSocket
+    // library, which closes the connection.
+    let max_size = usize::try_from(limits.max_size.as_u64()).ok();
+    let config = WebSocketConfig {
+        max_message_size: max_size,
+        max_frame_size: max_size,
+        ..Default::default()
+    };
+    let (mut sink, mut source) = WebSocketStream::from_raw_socket(io, Role::Server, Some(config))
+        .await
+        .split();
+
+    // Responses and subscription notifications are written from different
+    // tasks, so funnel all outgoing messages through a channel.
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let writer = tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            if sink.send(Message::Text(message)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let session = Arc::new(ClientSession::new());
+    let mut session = Session {
+        caller: Caller {
+            session: Some(session),
+            ..caller
+        },
+        limits,
+        node,
+        hub,
+        sender,
+        subscriptions: HashMap::new(),
+    };
+    let result = session.run(&mut source).await;
+
+    session.close().await;
+    writer.abort();
+    result
+}
+
+/// A WebSocket client session.
+struct Session {
+    caller: Caller,
+    limits: InputLimits,
+    node: Arc<Node>,
+    hub: Option<Arc<Hub>>,
+    sender: mpsc::UnboundedSender<String>,
+    subscriptions: HashMap<String, Subscription>,
+}
+
+impl Session {
+    /// Handles messages from the client until the connection is closed.
+    async fn run<S>(&mut self, source: &mut S) -> Result<()>
+    where
+        S: rocket::futures::Stream<Item = tokio_tungstenite::tungstenite::Result<Message>> + Unpin,
+    {
+        while let Some(message) = source.next().await {
+            let text = match message? {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+
+            let output = self.handle(&text).await;
+            if self.sender.send(serde_json::to_string(&output)?).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a single request or batch.
+    async fn handle(&mut self, text: &str) -> Output {
+        match Input::parse(text, &self.limits) {
+            Ok(Input::Request(request)) => Output::Response(self.handle_request(request).await),
+            Ok(Input::Batch(batch)) if !batch.iter().flatten().any(is_subscription_request) => {
+                let timed_out = node::timed_out_batch(&batch);
+                Output::Batch(
+                    self.node
+                        .within_timeout(None, self.node.handle_batch(batch, &self.caller), || {
+                            timed_out
+                        })
+                        .await,
+                )
+            }
+            Ok(Input::Batch(batch)) => {
+                let mut responses = Vec::with_capacity(batch.len());
+                for entry in batch {
+                    responses.push(match entry {
+                        Ok(request) => self.handle_request(request).await,
+                        Err(response) => response,
+                    });
+                }
+                Output::Batch(responses)
+            }
+            Ok(Input::Unrecognized(data)) => {
+                tracing::debug!(%text, "WebSocket message is not a valid request or batch");
+                Output::Response(Response::invalid_request(&data))
+            }
+            Ok(Input::Rejected(err)) | Err(err) => {
+                tracing::debug!(%err, "WebSocket message rejected");
+                Output::Response(Response {
+                    jsonrpc: JsonRpc::V2,
+                    result: Err(err),
+                    id: Id::Null,
+                })
+            }
+        }
+    }
+
+    /// Handles a single request, including subscription requests.
+    async fn handle_request(&mut self, request: Request) -> Response {
+        let result = match request.method.as_str() {
+            "eth_subscribe" => self.subscribe(request.params).await,
+            "eth_unsubscribe" => self.unsubscribe(request.params).await,
+            _ => {
+                let timed_out = node::timed_out(&request);
+                return self
+                    .node
+                    .within_timeout(
+                        None,
+                        self.node.handle_request(request, &self.caller),
+                        || timed_out,
+                    )
+                    .await;
+            }
+        };
+        Response {
+            jsonrpc: request.jsonrpc,
+            result,
+            id: request.id,
+        }
+    }
+
+    /// Subscribes the client to remote node notifications.
+    async fn subscribe(&mut self, params: Option<Params>) -> Result<Box<RawValue>, jsonrpc::Error> {
+        let hub = self
+            .hub
+            .as_ref()
+            .ok_or_else(jsonrpc::Error::method_not_found)?;
+        let params = params.ok_or_else(jsonrpc::Error::invalid_params)?;
+
+        let subscription = hub.subscribe(params, self.sender.clone()).await?;
+        let id = serde_json::value::to_raw_value(subscription.id()).map_err(|err| {
+            tracing::error!(?err, "unexpected error serializing subscription ID");
+            jsonrpc::Error::internal_error()
+        })?;
+        self.subscriptions
+            .insert(subscription.id().to_owned(), subscription);
+        if let Some(session) = &self.caller.session {
+            session.subscribed();
+        }
+
+        Ok(id)
+    }
+
+    /// Unsubscribes the client from a subscription.
+    async fn unsubscribe(
+        &mut self,
+        params: Option<Params>,
+    ) -> Result<Box<RawValue>, jsonrpc::Error> {
+        let (id,) = params
+            .and_then(|params| params.parse::<(String,)>().ok())
+            .ok_or_else(jsonrpc::Error::invalid_params)?;
+
+        let removed = match (self.subscriptions.remove(&id), &self.hub) {
+            (Some(subscription), Some(hub)) => {
+                hub.unsubscribe(subscription).await;
+                if let Some(session) = &self.caller.session {
+                    session.unsubscribed();
+                }
+                true
+            }
+            _ => false,
+        };
+        Ok(serde_json::value::to_raw_value(&removed).unwrap())
+    }
+
+    /// Removes all of the session's subscriptions and tears down its state.
+    async fn close(&mut self) {
+        if let Some(hub) = &self.hub {
+            for (_, subscription) in self.subscriptions.drain() {
+                hub.unsubscribe(subscription).await;
+            }
+        }
+        if let Some(session) = &self.caller.session {
+            session.close();
+        }
+    }
+}
+
+/// Returns true if the request is a subscription method that needs to be
+/// handled by the session.
+fn is_subscription_request(request: &Request) -> bool {
+    matches!(request.method.as_str(), "eth_subscribe" | "eth_unsubscribe")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockUpstream, ACCOUNT};
+    use rocket::serde::json::{serde_json::json, Value};
+    use std::time::Duration;
+
+    #[rocket::async_test]
+    async fn serves_websocket_connections() {
+        use rocket::futures::{Sink, SinkExt as _, Stream, StreamExt as _};
+        use tokio_tungstenite::tungstenite::{Error, Message};
+
+        let upstream = MockUpstream::start().await;
+        upstream.respond("eth_chainId", json!("0x1"));
+        let serve = |allowlist: &'static str| {
+            let port = std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port();
+            let figment = crate::cli::dev_figment(rocket::Config::figment())
+                .merge(("remote_node_url", upstream.url().as_str()))
+                .merge(("address", "127.0.0.1"))
+                .merge(("port", port))
+                .merge(("websocket", true))
+                .merge(("max_request_size", 256))
+                .merge(("rpc_allowlist", [allowlist]));
+            rocket::tokio::spawn(crate::rocket(figment).launch());
+            format!("ws://127.0.0.1:{port}/")
+        };
+        let connect = |url: String| async move {
+            for _ in 0..50 {
+                match tokio_tungstenite::connect_async(&url).await {
+                    Err(Error::Io(_)) => {
+                        rocket::tokio::time::sleep(Duration::from_millis(100)).await
+                    }
+                    result => return result,
+                }
+            }
+            panic!("node did not start");
+        };
+
+        async fn call<S>(stream: &mut S, message: String) -> Result<Value, Error>
+        where
+            S: Sink<Message, Error = Error> + Stream<Item = Result<Message, Error>> + Unpin,
+        {
+            stream.send(Message::Text(message)).await?;
+            let response = stream.next().await.unwrap()?.into_text()?;
+            Ok(serde_json::from_str(&response).unwrap())
+        }
+
+        let (mut stream, _) = connect(serve("127.0.0.1/32")).await.unwrap();
+        let request = json!({ "jsonrpc": "2.0", "method": "eth_accounts", "params": [], "id": 1 });
+        let response = call(&mut stream, request.to_string()).await.unwrap();
+        assert_eq!(response["result"][0], ACCOUNT);
+        let response = call(&mut stream, "{".to_owned()).await.unwrap();
+        assert_eq!(response["error"]["code"], -32700);
+        let oversized = format!("[{}0]", "0,".repeat(256));
+        assert!(call(&mut stream, oversized).await.is_err());
+
+        let err = connect(serve("10.0.0.0/8")).await.unwrap_err();
+        assert!(matches!(err, Error::Http(response) if response.status() == 403));
+    }
+}
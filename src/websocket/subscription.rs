@@ -0,0 +1,559 @@
+//! Module implementing subscription fan-out.
+//!
+//! Node providers usually limit the number of concurrent subscriptions, so
+//! clients subscribing with the same parameters share a single subscription on
+//! the remote node whose notifications are fanned out locally. When the
+//! connection to the remote node is lost, the hub reconnects and subscribes
+//! again, so that client subscriptions survive remote node restarts.
+
+use crate::jsonrpc::{Id, JsonRpc, Params, Request, Response};
+use anyhow::{ensure, Context as _, Result};
+use reqwest::Url;
+use rocket::{
+    futures::{SinkExt as _, StreamExt as _},
+    serde::{
+        json::{
+            serde_json::{self, json, value::RawValue},
+            Value,
+        },
+        Deserialize, DeserializeOwned,
+    },
+    tokio::{
+        self,
+        sync::{broadcast, mpsc, oneshot, OnceCell},
+        task::JoinHandle,
+        time,
+    },
+};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+/// The number of notifications buffered for each subscription. Subscribers
+/// that fall further behind miss notifications.
+const CAPACITY: usize = 256;
+
+/// The timeout for connecting to the remote node.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The initial and maximum delays between attempts to resubscribe after the
+/// remote node connection was lost.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+static ID: AtomicU64 = AtomicU64::new(1);
+
+/// A hub for sharing remote node subscriptions between clients.
+pub struct Hub(Arc<Inner>);
+
+struct Inner {
+    url: Url,
+    max_subscriptions: Option<usize>,
+    /// Serializes connecting to the remote node, so that concurrent
+    /// subscribers share a connection. It is only held while connecting.
+    connecting: tokio::sync::Mutex<()>,
+    state: Mutex<State>,
+}
+
+/// The remote node connection and subscriptions. This is never locked
+/// across an `.await`.
+#[derive(Default)]
+struct State {
+    connection: Option<Connection>,
+    feeds: HashMap<String, Feed>,
+    resubscribing: bool,
+}
+
+/// A remote node subscription shared by local subscribers. Feeds outlive
+/// remote node connections, and are subscribed again after reconnecting.
+struct Feed {
+    params: Params,
+    /// The subscription ID issued by the remote node, or `None` while not
+    /// subscribed on the current connection.
+    remote_id: Option<String>,
+    /// Initialized once the feed was first subscribed on the remote node, so
+    /// that concurrent first subscribers wait for the same subscription.
+    subscribed: Arc<OnceCell<()>>,
+    sender: broadcast::Sender<Box<RawValue>>,
+    subscribers: usize,
+}
+
+/// A local client subscription.
+pub struct Subscription {
+    id: String,
+    key: String,
+    task: JoinHandle<()>,
+}
+
+impl Subscription {
+    /// Returns the subscription ID issued to the client.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl Hub {
+    /// Creates a new subscription hub for the remote node WebSocket URL.
+    /// Subscriptions on the remote node are limited to `max_subscriptions`
+    /// if specified.
+    pub fn new(url: Url, max_subscriptions: Option<usize>) -> Self {
+        Self(Arc::new(Inner {
+            url,
+            max_subscriptions,
+            connecting: tokio::sync::Mutex::new(()),
+            state: Mutex::new(State::default()),
+        }))
+    }
+
+    /// Subscribes a client to notifications for the specified `eth_subscribe`
+    /// parameters. Notifications are sent to the client as serialized JSON
+    /// RPC messages.
+    pub async fn subscribe(
+        &self,
+        params: Params,
+        client: mpsc::UnboundedSender<String>,
+    ) -> Result<Subscription> {
+        // Use the parsed JSON value as the key, so that equivalent parameters
+        // with different formatting share a subscription.
+        let key = params.parse::<Value>()?.to_string();
+
+        let (receiver, subscribed) = {
+            let mut state = self.0.state.lock().unwrap();
+            let feed_count = state.feeds.len();
+            let feed = match state.feeds.entry(key.clone()) {
+                Entry::Occupied(entry) => {
+                    let feed = entry.into_mut();
+                    feed.subscribers += 1;
+                    feed
+                }
+                Entry::Vacant(entry) => {
+                    ensure!(
+                        !matches!(self.0.max_subscriptions, Some(max) if feed_count >= max),
+                        "remote node subscription limit reached",
+                    );
+                    entry.insert(Feed {
+                        params,
+                        remote_id: None,
+                        subscribed: Arc::default(),
+                        sender: broadcast::channel(CAPACITY).0,
+                        subscribers: 1,
+                    })
+                }
+            };
+            (feed.sender.subscribe(), feed.subscribed.clone())
+        };
+
+        if let Err(err) = subscribed
+            .get_or_try_init(|| self.0.subscribe_remote(&key))
+            .await
+        {
+            self.0.release(&key).await;
+            return Err(err);
+        }
+
+        let id = format!("{:#x}", ID.fetch_add(1, Ordering::SeqCst));
+        let task = tokio::spawn(forward(id.clone(), receiver, client));
+        Ok(Subscription { id, key, task })
+    }
+
+    /// Removes a client subscription, unsubscribing from the remote node once
+    /// no more clients share it.
+    pub async fn unsubscribe(&self, subscription: Subscription) {
+        subscription.task.abort();
+        self.0.release(&subscription.key).await;
+    }
+}
+
+impl Inner {
+    /// Returns the remote node connection, connecting if necessary.
+    async fn connection(self: &Arc<Self>) -> Result<Connection> {
+        let _connecting = self.connecting.lock().await;
+        if let Some(connection) = self.current() {
+            return Ok(connection);
+        }
+
+        let connection = time::timeout(CONNECT_TIMEOUT, Connection::open(self))
+            .await
+            .context("timed out connecting to remote node WebSocket")??;
+        self.state.lock().unwrap().connection = Some(connection.clone());
+        Ok(connection)
+    }
+
+    /// Returns the current remote node connection, if it is open.
+    fn current(&self) -> Option<Connection> {
+        self.state
+            .lock()
+            .unwrap()
+            .connection
+            .clone()
+            .filter(|connection| !connection.is_closed())
+    }
+
+    /// Subscribes a feed on the remote node for the first time.
+    async fn subscribe_remote(self: &Arc<Self>, key: &str) -> Result<()> {
+        let connection = self.connection().await?;
+        let params = match self.state.lock().unwrap().feeds.get(key) {
+            Some(feed) => feed.params.clone(),
+            None => return Ok(()),
+        };
+        let remote_id = connection.call::<String>("eth_subscribe", params).await?;
+        self.attach(key, &connection, remote_id)
+    }
+
+    /// Records the remote node subscription of a feed. Remote subscriptions
+    /// that are no longer needed, because the feed was removed or subscribed
+    /// concurrently, are unsubscribed again.
+    fn attach(&self, key: &str, connection: &Connection, remote_id: String) -> Result<()> {
+        let attached = {
+            let mut state = self.state.lock().unwrap();
+            ensure!(
+                !connection.is_closed(),
+                "remote node WebSocket connection closed"
+            );
+            match state.feeds.get_mut(key) {
+                Some(feed) if feed.remote_id.is_none() => {
+                    tracing::debug!(%remote_id, %key, "subscribed on remote node");
+                    feed.remote_id = Some(remote_id.clone());
+                    true
+                }
+                _ => false,
+            }
+        };
+        if !attached {
+            tokio::spawn(unsubscribe_remote(connection.clone(), remote_id));
+        }
+        Ok(())
+    }
+
+    /// Releases a subscriber of a feed, removing the feed and unsubscribing
+    /// from the remote node once no more clients share it.
+    async fn release(&self, key: &str) {
+        let (connection, remote_id) = {
+            let mut state = self.state.lock().unwrap();
+            let feed = match state.feeds.get_mut(key) {
+                Some(feed) => feed,
+                None => return,
+            };
+            feed.subscribers -= 1;
+            if feed.subscribers > 0 {
+                return;
+            }
+            let feed = state.feeds.remove(key).unwrap();
+            (state.connection.clone(), feed.remote_id)
+        };
+        if let (Some(connection), Some(remote_id)) = (connection, remote_id) {
+            unsubscribe_remote(connection, remote_id).await;
+        }
+    }
+
+    /// Handles the remote node connection closing. Feeds are subscribed
+    /// again on a new connection in the background, so that client
+    /// subscriptions keep working after the remote node reconnects.
+    fn disconnected(self: &Arc<Self>) {
+        let mut state = self.state.lock().unwrap();
+        for feed in state.feeds.values_mut() {
+            feed.remote_id = None;
+        }
+        if !state.feeds.is_empty() && !state.resubscribing {
+            state.resubscribing = true;
+            tokio::spawn(self.clone().resubscribe());
+        }
+    }
+
+    /// Subscribes all feeds on the remote node again, retrying with
+    /// exponential backoff until it succeeds or no feeds remain.
+    async fn resubscribe(self: Arc<Self>) {
+        let mut delay = RETRY_DELAY;
+        loop {
+            time::sleep(delay).await;
+            match self.resubscribe_feeds().await {
+                Ok(()) => {
+                    let mut state = self.state.lock().unwrap();
+                    if state.feeds.values().all(|feed| feed.remote_id.is_some()) {
+                        state.resubscribing = false;
+                        return;
+                    }
+                }
+                Err(err) => tracing::warn!(?err, "failed to resubscribe on remote node"),
+            }
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+
+    /// Subscribes the feeds without a remote node subscription.
+    async fn resubscribe_feeds(self: &Arc<Self>) -> Result<()> {
+        let connection = self.connection().await?;
+        let feeds = self
+            .state
+            .lock()
+            .unwrap()
+            .feeds
+            .iter()
+            .filter(|(_, feed)| feed.remote_id.is_none())
+            .map(|(key, feed)| (key.clone(), feed.params.clone()))
+            .collect::<Vec<_>>();
+        for (key, params) in feeds {
+            let remote_id = connection.call::<String>("eth_subscribe", params).await?;
+            self.attach(&key, &connection, remote_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Unsubscribes from a remote node subscription.
+async fn unsubscribe_remote(connection: Connection, remote_id: String) {
+    let result = match Params::new([&remote_id]) {
+        Ok(params) => connection.call::<bool>("eth_unsubscribe", params).await,
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(_) => tracing::debug!(%remote_id, "unsubscribed on remote node"),
+        Err(err) => tracing::warn!(?err, %remote_id, "failed to unsubscribe on remote node"),
+    }
+}
+
+/// A WebSocket connection to the remote node.
+#[derive(Clone)]
+struct Connection {
+    sender: mpsc::UnboundedSender<Message>,
+    calls: Arc<Mutex<Calls>>,
+}
+
+/// The pending calls of a remote node connection.
+#[derive(Default)]
+struct Calls {
+    closed: bool,
+    pending: HashMap<u64, oneshot::Sender<Response>>,
+}
+
+impl Connection {
+    /// Opens a new WebSocket connection to the remote node of a hub.
+    async fn open(hub: &Arc<Inner>) -> Result<Self> {
+        let url = &hub.url;
+        let (stream, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .context("failed to connect to remote node WebSocket")?;
+        let (mut sink, mut source) = stream.split();
+        tracing::debug!(%url, "connected to remote node WebSocket");
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                if let Err(err) = sink.send(message).await {
+                    tracing::warn!(?err, "error writing to remote node WebSocket");
+                    break;
+                }
+            }
+        });
+
+        let calls = Arc::new(Mutex::new(Calls::default()));
+        let reader = calls.clone();
+        let hub = Arc::downgrade(hub);
+        tokio::spawn(async move {
+            while let Some(message) = source.next().await {
+                match message {
+                    Ok(Message::Text(text)) => match hub.upgrade() {
+                        Some(hub) => dispatch(&reader, &hub, &text),
+                        None => break,
+                    },
+                    Ok(Message::Close(_)) => break,
+                    Ok(_) => {}
+                    Err(err) => {
+                        tracing::warn!(?err, "error reading from remote node WebSocket");
+                        break;
+                    }
+                }
+            }
+
+            tracing::warn!("remote node WebSocket connection closed");
+            {
+                let mut calls = reader.lock().unwrap();
+                calls.closed = true;
+                calls.pending.clear();
+            }
+            if let Some(hub) = hub.upgrade() {
+                hub.disconnected();
+            }
+        });
+
+        Ok(Self { sender, calls })
+    }
+
+    /// Returns true if the connection was closed.
+    fn is_closed(&self) -> bool {
+        self.calls.lock().unwrap().closed
+    }
+
+    /// Performs an RPC call over the connection.
+    async fn call<T>(&self, method: &str, params: Params) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let id = ID.fetch_add(1, Ordering::SeqCst);
+        let (response_tx, response_rx) = oneshot::channel();
+        {
+            let mut calls = self.calls.lock().unwrap();
+            ensure!(!calls.closed, "remote node WebSocket connection closed");
+            calls.pending.insert(id, response_tx);
+        }
+
+        let request = Request {
+            jsonrpc: JsonRpc::V2,
+            method: method.to_owned(),
+            params: Some(params),
+            id: Id::Number(id.into()),
+        };
+        self.sender
+            .send(Message::Text(serde_json::to_string(&request)?))
+            .context("remote node WebSocket connection closed")?;
+
+        let response = response_rx
+            .await
+            .context("remote node WebSocket connection closed")?;
+        Ok(serde_json::from_str(response.result?.get())?)
+    }
+}
+
+/// Dispatches a message received from the remote node to either a pending
+/// call or a subscription feed.
+fn dispatch(calls: &Mutex<Calls>, hub: &Inner, text: &str) {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Notification {
+        params: NotificationParams,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct NotificationParams {
+        subscription: String,
+        result: Box<RawValue>,
+    }
+
+    if let Ok(response) = serde_json::from_str::<Response>(text) {
+        let pending = match &response.id {
+            Id::Number(id) => id
+                .as_u64()
+                .and_then(|id| calls.lock().unwrap().pending.remove(&id)),
+            _ => None,
+        };
+        match pending {
+            Some(pending) => {
+                let _ = pending.send(response);
+            }
+            None => tracing::debug!(?response, "unexpected remote node response"),
+        }
+        return;
+    }
+
+    match serde_json::from_str::<Notification>(text) {
+        Ok(Notification { params }) => {
+            let state = hub.state.lock().unwrap();
+            if let Some(feed) = state
+                .feeds
+                .values()
+                .find(|feed| feed.remote_id.as_deref() == Some(&params.subscription))
+            {
+                // Sending only fails if there are no subscribers left, which
+                // happens briefly while unsubscribing.
+                let _ = feed.sender.send(params.result);
+            }
+        }
+        Err(err) => tracing::debug!(?err, %text, "unexpected remote node message"),
+    }
+}
+
+/// Forwards notifications from a feed to a client.
+async fn forward(
+    id: String,
+    mut receiver: broadcast::Receiver<Box<RawValue>>,
+    client: mpsc::UnboundedSender<String>,
+) {
+    loop {
+        let result = match receiver.recv().await {
+            Ok(result) => result,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                tracing::warn!(%id, missed, "subscriber missed notifications");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+
+        let notification = json!({
+            "jsonrpc": JsonRpc::V2,
+            "method": "eth_subscription",
+            "params": {
+                "subscription": id,
+                "result": result,
+            },
+        });
+        if client.send(notification.to_string()).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::tokio::net::TcpListener;
+
+    #[rocket::async_test]
+    async fn resubscribes_after_reconnecting() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let remote = tokio::spawn(async move {
+            for remote_id in ["0xa", "0xb"] {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+                let request = match stream.next().await.unwrap().unwrap() {
+                    Message::Text(text) => serde_json::from_str::<Value>(&text).unwrap(),
+                    message => panic!("unexpected message {message:?}"),
+                };
+                assert_eq!(request["method"], "eth_subscribe");
+                let response =
+                    json!({ "jsonrpc": "2.0", "result": remote_id, "id": request["id"] });
+                stream
+                    .send(Message::Text(response.to_string()))
+                    .await
+                    .unwrap();
+
+                time::sleep(Duration::from_millis(100)).await;
+                let notification = json!({
+                    "jsonrpc": "2.0",
+                    "method": "eth_subscription",
+                    "params": { "subscription": remote_id, "result": remote_id },
+                });
+                stream
+                    .send(Message::Text(notification.to_string()))
+                    .await
+                    .unwrap();
+                if remote_id == "0xa" {
+                    stream.close(None).await.unwrap();
+                } else {
+                    stream.next().await;
+                }
+            }
+        });
+
+        let hub = Hub::new(url, None);
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let params = Params::new(["newHeads"]).unwrap();
+        let subscription = hub.subscribe(params, sender).await.unwrap();
+        for remote_id in ["0xa", "0xb"] {
+            let notification = receiver.recv().await.unwrap();
+            let notification = serde_json::from_str::<Value>(&notification).unwrap();
+            assert_eq!(notification["params"]["subscription"], subscription.id());
+            assert_eq!(notification["params"]["result"], remote_id);
+        }
+        remote.abort();
+    }
+}
@@ -5,6 +5,11 @@ function sighash(data)
     return string.sub(data, 1, 10)
 end
 
+function validate_hash()
+    print("raw hash signature not accepted")
+    return false
+end
+
 function validate_message()
     print("message signature not accepted")
     return false
@@ -46,4 +51,9 @@ function validate_typed_data(account, typed_data)
     return true
 end
 
+function validate_user_operation()
+    print("user operation signature not accepted")
+    return false
+end
+
 print("loaded CowSwap validator. MOO!")